@@ -0,0 +1,446 @@
+// benches/hot_paths.rs
+//! Numbers for the paths every game leans on: encoding server messages, dealing boards,
+//! finding a move on one, fanning a broadcast out to a room and handling the score updates a
+//! game is mostly made of.
+//!
+//!     cargo bench --bench hot_paths
+//!     cargo bench --bench hot_paths -- serialize/GameStarted
+//!     cargo bench --bench hot_paths -- fan_out
+//!     cargo bench --bench hot_paths -- score_updates
+//!
+//! Run it before and after a change on the same machine; criterion keeps the last run in
+//! `target/criterion` and reports the difference.
+
+mod support;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use fruitbox_fsg::{
+    board,
+    room_bus::{LocalBus, RoomTx},
+    stats::Counters,
+    ws_messages::{
+        BoardData, ChatChannel, EmoteKind, ErrorCode, Player, PlayerId, Rect, RoomCloseReason,
+        RoomEvent, RoomLogEntry, RoomSettings, SystemMessageKind, WsServerMsg,
+    },
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{hint::black_box, sync::Arc};
+use tokio::sync::broadcast;
+use support::{Games, UPDATES};
+
+/// Same shape as the real combos: eight counts, padded out to a full board with 9s.
+const COMBOS: [[u8; 8]; 1] = [[19; 8]];
+
+/// A busy room, so list-carrying messages are as big as they get in practice.
+const PLAYERS: usize = 8;
+/// Sockets subscribed to the room a broadcast fans out to.
+const SUBSCRIBERS: usize = 16;
+const LOG_ENTRIES: usize = 50;
+
+fn players() -> Vec<Player> {
+    (0..PLAYERS)
+        .map(|i| Player {
+            player_id: format!("3f0c6a1e-5b7d-4c2a-9e8f-00000000000{i}"),
+            name: format!("Player {i}"),
+            ready: i % 2 == 0,
+            muted: false,
+        })
+        .collect()
+}
+
+fn scores() -> Vec<(PlayerId, u32)> {
+    players()
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| (p.player_id, 40 + i as u32 * 7))
+        .collect()
+}
+
+fn log() -> Vec<RoomLogEntry> {
+    let player = players().remove(0);
+    (0..LOG_ENTRIES as u64)
+        .map(|i| match i % 5 {
+            0 => RoomLogEntry::System {
+                kind: SystemMessageKind::PlayerJoined,
+                text: "Player 3 joined".to_owned(),
+                at_ms: 1_760_000_000_000 + i,
+            },
+            _ => RoomLogEntry::Chat {
+                message_id: i,
+                channel: ChatChannel::All,
+                player: player.clone(),
+                message: "gg, that last column was brutal".to_owned(),
+                is_spectator: false,
+                at_ms: 1_760_000_000_000 + i,
+            },
+        })
+        .collect()
+}
+
+fn full_board() -> Arc<BoardData> {
+    Arc::new(board::board_from_seed(&COMBOS, 7, &RoomSettings::default()))
+}
+
+fn player_board() -> Vec<Option<u8>> {
+    board::player_board(&full_board())
+}
+
+/// One of every `WsServerMsg`, named for the bench IDs.
+fn every_message() -> Vec<(&'static str, WsServerMsg)> {
+    let room_id = "4821".to_owned();
+    let (player, pid) = (players().remove(0), players().remove(0).player_id);
+    let rect = Rect { x: 3, y: 4, w: 2, h: 3 };
+    vec![
+        ("RoomCreated", WsServerMsg::RoomCreated { room_id: room_id.clone() }),
+        (
+            "SeatGranted",
+            WsServerMsg::SeatGranted {
+                room_id: room_id.clone(),
+                player_id: pid.clone(),
+                seat_token: "0f5c3b1e9a7d4c2e8b6a4f2d0c8e6a4b".to_owned(),
+            },
+        ),
+        (
+            "RoomPlayersUpdate",
+            WsServerMsg::RoomPlayersUpdate {
+                room_id: room_id.clone(),
+                players: players(),
+                owner_id: pid.clone(),
+            },
+        ),
+        (
+            "OwnerChanged",
+            WsServerMsg::OwnerChanged {
+                room_id: room_id.clone(),
+                owner_id: pid.clone(),
+                previous_owner_id: pid.clone(),
+            },
+        ),
+        ("GameStarting", WsServerMsg::GameStarting { room_id: room_id.clone(), in_secs: 3 }),
+        (
+            "GameStarted",
+            WsServerMsg::GameStarted {
+                room_id: room_id.clone(),
+                board: full_board(),
+                duration_secs: 120,
+                settings: RoomSettings::default(),
+            },
+        ),
+        ("TimerTick", WsServerMsg::TimerTick { remaining_secs: 87, ends_at_ms: 1_760_000_087_000 }),
+        (
+            "LeaderboardUpdate",
+            WsServerMsg::LeaderboardUpdate {
+                room_id: room_id.clone(),
+                scores: scores(),
+                turns: scores(),
+            },
+        ),
+        (
+            "ChatBroadcast",
+            WsServerMsg::ChatBroadcast {
+                room_id: room_id.clone(),
+                message_id: 118,
+                channel: ChatChannel::All,
+                player: player.clone(),
+                message: "gg, that last column was brutal".to_owned(),
+                is_spectator: false,
+                at_ms: 1_760_000_000_000,
+            },
+        ),
+        (
+            "LobbyChatBroadcast",
+            WsServerMsg::LobbyChatBroadcast {
+                name: "Player 0".to_owned(),
+                message: "anyone up for a round?".to_owned(),
+            },
+        ),
+        (
+            "EmoteBroadcast",
+            WsServerMsg::EmoteBroadcast {
+                room_id: room_id.clone(),
+                player_id: pid.clone(),
+                emote: EmoteKind::GoodGame,
+            },
+        ),
+        (
+            "RoomClosed",
+            WsServerMsg::RoomClosed { room_id: room_id.clone(), reason: RoomCloseReason::Empty },
+        ),
+        ("LeftRoom", WsServerMsg::LeftRoom { room_id: room_id.clone() }),
+        ("SessionReplaced", WsServerMsg::SessionReplaced {}),
+        (
+            "ReclaimOffer",
+            WsServerMsg::ReclaimOffer { room_id: room_id.clone(), name: player.name.clone() },
+        ),
+        (
+            "Resumed",
+            WsServerMsg::Resumed {
+                room_id: room_id.clone(),
+                board: Some(player_board()),
+                scores: scores(),
+            },
+        ),
+        (
+            "StateSync",
+            WsServerMsg::StateSync {
+                room_id: room_id.clone(),
+                scores: scores(),
+                board: Some(player_board().into_boxed_slice()),
+                remaining_secs: Some(87),
+                ends_at_ms: Some(1_760_000_087_000),
+                entries: log(),
+            },
+        ),
+        (
+            "ReadyAck",
+            WsServerMsg::ReadyAck { room_id: room_id.clone(), ready: true, counted: true },
+        ),
+        (
+            "ReadyStatus",
+            WsServerMsg::ReadyStatus {
+                room_id: room_id.clone(),
+                ready: players().into_iter().map(|p| p.player_id).take(4).collect(),
+                not_ready: players().into_iter().map(|p| p.player_id).skip(4).collect(),
+                can_start: false,
+            },
+        ),
+        (
+            "ScoreAck",
+            WsServerMsg::ScoreAck { room_id: room_id.clone(), turn: 12, total: 48, applied: true },
+        ),
+        (
+            "BoardCleared",
+            WsServerMsg::BoardCleared {
+                room_id: room_id.clone(),
+                player_id: pid.clone(),
+                rect: rect.clone(),
+            },
+        ),
+        (
+            "NoMovesLeft",
+            WsServerMsg::NoMovesLeft { room_id: room_id.clone(), player_id: pid.clone() },
+        ),
+        (
+            "MovedToSpectators",
+            WsServerMsg::MovedToSpectators { room_id: room_id.clone(), player_id: pid.clone() },
+        ),
+        (
+            "PlayerScore",
+            WsServerMsg::PlayerScore {
+                room_id: room_id.clone(),
+                player_id: pid.clone(),
+                score: 48,
+                turns: 12,
+            },
+        ),
+        (
+            "PlayerFinished",
+            WsServerMsg::PlayerFinished {
+                room_id: room_id.clone(),
+                player_id: pid.clone(),
+                score: 48,
+                elapsed_ms: 97_250,
+            },
+        ),
+        (
+            "GameOver",
+            WsServerMsg::GameOver {
+                room_id: room_id.clone(),
+                scores: scores(),
+                ended_early: false,
+                finish_times_ms: scores(),
+                disconnected: vec![pid.clone()],
+            },
+        ),
+        (
+            "GameAborted",
+            WsServerMsg::GameAborted {
+                room_id: room_id.clone(),
+                reason: "the game timer failed".to_owned(),
+                scores: scores(),
+            },
+        ),
+        ("Hint", WsServerMsg::Hint { room_id: room_id.clone(), rect: Some(rect) }),
+        (
+            "SystemMessage",
+            WsServerMsg::SystemMessage {
+                room_id: room_id.clone(),
+                kind: SystemMessageKind::PlayerLeft,
+                text: "Player 3 left".to_owned(),
+                at_ms: 1_760_000_000_000,
+            },
+        ),
+        ("RoomHistory", WsServerMsg::RoomHistory { room_id: room_id.clone(), entries: log() }),
+        (
+            "ChatHistory",
+            WsServerMsg::ChatHistory { room_id: room_id.clone(), messages: log(), has_more: true },
+        ),
+        (
+            "Error",
+            WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "Turn 14 came before turn 13".to_owned(),
+                code: Some(ErrorCode::TurnOutOfOrder { expected: 13 }),
+            },
+        ),
+        (
+            "ServerInfo",
+            WsServerMsg::ServerInfo {
+                server_name: "Fruitbox".to_owned(),
+                motd: Some("Weekend tournament starts Saturday 18:00 UTC".to_owned()),
+            },
+        ),
+        (
+            "Top10Scores",
+            WsServerMsg::Top10Scores {
+                scores: Arc::new((0..10).map(|i| (200 - i * 9, format!("Player {i}"))).collect()),
+            },
+        ),
+        ("GlobalPresence", WsServerMsg::GlobalPresence { online: 412, rooms: 57 }),
+        (
+            "ServerStats",
+            WsServerMsg::ServerStats {
+                messages_handled: 9_812_331,
+                broadcasts: 4_100_772,
+                games_completed: 3_117,
+            },
+        ),
+    ]
+}
+
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    for (name, msg) in every_message() {
+        group.bench_function(name, |b| b.iter(|| serde_json::to_string(black_box(&msg))));
+    }
+    // broadcasts go out wrapped with their `seq`, which flattens the message
+    let (_, started) = every_message()
+        .into_iter()
+        .find(|(name, _)| *name == "GameStarted")
+        .unwrap();
+    let event = RoomEvent { seq: 41, msg: started };
+    group.bench_function("RoomEvent/GameStarted", |b| {
+        b.iter(|| serde_json::to_string(black_box(&event)))
+    });
+    group.finish();
+}
+
+fn deal(c: &mut Criterion) {
+    let classic = RoomSettings::default();
+    let custom = RoomSettings {
+        min_value: 1,
+        max_value: 12,
+        target_sum: 15,
+        ..RoomSettings::default()
+    };
+    let mut group = c.benchmark_group("deal");
+    group.bench_function("classic/combos", |b| {
+        let mut rng = StdRng::seed_from_u64(1);
+        b.iter(|| board::generate_board(&COMBOS, &classic, &mut rng))
+    });
+    group.bench_function("custom/solvable", |b| {
+        let mut rng = StdRng::seed_from_u64(1);
+        b.iter(|| board::random_board(&mut rng, &custom))
+    });
+    group.bench_function("custom/unguarded", |b| {
+        let mut rng = StdRng::seed_from_u64(1);
+        b.iter(|| board::uniform_board(&mut rng, &custom))
+    });
+    group.finish();
+}
+
+fn find_clear(c: &mut Criterion) {
+    let settings = RoomSettings::default();
+    let mut rng = StdRng::seed_from_u64(2);
+    let boards: Vec<_> = (0..64)
+        .map(|_| board::player_board(&board::uniform_board(&mut rng, &settings)))
+        .collect();
+    // nothing adds up to 10, so every rectangle gets looked at
+    let dead = vec![Some(9); boards[0].len()];
+
+    let mut group = c.benchmark_group("find_clear");
+    group.bench_function("random", |b| {
+        let mut next = boards.iter().cycle();
+        b.iter(|| board::find_clear(next.next().unwrap(), settings.target_sum))
+    });
+    group.bench_function("no_moves", |b| {
+        b.iter(|| board::find_clear(black_box(&dead), settings.target_sum))
+    });
+    // what a client's clears look like to the server over a game: clear, look again
+    group.bench_function("play_out", |b| {
+        b.iter_batched(
+            || boards[0].clone(),
+            |mut board| {
+                while let Some(rect) = board::find_clear(&board, settings.target_sum) {
+                    board::clear_rect(&mut board, &rect);
+                }
+                board
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+/// One `GameStarted` (a full board) to `SUBSCRIBERS` sockets, the way `RoomTx::send` does it
+/// (serialized once, every socket handed the same `Arc<str>`) and the way it would be if each
+/// socket encoded its own copy.
+fn fan_out(c: &mut Criterion) {
+    let (_, started) = every_message()
+        .into_iter()
+        .find(|(name, _)| *name == "GameStarted")
+        .unwrap();
+    let mut group = c.benchmark_group("fan_out");
+
+    let tx = RoomTx::new(
+        "0001".to_owned(),
+        Arc::new(LocalBus),
+        Arc::new(Counters::default()),
+        16,
+    );
+    let mut sockets: Vec<_> = (0..SUBSCRIBERS).map(|_| tx.subscribe()).collect();
+    group.bench_function("serialize_once", |b| {
+        b.iter(|| {
+            tx.send(started.clone());
+            for socket in &mut sockets {
+                black_box(socket.try_recv().unwrap());
+            }
+        })
+    });
+
+    let (events, _) = broadcast::channel(16);
+    let mut sockets: Vec<_> = (0..SUBSCRIBERS).map(|_| events.subscribe()).collect();
+    let mut seq = 0;
+    group.bench_function("per_recipient", |b| {
+        b.iter(|| {
+            seq += 1;
+            let _ = events.send(RoomEvent { seq, msg: started.clone() });
+            for socket in &mut sockets {
+                let event = socket.try_recv().unwrap();
+                black_box(serde_json::to_string(&event).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+/// `UPDATES` score updates through `handle_client_msg`, on one thread so the number is what the
+/// handler costs rather than how well rooms spread over cores (`room_locks` is for that).
+fn score_updates(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("score_updates");
+    group.throughput(Throughput::Elements(UPDATES.into()));
+    for rooms in [1, 4, 16] {
+        let games = rt.block_on(Games::start(rooms));
+        group.bench_with_input(BenchmarkId::from_parameter(rooms), &games, |b, games| {
+            b.to_async(&rt).iter(|| games.flood())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialize, deal, find_clear, fan_out, score_updates);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+// benches/room_locks.rs
+//! Score floods in several rooms at once, through the real handler: each update looks its room
+//! up in `AppState::rooms` and locks only that room (`lock_room`), so rooms shouldn't wait on
+//! each other.
+//!
+//!     cargo bench --bench room_locks
+//!
+//! Throughput should climb with the number of rooms until there are more rooms than cores; a
+//! flat line means something shared across rooms is being held. The runtime gets a worker per
+//! core, so there's only a climb to see on a machine with more than one.
+
+mod support;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use support::{Games, UPDATES};
+
+fn room_locks(c: &mut Criterion) {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(workers)
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("score_flood");
+    group.throughput(Throughput::Elements(UPDATES.into()));
+    for rooms in [1, 4, 16] {
+        let games = runtime.block_on(Games::start(rooms));
+        group.bench_with_input(BenchmarkId::new("per_room_lock", rooms), &games, |b, games| {
+            b.to_async(&runtime).iter(|| games.flood())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, room_locks);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+// benches/support/mod.rs
+//! Games under way on a real `AppState` (in-memory top 10, single-instance bus), played by
+//! socketless `Connection`s through the same handler a socket's messages go to.
+
+use fruitbox_fsg::{
+    config::Config,
+    game_timer::GameTimers,
+    score_store::MemoryStore,
+    server_state::AppState,
+    ws_messages::{Player, WsClientMsg},
+    Connection,
+};
+use futures_util::sink;
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Score updates per flood, split evenly across the rooms.
+pub const UPDATES: u32 = 10_000;
+
+/// Same shape as the real combos: eight counts, padded out to a full board with 9s.
+const COMBOS: [[u8; 8]; 1] = [[19; 8]];
+
+/// One solo game's player, and the turn they're up to.
+struct Seat {
+    conn: Connection,
+    turn: u32,
+}
+
+/// Solo games, one per room, started on the runtime the benches run them on. Games last two
+/// minutes, far longer than a bench.
+pub struct Games {
+    state: AppState,
+    /// Handed to a flood's tasks while it runs.
+    seats: Mutex<Vec<Seat>>,
+}
+
+impl Games {
+    pub async fn start(rooms: usize) -> Self {
+        let config = Config {
+            start_countdown: Duration::ZERO,
+            max_rooms_per_ip_per_minute: 0,
+            ..Config::default()
+        };
+        let mut state = AppState::with_score_store(Arc::new(MemoryStore::default()), config).await;
+        state.combos = Arc::new(COMBOS);
+        state.timers = Arc::new(GameTimers::start(state.clone()));
+        let mut seats = Vec::new();
+        for i in 0..rooms {
+            // replies are taken as fast as they come, like a socket whose client keeps up
+            let mut conn = Connection::new(&state, IpAddr::from([127, 0, 0, 1]), sink::drain());
+            let player = Player {
+                player_id: format!("3f0c6a1e-5b7d-4c2a-9e8f-{i:012}"),
+                name: format!("Player {i}"),
+                ready: false,
+                muted: false,
+            };
+            if let Err(refusal) = conn.handle(WsClientMsg::StartSolo { player }, &state).await {
+                panic!("game not started: {refusal:?}");
+            }
+            seats.push(Seat { conn, turn: 0 });
+        }
+        Games { state, seats: Mutex::new(seats) }
+    }
+
+    /// `UPDATES` two-apple clears, each room's played out by a task of its own.
+    pub async fn flood(&self) {
+        let seats = std::mem::take(&mut *self.seats.lock().unwrap());
+        let per_room = UPDATES / seats.len() as u32;
+        let floods: Vec<_> = seats
+            .into_iter()
+            .map(|mut seat| {
+                let state = self.state.clone();
+                tokio::spawn(async move {
+                    for _ in 0..per_room {
+                        seat.turn += 1;
+                        let update = WsClientMsg::ScoreUpdate {
+                            cleared_count: 2,
+                            turn: seat.turn,
+                            cleared_values: vec![1, 9],
+                            rect: None,
+                        };
+                        if let Err(refusal) = seat.conn.handle(update, &state).await {
+                            panic!("score update refused: {refusal:?}");
+                        }
+                    }
+                    seat
+                })
+            })
+            .collect();
+        let mut seats = Vec::with_capacity(floods.len());
+        for flood in floods {
+            seats.push(flood.await.unwrap());
+        }
+        *self.seats.lock().unwrap() = seats;
+    }
+}
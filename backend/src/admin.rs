@@ -0,0 +1,280 @@
+// src/admin.rs
+use crate::{
+    audit::admin_actor, net::parse_ip_nets, server_state::AppState, ws_messages::RoomCloseReason,
+};
+use axum::{
+    extract::{FromRequestParts, Path, Query, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::{sync::atomic::Ordering, time::Duration};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde_json::{json, Value};
+
+/// Longest MOTD the admin API accepts, in characters.
+const MAX_MOTD_LEN: usize = 500;
+
+/// Operator-only HTTP API, mounted under `/api/admin`.
+/// Every route requires `Authorization: Bearer <ADMIN_TOKEN>`; without a configured token the API is off.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/seeds", get(list_seeds))
+        .route("/bans", get(list_bans).post(add_ban))
+        .route("/bans/{id}", delete(remove_ban))
+        .route("/audit", get(list_audit))
+        .route("/motd", put(set_motd))
+        .route("/deny-list/reload", post(reload_deny_list))
+        .route("/rooms/{id}", delete(close_room))
+        .route("/sessions/{player_id}", get(list_sessions))
+        .route("/drain", get(drain_status).post(start_drain).delete(stop_drain))
+}
+
+/// Extractor that only succeeds for requests carrying the configured admin token.
+/// `actor` names the token in the audit log without revealing it.
+pub struct AdminAuth {
+    pub actor: String,
+}
+
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Some(expected) = state.config.admin_token.as_deref() else {
+            // admin API disabled → pretend it doesn't exist
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| StatusCode::UNAUTHORIZED)?;
+        if constant_time_eq(bearer.token().as_bytes(), expected.as_bytes()) {
+            Ok(AdminAuth {
+                actor: admin_actor(expected),
+            })
+        } else {
+            tracing::warn!("admin request with wrong token");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// `GET /api/stats` — the server's running counters plus a per-room breakdown. Admin-only: room
+/// IDs double as join codes.
+pub async fn stats(_: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    let mut rooms = Vec::new();
+    for (room_id, room) in state.all_rooms() {
+        let room = room.lock().await;
+        rooms.push(json!({
+            "room_id": room_id,
+            "players": room.players.len(),
+            "spectators": room.spectators.len(),
+            "broadcasts": room.tx.seq(),
+            "lagged": room.lagged,
+            "game_in_progress": room.game_in_progress(),
+        }));
+    }
+    Json(json!({
+        "online": state.online.load(Ordering::Relaxed),
+        "counters": state.counters.snapshot(),
+        "rooms": rooms,
+    }))
+}
+
+/// Compare two secrets without bailing out at the first differing byte.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// `GET /api/admin/seeds` — the most recent board seeds, newest first, for reproducing bug reports.
+async fn list_seeds(_: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    let seeds: Vec<_> = state.recent_seeds.lock().unwrap().iter().rev().cloned().collect();
+    Json(json!({ "seeds": seeds }))
+}
+
+/// `GET /api/admin/bans` — every ban still in force.
+async fn list_bans(_: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "bans": state.bans.list() }))
+}
+
+#[derive(Deserialize)]
+struct NewBan {
+    /// An address or CIDR, e.g. `203.0.113.7` or `203.0.113.0/24`.
+    net: String,
+    #[serde(default)]
+    reason: String,
+    /// Omit for a permanent ban.
+    duration_secs: Option<u64>,
+}
+
+/// `POST /api/admin/bans` — ban an address or network; sockets from it are closed right away.
+async fn add_ban(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Json(req): Json<NewBan>,
+) -> Response {
+    let (nets, _) = parse_ip_nets(&req.net);
+    let [net] = nets[..] else {
+        let error = format!("expected one address or CIDR, got {:?}", req.net);
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    };
+    let duration = req.duration_secs.map(Duration::from_secs);
+    let ban = state.bans.add(net, req.reason, duration).await;
+    tracing::warn!(ban_id = ban.id, net = %ban.net, reason = %ban.reason, "address banned");
+    state
+        .audit
+        .record(admin.actor, "ban_added", Some(ban.net.to_string()), None);
+    (StatusCode::CREATED, Json(ban)).into_response()
+}
+
+/// `DELETE /api/admin/bans/{id}` — lift a ban.
+async fn remove_ban(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> StatusCode {
+    if state.bans.remove(id).await {
+        tracing::warn!(ban_id = id, "ban lifted");
+        state
+            .audit
+            .record(admin.actor, "ban_removed", Some(id.to_string()), None);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    limit: Option<usize>,
+}
+
+/// `GET /api/admin/audit?limit=100` — the most recent audit entries, oldest first (at most 1000).
+async fn list_audit(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Value> {
+    let limit = query.limit.unwrap_or(100).min(1000);
+    Json(json!({ "entries": state.audit.recent(limit).await }))
+}
+
+#[derive(Deserialize)]
+struct NewMotd {
+    /// `null` or blank clears the MOTD.
+    motd: Option<String>,
+}
+
+/// `PUT /api/admin/motd` — replace the message of the day; lobby sockets get it right away.
+async fn set_motd(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Json(req): Json<NewMotd>,
+) -> Response {
+    let motd = req.motd.filter(|m| !m.trim().is_empty());
+    if motd.as_ref().is_some_and(|m| m.chars().count() > MAX_MOTD_LEN) {
+        let error = format!("motd is longer than {MAX_MOTD_LEN} characters");
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    tracing::info!(motd = ?motd, "motd updated");
+    state.audit.record(admin.actor, "motd_updated", motd.clone(), None);
+    state.set_motd(motd);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// `POST /api/admin/deny-list/reload` — reread the profanity filter's word file; new chat and
+/// names are checked against it right away. 409 when the filter is off.
+async fn reload_deny_list(admin: AdminAuth, State(state): State<AppState>) -> Response {
+    if !state.config.profanity_filter {
+        let error = "the profanity filter is off";
+        return (StatusCode::CONFLICT, Json(json!({ "error": error }))).into_response();
+    }
+    match state.deny_list.reload() {
+        Ok(words) => {
+            tracing::info!(words, "deny list reloaded");
+            state
+                .audit
+                .record(admin.actor, "deny_list_reloaded", Some(words.to_string()), None);
+            Json(json!({ "words": words })).into_response()
+        }
+        Err(e) => {
+            tracing::error!("{e:#}");
+            let error = format!("{e:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": error }))).into_response()
+        }
+    }
+}
+
+/// `DELETE /api/admin/rooms/{id}` — shut a room down; everyone in it gets `RoomClosed` and is
+/// disconnected, and a running game is abandoned without recording scores.
+async fn close_room(
+    admin: AdminAuth,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> StatusCode {
+    if !state.close_room(&room_id, RoomCloseReason::AdminClosed).await {
+        return StatusCode::NOT_FOUND;
+    }
+    tracing::warn!(room_id = %room_id, "room closed by admin");
+    state
+        .audit
+        .record(admin.actor, "room_closed", None, Some(room_id));
+    StatusCode::NO_CONTENT
+}
+
+/// `GET /api/admin/sessions/{player_id}` — the sockets currently using this player ID, oldest
+/// first.
+async fn list_sessions(
+    _: AdminAuth,
+    State(state): State<AppState>,
+    Path(player_id): Path<String>,
+) -> Json<Value> {
+    Json(json!({ "sessions": state.sessions.list(&player_id) }))
+}
+
+/// `GET /api/admin/drain` — whether the server is draining, and what it's still waiting on.
+/// Deploy scripts poll this until `games_in_progress` reaches zero.
+async fn drain_status(_: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    drain_report(&state).await
+}
+
+/// `POST /api/admin/drain` — stop accepting new sockets, rooms and games; running games finish.
+async fn start_drain(admin: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    state.set_draining(true);
+    tracing::warn!("draining started");
+    state.audit.record(admin.actor, "drain_started", None, None);
+    drain_report(&state).await
+}
+
+/// `DELETE /api/admin/drain` — accept new sockets, rooms and games again.
+async fn stop_drain(admin: AdminAuth, State(state): State<AppState>) -> Json<Value> {
+    state.set_draining(false);
+    tracing::warn!("draining stopped");
+    state.audit.record(admin.actor, "drain_stopped", None, None);
+    drain_report(&state).await
+}
+
+async fn drain_report(state: &AppState) -> Json<Value> {
+    let mut games_in_progress = 0;
+    for (_, room) in state.all_rooms() {
+        if room.lock().await.game_in_progress() {
+            games_in_progress += 1;
+        }
+    }
+    Json(json!({
+        "draining": state.is_draining(),
+        "shutting_down": state.is_shutting_down(),
+        "online": state.online.load(Ordering::Relaxed),
+        "rooms": state.room_count.load(Ordering::Relaxed),
+        "games_in_progress": games_in_progress,
+        "games_running": state.timers.running(),
+    }))
+}
@@ -0,0 +1,501 @@
+// src/assets.rs
+use crate::config::Config;
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+use std::path::PathBuf;
+use tower_http::{
+    compression::CompressionLayer,
+    services::{ServeDir, ServeFile},
+};
+
+/// Where the built frontend lives when it's served from disk.
+pub fn default_assets_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("frontend")
+        .join("dist")
+}
+
+/// The static frontend, used as the router's fallback.
+///
+/// Unknown `/api/` and `/ws` paths get a JSON 404, paths that look like files are served as
+/// files, and anything else is a client-side route and gets `index.html`. Pre-compressed
+/// `.br`/`.gz` siblings are served when the client accepts them; HTML without one is gzipped on
+/// the fly. None of this applies to `/ws` or the API, which are routed before the fallback.
+///
+/// With the `embed-assets` feature the dist is compiled into the binary and served from memory,
+/// unless `--assets-dir` overrides it; otherwise files come from that directory (or the default
+/// dist directory) on disk. `--no-assets` serves only a small status page at `/`.
+/// Fails when the frontend to serve has no `index.html`, rather than 404ing every page later.
+pub fn router(config: &Config) -> Result<Router> {
+    let ws_path = config.ws_path.clone();
+    if config.no_assets {
+        tracing::info!("frontend disabled, running API-only");
+        let ws_url = format!("{}{}", config.base_path, config.ws_path);
+        return Ok(Router::new()
+            .route("/", get(move || status_page(ws_url)))
+            .fallback(move |req: Request| async move {
+                match classify(req.uri().path(), &ws_path) {
+                    Target::Api => api_not_found(),
+                    Target::Asset | Target::Page => StatusCode::NOT_FOUND.into_response(),
+                }
+            }));
+    }
+
+    #[cfg(feature = "embed-assets")]
+    if config.assets_dir.is_none() {
+        if !embedded::has_index() {
+            bail!("the embedded frontend has no index.html; build the frontend before the backend");
+        }
+        tracing::info!("serving embedded frontend assets");
+        return Ok(Router::new()
+            .fallback(move |uri: axum::http::Uri, headers: HeaderMap| async move {
+                match classify(uri.path(), &ws_path) {
+                    Target::Api => api_not_found(),
+                    Target::Asset => embedded::serve(uri.path(), &headers),
+                    Target::Page => embedded::serve("/index.html", &headers),
+                }
+            })
+            .layer(html_compression())
+            .layer(middleware::from_fn(static_headers)));
+    }
+
+    let dir = config.assets_dir.clone().unwrap_or_else(default_assets_dir);
+    let dir = dir.canonicalize().with_context(|| {
+        format!(
+            "assets directory {} does not exist (build the frontend, pass --assets-dir, or --no-assets)",
+            dir.display()
+        )
+    })?;
+    if !dir.join("index.html").is_file() {
+        bail!("assets directory {} has no index.html", dir.display());
+    }
+    tracing::info!(dir = %dir.display(), "serving frontend assets from disk");
+    let files = ServeDir::new(&dir).precompressed_br().precompressed_gzip();
+    let index = ServeFile::new(dir.join("index.html"))
+        .precompressed_br()
+        .precompressed_gzip();
+    Ok(Router::new()
+        .fallback(move |req: Request| {
+            let (mut files, mut index) = (files.clone(), index.clone());
+            let ws_path = ws_path.clone();
+            async move {
+                let served = match classify(req.uri().path(), &ws_path) {
+                    Target::Api => return api_not_found(),
+                    Target::Asset => files.try_call(req).await,
+                    Target::Page => index.try_call(req).await,
+                };
+                match served {
+                    Ok(res) => res.into_response(),
+                    Err(e) => {
+                        tracing::error!(error = %e, "failed to read frontend asset");
+                        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                }
+            }
+        })
+        .layer(html_compression())
+        .layer(middleware::from_fn(static_headers)))
+}
+
+/// On-the-fly gzip, only for HTML; everything else ships pre-compressed or not at all.
+/// Responses that already carry a `Content-Encoding` are left alone.
+fn html_compression() -> CompressionLayer<impl tower_http::compression::Predicate> {
+    CompressionLayer::new()
+        .gzip(true)
+        .compress_when(|_: StatusCode, _: axum::http::Version, headers: &HeaderMap, _: &axum::http::Extensions| {
+            headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|ct| ct.starts_with("text/html"))
+        })
+}
+
+/// What a fallback request is after.
+enum Target {
+    /// `/api/...` or the WebSocket path (`/ws...`) that no route matched.
+    Api,
+    /// A file, recognised by a dot in the last path segment (`/assets/index-3f9a1c2b.js`).
+    Asset,
+    /// A client-side route such as `/room/1234`.
+    Page,
+}
+
+/// `path` is relative to the base path, as the fallback sees it.
+fn classify(path: &str, ws_path: &str) -> Target {
+    if path == "/api" || path.starts_with("/api/") || path.starts_with(ws_path) {
+        Target::Api
+    } else if path.rsplit('/').next().is_some_and(|last| last.contains('.')) {
+        Target::Asset
+    } else {
+        Target::Page
+    }
+}
+
+fn api_not_found() -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "error": "not found" }))).into_response()
+}
+
+/// What `/` shows in API-only mode.
+async fn status_page(ws_url: String) -> Html<String> {
+    Html(format!(
+        concat!(
+            "<!doctype html><title>fruitbox-fsg</title>",
+            "<h1>fruitbox-fsg ",
+            env!("CARGO_PKG_VERSION"),
+            "</h1><p>Game server is running without a frontend. WebSocket endpoint: <code>{}</code>.</p>",
+        ),
+        ws_url
+    ))
+}
+
+/// Long-lived caching for content-hashed bundles (`index-3f9a1c2b.js`), revalidation for the rest,
+/// so a deploy is picked up immediately through `index.html` while bundles stay cached.
+/// Every static response may differ by `Accept-Encoding`, so caches are told as much.
+async fn static_headers(req: Request, next: Next) -> Response {
+    let hashed = is_hashed_asset(req.uri().path());
+    let mut res = next.run(req).await;
+    if res.status().is_success() {
+        let value = if hashed {
+            "public, max-age=31536000, immutable"
+        } else {
+            "no-cache"
+        };
+        let headers = res.headers_mut();
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(value));
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    res
+}
+
+/// Vite names bundles `<name>-<hash>.<ext>`; treat a trailing 8+ character hash as content-addressed.
+fn is_hashed_asset(path: &str) -> bool {
+    let file = path.rsplit('/').next().unwrap_or("");
+    let Some((stem, _ext)) = file.split_once('.') else {
+        return false;
+    };
+    stem.rsplit_once('-').is_some_and(|(_, hash)| {
+        hash.len() >= 8 && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+    };
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[cfg_attr(not(test), folder = "frontend/dist")]
+    #[cfg_attr(test, folder = "src/tests/fixtures/dist")]
+    struct Dist;
+
+    pub fn has_index() -> bool {
+        Dist::get("index.html").is_some()
+    }
+
+    /// Serves a file from the embedded dist, preferring an embedded `.br` or `.gz` sibling
+    /// when the client accepts it.
+    pub fn serve(path: &str, headers: &HeaderMap) -> Response {
+        let path = path.trim_start_matches('/');
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        for (coding, ext) in [("br", "br"), ("gzip", "gz")] {
+            if !accepts_encoding(headers, coding) {
+                continue;
+            }
+            if let Some(file) = Dist::get(&format!("{path}.{ext}")) {
+                return (
+                    [
+                        (header::CONTENT_TYPE, mime.as_ref()),
+                        (header::CONTENT_ENCODING, coding),
+                    ],
+                    Body::from(file.data.into_owned()),
+                )
+                    .into_response();
+            }
+        }
+        match Dist::get(path) {
+            Some(file) => (
+                [(header::CONTENT_TYPE, mime.as_ref())],
+                Body::from(file.data.into_owned()),
+            )
+                .into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    /// Whether `Accept-Encoding` lists `coding` without ruling it out via `q=0`.
+    fn accepts_encoding(headers: &HeaderMap, coding: &str) -> bool {
+        headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|entry| {
+                let mut parts = entry.split(';').map(str::trim);
+                let name = parts.next().unwrap_or("");
+                let refused = parts.any(|p| {
+                    p.strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                });
+                (name.eq_ignore_ascii_case(coding) || name == "*") && !refused
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    /// A small built frontend: `index.html` and one hashed bundle with `.br`/`.gz` siblings.
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/fixtures/dist");
+    const BUNDLE: &str = "/assets/index-3f9a1c2b.js";
+
+    async fn get(app: &Router, path: &str, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut req = Request::get(path);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        app.clone().oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    async fn body(res: Response) -> Vec<u8> {
+        to_bytes(res.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{FIXTURE}{name}")).unwrap()
+    }
+
+    fn header_of(res: &Response, name: header::HeaderName) -> Option<&str> {
+        res.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[cfg(feature = "embed-assets")]
+    #[tokio::test]
+    async fn the_embedded_frontend_serves_the_page_and_its_bundle() {
+        let app = router(&Config::default()).unwrap();
+
+        let res = get(&app, "/", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header_of(&res, header::CONTENT_TYPE), Some("text/html"));
+        assert_eq!(header_of(&res, header::CACHE_CONTROL), Some("no-cache"));
+        assert_eq!(body(res).await, fixture("/index.html"));
+
+        let res = get(&app, BUNDLE, &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header_of(&res, header::CONTENT_TYPE), Some("text/javascript"));
+        assert_eq!(
+            header_of(&res, header::CACHE_CONTROL),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(body(res).await, fixture(BUNDLE));
+
+        // client-side routes get the page, missing files don't
+        let res = get(&app, "/room/4821", &[]).await;
+        assert_eq!(body(res).await, fixture("/index.html"));
+        let res = get(&app, "/assets/gone-00000000.js", &[]).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "embed-assets")]
+    #[tokio::test]
+    async fn assets_dir_overrides_the_embedded_frontend() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("index.html"), "on disk").unwrap();
+        let app = router(&on_disk(dir.clone())).unwrap();
+        assert_eq!(body(get(&app, "/", &[]).await).await, b"on disk");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_frontend_on_disk_serves_the_page_and_its_bundle() {
+        let app = router(&on_disk(FIXTURE.into())).unwrap();
+
+        let res = get(&app, "/", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header_of(&res, header::CACHE_CONTROL), Some("no-cache"));
+        assert_eq!(body(res).await, fixture("/index.html"));
+
+        let res = get(&app, BUNDLE, &[]).await;
+        assert_eq!(header_of(&res, header::CONTENT_TYPE), Some("text/javascript"));
+        assert_eq!(
+            header_of(&res, header::CACHE_CONTROL),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(body(res).await, fixture(BUNDLE));
+    }
+
+    fn on_disk(dir: PathBuf) -> Config {
+        Config {
+            assets_dir: Some(dir),
+            ..Config::default()
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dist-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_missing_assets_dir_fails_with_the_path_and_the_ways_out() {
+        let dir = std::env::temp_dir().join("no-such-dist");
+        let error = format!("{:#}", router(&on_disk(dir.clone())).unwrap_err());
+        assert!(error.contains(&format!("assets directory {} does not exist", dir.display())));
+        assert!(error.contains("--no-assets"));
+    }
+
+    #[test]
+    fn an_assets_dir_without_index_html_fails() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("app.js"), "").unwrap();
+        let error = format!("{:#}", router(&on_disk(dir.clone())).unwrap_err());
+        let canonical = dir.canonicalize().unwrap();
+        assert_eq!(error, format!("assets directory {} has no index.html", canonical.display()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn api_only_mode_serves_a_status_page_and_nothing_else() {
+        let config = Config {
+            no_assets: true,
+            // never looked at
+            assets_dir: Some(std::env::temp_dir().join("no-such-dist")),
+            base_path: "/fruit".to_owned(),
+            ..Config::default()
+        };
+        let app = router(&config).unwrap();
+
+        let res = get(&app, "/", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(body(res).await).unwrap();
+        assert!(page.contains(env!("CARGO_PKG_VERSION")));
+        assert!(page.contains(&format!("<code>/fruit{}</code>", config.ws_path)));
+
+        assert_eq!(get(&app, "/room/4821", &[]).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get(&app, BUNDLE, &[]).await.status(), StatusCode::NOT_FOUND);
+        let res = get(&app, "/api/nope", &[]).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(res).await, br#"{"error":"not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn client_side_routes_get_the_page() {
+        let app = router(&on_disk(FIXTURE.into())).unwrap();
+        for path in ["/room/ABC123", "/room/ABC123/", "/leaderboard", "/deep/link/here"] {
+            let res = get(&app, path, &[]).await;
+            assert_eq!(res.status(), StatusCode::OK, "{path}");
+            assert_eq!(header_of(&res, header::CONTENT_TYPE), Some("text/html"), "{path}");
+            assert_eq!(body(res).await, fixture("/index.html"), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_api_and_socket_paths_get_a_json_404() {
+        let app = router(&on_disk(FIXTURE.into())).unwrap();
+        for path in ["/api", "/api/nope", "/api/rooms/4821", "/ws", "/ws/extra"] {
+            let res = get(&app, path, &[]).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(header_of(&res, header::CONTENT_TYPE), Some("application/json"), "{path}");
+            assert_eq!(body(res).await, br#"{"error":"not found"}"#, "{path}");
+        }
+        // only a whole `/api` segment counts
+        let res = get(&app, "/apiary", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn paths_that_look_like_files_are_files_or_404() {
+        let app = router(&on_disk(FIXTURE.into())).unwrap();
+        assert_eq!(body(get(&app, BUNDLE, &[]).await).await, fixture(BUNDLE));
+        for path in ["/assets/gone-00000000.js", "/favicon.ico", "/room/ABC123/logo.png"] {
+            let res = get(&app, path, &[]).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{path}");
+            // never the page: a missing script must not come back as HTML
+            assert_ne!(header_of(&res, header::CONTENT_TYPE), Some("text/html"), "{path}");
+        }
+    }
+
+    /// Every way this build can serve the fixture.
+    fn frontends() -> Vec<Router> {
+        let mut apps = vec![router(&on_disk(FIXTURE.into())).unwrap()];
+        if cfg!(feature = "embed-assets") {
+            apps.push(router(&Config::default()).unwrap());
+        }
+        apps
+    }
+
+    #[tokio::test]
+    async fn the_bundle_comes_precompressed_when_the_client_accepts_it() {
+        for app in frontends() {
+            let plain = get(&app, BUNDLE, &[]).await;
+            assert_eq!(header_of(&plain, header::CONTENT_ENCODING), None);
+            assert_eq!(header_of(&plain, header::VARY), Some("accept-encoding"));
+            let plain = body(plain).await;
+            assert_eq!(plain, fixture(BUNDLE));
+
+            let br = get(&app, BUNDLE, &[(header::ACCEPT_ENCODING, "gzip, deflate, br")]).await;
+            assert_eq!(header_of(&br, header::CONTENT_ENCODING), Some("br"));
+            assert_eq!(header_of(&br, header::CONTENT_TYPE), Some("text/javascript"));
+            assert_eq!(header_of(&br, header::VARY), Some("accept-encoding"));
+            let br = body(br).await;
+            assert_eq!(br, fixture(&format!("{BUNDLE}.br")));
+            assert!(br.len() < plain.len() / 4);
+
+            let gzip = get(&app, BUNDLE, &[(header::ACCEPT_ENCODING, "gzip")]).await;
+            assert_eq!(header_of(&gzip, header::CONTENT_ENCODING), Some("gzip"));
+            assert_eq!(body(gzip).await, fixture(&format!("{BUNDLE}.gz")));
+
+            // br refused outright falls back to the next best
+            let refused = get(&app, BUNDLE, &[(header::ACCEPT_ENCODING, "br;q=0, gzip")]).await;
+            assert_eq!(header_of(&refused, header::CONTENT_ENCODING), Some("gzip"));
+        }
+    }
+
+    #[tokio::test]
+    async fn html_without_a_sibling_is_gzipped_on_the_fly() {
+        for app in frontends() {
+            let res = get(&app, "/room/4821", &[(header::ACCEPT_ENCODING, "gzip")]).await;
+            assert_eq!(header_of(&res, header::CONTENT_ENCODING), Some("gzip"));
+            let gzipped = body(res).await;
+            let mut html = Vec::new();
+            flate2::read::GzDecoder::new(&gzipped[..]).read_to_end(&mut html).unwrap();
+            assert_eq!(html, fixture("/index.html"));
+        }
+    }
+
+    #[tokio::test]
+    async fn api_replies_are_never_compressed() {
+        for app in frontends() {
+            let res = get(&app, "/api/nope", &[(header::ACCEPT_ENCODING, "gzip, br")]).await;
+            assert_eq!(header_of(&res, header::CONTENT_ENCODING), None);
+            assert_eq!(body(res).await, br#"{"error":"not found"}"#);
+        }
+    }
+
+    #[test]
+    fn only_content_hashed_names_are_cached_for_good() {
+        assert!(is_hashed_asset("/assets/index-3f9a1c2b.js"));
+        assert!(is_hashed_asset("/assets/vendor-B_x9k2Lq.css"));
+        assert!(!is_hashed_asset("/index.html"));
+        assert!(!is_hashed_asset("/favicon.ico"));
+        assert!(!is_hashed_asset("/assets/my-app.js"));
+        assert!(!is_hashed_asset("/assets/index-3f9a1c2"));
+    }
+}
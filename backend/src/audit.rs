@@ -0,0 +1,147 @@
+// src/audit.rs
+use crate::{server_state::now_ms, ws_messages::RoomId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{fs, io::AsyncWriteExt, sync::mpsc};
+
+/// Entries waiting for the writer. Past this, new entries are dropped (and counted) rather than
+/// making a handler wait on a stalled disk.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// One moderation or admin action, one JSON object per line in the audit file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub at_ms: u64,
+    /// `admin:<fingerprint>` for the admin API, `player:<id>` for room owners.
+    pub actor: String,
+    /// Short snake_case verb, e.g. `ban_added`.
+    pub action: String,
+    /// What was acted on (an address, a ban id, a player id), if anything.
+    pub target: Option<String>,
+    pub room_id: Option<RoomId>,
+}
+
+/// Append-only audit trail.
+///
+/// `record` never waits: entries go through a bounded channel to a writer task that appends
+/// them to a JSONL file and rotates it to `<file>.1` once it passes `max_bytes`.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    tx: mpsc::Sender<AuditEntry>,
+    dropped: AtomicU64,
+}
+
+impl Default for AuditLog {
+    /// A log that discards everything (no writer task, nothing on disk).
+    fn default() -> Self {
+        let (tx, _) = mpsc::channel(1);
+        AuditLog {
+            path: None,
+            tx,
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+impl AuditLog {
+    /// Spawns the writer task for `path`. Must be called inside the runtime.
+    pub fn start(path: PathBuf, max_bytes: u64) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_entries(path.clone(), max_bytes, rx));
+        AuditLog {
+            path: Some(path),
+            tx,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(
+        &self,
+        actor: String,
+        action: &str,
+        target: Option<String>,
+        room_id: Option<RoomId>,
+    ) {
+        let entry = AuditEntry {
+            at_ms: now_ms(),
+            actor,
+            action: action.to_owned(),
+            target,
+            room_id,
+        };
+        match self.tx.try_send(entry) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(entry)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::warn!(action = %entry.action, dropped, "audit queue full, entry dropped");
+            }
+            // disabled log
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// The last `limit` entries on disk, oldest first. Entries still queued aren't included.
+    pub async fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let Some(path) = &self.path else {
+            return Vec::new();
+        };
+        let Ok(data) = fs::read_to_string(path).await else {
+            return Vec::new();
+        };
+        let mut entries: Vec<_> = data
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .take(limit)
+            .collect();
+        entries.reverse();
+        entries
+    }
+}
+
+/// `admin:` plus a short hash of the admin token, so entries say which token acted without
+/// putting the token itself in the file.
+pub fn admin_actor(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    let hex: String = digest[..4].iter().map(|b| format!("{b:02x}")).collect();
+    format!("admin:{hex}")
+}
+
+async fn write_entries(path: PathBuf, max_bytes: u64, mut rx: mpsc::Receiver<AuditEntry>) {
+    let mut size = fs::metadata(&path).await.map_or(0, |m| m.len());
+    while let Some(entry) = rx.recv().await {
+        let mut line = serde_json::to_vec(&entry).unwrap();
+        line.push(b'\n');
+        if max_bytes > 0 && size > 0 && size + line.len() as u64 > max_bytes {
+            rotate(&path).await;
+            size = 0;
+        }
+        match append(&path, &line).await {
+            Ok(()) => size += line.len() as u64,
+            Err(e) => tracing::error!(error = %e, path = %path.display(), "failed to write audit entry"),
+        }
+    }
+}
+
+async fn append(path: &Path, line: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line).await?;
+    file.flush().await
+}
+
+async fn rotate(path: &Path) {
+    let mut old = path.to_path_buf().into_os_string();
+    old.push(".1");
+    if let Err(e) = fs::rename(path, &old).await {
+        tracing::error!(error = %e, path = %path.display(), "failed to rotate audit log");
+    }
+}
@@ -0,0 +1,220 @@
+// src/bans.rs
+use crate::server_state::now_ms;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+/// A banned address or network, as stored in the ban file and returned by the admin API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    pub id: u64,
+    /// A single address is stored as a /32 (or /128).
+    pub net: IpNet,
+    pub reason: String,
+    pub created_ms: u64,
+    /// `None` bans forever.
+    pub expires_ms: Option<u64>,
+}
+
+impl Ban {
+    fn expired(&self, now_ms: u64) -> bool {
+        self.expires_ms.is_some_and(|at| at <= now_ms)
+    }
+}
+
+/// Addresses refused at the WebSocket upgrade, persisted to a JSON file so bans survive restarts.
+///
+/// Adding a ban bumps a generation counter; every open socket watches it and closes itself if
+/// its client address is now covered, so a fresh ban takes effect without waiting for reconnects.
+#[derive(Debug)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Arc<Mutex<Vec<Ban>>>,
+    generation: watch::Sender<u64>,
+    /// Held across a whole save, so two saves never share the temp file.
+    saving: Arc<Mutex<()>>,
+}
+
+impl Default for BanList {
+    /// An empty, in-memory list (nothing is written to disk).
+    fn default() -> Self {
+        BanList {
+            path: None,
+            bans: Arc::default(),
+            generation: watch::channel(0).0,
+            saving: Arc::default(),
+        }
+    }
+}
+
+impl BanList {
+    /// Reads `path` if it exists; a missing file is an empty list, a broken one is an error.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let bans = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice::<Vec<Ban>>(&data)
+                .with_context(|| format!("cannot parse ban list {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("cannot read ban list {}", path.display()))
+            }
+        };
+        Ok(BanList {
+            path: Some(path),
+            bans: Arc::new(Mutex::new(bans)),
+            generation: watch::channel(0).0,
+            saving: Arc::default(),
+        })
+    }
+
+    /// The active ban covering `ip`, if any.
+    pub fn check(&self, ip: IpAddr) -> Option<Ban> {
+        let now = now_ms();
+        self.bans
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|ban| !ban.expired(now) && ban.net.contains(&ip))
+            .cloned()
+    }
+
+    /// Every ban that hasn't expired yet, oldest first.
+    pub fn list(&self) -> Vec<Ban> {
+        let now = now_ms();
+        self.bans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|ban| !ban.expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Bans `net` for `duration` (forever when `None`), saves, and tells open sockets to re-check.
+    pub async fn add(&self, net: IpNet, reason: String, duration: Option<Duration>) -> Ban {
+        let created_ms = now_ms();
+        let ban = {
+            let mut bans = self.bans.lock().unwrap();
+            let ban = Ban {
+                id: bans.iter().map(|b| b.id).max().unwrap_or(0) + 1,
+                net: net.trunc(),
+                reason,
+                created_ms,
+                expires_ms: duration.map(|d| created_ms + d.as_millis() as u64),
+            };
+            bans.push(ban.clone());
+            ban
+        };
+        self.generation.send_modify(|g| *g += 1);
+        self.save().await;
+        ban
+    }
+
+    /// Lifts ban `id`; `false` if there was no such ban.
+    pub async fn remove(&self, id: u64) -> bool {
+        let removed = {
+            let mut bans = self.bans.lock().unwrap();
+            let before = bans.len();
+            bans.retain(|ban| ban.id != id);
+            bans.len() != before
+        };
+        if removed {
+            self.save().await;
+        }
+        removed
+    }
+
+    /// Drops expired bans, saving if anything changed.
+    pub async fn prune(&self) {
+        let now = now_ms();
+        let pruned = {
+            let mut bans = self.bans.lock().unwrap();
+            let before = bans.len();
+            bans.retain(|ban| !ban.expired(now));
+            before - bans.len()
+        };
+        if pruned > 0 {
+            tracing::info!(pruned, "expired bans removed");
+            self.save().await;
+        }
+    }
+
+    /// Changes whenever a ban is added. Removing or pruning a ban doesn't change it, since no
+    /// open socket can become banned that way.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.generation.subscribe()
+    }
+
+    async fn save(&self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let (bans, saving) = (self.bans.clone(), self.saving.clone());
+        // One save at a time, and the list is read under the save lock, so the last save to
+        // finish always has the newest list. Blocking I/O, hence the blocking thread.
+        let saved = tokio::task::spawn_blocking(move || {
+            let _saving = saving.lock().unwrap();
+            let data = serde_json::to_vec_pretty(&*bans.lock().unwrap()).unwrap();
+            // same write-then-rename dance as the scores file
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            if let Err(e) = std::fs::write(&tmp, data) {
+                tracing::error!(error = %e, path = ?tmp, "failed to write ban list temp file");
+                return;
+            }
+            if let Err(e) = std::fs::rename(&tmp, &path) {
+                tracing::error!(error = %e, path = %path.display(), "failed to replace ban list");
+            }
+        })
+        .await;
+        if let Err(e) = saved {
+            tracing::error!(error = %e, "ban list save task failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_changes_all_reach_the_file() {
+        let path = std::env::temp_dir().join(format!("bans-{}.json", uuid::Uuid::new_v4()));
+        let bans = Arc::new(BanList::load(path.clone()).unwrap());
+        let adds = (0..16u8).map(|i| {
+            let bans = bans.clone();
+            tokio::spawn(async move {
+                let net: IpNet = format!("10.0.0.{i}/32").parse().unwrap();
+                bans.add(net, "test".to_string(), None).await
+            })
+        });
+        for add in adds.collect::<Vec<_>>() {
+            add.await.unwrap();
+        }
+        assert!(bans.remove(1).await);
+
+        let reloaded = BanList::load(path.clone()).unwrap();
+        assert_eq!(reloaded.list().len(), 15);
+        assert!(reloaded.check("10.0.0.5".parse().unwrap()).is_some());
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!std::path::Path::new(&tmp).exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_adding_bumps_the_generation() {
+        let bans = BanList::default();
+        let rx = bans.subscribe();
+        let ban = bans.add("10.0.0.0/8".parse().unwrap(), "test".to_string(), None).await;
+        assert_eq!(*rx.borrow(), 1);
+        assert!(bans.remove(ban.id).await);
+        assert_eq!(*rx.borrow(), 1);
+    }
+}
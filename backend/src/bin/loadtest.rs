@@ -0,0 +1,425 @@
+// src/bin/loadtest.rs
+//! Simulates a crowd of players against a running server and reports how it held up.
+//!
+//! `--players` sockets are spread over `--rooms` rooms: the first in each creates it, the rest
+//! join and ready up, and the owner starts a game once everyone is in. Until `--duration` runs
+//! out every player sends `ScoreUpdate`s and chat at the given rates; games that end are started
+//! again. Round trips are timed with WebSocket pings carrying a nonce the pong echoes back.
+//!
+//! Exits non-zero when errors (error replies, failed connections, dropped sockets) exceed
+//! `--max-error-rate` of the messages sent, so it doubles as a smoke test. All sockets come from
+//! one address, so run the server with `--max-connections-per-ip 0 --max-rooms-per-ip-per-minute 0`.
+//!
+//!     cargo run --release --bin loadtest -- --url ws://127.0.0.1:3123/ws --players 60 --rooms 10
+
+#[allow(dead_code)]
+#[path = "../ws_messages.rs"]
+mod ws_messages;
+
+use anyhow::{bail, Context};
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+use std::{
+    collections::{BTreeMap, HashMap},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::watch,
+    time::{Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::tungstenite::Message;
+use ws_messages::{
+    Player, RoomEvent, RoomId, SystemMessageKind, WsClientMsg, WsServerMsg, TARGET_SUM,
+};
+
+/// How long an owner waits for missing players before starting with whoever made it.
+const JOIN_WAIT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage: loadtest [--url URL] [--players N] [--rooms N] [--duration SECS] \
+[--score-rate PER_SEC] [--chat-rate PER_SEC] [--ping-interval-ms MS] [--max-error-rate FRACTION]";
+
+#[derive(Debug, Clone)]
+struct Args {
+    url: String,
+    players: usize,
+    rooms: usize,
+    duration: Duration,
+    /// Per player.
+    score_rate: f64,
+    /// Per player.
+    chat_rate: f64,
+    ping_interval: Duration,
+    max_error_rate: f64,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut args = Args {
+            url: "ws://127.0.0.1:3123/ws".to_owned(),
+            players: 60,
+            rooms: 10,
+            duration: Duration::from_secs(60),
+            score_rate: 1.0,
+            chat_rate: 0.1,
+            ping_interval: Duration::from_millis(1000),
+            max_error_rate: 0.01,
+        };
+        let mut it = std::env::args().skip(1);
+        while let Some(flag) = it.next() {
+            let mut value = || it.next().with_context(|| format!("{flag} needs a value"));
+            match flag.as_str() {
+                "--url" => args.url = value()?,
+                "--players" => args.players = value()?.parse()?,
+                "--rooms" => args.rooms = value()?.parse()?,
+                "--duration" => args.duration = Duration::from_secs(value()?.parse()?),
+                "--score-rate" => args.score_rate = value()?.parse()?,
+                "--chat-rate" => args.chat_rate = value()?.parse()?,
+                "--ping-interval-ms" => {
+                    args.ping_interval = Duration::from_millis(value()?.parse()?)
+                }
+                "--max-error-rate" => args.max_error_rate = value()?.parse()?,
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                other => bail!("unknown flag {other}\n{USAGE}"),
+            }
+        }
+        if args.rooms == 0 || args.players < args.rooms {
+            bail!("need at least one room and one player per room");
+        }
+        Ok(args)
+    }
+}
+
+/// Shared by every simulated player.
+#[derive(Debug, Default)]
+struct Stats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    games_started: AtomicU64,
+    // error replies by message, plus our own failures (connects, drops)
+    errors: Mutex<BTreeMap<String, u64>>,
+    rtts: Mutex<Vec<Duration>>,
+}
+
+impl Stats {
+    fn error(&self, what: impl Into<String>) {
+        *self.errors.lock().unwrap().entry(what.into()).or_default() += 1;
+    }
+
+    fn error_count(&self) -> u64 {
+        self.errors.lock().unwrap().values().sum()
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::parse() {
+        Ok(args) => Arc::new(args),
+        Err(e) => {
+            eprintln!("{e:#}");
+            return ExitCode::from(2);
+        }
+    };
+    let stats = Arc::new(Stats::default());
+    let deadline = Instant::now() + args.duration;
+    println!(
+        "{} players in {} rooms against {} for {}s",
+        args.players,
+        args.rooms,
+        args.url,
+        args.duration.as_secs()
+    );
+
+    // player i sits in room i % rooms; the first one there creates it and shares its id
+    let rooms: Vec<_> = (0..args.rooms).map(|_| watch::channel(None)).collect();
+    let mut tasks = Vec::new();
+    for i in 0..args.players {
+        let room = i % args.rooms;
+        let seats = (args.players - room).div_ceil(args.rooms);
+        let role = if i < args.rooms {
+            Role::Owner {
+                room_id: rooms[room].0.clone(),
+                seats,
+            }
+        } else {
+            Role::Guest {
+                room_id: rooms[room].1.clone(),
+            }
+        };
+        let (args, stats) = (args.clone(), stats.clone());
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = play(i, role, &args, &stats, deadline).await {
+                stats.error(format!("player: {e:#}"));
+            }
+        }));
+        // don't open hundreds of sockets in the same instant
+        if i % 20 == 19 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    report(&args, &stats)
+}
+
+enum Role {
+    Owner {
+        room_id: watch::Sender<Option<RoomId>>,
+        seats: usize,
+    },
+    Guest {
+        room_id: watch::Receiver<Option<RoomId>>,
+    },
+}
+
+/// One player's connection, from joining until the deadline.
+async fn play(
+    i: usize,
+    role: Role,
+    args: &Args,
+    stats: &Stats,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    let player = Player {
+        player_id: format!("load-{i}"),
+        name: format!("Load {i}"),
+        ready: false,
+        muted: false,
+    };
+    let join = match &role {
+        Role::Owner { .. } => WsClientMsg::CreateRoom {
+            player: player.clone(),
+            settings: None,
+        },
+        Role::Guest { room_id } => {
+            let mut room_id = room_id.clone();
+            let room_id = tokio::select! {
+                found = room_id.wait_for(Option::is_some) => found?.clone().unwrap(),
+                _ = tokio::time::sleep_until(deadline) => return Ok(()),
+            };
+            WsClientMsg::JoinRoom {
+                room_id,
+                player: player.clone(),
+                seat_token: None,
+            }
+        }
+    };
+
+    let (ws, _) = tokio_tungstenite::connect_async(args.url.as_str())
+        .await
+        .context("connect")?;
+    let (mut sink, mut stream) = ws.split();
+    let frame = |msg: &WsClientMsg| {
+        stats.sent.fetch_add(1, Ordering::Relaxed);
+        Message::text(serde_json::to_string(msg).unwrap())
+    };
+    sink.send(frame(&join)).await?;
+
+    let mut score = every(args.score_rate);
+    let mut chat = every(args.chat_rate);
+    let mut ping = tokio::time::interval(args.ping_interval);
+    let mut pings: HashMap<u64, Instant> = HashMap::new();
+    let mut nonce = 0u64;
+    let created = Instant::now();
+    let (mut in_room, mut in_game, mut turn) = (false, false, 0u32);
+    // a StartGame is out and neither GameStarted nor an error has come back
+    let mut starting = false;
+    let mut last_players = Vec::new();
+
+    loop {
+        let out = tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            frame = stream.next() => {
+                let text = match frame {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Pong(payload))) => {
+                        let sent_at = <[u8; 8]>::try_from(&payload[..])
+                            .ok()
+                            .and_then(|n| pings.remove(&u64::from_be_bytes(n)));
+                        if let Some(sent_at) = sent_at {
+                            stats.rtts.lock().unwrap().push(sent_at.elapsed());
+                        }
+                        continue;
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        stats.error(format!("closed by server: {frame:?}"));
+                        return Ok(());
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => bail!("socket error: {e}"),
+                    None => bail!("socket closed"),
+                };
+                stats.received.fetch_add(1, Ordering::Relaxed);
+                // room broadcasts come wrapped with their `seq`, direct replies don't
+                let msg = match serde_json::from_str::<RoomEvent>(&text) {
+                    Ok(event) => event.msg,
+                    Err(_) => serde_json::from_str::<WsServerMsg>(&text)
+                        .with_context(|| format!("unparseable server message {text}"))?,
+                };
+                match msg {
+                    WsServerMsg::RoomCreated { room_id: created_id } => {
+                        if let Role::Owner { room_id, .. } = &role {
+                            room_id.send_replace(Some(created_id));
+                        }
+                        in_room = true;
+                        None
+                    }
+                    WsServerMsg::RoomPlayersUpdate { players, .. } => {
+                        let first = !in_room;
+                        in_room = true;
+                        last_players = players;
+                        match role {
+                            // ready up on joining, and again after each game
+                            Role::Guest { .. } if first => {
+                                Some(WsClientMsg::ReadyUp { ready: true })
+                            }
+                            Role::Owner { seats, .. } if !in_game && !starting => {
+                                may_start(&last_players, &player, seats, created)
+                            }
+                            _ => None,
+                        }
+                    }
+                    WsServerMsg::GameStarted { .. } => {
+                        if matches!(role, Role::Owner { .. }) {
+                            stats.games_started.fetch_add(1, Ordering::Relaxed);
+                        }
+                        (in_game, starting, turn) = (true, false, 0);
+                        None
+                    }
+                    WsServerMsg::GameOver { .. } => {
+                        in_game = false;
+                        match role {
+                            Role::Guest { .. } => Some(WsClientMsg::ReadyUp { ready: true }),
+                            Role::Owner { .. } => None,
+                        }
+                    }
+                    WsServerMsg::Error { msg, .. } => {
+                        stats.error(msg);
+                        starting = false;
+                        None
+                    }
+                    // someone joined during the start countdown; ask again once they're ready
+                    WsServerMsg::SystemMessage { kind: SystemMessageKind::StartCalledOff, .. } => {
+                        starting = false;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            _ = score.tick(), if in_game => {
+                turn += 1;
+                let first = rand::rng().random_range(1..TARGET_SUM as u8);
+                Some(WsClientMsg::ScoreUpdate {
+                    cleared_count: 2,
+                    turn,
+                    cleared_values: vec![first, TARGET_SUM as u8 - first],
+                    rect: None,
+                })
+            }
+            _ = chat.tick(), if in_room => Some(WsClientMsg::ChatMessage {
+                message: format!("gg from {} #{}", player.name, rand::rng().random::<u16>()),
+                name: None,
+                channel: None,
+            }),
+            _ = ping.tick() => {
+                nonce += 1;
+                pings.insert(nonce, Instant::now());
+                sink.send(Message::Ping(nonce.to_be_bytes().to_vec().into())).await?;
+                // an owner short of players starts anyway once JOIN_WAIT is up
+                match role {
+                    Role::Owner { seats, .. } if in_room && !in_game && !starting => {
+                        may_start(&last_players, &player, seats, created)
+                    }
+                    _ => None,
+                }
+            }
+        };
+        if let Some(msg) = out {
+            // RoomPlayersUpdates keep coming until the game is on; ask only once
+            starting |= matches!(msg, WsClientMsg::StartGame { .. });
+            sink.send(frame(&msg)).await?;
+        }
+    }
+    let _ = sink.send(frame(&WsClientMsg::LeaveRoom {})).await;
+    let _ = sink.close().await;
+    Ok(())
+}
+
+/// `StartGame` once every seat is taken (or `JOIN_WAIT` is up) and everyone else is ready.
+fn may_start(
+    players: &[Player],
+    me: &Player,
+    seats: usize,
+    created: Instant,
+) -> Option<WsClientMsg> {
+    let full = players.len() >= seats || created.elapsed() >= JOIN_WAIT;
+    let ready = players
+        .iter()
+        .all(|p| p.ready || p.player_id == me.player_id);
+    (full && ready && !players.is_empty()).then_some(WsClientMsg::StartGame { restart: None })
+}
+
+/// A ticker at `rate` per second, staggered so players don't all fire together.
+/// A rate of 0 never ticks.
+fn every(rate: f64) -> tokio::time::Interval {
+    let period = if rate > 0.0 {
+        Duration::from_secs_f64(1.0 / rate)
+    } else {
+        Duration::from_secs(u32::MAX as u64)
+    };
+    let offset = period.mul_f64(rand::rng().random::<f64>());
+    let mut ticker = tokio::time::interval_at(Instant::now() + offset, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+fn report(args: &Args, stats: &Stats) -> ExitCode {
+    let sent = stats.sent.load(Ordering::Relaxed);
+    let errors = stats.error_count();
+    let error_rate = errors as f64 / sent.max(1) as f64;
+
+    println!();
+    println!(
+        "messages: {sent} sent, {} received; {} games started",
+        stats.received.load(Ordering::Relaxed),
+        stats.games_started.load(Ordering::Relaxed)
+    );
+    let mut rtts = stats.rtts.lock().unwrap().clone();
+    rtts.sort();
+    if rtts.is_empty() {
+        println!("ping rtt: no pongs");
+    } else {
+        let at = |q: f64| rtts[((rtts.len() - 1) as f64 * q).round() as usize];
+        println!(
+            "ping rtt: n={} p50={:?} p95={:?} p99={:?} max={:?}",
+            rtts.len(),
+            at(0.5),
+            at(0.95),
+            at(0.99),
+            rtts[rtts.len() - 1]
+        );
+    }
+    println!("errors: {errors} ({:.2}% of messages sent)", error_rate * 100.0);
+    for (what, count) in stats.errors.lock().unwrap().iter() {
+        println!("  {count:>6}  {what}");
+    }
+
+    if error_rate > args.max_error_rate {
+        println!(
+            "FAIL: error rate above {:.2}%",
+            args.max_error_rate * 100.0
+        );
+        return ExitCode::FAILURE;
+    }
+    println!("ok");
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,154 @@
+// src/board.rs
+use crate::ws_messages::{BoardData, Rect, RoomSettings, COLS, ROWS};
+use rand::prelude::*;
+
+/// Apples on a board.
+const LEN: usize = COLS * ROWS;
+
+/// One player's copy of the board as they play it: `None` where an apple has been cleared.
+pub type PlayerBoard = Vec<Option<u8>>;
+
+pub fn player_board(board: &BoardData) -> PlayerBoard {
+    board.iter().map(|&v| Some(v)).collect()
+}
+
+/// Whether `rect` is non-empty and lies entirely on the board.
+pub fn in_bounds(rect: &Rect) -> bool {
+    rect.w > 0 && rect.h > 0 && rect.x + rect.w <= COLS && rect.y + rect.h <= ROWS
+}
+
+/// The apples still inside `rect`, row by row. Callers check `in_bounds` first.
+pub fn rect_values(board: &PlayerBoard, rect: &Rect) -> Vec<u8> {
+    (rect.y..rect.y + rect.h)
+        .flat_map(|y| (rect.x..rect.x + rect.w).map(move |x| y * COLS + x))
+        .filter_map(|i| board[i])
+        .collect()
+}
+
+/// Removes every apple inside `rect`.
+pub fn clear_rect(board: &mut PlayerBoard, rect: &Rect) {
+    for y in rect.y..rect.y + rect.h {
+        for x in rect.x..rect.x + rect.w {
+            board[y * COLS + x] = None;
+        }
+    }
+}
+
+/// Some rectangle whose remaining apples add up to exactly `target`, preferring small ones,
+/// or `None` when the board has no moves left.
+pub fn find_clear(board: &PlayerBoard, target: u32) -> Option<Rect> {
+    // prefix[y][x] = sum of the cells above and to the left of (x, y)
+    let mut prefix = [[0u32; COLS + 1]; ROWS + 1];
+    for y in 0..ROWS {
+        for x in 0..COLS {
+            let v = board[y * COLS + x].unwrap_or(0) as u32;
+            prefix[y + 1][x + 1] = v + prefix[y][x + 1] + prefix[y + 1][x] - prefix[y][x];
+        }
+    }
+    let sum = |x: usize, y: usize, w: usize, h: usize| {
+        prefix[y + h][x + w] + prefix[y][x] - prefix[y][x + w] - prefix[y + h][x]
+    };
+
+    let mut best: Option<Rect> = None;
+    for h in 1..=ROWS {
+        for w in 1..=COLS {
+            if best.as_ref().is_some_and(|b| b.w * b.h <= w * h) {
+                continue;
+            }
+            for y in 0..=ROWS - h {
+                for x in 0..=COLS - w {
+                    if sum(x, y, w, h) == target {
+                        best = Some(Rect { x, y, w, h });
+                        break;
+                    }
+                }
+                if best.as_ref().is_some_and(|b| b.w == w && b.h == h) {
+                    break;
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Builds a board from a random combo, drawing every choice from `rng`, so the same RNG state
+/// always deals the same board. The combos describe 1..=9 boards; rooms with other value ranges
+/// get `random_board` instead.
+pub fn generate_board<R: Rng + ?Sized>(
+    combos: &[[u8; 8]],
+    settings: &RoomSettings,
+    rng: &mut R,
+) -> Vec<u8> {
+    if !settings.uses_classic_values() {
+        return random_board(rng, settings);
+    }
+    let counts = combos.choose(rng).expect("no combos loaded");
+
+    let mut flat = Vec::with_capacity(LEN);
+    for (i, &cnt) in counts.iter().enumerate() {
+        flat.extend(std::iter::repeat_n((i as u8) + 1, cnt as usize));
+    }
+    if flat.len() < LEN {
+        flat.extend(std::iter::repeat_n(9u8, LEN - flat.len()));
+    }
+
+    flat.shuffle(rng);
+    assert_eq!(flat.len(), LEN);
+    flat
+}
+
+/// The board for `seed`: what `/api/admin/seeds` entries reproduce.
+pub fn board_from_seed(combos: &[[u8; 8]], seed: u64, settings: &RoomSettings) -> Vec<u8> {
+    generate_board(combos, settings, &mut StdRng::seed_from_u64(seed))
+}
+
+/// Uniform values in the room's range, with one adjacent pair that clears and the total nudged
+/// to a multiple of the target sum, so the board is never dead on arrival.
+pub fn random_board<R: Rng + ?Sized>(rng: &mut R, settings: &RoomSettings) -> Vec<u8> {
+    let mut flat = uniform_board(rng, settings);
+    make_solvable(&mut flat, rng, settings);
+    flat
+}
+
+/// Uniform values in the room's range and nothing more: may well have no move at all.
+pub fn uniform_board<R: Rng + ?Sized>(rng: &mut R, settings: &RoomSettings) -> Vec<u8> {
+    let (min, max) = (settings.min_value, settings.max_value);
+    (0..LEN).map(|_| rng.random_range(min..=max)).collect()
+}
+
+/// `random_board`'s guarantee: plants a pair that clears and evens out the total.
+fn make_solvable<R: Rng + ?Sized>(flat: &mut [u8], rng: &mut R, settings: &RoomSettings) {
+    let (min, max) = (settings.min_value, settings.max_value);
+    let target = settings.target_sum;
+
+    // plant a horizontal pair that adds up to the target (validate() guarantees one exists)
+    let (lo, hi) = (min as u32, max as u32);
+    let a = rng.random_range(lo.max(target - hi)..=hi.min(target - lo));
+    let row = rng.random_range(0..LEN / COLS);
+    let col = rng.random_range(0..COLS - 1);
+    let i = row * COLS + col;
+    flat[i] = a as u8;
+    flat[i + 1] = (target - a) as u8;
+
+    // shave (or, failing that, pad) other cells until the total divides evenly
+    let mut others: Vec<usize> = (0..LEN).filter(|&j| j != i && j != i + 1).collect();
+    others.shuffle(rng);
+    let mut excess = flat.iter().map(|&v| v as u32).sum::<u32>() % target;
+    for &j in &others {
+        if excess == 0 {
+            break;
+        }
+        let d = ((flat[j] - min) as u32).min(excess);
+        flat[j] -= d as u8;
+        excess -= d;
+    }
+    let mut missing = if excess == 0 { 0 } else { target - excess };
+    for &j in &others {
+        if missing == 0 {
+            break;
+        }
+        let d = ((max - flat[j]) as u32).min(missing);
+        flat[j] += d as u8;
+        missing -= d;
+    }
+}
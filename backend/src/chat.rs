@@ -0,0 +1,155 @@
+// src/chat.rs
+
+/// Most newlines allowed in a row; longer runs are cut down to this.
+const MAX_NEWLINES_IN_A_ROW: usize = 2;
+
+/// Cleans up a chat message before it's broadcast: drops control characters (keeping
+/// newlines, and turning tabs into spaces) and invisible formatting characters, cuts runs of
+/// newlines down to two and trims the ends. Rejects a message with nothing left, or one
+/// longer than `max_len` characters once cleaned (0 allows any length).
+///
+/// Everything that makes text render as intended survives: combining marks, the zero-width
+/// (non-)joiners in emoji sequences and scripts that need them, and the tag characters of
+/// subdivision flags.
+pub fn sanitize(message: &str, max_len: usize) -> Result<String, String> {
+    let mut clean = String::with_capacity(message.len());
+    let mut newlines = 0;
+    for c in message.chars() {
+        let c = match c {
+            '\t' => ' ',
+            c if c != '\n' && (c.is_control() || is_invisible_format(c)) => continue,
+            c => c,
+        };
+        if c == '\n' {
+            newlines += 1;
+            if newlines > MAX_NEWLINES_IN_A_ROW {
+                continue;
+            }
+        } else {
+            newlines = 0;
+        }
+        clean.push(c);
+    }
+
+    let clean = clean.trim();
+    if clean.is_empty() {
+        return Err("Message can't be empty".to_string());
+    }
+    if max_len > 0 && clean.chars().count() > max_len {
+        return Err(format!("Message is longer than {max_len} characters"));
+    }
+    Ok(clean.to_string())
+}
+
+/// Format characters (Unicode category Cf) that only hide or reorder text: zero-width spaces,
+/// bidi overrides, byte order marks and the like. ZWJ, ZWNJ and emoji tag characters aren't
+/// among them.
+fn is_invisible_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{0600}'..='\u{0605}'
+            | '\u{061C}'
+            | '\u{06DD}'
+            | '\u{070F}'
+            | '\u{08E2}'
+            | '\u{180E}'
+            | '\u{200B}'
+            | '\u{200E}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{FEFF}'
+            | '\u{FFF9}'..='\u{FFFB}'
+            | '\u{110BD}'
+            | '\u{110CD}'
+            | '\u{1BCA0}'..='\u{1BCA3}'
+            | '\u{1D173}'..='\u{1D17A}'
+            | '\u{E0001}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(message: &str) -> String {
+        sanitize(message, 500).unwrap_or_else(|e| panic!("{message:?} refused: {e}"))
+    }
+
+    #[test]
+    fn plain_text_is_only_trimmed() {
+        let message = "gg, that last column was brutal";
+        assert_eq!(clean(&format!("  {message} \n")), message);
+    }
+
+    #[test]
+    fn a_message_with_nothing_visible_left_is_refused() {
+        for message in ["", "   ", "\n\n\t", "\u{200B}\u{FEFF}", "\u{7}\u{1B}"] {
+            assert!(sanitize(message, 500).is_err(), "{message:?} got through");
+        }
+    }
+
+    #[test]
+    fn control_characters_go_and_tabs_become_spaces() {
+        assert_eq!(clean("a\u{0}b\u{7}c\u{1B}[31md\u{7F}e"), "abc[31mde");
+        assert_eq!(clean("name:\tscore"), "name: score");
+        assert_eq!(clean("one\r\ntwo"), "one\ntwo");
+    }
+
+    #[test]
+    fn newlines_are_cut_down_to_two_in_a_row() {
+        assert_eq!(clean("a\nb"), "a\nb");
+        assert_eq!(clean("a\n\nb"), "a\n\nb");
+        assert_eq!(clean("a\n\n\n\n\nb"), "a\n\nb");
+        // dropped characters in between don't break the run up
+        assert_eq!(clean("a\r\n\r\n\u{200B}\r\nb"), "a\n\nb");
+    }
+
+    #[test]
+    fn invisible_formatting_goes() {
+        assert_eq!(clean("gg\u{200B}wp"), "ggwp");
+        assert_eq!(clean("\u{FEFF}hello"), "hello");
+        // a right-to-left override that would show "evil.exe" as "exe.live"
+        assert_eq!(clean("\u{202E}evil.exe\u{202C}"), "evil.exe");
+        assert_eq!(clean("soft\u{00AD}hyphen"), "softhyphen");
+    }
+
+    #[test]
+    fn combining_marks_survive() {
+        assert_eq!(clean("caf\u{0065}\u{0301}"), "caf\u{0065}\u{0301}");
+        let stacked = "z\u{0335}\u{0321}a\u{0334}\u{0352}";
+        assert_eq!(clean(stacked), stacked);
+    }
+
+    #[test]
+    fn joiners_survive() {
+        // a family emoji is four emoji held together by zero-width joiners
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+        assert_eq!(clean(family), family);
+        let rainbow_flag = "\u{1F3F3}\u{FE0F}\u{200D}\u{1F308}";
+        assert_eq!(clean(rainbow_flag), rainbow_flag);
+        // Persian needs the non-joiner to spell some words right
+        let persian = "\u{0645}\u{06CC}\u{200C}\u{062E}\u{0648}\u{0627}\u{0647}\u{0645}";
+        assert_eq!(clean(persian), persian);
+    }
+
+    #[test]
+    fn subdivision_flags_keep_their_tags() {
+        let scotland = "\u{1F3F4}\u{E0067}\u{E0062}\u{E0073}\u{E0063}\u{E0074}\u{E007F}";
+        assert_eq!(clean(scotland), scotland);
+        // the deprecated language tag isn't part of any flag
+        assert_eq!(clean("\u{E0001}hi"), "hi");
+    }
+
+    #[test]
+    fn the_length_limit_counts_characters_once_cleaned() {
+        let at_limit = "é".repeat(500);
+        assert_eq!(clean(&at_limit), at_limit);
+        assert!(sanitize(&"é".repeat(501), 500).is_err());
+        // what gets dropped doesn't count against it
+        let padded = format!("{at_limit}{}", "\u{200B}".repeat(100));
+        assert_eq!(clean(&padded), at_limit);
+        assert_eq!(sanitize(&"x".repeat(10_000), 0).map(|m| m.len()), Ok(10_000));
+    }
+}
@@ -0,0 +1,672 @@
+// src/config.rs
+use crate::{
+    limits::{BucketConfig, ChatLimitConfig},
+    moderation::DEFAULT_LEET_MAP,
+    net::parse_ip_nets,
+    webhooks::WebhookTarget,
+};
+use anyhow::{bail, Result};
+use ipnet::IpNet;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// Server-wide settings, read once at startup.
+/// Every setting can be given as `--some-setting value` on the command line or as
+/// `SOME_SETTING=value` in the environment; the command line wins.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Shown to clients on connect, e.g. in the landing page header.
+    pub server_name: String,
+    /// Message of the day sent on connect; can be changed at runtime through the admin API.
+    pub motd: Option<String>,
+    /// Addresses to listen on (`--bind`, repeatable: `[::]:3123`, or a bare IP for the default
+    /// port). Defaults to localhost, or every interface when `PORT`/`RENDER` say we're hosted.
+    pub binds: Vec<SocketAddr>,
+    /// Further listeners (`--bind-admin`) that also serve the admin API; when any are given the
+    /// `--bind` listeners don't.
+    pub admin_binds: Vec<SocketAddr>,
+    /// How often the lobby presence summary is pushed to sockets that are not in a room.
+    pub presence_interval: Duration,
+    /// Bearer token for the `/api/admin` routes; the admin API is disabled when unset.
+    pub admin_token: Option<String>,
+    /// Shared secret every WebSocket upgrade must present (private instances); open when unset.
+    pub require_ws_token: Option<String>,
+    /// How many recent `(room_id, seed)` pairs to keep for the admin API (0 disables the log).
+    pub seed_log_capacity: usize,
+    /// Reverse proxies whose `X-Forwarded-For` / `Forwarded` headers we believe.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Serve HTTPS/WSS directly when both a certificate and key are configured.
+    pub tls: Option<TlsPaths>,
+    /// Flip players back to unready after they've been ready this long without a game starting.
+    pub ready_timeout: Option<Duration>,
+    /// Extra origins (e.g. `https://fruitbox.example`) allowed to open WebSockets, on top of
+    /// same-host and localhost. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// Whether upgrades without an `Origin` header (native clients, load testers) are accepted.
+    pub allow_missing_origin: bool,
+    /// How many chat/system lines each room keeps (joiners get the newest
+    /// `HISTORY_REPLAY_LEN`, `GetChatHistory` pages through the rest); rooms may ask for
+    /// fewer (0 disables).
+    pub chat_history_len: usize,
+    /// Most bytes of text a room's log holds; the oldest lines go first (0 doesn't cap it).
+    pub chat_history_max_bytes: usize,
+    /// Drop room log lines older than this.
+    pub chat_history_max_age: Option<Duration>,
+    /// Open WebSockets allowed per client IP before upgrades get HTTP 429 (0 = unlimited).
+    pub max_connections_per_ip: usize,
+    /// Rooms that may exist at once; `CreateRoom` is refused beyond this (0 = unlimited).
+    pub max_rooms: usize,
+    /// Games that may run at once; `StartGame` is refused beyond this (0 = unlimited).
+    pub max_running_games: usize,
+    /// Rooms one client IP may create per minute (0 = unlimited).
+    pub max_rooms_per_ip_per_minute: usize,
+    /// Largest client message we parse; bigger text gets an error and the socket is closed.
+    /// Frames over twice this are refused by the WebSocket layer before they are buffered.
+    pub max_message_bytes: usize,
+    /// Broadcasts a room buffers per socket; a socket further behind than this misses some.
+    pub room_channel_capacity: usize,
+    /// Times a socket may fall behind in one game before it's sent a `StateSync` (0 never does).
+    pub lag_resync_after: u32,
+    /// Times it may fall behind in one game before it's closed as too slow (0 never closes it).
+    pub lag_disconnect_after: u32,
+    /// Frames queued for each socket's writer; room broadcasts wait in the room channel while
+    /// its queue is nearly full.
+    pub send_queue_capacity: usize,
+    /// A socket whose queue stays nearly full for this long is closed as too slow.
+    pub send_stall_timeout: Duration,
+    /// How often a ping is sent to each socket.
+    pub heartbeat_interval: Duration,
+    /// A socket that has sent nothing (not even a pong) for this long is treated as dropped.
+    pub heartbeat_timeout: Duration,
+    /// How long a dropped player's seat is held for them to reconnect (0 removes them at once).
+    pub reconnect_grace: Duration,
+    /// Offer a held seat (`ReclaimOffer`) to a `JoinRoom` under the same name but a different
+    /// player ID, for clients that lost theirs. Anyone who knows the name can take the offer,
+    /// so this trusts names the way the normal reconnect trusts player IDs, only more weakly.
+    pub reclaim_by_name: bool,
+    /// Close sockets that have sent no message and joined no room for this long (0 disables).
+    pub idle_timeout: Duration,
+    /// The same for sockets inside a room, where the heartbeat already catches dead peers.
+    pub room_idle_timeout: Duration,
+    /// Minimum time between two hints for the same player.
+    pub hint_cooldown: Duration,
+    /// Longest chat message, in characters, after surrounding whitespace and invisible
+    /// characters are dropped (0 allows any length up to `max_message_bytes`).
+    pub chat_max_len: usize,
+    /// Chat messages a room member may send in a burst (0 doesn't limit chat); one more is
+    /// allowed every `chat_refill`.
+    pub chat_burst: u32,
+    pub chat_refill: Duration,
+    /// Times within a minute a member may hit the chat limit before they're muted for
+    /// `chat_mute` (0 never mutes).
+    pub chat_mute_after: u32,
+    pub chat_mute: Duration,
+    /// How long `GameStarting` comes before `GameStarted`, for manual and automatic starts
+    /// alike (0 starts at once).
+    pub start_countdown: Duration,
+    /// Append-only JSONL trail of admin and moderation actions.
+    pub audit_file: PathBuf,
+    /// Rotate the audit file to `<file>.1` once it grows past this many bytes (0 never rotates).
+    pub audit_max_bytes: u64,
+    /// Where the IP ban list managed through `/api/admin/bans` is persisted.
+    pub bans_file: PathBuf,
+    /// Let sockets that aren't in a room chat with each other (`LobbyChatBroadcast`), under
+    /// the same limits as room chat.
+    pub lobby_chat: bool,
+    /// Let each player ID be in use by one socket at a time: when a second one enters a room
+    /// under it, the older socket gets `SessionReplaced` and is closed.
+    pub single_session: bool,
+    /// Mask the words in `deny_list_file` out of chat messages and player names.
+    pub profanity_filter: bool,
+    /// One word or phrase per line; reloaded by `POST /api/admin/deny-list/reload`.
+    pub deny_list_file: PathBuf,
+    /// Characters the filter reads as others (`0=o,$=s,...`), so trivial leetspeak still
+    /// matches. Replaces `DEFAULT_LEET_MAP` when given.
+    pub leet_map: HashMap<char, char>,
+    /// Scores below this never enter the all-time top 10 (1 keeps out players who never scored).
+    pub min_top10_score: u32,
+    /// Where the all-time top 10 is persisted; a name ending in `.gz` is stored gzip-compressed.
+    pub scores_file: PathBuf,
+    /// POSTed a JSON summary when games finish (`url` or `url#game_ended+new_record`, see
+    /// `WebhookTarget`).
+    pub webhooks: Vec<WebhookTarget>,
+    /// Fan room broadcasts out through this Redis server (needs the `redis-bus` feature).
+    pub redis_url: Option<String>,
+    /// Mount everything (frontend, WebSocket, API) under this prefix, e.g. `/fruitbox`; empty
+    /// serves from the root.
+    pub base_path: String,
+    /// Where the WebSocket endpoint lives, relative to `base_path`.
+    pub ws_path: String,
+    /// Serve the frontend from this directory instead of the built-in/default dist.
+    pub assets_dir: Option<PathBuf>,
+    /// Run API-only: no frontend, just a status page at `/`.
+    pub no_assets: bool,
+}
+
+/// How log lines are written. Read before anything else so the rest of startup can log.
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// `--log-format full|pretty|compact|json`.
+    pub format: LogFormat,
+    /// `--log-file <path>`: append to this file instead of stdout.
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// tracing-subscriber's default single-line format.
+    #[default]
+    Full,
+    Pretty,
+    Compact,
+    /// One JSON object per line, span fields flattened to top-level keys.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            other => bail!("unknown log format {other:?} (expected full, pretty, compact or json)"),
+        }
+    }
+}
+
+impl LogOptions {
+    /// Unlike `Config::load`, bad values are errors: there is no logger yet to warn through.
+    pub fn load() -> Result<Self> {
+        let src = Sources::from_process();
+        Ok(LogOptions {
+            format: match src.get("log-format") {
+                Some(format) => format.parse()?,
+                None => LogFormat::default(),
+            },
+            file: src.get("log-file").filter(|f| !f.is_empty()).map(PathBuf::from),
+        })
+    }
+}
+
+/// PEM files for native TLS (`--tls-cert` / `--tls-key`).
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            binds: vec![SocketAddr::from(([127, 0, 0, 1], 3123))],
+            admin_binds: Vec::new(),
+            server_name: "Fruitbox".to_owned(),
+            motd: None,
+            presence_interval: Duration::from_secs(5),
+            admin_token: None,
+            require_ws_token: None,
+            seed_log_capacity: 100,
+            trusted_proxies: Vec::new(),
+            tls: None,
+            ready_timeout: None,
+            allowed_origins: Vec::new(),
+            allow_missing_origin: true,
+            chat_history_len: 300,
+            chat_history_max_bytes: 64 * 1024,
+            chat_history_max_age: None,
+            max_connections_per_ip: 20,
+            max_rooms: 1000,
+            max_running_games: 0,
+            max_rooms_per_ip_per_minute: 10,
+            max_message_bytes: 16 * 1024,
+            room_channel_capacity: 32,
+            lag_resync_after: 1,
+            lag_disconnect_after: 5,
+            send_queue_capacity: 64,
+            send_stall_timeout: Duration::from_secs(10),
+            heartbeat_interval: Duration::from_secs(15),
+            heartbeat_timeout: Duration::from_secs(45),
+            reconnect_grace: Duration::from_secs(30),
+            reclaim_by_name: true,
+            idle_timeout: Duration::from_secs(15 * 60),
+            room_idle_timeout: Duration::from_secs(2 * 60 * 60),
+            hint_cooldown: Duration::from_secs(10),
+            chat_max_len: 500,
+            chat_burst: 3,
+            chat_refill: Duration::from_secs(2),
+            chat_mute_after: 3,
+            chat_mute: Duration::from_secs(60),
+            start_countdown: Duration::from_secs(3),
+            audit_file: PathBuf::from("audit.jsonl"),
+            audit_max_bytes: 10 * 1024 * 1024,
+            bans_file: PathBuf::from("bans.json"),
+            lobby_chat: false,
+            single_session: false,
+            profanity_filter: false,
+            deny_list_file: PathBuf::from("deny_list.txt"),
+            leet_map: parse_leet_map(DEFAULT_LEET_MAP).0,
+            min_top10_score: 1,
+            scores_file: PathBuf::from("top10.json"),
+            webhooks: Vec::new(),
+            redis_url: None,
+            base_path: String::new(),
+            ws_path: "/ws".to_owned(),
+            assets_dir: None,
+            no_assets: false,
+        }
+    }
+}
+
+impl Config {
+    /// The chat limiter settings for each room member, or `None` if chat isn't limited.
+    pub fn chat_limits(&self) -> Option<ChatLimitConfig> {
+        (self.chat_burst > 0).then_some(ChatLimitConfig {
+            bucket: BucketConfig {
+                capacity: self.chat_burst,
+                refill_every: self.chat_refill,
+            },
+            // a zero-length mute is no mute
+            mute_after: if self.chat_mute.is_zero() { 0 } else { self.chat_mute_after },
+        })
+    }
+
+    /// Build the config from the command line and environment, falling back to defaults.
+    pub fn load() -> Result<Self> {
+        let src = Sources::from_process();
+        let defaults = Config::default();
+
+        let tls = match (src.get("tls-cert"), src.get("tls-key")) {
+            (Some(cert), Some(key)) => Some(TlsPaths {
+                cert: cert.into(),
+                key: key.into(),
+            }),
+            (None, None) => None,
+            _ => bail!("--tls-cert and --tls-key must be given together"),
+        };
+
+        // hosting platforms hand us PORT (Render also sets RENDER) and expect us on every interface
+        let hosted = src.get("port").is_some() || src.get("render").is_some();
+        let port: u16 = src
+            .parse("port")
+            .unwrap_or(if src.get("render").is_some() { 10000 } else { 3123 });
+        let default_ip = if hosted {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        };
+        let mut binds = src.bind_addrs("bind", port)?;
+        if binds.is_empty() {
+            binds.push(SocketAddr::new(default_ip, port));
+        }
+
+        Ok(Config {
+            binds,
+            admin_binds: src.bind_addrs("bind-admin", port)?,
+            server_name: src
+                .get("server-name")
+                .map(|name| name.trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .unwrap_or(defaults.server_name),
+            motd: src.get("motd").filter(|m| !m.trim().is_empty()),
+            presence_interval: src
+                .parse::<u64>("presence-interval-secs")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.presence_interval),
+            admin_token: src.get("admin-token").filter(|t| !t.is_empty()),
+            require_ws_token: src.get("require-ws-token").filter(|t| !t.is_empty()),
+            seed_log_capacity: src
+                .parse("seed-log-capacity")
+                .unwrap_or(defaults.seed_log_capacity),
+            trusted_proxies: src.ip_nets("trusted-proxies"),
+            tls,
+            ready_timeout: src
+                .parse::<u64>("ready-timeout-mins")
+                .filter(|&mins| mins > 0)
+                .map(|mins| Duration::from_secs(mins * 60)),
+            allowed_origins: src.list("allowed-origins"),
+            allow_missing_origin: src
+                .parse("allow-missing-origin")
+                .unwrap_or(defaults.allow_missing_origin),
+            chat_history_len: src
+                .parse("chat-history-len")
+                .unwrap_or(defaults.chat_history_len),
+            chat_history_max_bytes: src
+                .parse("chat-history-max-bytes")
+                .unwrap_or(defaults.chat_history_max_bytes),
+            chat_history_max_age: src
+                .parse::<u64>("chat-history-max-age-secs")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            max_connections_per_ip: src
+                .parse("max-connections-per-ip")
+                .unwrap_or(defaults.max_connections_per_ip),
+            max_rooms: src.parse("max-rooms").unwrap_or(defaults.max_rooms),
+            max_running_games: src
+                .parse("max-running-games")
+                .unwrap_or(defaults.max_running_games),
+            max_rooms_per_ip_per_minute: src
+                .parse("max-rooms-per-ip-per-minute")
+                .unwrap_or(defaults.max_rooms_per_ip_per_minute),
+            max_message_bytes: src
+                .parse::<usize>("max-message-bytes")
+                .filter(|&bytes| bytes > 0)
+                .unwrap_or(defaults.max_message_bytes),
+            room_channel_capacity: src
+                .parse::<usize>("room-channel-capacity")
+                .filter(|&capacity| capacity > 0)
+                .unwrap_or(defaults.room_channel_capacity),
+            lag_resync_after: src
+                .parse("lag-resync-after")
+                .unwrap_or(defaults.lag_resync_after),
+            lag_disconnect_after: src
+                .parse("lag-disconnect-after")
+                .unwrap_or(defaults.lag_disconnect_after),
+            send_queue_capacity: src
+                .parse::<usize>("send-queue-capacity")
+                .filter(|&capacity| capacity > 0)
+                .unwrap_or(defaults.send_queue_capacity),
+            send_stall_timeout: src
+                .parse::<u64>("send-stall-timeout-secs")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.send_stall_timeout),
+            heartbeat_interval: src
+                .parse::<u64>("heartbeat-interval-secs")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_interval),
+            heartbeat_timeout: src
+                .parse::<u64>("heartbeat-timeout-secs")
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_timeout),
+            reconnect_grace: src
+                .parse::<u64>("reconnect-grace-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.reconnect_grace),
+            reclaim_by_name: src
+                .parse("reclaim-by-name")
+                .unwrap_or(defaults.reclaim_by_name),
+            idle_timeout: src
+                .parse::<u64>("idle-timeout-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.idle_timeout),
+            room_idle_timeout: src
+                .parse::<u64>("room-idle-timeout-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.room_idle_timeout),
+            hint_cooldown: src
+                .parse::<u64>("hint-cooldown-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.hint_cooldown),
+            chat_max_len: src.parse("chat-max-len").unwrap_or(defaults.chat_max_len),
+            chat_burst: src.parse("chat-burst").unwrap_or(defaults.chat_burst),
+            chat_refill: src
+                .parse::<u64>("chat-refill-ms")
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.chat_refill),
+            chat_mute_after: src
+                .parse("chat-mute-after")
+                .unwrap_or(defaults.chat_mute_after),
+            chat_mute: src
+                .parse::<u64>("chat-mute-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.chat_mute),
+            start_countdown: src
+                .parse::<u64>("start-countdown-secs")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.start_countdown),
+            audit_file: src
+                .get("audit-file")
+                .filter(|p| !p.is_empty())
+                .map_or(defaults.audit_file, PathBuf::from),
+            audit_max_bytes: src
+                .parse("audit-max-bytes")
+                .unwrap_or(defaults.audit_max_bytes),
+            bans_file: src
+                .get("bans-file")
+                .filter(|p| !p.is_empty())
+                .map_or(defaults.bans_file, PathBuf::from),
+            lobby_chat: src.parse("lobby-chat").unwrap_or(defaults.lobby_chat),
+            single_session: src
+                .parse("single-session")
+                .unwrap_or(defaults.single_session),
+            profanity_filter: src
+                .parse("profanity-filter")
+                .unwrap_or(defaults.profanity_filter),
+            deny_list_file: src
+                .get("deny-list-file")
+                .filter(|p| !p.is_empty())
+                .map_or(defaults.deny_list_file, PathBuf::from),
+            leet_map: src.leet_map("leet-map").unwrap_or(defaults.leet_map),
+            min_top10_score: src
+                .parse("min-top10-score")
+                .unwrap_or(defaults.min_top10_score),
+            scores_file: src
+                .get("scores-file")
+                .filter(|p| !p.is_empty())
+                .map_or(defaults.scores_file, PathBuf::from),
+            webhooks: src.webhooks("webhooks"),
+            redis_url: src.get("redis-url").filter(|u| !u.is_empty()),
+            base_path: src.get("base-path").map_or(defaults.base_path, |p| url_path(&p)),
+            ws_path: src
+                .get("ws-path")
+                .map(|p| url_path(&p))
+                .filter(|p| !p.is_empty())
+                .unwrap_or(defaults.ws_path),
+            assets_dir: src.get("assets-dir").map(PathBuf::from),
+            no_assets: src.parse("no-assets").unwrap_or(defaults.no_assets),
+        })
+    }
+}
+
+/// Raw setting values: command-line `--key value` pairs, with the environment as fallback.
+struct Sources {
+    args: HashMap<String, Vec<String>>,
+}
+
+impl Sources {
+    fn from_process() -> Self {
+        Sources {
+            args: parse_args(std::env::args().skip(1)),
+        }
+    }
+
+    /// Last value given for `key` (`--key` on the command line, else `KEY` in the environment).
+    fn get(&self, key: &str) -> Option<String> {
+        if let Some(value) = self.args.get(key).and_then(|values| values.last()) {
+            return Some(value.clone());
+        }
+        std::env::var(key.replace('-', "_").to_uppercase()).ok()
+    }
+
+    /// Parse a setting, treating unset or malformed values as absent.
+    fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.get(key)?;
+        match value.trim().parse() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                tracing::warn!(key, value = %value, "ignoring malformed setting");
+                None
+            }
+        }
+    }
+
+    /// Every value given for `key`: each `--key` on the command line, else the comma-separated
+    /// `KEY` environment variable.
+    fn all(&self, key: &str) -> Vec<String> {
+        let values = match self.args.get(key) {
+            Some(values) => values.clone(),
+            None => self.get(key).into_iter().collect(),
+        };
+        values
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Listen addresses; a bare IP gets `default_port`. Unlike most settings a bad one is an
+    /// error, since silently not listening somewhere is worse than not starting.
+    fn bind_addrs(&self, key: &str, default_port: u16) -> Result<Vec<SocketAddr>> {
+        self.all(key)
+            .iter()
+            .map(|addr| {
+                if let Ok(addr) = addr.parse::<SocketAddr>() {
+                    return Ok(addr);
+                }
+                match addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+                    Ok(ip) => Ok(SocketAddr::new(ip, default_port)),
+                    Err(_) => bail!("--{key} {addr:?} is not an address (expected ip:port or ip)"),
+                }
+            })
+            .collect()
+    }
+
+    /// A comma-separated list, with blanks dropped.
+    fn list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .map(|list| {
+                list.split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Parse a comma-separated webhook list, warning about entries we skip.
+    fn webhooks(&self, key: &str) -> Vec<WebhookTarget> {
+        self.list(key)
+            .iter()
+            .filter_map(|entry| match entry.parse() {
+                Ok(target) => Some(target),
+                Err(e) => {
+                    tracing::warn!(key, error = %e, "ignoring invalid webhook in config");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Parse a comma-separated CIDR list, warning about entries we skip.
+    fn ip_nets(&self, key: &str) -> Vec<IpNet> {
+        let Some(list) = self.get(key) else {
+            return Vec::new();
+        };
+        let (nets, invalid) = parse_ip_nets(&list);
+        for entry in invalid {
+            tracing::warn!(key, entry = %entry, "ignoring invalid address in config");
+        }
+        nets
+    }
+
+    /// Parse a comma-separated `from=to` list, warning about entries we skip.
+    fn leet_map(&self, key: &str) -> Option<HashMap<char, char>> {
+        let (map, invalid) = parse_leet_map(&self.get(key)?);
+        for entry in invalid {
+            tracing::warn!(key, entry = %entry, "ignoring invalid substitution in config");
+        }
+        Some(map)
+    }
+}
+
+/// `from=to` pairs of single characters, plus the entries that aren't.
+fn parse_leet_map(list: &str) -> (HashMap<char, char>, Vec<String>) {
+    let mut map = HashMap::new();
+    let mut invalid = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut chars = entry.chars();
+        match (chars.next(), chars.next(), chars.next(), chars.next()) {
+            (Some(from), Some('='), Some(to), None) => {
+                map.insert(from, to);
+            }
+            _ => invalid.push(entry.to_string()),
+        }
+    }
+    (map, invalid)
+}
+
+/// `fruitbox/`, `/fruitbox` and `/fruitbox/` all become `/fruitbox`; `/` and blank become empty.
+fn url_path(path: &str) -> String {
+    let path = path.trim().trim_matches('/');
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Collects `--key value`, `--key=value` and bare `--flag` (stored as `"true"`) arguments.
+fn parse_args(args: impl Iterator<Item = String>) -> HashMap<String, Vec<String>> {
+    let mut parsed: HashMap<String, Vec<String>> = HashMap::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            tracing::warn!(arg = %arg, "ignoring unexpected argument");
+            continue;
+        };
+        let (key, value) = match name.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => match args.next_if(|next| !next.starts_with("--")) {
+                Some(value) => (name.to_string(), value),
+                None => (name.to_string(), "true".to_string()),
+            },
+        };
+        parsed.entry(key).or_default().push(value);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(args: &[&str]) -> Sources {
+        Sources {
+            args: parse_args(args.iter().map(|a| a.to_string())),
+        }
+    }
+
+    #[test]
+    fn bind_addresses_add_up_and_bare_ips_get_the_port() {
+        let src = sources(&["--bind", "[::]:3123", "--bind=127.0.0.1", "--bind", "::1"]);
+        let addrs: Vec<_> = src.bind_addrs("bind", 8080).unwrap();
+        let expected: Vec<SocketAddr> = ["[::]:3123", "127.0.0.1:8080", "[::1]:8080"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        assert_eq!(addrs, expected);
+
+        let listed = sources(&["--bind", "127.0.0.1:1, [::1]:2"]);
+        assert_eq!(listed.bind_addrs("bind", 8080).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn a_bind_address_that_is_not_one_is_an_error() {
+        let err = sources(&["--bind", "localhost:3123"]).bind_addrs("bind", 3123).unwrap_err();
+        assert!(err.to_string().contains("\"localhost:3123\""), "{err}");
+    }
+
+    #[test]
+    fn url_paths_get_one_leading_slash_and_no_trailing_one() {
+        assert_eq!(url_path("fruitbox"), "/fruitbox");
+        assert_eq!(url_path(" /fruitbox/ "), "/fruitbox");
+        assert_eq!(url_path("//games/fruitbox//"), "/games/fruitbox");
+        assert_eq!(url_path("/"), "");
+        assert_eq!(url_path(""), "");
+    }
+}
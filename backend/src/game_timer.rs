@@ -0,0 +1,802 @@
+// src/game_timer.rs
+use crate::{
+    room_bus::{RoomChange, RoomTx},
+    server_state::{now_ms, AppState, Notice},
+    webhooks::{GameResult, PlayerScore},
+    ws_messages::{RoomId, RoomSettings, WsServerMsg},
+};
+use futures_util::FutureExt;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tracing::Instrument;
+
+const TICK: Duration = Duration::from_secs(1);
+
+/// Countdowns for every running game, driven by one scheduler task.
+///
+/// The scheduler keeps a heap of `(next tick, game)` deadlines and sleeps until the earliest,
+/// so a hundred rooms mean one timer, not a hundred tasks waking every second. It sends each
+/// room's `TimerTick`s and, once a game is over (time's up, everyone is done or the server is
+/// shutting down), spawns `finish_game` for it.
+///
+/// Ticks fall on whole seconds from the game's start and `remaining_secs` is read off the
+/// clock, not counted, so a busy scheduler never stretches a game: a late wake-up skips the
+/// ticks it missed, and the game ends at its deadline, `RoomSettings::game_secs` after the
+/// start. Endless games don't tick; their deadline is only the cap that stops a forgotten one.
+#[derive(Debug)]
+pub struct GameTimers {
+    cmds: mpsc::UnboundedSender<Command>,
+    next_id: AtomicU64,
+    // Games registered and not yet over; see `running`.
+    running: Arc<AtomicUsize>,
+}
+
+impl Default for GameTimers {
+    /// No scheduler running: games start but never tick or end. `start` replaces it at startup.
+    fn default() -> Self {
+        GameTimers {
+            cmds: mpsc::unbounded_channel().0,
+            next_id: Default::default(),
+            running: Default::default(),
+        }
+    }
+}
+
+impl GameTimers {
+    /// Spawns the scheduler. `state` is what finished games are recorded into; it must already
+    /// carry the score store and webhooks the games should use.
+    pub fn start(state: AppState) -> Self {
+        let (cmds, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(state, rx));
+        GameTimers {
+            cmds,
+            next_id: Default::default(),
+            running: Default::default(),
+        }
+    }
+
+    /// Games holding a slot right now: about to be dealt, counting down or recording their final
+    /// scores. A game stops counting once its `GameOver` is recorded, it's cancelled (aborted or
+    /// restarted), or the scheduler drops it; one that never got dealt, once its slot is dropped.
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Takes a slot for a game if fewer than `max` hold one (any number when `max` is 0). The
+    /// check and the count are one step, so two games racing for the last slot can't both win.
+    pub fn reserve(&self, max: usize) -> Option<RunningGuard> {
+        self.running
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (max == 0 || n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| RunningGuard(Arc::clone(&self.running)))
+    }
+
+    /// Registers a countdown for the room that sends on `tx`, as long as `settings` say.
+    /// `span` is the room's span; the game's finalization is logged under it. The game keeps
+    /// `slot` until it's over.
+    pub fn start_game(
+        &self,
+        room_id: RoomId,
+        tx: RoomTx,
+        span: tracing::Span,
+        settings: &RoomSettings,
+        slot: RunningGuard,
+    ) -> GameTimer {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (done, done_rx) = watch::channel(false);
+        let secs = settings.game_secs();
+        let duration = Duration::from_secs(secs);
+        let started = Instant::now();
+        let (deadline, ends_at_ms) = (started + duration, now_ms() + duration.as_millis() as u64);
+        let _ = self.cmds.send(Command::Start(Countdown {
+            id,
+            room_id,
+            tx,
+            span,
+            started,
+            secs,
+            endless: settings.is_endless(),
+            deadline,
+            ends_at_ms,
+            done,
+            _running: slot,
+        }));
+        GameTimer {
+            id,
+            cmds: self.cmds.clone(),
+            done: done_rx,
+            ends_at: deadline,
+            ends_at_ms,
+        }
+    }
+}
+
+/// A room's registration with the scheduler, kept in `RoomState` while its game runs.
+#[derive(Debug)]
+pub struct GameTimer {
+    id: u64,
+    cmds: mpsc::UnboundedSender<Command>,
+    done: watch::Receiver<bool>,
+    /// The deadline the scheduler ends the game at, and the same as Unix ms.
+    pub ends_at: Instant,
+    pub ends_at_ms: u64,
+}
+
+impl GameTimer {
+    /// Counting down, or recording the final scores.
+    pub fn is_running(&self) -> bool {
+        // a closed channel means the scheduler dropped the game without finishing it
+        !*self.done.borrow() && self.done.has_changed().is_ok()
+    }
+
+    /// Stop the countdown now and record the game as ended early.
+    pub fn end_early(&self) {
+        let _ = self.cmds.send(Command::EndEarly(self.id));
+    }
+
+    /// Drop the countdown without recording anything. Finalization already under way runs on.
+    pub fn cancel(&self) {
+        let _ = self.cmds.send(Command::Cancel(self.id));
+    }
+
+    /// Resolves once the game is over and its scores are recorded (or it was cancelled).
+    pub fn finished(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut done = self.done.clone();
+        async move {
+            let _ = done.wait_for(|&done| done).await;
+        }
+    }
+}
+
+/// What rooms ask of the scheduler. Anything else that moves a game's deadline (pausing,
+/// extending) belongs here too, acting on the game's `Countdown`.
+#[derive(Debug)]
+enum Command {
+    Start(Countdown),
+    EndEarly(u64),
+    Cancel(u64),
+}
+
+#[derive(Debug)]
+struct Countdown {
+    id: u64,
+    room_id: RoomId,
+    tx: RoomTx,
+    span: tracing::Span,
+    started: Instant,
+    // How long from `started` to `deadline`.
+    secs: u64,
+    // No clock for the players: nothing is ticked, and reaching `deadline` aborts the game.
+    endless: bool,
+    // When time is up: the last `TimerTick` (0) goes out and the game ends.
+    deadline: Instant,
+    // The same as Unix ms, for `TimerTick`.
+    ends_at_ms: u64,
+    done: watch::Sender<bool>,
+    // Counts the game in `GameTimers::running` for as long as the countdown exists.
+    _running: RunningGuard,
+}
+
+/// One game's share of `GameTimers::running`, from `GameTimers::reserve`; given back when
+/// dropped.
+#[derive(Debug)]
+pub struct RunningGuard(Arc<AtomicUsize>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Countdown {
+    /// Sends the `TimerTick` for `now` and returns when the next one is due, or `None` once
+    /// the deadline is reached (that last tick says 0). An Endless game is only woken at its
+    /// deadline.
+    fn tick(&self, now: Instant) -> Option<Instant> {
+        if self.endless {
+            return (now < self.deadline).then_some(self.deadline);
+        }
+        let elapsed = now.saturating_duration_since(self.started).as_secs().min(self.secs);
+        let remaining_secs = self.secs - elapsed;
+        self.tx.send(WsServerMsg::TimerTick {
+            remaining_secs,
+            ends_at_ms: self.ends_at_ms,
+        });
+        (remaining_secs > 0).then(|| self.started + TICK * (elapsed as u32 + 1))
+    }
+}
+
+async fn run(state: AppState, mut cmds: mpsc::UnboundedReceiver<Command>) {
+    let mut games: HashMap<u64, Countdown> = HashMap::new();
+    // One entry per running game; entries of games that already ended are skipped when due.
+    let mut deadlines: BinaryHeap<Reverse<(Instant, u64)>> = BinaryHeap::new();
+    let mut shutdown_rx = state.shutdown.subscribe();
+    let mut shutting_down = false;
+
+    loop {
+        let next = deadlines.peek().map(|Reverse((at, _))| *at);
+        tokio::select! {
+            cmd = cmds.recv() => match cmd {
+                Some(Command::Start(game)) => {
+                    // a game started as the server goes down still gets its GameOver
+                    if shutting_down {
+                        finish(&state, game, false);
+                        continue;
+                    }
+                    match game.tick(Instant::now()) {
+                        Some(next) => {
+                            deadlines.push(Reverse((next, game.id)));
+                            games.insert(game.id, game);
+                        }
+                        None => time_up(&state, game),
+                    }
+                }
+                Some(Command::EndEarly(id)) => {
+                    if let Some(game) = games.remove(&id) {
+                        finish(&state, game, true);
+                    }
+                }
+                Some(Command::Cancel(id)) => {
+                    if let Some(game) = games.remove(&id) {
+                        tracing::debug!(room_id = %game.room_id, "game timer cancelled");
+                        game.done.send_replace(true);
+                    }
+                }
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let now = Instant::now();
+                while let Some(&Reverse((at, id))) = deadlines.peek() {
+                    if at > now {
+                        break;
+                    }
+                    deadlines.pop();
+                    let Some(game) = games.get(&id) else {
+                        continue;
+                    };
+                    if let Some(next) = game.tick(now) {
+                        deadlines.push(Reverse((next, id)));
+                    } else if let Some(game) = games.remove(&id) {
+                        time_up(&state, game);
+                    }
+                }
+            }
+            // server is going down → skip the rest of every countdown so the scores still get recorded
+            _ = shutdown_rx.wait_for(|&down| down), if !shutting_down => {
+                shutting_down = true;
+                for (_, game) in games.drain() {
+                    tracing::info!(
+                        room_id = %game.room_id,
+                        sec_left = game.deadline.saturating_duration_since(Instant::now()).as_secs(),
+                        "shutting down, ending game early"
+                    );
+                    finish(&state, game, false);
+                }
+                deadlines.clear();
+            }
+        }
+    }
+}
+
+/// `game` reached its deadline. A timed game is over; an Endless one was left running, so it's
+/// called off rather than recorded.
+fn time_up(state: &AppState, game: Countdown) {
+    if !game.endless {
+        finish(state, game, false);
+        return;
+    }
+    tracing::info!(room_id = %game.room_id, "endless game hit its cap, aborting");
+    let state = state.clone();
+    tokio::spawn(async move {
+        abort_game(&game.room_id, &state, "The game ran too long and was stopped").await;
+        game.done.send_replace(true);
+    });
+}
+
+/// Records a game that is over, off the scheduler task, and marks its timer done afterwards.
+fn finish(state: &AppState, game: Countdown, ended_early: bool) {
+    let state = state.clone();
+    let span = tracing::info_span!(parent: &game.span, "game_timer");
+    tokio::spawn(
+        async move {
+            let finished = finish_game(&state, &game.room_id, game.started, ended_early);
+            // a panic here would otherwise vanish with the task and leave everyone staring at
+            // a frozen timer
+            if let Err(panic) = AssertUnwindSafe(finished).catch_unwind().await {
+                let panic = panic_message(&*panic);
+                tracing::error!(room_id = %game.room_id, panic, "game timer panicked, aborting game");
+                abort_game(&game.room_id, &state, "Internal server error").await;
+            }
+            game.done.send_replace(true);
+        }
+        .instrument(span),
+    );
+}
+
+/// Ends a game: announces the final scores to the room, records them into the top 10 (for
+/// ranked rooms) and hands the result to the webhooks.
+pub async fn finish_game(state: &AppState, room_id: &RoomId, started: Instant, ended_early: bool) {
+    // points scored through other instances count too
+    state.sync_room(room_id).await;
+    // take the final scores from the room and announce them...
+    let (players, entries, ranked) = {
+        let Some(mut room_state) = state.lock_room(room_id).await else {
+            return;
+        };
+        tracing::info!(
+            event = "game_ended",
+            duration_secs = started.elapsed().as_secs(),
+            ended_early,
+            ranked = room_state.settings.ranked,
+            players = room_state.players.len(),
+            best_score = room_state.scores.values().max().copied().unwrap_or(0),
+            scores = ?room_state.scores,
+            "game ended"
+        );
+
+        let mut players: Vec<_> = room_state
+            .player_list()
+            .into_iter()
+            .map(|p| PlayerScore {
+                score: room_state.scores.get(&p.player_id).copied().unwrap_or(0),
+                name: p.name,
+            })
+            .collect();
+        players.sort_by_key(|p| Reverse(p.score));
+        let entries: Vec<_> = room_state
+            .scores
+            .iter()
+            .filter_map(|(pid, &score)| {
+                let player = room_state.players.get(pid)?;
+                Some((pid.clone(), player.name.clone(), score))
+            })
+            .collect();
+
+        let scores: Vec<_> = room_state
+            .scores
+            .iter()
+            .map(|(pid, &s)| (pid.clone(), s))
+            .collect();
+        let mut finish_times_ms: Vec<_> = room_state
+            .finished
+            .iter()
+            .map(|(pid, &ms)| (pid.clone(), ms))
+            .collect();
+        finish_times_ms.sort_by_key(|&(_, ms)| ms);
+        #[cfg(test)]
+        if room_id == tests::PANICKING_ROOM {
+            panic!("injected by the test");
+        }
+        room_state.tx.share(RoomChange::RoundEnded { round: room_state.round });
+        room_state.tx.send(WsServerMsg::GameOver {
+            room_id: room_id.clone(),
+            scores,
+            ended_early,
+            finish_times_ms,
+            disconnected: room_state.disconnected.keys().cloned().collect(),
+        });
+        let best = players.first().map(|p| (p.name.as_str(), p.score));
+        room_state.announce(room_id, Notice::GameEnded { best });
+        state.counters.game_completed();
+        (players, entries, room_state.settings.ranked)
+    };
+
+    // ...then record them into the top-10, and save it once nothing is locked any more.
+    // Casual rooms leave it alone.
+    let (previous_best, mut new_top10, snapshot) = if !ranked {
+        (None, Vec::new(), None)
+    } else {
+        let mut top_10 = state.top_10.lock().await;
+        let previous_best = top_10.iter().map(|(Reverse(s), _)| *s).max();
+        let mut new_top10 = Vec::new();
+        for (pid, player_name, score) in entries {
+            if score < state.config.min_top10_score {
+                continue;
+            }
+            if top_10.len() < 10 {
+                top_10.push((Reverse(score), player_name.clone()));
+                new_top10.push(PlayerScore { name: player_name, score });
+            } else if let Some((Reverse(min_score), _)) = top_10.peek() {
+                if score > *min_score {
+                    tracing::info!(
+                        room_id = %room_id,
+                        player_id = %pid,
+                        player_name = %player_name,
+                        score,
+                        "new top-10 entry"
+                    );
+                    top_10.pop();
+                    top_10.push((Reverse(score), player_name.clone()));
+                    new_top10.push(PlayerScore { name: player_name, score });
+                }
+            }
+        }
+
+        let snapshot = (!new_top10.is_empty()).then(|| {
+            state.publish_top_10(&top_10);
+            state.score_saves.snapshot(&top_10)
+        });
+        (previous_best, new_top10, snapshot)
+    };
+    if let Some(snapshot) = snapshot {
+        state.score_saves.save(&*state.scores, snapshot).await;
+    }
+
+    let new_record = players
+        .first()
+        .filter(|best| ranked && best.score > previous_best.unwrap_or(0))
+        .cloned();
+    new_top10.sort_by_key(|p| Reverse(p.score));
+    state.webhooks.game_finished(GameResult {
+        room_id: room_id.clone(),
+        duration_secs: started.elapsed().as_secs(),
+        ended_early,
+        players,
+        new_top10,
+        new_record,
+    });
+}
+
+/// Calls off a game without recording it (its finalization panicked, or it was an Endless game
+/// left running): drops the round's boards so the room is back in its lobby, and tells the
+/// players why.
+async fn abort_game(room_id: &RoomId, state: &AppState, reason: &str) {
+    let Some(mut room_state) = state.lock_room(room_id).await else {
+        return;
+    };
+    room_state.board = None;
+    room_state.boards.clear();
+    room_state.stuck.clear();
+    room_state.finished.clear();
+    room_state.last_hint.clear();
+    room_state.tx.share(RoomChange::RoundEnded { round: room_state.round });
+    let scores = room_state.scores.iter().map(|(pid, &s)| (pid.clone(), s)).collect();
+    room_state.tx.send(WsServerMsg::GameAborted {
+        room_id: room_id.clone(),
+        reason: reason.to_string(),
+        scores,
+    });
+}
+
+/// The message a panic was raised with, when it was a string.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board,
+        config::Config,
+        room_bus::RoomPayload,
+        ws_messages::{
+            Player, RoomEvent, DEFAULT_DURATION_SECS, ENDLESS_CAP_SECS, MAX_DURATION_SECS,
+            MIN_DURATION_SECS,
+        },
+    };
+    use tokio::sync::{broadcast, Mutex};
+
+    /// `finish_game` panics for this room as it is about to announce the result, standing in
+    /// for a bug in adding up the scores: without a `GameAborted` the players would be left
+    /// at 0s with no final message.
+    pub(super) const PANICKING_ROOM: &str = "panics";
+
+    /// A state with its scheduler running, and no sockets: these tests drive the clock.
+    fn state() -> AppState {
+        // a whole game's ticks fit, for tests that only read them at the end
+        let config = Config {
+            room_channel_capacity: 256,
+            ..Config::default()
+        };
+        let mut state = AppState::new_with_top_10(BinaryHeap::new(), config);
+        state.timers = Arc::new(GameTimers::start(state.clone()));
+        state
+    }
+
+    /// How long the games here last.
+    const GAME_DURATION_SECS: u64 = DEFAULT_DURATION_SECS as u64;
+
+    /// Starts a game in a new one-player room `room_id`; returns what the room broadcasts.
+    async fn start(state: &AppState, room_id: &str) -> broadcast::Receiver<RoomPayload> {
+        start_with(state, room_id, RoomSettings::default()).await
+    }
+
+    /// `start`, for a room playing by `settings`.
+    async fn start_with(
+        state: &AppState,
+        room_id: &str,
+        settings: RoomSettings,
+    ) -> broadcast::Receiver<RoomPayload> {
+        let owner = Player {
+            player_id: "owner".to_owned(),
+            name: "Owner".to_owned(),
+            ready: false,
+            muted: false,
+        };
+        let mut room = state.new_room(&room_id.to_owned(), owner, settings.clone());
+        let rx = room.tx.subscribe();
+        let dealt = Arc::new(board::board_from_seed(&[[19; 8]], 1, &settings));
+        room.boards.insert("owner".to_owned(), board::player_board(&dealt));
+        room.board = Some(dealt);
+        room.scores.insert("owner".to_owned(), 12);
+        let (tx, span) = (room.tx.clone(), room.span.clone());
+        let slot = state.timers.reserve(0).unwrap();
+        let timer = state.timers.start_game(room_id.to_owned(), tx, span, &settings, slot);
+        (room.ends_at, room.ends_at_ms) = (Some(timer.ends_at), Some(timer.ends_at_ms));
+        room.timer = Some(timer);
+        state.rooms.insert(room_id.to_owned(), Arc::new(Mutex::new(room)));
+        rx
+    }
+
+    /// The room's broadcasts up to and including the first one `last` picks.
+    async fn until(
+        rx: &mut broadcast::Receiver<RoomPayload>,
+        last: impl Fn(&WsServerMsg) -> bool,
+    ) -> Vec<WsServerMsg> {
+        let mut seen = Vec::new();
+        loop {
+            let payload = rx.recv().await.expect("room channel closed");
+            let event: RoomEvent = serde_json::from_str(&payload).unwrap();
+            let done = last(&event.msg);
+            seen.push(event.msg);
+            if done {
+                return seen;
+            }
+        }
+    }
+
+    /// Resolves once the game in `room_id` is done with, however it ended.
+    async fn finished(state: &AppState, room_id: &str) {
+        let finished = {
+            let room = state.lock_room(room_id).await.unwrap();
+            room.timer.as_ref().expect("no game was started").finished()
+        };
+        finished.await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_panic_while_recording_aborts_the_game_and_frees_the_room() {
+        let state = state();
+        let mut rx = start(&state, PANICKING_ROOM).await;
+        let seen = until(&mut rx, |msg| matches!(msg, WsServerMsg::GameAborted { .. })).await;
+        assert!(!seen.iter().any(|msg| matches!(msg, WsServerMsg::GameOver { .. })));
+        match seen.last() {
+            Some(WsServerMsg::GameAborted { scores, .. }) => {
+                assert_eq!(scores, &[("owner".to_owned(), 12)]);
+            }
+            other => panic!("not GameAborted: {other:?}"),
+        }
+
+        finished(&state, PANICKING_ROOM).await;
+        {
+            let room = state.lock_room(PANICKING_ROOM).await.unwrap();
+            assert!(!room.game_in_progress(), "the timer still looks like it's running");
+            assert!(room.board.is_none() && room.boards.is_empty(), "still in the game");
+        }
+        assert_eq!(state.timers.running(), 0);
+        // nothing half-recorded, and no GameOver after the abort either
+        assert!(state.top_10.lock().await.is_empty());
+        while let Ok(payload) = rx.try_recv() {
+            let event: RoomEvent = serde_json::from_str(&payload).unwrap();
+            assert!(!matches!(event.msg, WsServerMsg::GameOver { .. }), "GameOver after all");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_rooms_finish_normally_after_one_panicked() {
+        let state = state();
+        let mut broken = start(&state, PANICKING_ROOM).await;
+        let mut fine = start(&state, "1234").await;
+        until(&mut broken, |msg| matches!(msg, WsServerMsg::GameAborted { .. })).await;
+        let seen = until(&mut fine, |msg| matches!(msg, WsServerMsg::GameOver { .. })).await;
+        assert!(!seen.iter().any(|msg| matches!(msg, WsServerMsg::GameAborted { .. })));
+
+        finished(&state, "1234").await;
+        {
+            let room = state.lock_room("1234").await.unwrap();
+            assert!(room.board.is_some(), "a finished game keeps its board for the recap");
+        }
+        assert_eq!(state.top_10.lock().await.peek(), Some(&(Reverse(12), "Owner".to_owned())));
+    }
+
+    /// `until`, with how long after `started` each broadcast went out.
+    async fn until_timed(
+        rx: &mut broadcast::Receiver<RoomPayload>,
+        started: Instant,
+        last: impl Fn(&WsServerMsg) -> bool,
+    ) -> Vec<(WsServerMsg, Duration)> {
+        let mut seen = Vec::new();
+        loop {
+            let payload = rx.recv().await.expect("room channel closed");
+            let event: RoomEvent = serde_json::from_str(&payload).unwrap();
+            let done = last(&event.msg);
+            seen.push((event.msg, started.elapsed()));
+            if done {
+                return seen;
+            }
+        }
+    }
+
+    /// Each `TimerTick`'s `remaining_secs`, with when it went out.
+    fn ticks(seen: &[(WsServerMsg, Duration)]) -> Vec<(u64, Duration)> {
+        seen.iter()
+            .filter_map(|(msg, at)| match msg {
+                WsServerMsg::TimerTick { remaining_secs, .. } => Some((*remaining_secs, *at)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn is_game_over(msg: &WsServerMsg) -> bool {
+        matches!(msg, WsServerMsg::GameOver { .. })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_game_ticks_down_its_full_length_and_no_further() {
+        let state = state();
+        let started = Instant::now();
+        let mut rx = start(&state, "1234").await;
+        let seen = until_timed(&mut rx, started, is_game_over).await;
+
+        // one tick per second on the second, from the full length down to 0...
+        let expected: Vec<_> = (0..=GAME_DURATION_SECS)
+            .map(|elapsed| (GAME_DURATION_SECS - elapsed, Duration::from_secs(elapsed)))
+            .collect();
+        assert_eq!(ticks(&seen), expected);
+        // ...and the game is over right at the end, not a second later
+        assert_eq!(seen.last().unwrap().1, Duration::from_secs(GAME_DURATION_SECS));
+        finished(&state, "1234").await;
+        assert_eq!(state.timers.running(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_shortest_and_longest_games_last_as_long_as_they_are_set_to() {
+        let state = state();
+        for secs in [MIN_DURATION_SECS, MAX_DURATION_SECS] {
+            let room_id = secs.to_string();
+            let settings = RoomSettings { duration_secs: Some(secs), ..RoomSettings::default() };
+            let started = Instant::now();
+            let mut rx = start_with(&state, &room_id, settings).await;
+            let seen = until_timed(&mut rx, started, is_game_over).await;
+            let (secs, ticks) = (u64::from(secs), ticks(&seen));
+            assert_eq!(ticks.first(), Some(&(secs, Duration::ZERO)));
+            assert_eq!(ticks.len() as u64, secs + 1);
+            assert_eq!(seen.last().unwrap().1, Duration::from_secs(secs));
+            finished(&state, &room_id).await;
+        }
+        assert_eq!(state.timers.running(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_endless_game_has_no_clock_but_is_stopped_at_its_cap() {
+        let state = state();
+        let settings = RoomSettings { duration_secs: None, ..RoomSettings::default() };
+        let started = Instant::now();
+        let mut rx = start_with(&state, "1234", settings).await;
+        let seen = until_timed(&mut rx, started, |msg| {
+            matches!(msg, WsServerMsg::GameAborted { .. })
+        })
+        .await;
+        assert!(ticks(&seen).is_empty(), "an endless game ticked");
+        assert!(!seen.iter().any(|(msg, _)| is_game_over(msg)));
+        // left alone that long, it's called off rather than recorded
+        assert_eq!(seen.last().unwrap().1, Duration::from_secs(ENDLESS_CAP_SECS.into()));
+        finished(&state, "1234").await;
+        {
+            let room = state.lock_room("1234").await.unwrap();
+            assert!(!room.game_in_progress());
+            assert!(room.board.is_none(), "still in the game");
+        }
+        assert!(state.top_10.lock().await.is_empty());
+        assert_eq!(state.timers.running(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_resync_reads_the_clock_off_the_deadline_the_ticks_go_by() {
+        let state = state();
+        let mut rx = start(&state, "1234").await;
+        let first_tick =
+            until(&mut rx, |msg| matches!(msg, WsServerMsg::TimerTick { .. })).await.pop();
+        let Some(WsServerMsg::TimerTick { ends_at_ms: ticked, .. }) = first_tick else {
+            unreachable!()
+        };
+        tokio::time::advance(Duration::from_millis(30_400)).await;
+        let [_, sync] = state.lock_room("1234").await.unwrap().state_sync(
+            &"1234".to_owned(),
+            &"owner".to_owned(),
+            false,
+        );
+        match sync {
+            WsServerMsg::StateSync { remaining_secs, ends_at_ms, .. } => {
+                // 89.6 s to go, rounded up like the ticks
+                assert_eq!(remaining_secs, Some(GAME_DURATION_SECS - 30));
+                assert_eq!(ends_at_ms, Some(ticked));
+            }
+            other => panic!("not StateSync: {other:?}"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rooms_started_apart_each_keep_their_own_clock() {
+        let state = state();
+        let mut games = Vec::new();
+        for (room_id, after) in [("1111", 0), ("2222", 400), ("3333", 1700)] {
+            tokio::time::sleep(Duration::from_millis(after)).await;
+            let started = Instant::now();
+            let mut rx = start(&state, room_id).await;
+            // read as it goes out, so each broadcast is timed when it was sent
+            let seen =
+                tokio::spawn(async move { until_timed(&mut rx, started, is_game_over).await });
+            games.push((room_id, seen));
+        }
+        assert_eq!(state.timers.running(), 3);
+
+        let expected: Vec<_> = (0..=GAME_DURATION_SECS)
+            .map(|elapsed| (GAME_DURATION_SECS - elapsed, Duration::from_secs(elapsed)))
+            .collect();
+        for (left, (room_id, seen)) in (0..3).rev().zip(games) {
+            let seen = seen.await.unwrap();
+            assert_eq!(ticks(&seen), expected, "room {room_id} ticked off its own start");
+            assert_eq!(seen.last().unwrap().1, Duration::from_secs(GAME_DURATION_SECS));
+            finished(&state, room_id).await;
+            assert_eq!(state.timers.running(), left, "room {room_id} still counted as running");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_ticks_neither_pile_up_nor_push_back_the_end() {
+        let state = state();
+        let started = Instant::now();
+        let mut rx = start(&state, "1234").await;
+        let seen = tokio::spawn(async move { until_timed(&mut rx, started, is_game_over).await });
+
+        // the clock jumps ahead just before these ticks are due, as if the scheduler had been
+        // held up: (due second, how late it gets to it)
+        for (due, late) in [(10, 300), (30, 2_500), (119, 300), (120, 2)] {
+            tokio::time::sleep_until(started + Duration::from_millis(due * 1000 - 1)).await;
+            tokio::time::advance(Duration::from_millis(late + 1)).await;
+        }
+        let seen = seen.await.unwrap();
+
+        // a late tick says what's left when it goes out, the ones it was late for are skipped,
+        // and the tick after it is back on the second
+        let late_by = |elapsed| match elapsed {
+            10 | 119 => 300,
+            32 => 500,
+            120 => 2,
+            _ => 0,
+        };
+        let expected: Vec<_> = (0..=GAME_DURATION_SECS)
+            .filter(|elapsed| !(30..32).contains(elapsed))
+            .map(|elapsed| {
+                let at = Duration::from_secs(elapsed) + Duration::from_millis(late_by(elapsed));
+                (GAME_DURATION_SECS - elapsed, at)
+            })
+            .collect();
+        assert_eq!(ticks(&seen), expected);
+        // and the game ends as the clock reaches the deadline, not a second or a tick later
+        let (_, over_at) = seen.last().unwrap();
+        assert_eq!(*over_at, Duration::from_millis(120_002));
+        finished(&state, "1234").await;
+        assert_eq!(state.timers.running(), 0);
+    }
+}
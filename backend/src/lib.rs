@@ -0,0 +1,3194 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{
+        header::{AUTHORIZATION, HOST, ORIGIN, RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL},
+        HeaderMap, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Router,
+};
+use game_timer::RunningGuard;
+use limits::{ChatLimiter, ChatRefusal, TokenBucket};
+use moderation::MASKED_MESSAGES_BEFORE_MUTE;
+use net::Subprotocol;
+use outbox::Outbox;
+use room_bus::{LiveRound, RoomChange, RoomPayload, SeatClaim};
+use server_state::{now_ms, AppState, Notice, OnlineGuard, RoomGuard, RoomState};
+use sessions::{Replacement, SessionInfo};
+use std::sync::{atomic::Ordering, Arc};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Mutex,
+};
+use ws_messages::{
+    ChatChannel, ErrorCode, Player, PlayerId, Rect, RoomCloseReason, RoomEvent, RoomId, RoomSettings,
+    WsClientMsg, WsServerMsg, CHAT_HISTORY_PAGE_MAX, MALFORMED_JSON_SNIPPET_BYTES,
+    MAX_NAME_LEN, MAX_SLOW_MODE_SECS,
+};
+
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tower::ServiceExt;
+use tower_http::{sensitive_headers::SetSensitiveRequestHeadersLayer, trace::TraceLayer};
+use tracing::Instrument;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::time::Instant;
+use anyhow::Result;
+use dashmap::Entry;
+use axum::routing::get;
+use futures_util::StreamExt;
+use config::{Config, LogOptions};
+
+/// Query parameters accepted on the `/ws` upgrade.
+#[derive(Deserialize)]
+struct WsParams {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Combos {
+    data: Vec<[u8; 8]>,
+}
+
+/// What a draining server tells refused clients to wait before reconnecting.
+const DRAIN_RETRY_AFTER_SECS: u64 = 30;
+
+/// Random room codes tried before giving up on finding a free one.
+const ROOM_ID_ATTEMPTS: usize = 100;
+
+fn load_combos_from_dir(dir: &str) -> Result<Vec<[u8; 8]>> {
+    let mut all_data = Vec::new();
+
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file() &&
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with("combos_"))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    paths.sort();
+
+    for path in paths {
+        let s = fs::read_to_string(&path)?;
+        let c: Combos = serde_json::from_str(&s)?;
+        all_data.extend(c.data);
+    }
+
+    Ok(all_data)
+}
+
+// fn load_combos(paths: &[&str]) -> anyhow::Result<Vec<[u8; 8]>> {
+//     let mut all_data = Vec::new();
+
+//     for path in paths {
+//         let s = fs::read_to_string(path)?;
+//         let c: Combos = serde_json::from_str(&s)?;
+//         all_data.extend(c.data);
+//     }
+
+//     Ok(all_data)
+// }
+
+/// Checks a reported clear and returns the score it is worth (by the room's `scoring_mode`).
+/// The apples must all be values the room deals and add up to a multiple of its target sum,
+/// and `cleared_count` must agree with how many values were sent.
+fn score_for_clear(
+    cleared_count: u32,
+    cleared_values: &[u8],
+    settings: &RoomSettings,
+) -> Result<u32, String> {
+    if cleared_values.is_empty() {
+        return Err("Clear must contain at least one apple".to_string());
+    }
+    if let Some(bad) = cleared_values.iter().find(|&&v| !settings.contains(v)) {
+        return Err(format!("Invalid apple value {}", bad));
+    }
+    let sum: u32 = cleared_values.iter().map(|&v| v as u32).sum();
+    let target = settings.target_sum;
+    if !sum.is_multiple_of(target) {
+        return Err(format!("Cleared apples sum to {}, not a multiple of {}", sum, target));
+    }
+    let apples = cleared_values.len() as u32;
+    if cleared_count != apples {
+        return Err(format!(
+            "cleared_count {} does not match {} cleared apples",
+            cleared_count, apples
+        ));
+    }
+    Ok(settings.scoring_mode.points(apples, sum / target))
+}
+
+/// Checks a clear reported with its rectangle against the player's board, removes those
+/// apples, and returns the score (by the room's `scoring_mode`; the rectangle is one group). The
+/// rectangle's remaining apples must add up to exactly the room's target sum.
+fn clear_on_board(
+    board: &mut board::PlayerBoard,
+    rect: &Rect,
+    cleared_count: u32,
+    settings: &RoomSettings,
+) -> Result<u32, String> {
+    if !board::in_bounds(rect) {
+        return Err("Clear is outside the board".to_string());
+    }
+    let values = board::rect_values(board, rect);
+    let sum: u32 = values.iter().map(|&v| v as u32).sum();
+    if sum != settings.target_sum {
+        return Err(format!("Cleared apples sum to {}, not {}", sum, settings.target_sum));
+    }
+    let apples = values.len() as u32;
+    if cleared_count != apples {
+        return Err(format!(
+            "cleared_count {} does not match {} apples in that rectangle",
+            cleared_count, apples
+        ));
+    }
+    board::clear_rect(board, rect);
+    Ok(settings.scoring_mode.points(apples, 1))
+}
+
+// allows to extract the IP of connecting user
+use axum::extract::connect_info::ConnectInfo;
+
+pub mod admin;
+pub mod assets;
+pub mod audit;
+pub mod bans;
+pub mod board;
+pub mod chat;
+pub mod config;
+pub mod game_timer;
+pub mod limits;
+pub mod logging;
+pub mod moderation;
+pub mod room_bus;
+pub mod score_store;
+pub mod net;
+pub mod outbox;
+pub mod server_state;
+pub mod sessions;
+pub mod stats;
+pub mod systemd;
+pub mod tls;
+pub mod webhooks;
+pub mod ws_messages;
+#[cfg(test)]
+mod tests;
+
+/// Holds all of the per‐connection mutable state:
+///   - which room this socket has joined (if any)
+///   - this client’s PlayerId (once they create or join)
+///   - the broadcast‐receiver, used to forward room broadcasts back to this socket
+///   - the presence receiver, only held while the socket is not in a room
+struct ConnContext {
+    joined_room: Option<RoomId>,
+    my_player_id: Option<PlayerId>,
+    room_rx: Option<broadcast::Receiver<RoomPayload>>,
+    // Watching rather than playing, and (as a player) getting the spectators' chat anyway;
+    // see `wants`.
+    spectating: bool,
+    follows_spectator_chat: bool,
+    // The lobby channel, while not in a room (`room_rx` takes over inside one).
+    lobby_rx: Option<broadcast::Receiver<WsServerMsg>>,
+    client: IpAddr,
+    // What the client negotiated at the upgrade.
+    protocol: Subprotocol,
+    // This socket's entry in `state.sessions` while it acts as `my_player_id`, and what a
+    // newer session of the same player wakes to close it.
+    session_id: u64,
+    replaced: Arc<Replacement>,
+    // The held seat last offered to this socket (`ReclaimOffer`): the room and the seated
+    // player, whose ID stays here rather than going out with the offer.
+    reclaim_offer: Option<(RoomId, Player)>,
+
+    last_msg_text: Option<String>,
+    last_msg_instant: Option<Instant>,
+
+    // Last time anything arrived from the client, for the heartbeat.
+    last_seen: Instant,
+    // Last time the client sent an actual message (pongs don't count), for the idle timeout.
+    last_activity: Instant,
+
+    // Per-connection throttles, so one socket can't spam rooms or brute-force join codes.
+    create_room_bucket: TokenBucket,
+    join_failures: TokenBucket,
+    emotes: TokenBucket,
+    chat_history_pages: TokenBucket,
+    // Lobby chat throttling and mutes, like a room's for its members.
+    lobby_chat_limit: Option<ChatLimiter>,
+    lobby_muted_until: Option<Instant>,
+    // Rate-limited requests since the last accepted one; too many and the socket is closed.
+    rate_limit_strikes: u32,
+
+    // The joined room's span, parent of the handler span for messages about that room.
+    room_span: Option<tracing::Span>,
+}
+
+impl ConnContext {
+    fn new(state: &AppState, client: IpAddr, protocol: Subprotocol) -> Self {
+        ConnContext {
+            joined_room: None,
+            my_player_id: None,
+            room_rx: None,
+            spectating: false,
+            follows_spectator_chat: false,
+            lobby_rx: Some(state.lobby_tx.subscribe()),
+            client,
+            protocol,
+            session_id: state.sessions.next_id(),
+            replaced: Arc::new(Replacement::default()),
+            reclaim_offer: None,
+            last_msg_text: None,
+            last_msg_instant: None,
+            last_seen: Instant::now(),
+            last_activity: Instant::now(),
+            create_room_bucket: TokenBucket::new(limits::CREATE_ROOM_BUCKET),
+            join_failures: TokenBucket::new(limits::JOIN_FAILURE_BUCKET),
+            emotes: TokenBucket::new(limits::EMOTE_BUCKET),
+            chat_history_pages: TokenBucket::new(limits::CHAT_HISTORY_BUCKET),
+            lobby_chat_limit: state.config.chat_limits().map(ChatLimiter::new),
+            lobby_muted_until: None,
+            rate_limit_strikes: 0,
+            room_span: None,
+        }
+    }
+
+    /// Frames a message for this socket in the negotiated subprotocol.
+    fn encode(&self, msg: &impl Serialize) -> Message {
+        match self.protocol {
+            Subprotocol::V1 => Message::Text(serde_json::to_string(msg).unwrap().into()),
+        }
+    }
+
+    /// Frames a room broadcast, already serialized as JSON once for the whole room.
+    fn forward(&self, payload: &RoomPayload) -> Message {
+        match self.protocol {
+            Subprotocol::V1 => Message::Text((**payload).into()),
+        }
+    }
+
+    /// A direct reply that snapshots room state, stamped with the room `seq` it is current as of.
+    fn room_snapshot(&self, seq: u64, msg: WsServerMsg) -> Message {
+        self.encode(&RoomEvent { seq, msg })
+    }
+
+    /// Moves this socket into a room: its broadcasts replace the lobby channel.
+    fn enter_room(
+        &mut self,
+        state: &AppState,
+        room_id: &RoomId,
+        player_id: &PlayerId,
+        spectating: bool,
+        rx: broadcast::Receiver<RoomPayload>,
+        span: tracing::Span,
+    ) {
+        let info = SessionInfo {
+            id: self.session_id,
+            client: self.client,
+            room_id: room_id.clone(),
+            spectating,
+            since_ms: now_ms(),
+        };
+        let single = state.config.single_session;
+        let displaced = state.sessions.claim(player_id, info, &self.replaced, single);
+        if !displaced.is_empty() {
+            tracing::info!(player_id = %player_id, ?displaced, "replacing older sessions");
+        }
+        self.joined_room = Some(room_id.clone());
+        self.my_player_id = Some(player_id.clone());
+        self.reclaim_offer = None;
+        self.spectating = spectating;
+        self.follows_spectator_chat = false;
+        self.room_rx = Some(rx);
+        self.room_span = Some(span);
+        self.lobby_rx = None;
+    }
+
+    /// Whether a room broadcast is for this socket: chat on a channel it doesn't get is
+    /// skipped. Also notices this player being moved to the spectators.
+    ///
+    /// Broadcasts are already JSON by now, so those two types are told by a look at the text
+    /// before anything is parsed. Quotes inside strings are escaped, so a message can't fake it.
+    fn wants(&mut self, payload: &str) -> bool {
+        if payload.contains(r#""type":"ChatBroadcast""#) {
+            if let Ok(RoomEvent {
+                msg: WsServerMsg::ChatBroadcast { channel, .. },
+                ..
+            }) = serde_json::from_str(payload)
+            {
+                return channel.reaches(self.spectating, self.follows_spectator_chat);
+            }
+        } else if !self.spectating && payload.contains(r#""type":"MovedToSpectators""#) {
+            if let Ok(RoomEvent {
+                msg: WsServerMsg::MovedToSpectators { player_id, .. },
+                ..
+            }) = serde_json::from_str(payload)
+            {
+                if self.my_player_id.as_ref() == Some(&player_id) {
+                    self.spectating = true;
+                }
+            }
+        }
+        true
+    }
+
+    /// Takes this socket out of its room and back onto the lobby channel.
+    fn return_to_lobby(&mut self, state: &AppState) {
+        if let Some(player_id) = &self.my_player_id {
+            state.sessions.release(player_id, self.session_id);
+        }
+        self.joined_room = None;
+        self.my_player_id = None;
+        self.room_rx = None;
+        self.room_span = None;
+        self.lobby_rx = Some(state.lobby_tx.subscribe());
+    }
+
+    /// The error for a throttled request, counting it towards `MAX_RATE_LIMIT_STRIKES`.
+    fn rate_limited(&mut self, what: &str, wait: Duration) -> WsServerMsg {
+        self.rate_limit_strikes += 1;
+        WsServerMsg::Error {
+            room_id: self.joined_room.clone(),
+            msg: format!("{what}, try again in {}s", wait.as_secs() + 1),
+            code: Some(ErrorCode::RateLimited {
+                retry_after_ms: wait.as_millis() as u64,
+            }),
+        }
+    }
+
+    /// How long this socket may go without sending a message before it's closed, if at all.
+    fn idle_limit(&self, config: &Config) -> Option<Duration> {
+        let limit = if self.joined_room.is_some() {
+            config.room_idle_timeout
+        } else {
+            config.idle_timeout
+        };
+        (!limit.is_zero()).then_some(limit)
+    }
+}
+
+impl ConnContext {
+    pub fn require_room_and_player(&self) -> Result<(&RoomId, &PlayerId), WsServerMsg> {
+        let room_id = self
+            .joined_room
+            .as_ref()
+            .ok_or_else(|| WsServerMsg::Error {
+                room_id: None,
+                msg: "Not in a room".to_string(),
+                code: None,
+            })?;
+
+        let player_id = self
+            .my_player_id
+            .as_ref()
+            .ok_or_else(|| WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "Player ID not assigned".to_string(),
+                code: None,
+            })?;
+
+        Ok((room_id, player_id))
+    }
+}
+
+/// Runs the server until it's shut down: everything `main` does.
+pub async fn run() {
+    let _log_guard = LogOptions::load()
+        .and_then(|options| logging::init(&options))
+        .unwrap_or_else(|e| {
+            eprintln!("cannot set up logging: {e:#}");
+            std::process::exit(1);
+        });
+
+    let config = Config::load().unwrap_or_else(|e| {
+        tracing::error!("invalid configuration: {e:#}");
+        std::process::exit(1);
+    });
+
+    // Load persisted top-10 scores from disk
+    let store = Arc::new(score_store::FileStore::new(config.scores_file.clone()));
+    let mut state = AppState::with_score_store(store, config).await;
+    // Read once here, so a missing or broken combos file stops startup instead of panicking
+    // whichever room starts the first game
+    state.combos = match load_combos_from_dir("./") {
+        Ok(combos) if !combos.is_empty() => {
+            tracing::info!(combos = combos.len(), "board combos loaded");
+            combos.into()
+        }
+        Ok(_) => {
+            tracing::error!("no combos_*.json files in the working directory, cannot deal boards");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("cannot load board combos: {e:#}");
+            std::process::exit(1);
+        }
+    };
+    {
+        let top_10 = state.top_10_list.load();
+        let path = state.config.scores_file.display();
+        tracing::info!(entries = top_10.len(), path = %path, "top-10 loaded");
+        tracing::trace!(?top_10, "top-10 contents");
+    }
+    state.audit = Arc::new(audit::AuditLog::start(
+        state.config.audit_file.clone(),
+        state.config.audit_max_bytes,
+    ));
+    state.bans = match bans::BanList::load(state.config.bans_file.clone()) {
+        Ok(bans) => Arc::new(bans),
+        Err(e) => {
+            tracing::error!("{e:#}");
+            std::process::exit(1);
+        }
+    };
+    if state.config.profanity_filter {
+        let path = state.config.deny_list_file.clone();
+        state.deny_list = match moderation::DenyList::load(path, state.config.leet_map.clone()) {
+            Ok(list) => Arc::new(list),
+            Err(e) => {
+                tracing::error!("{e:#}");
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(url) = state.config.redis_url.clone() {
+        state.bus = connect_redis_bus(&url).await;
+    }
+    state.webhooks = Arc::new(webhooks::Webhooks::start(state.config.webhooks.clone()));
+    // last, so the scheduler's copy of the state has everything finished games are recorded into
+    state.timers = Arc::new(game_timer::GameTimers::start(state.clone()));
+
+    // Push lobby presence counts to everyone who isn't in a room
+    tokio::spawn(presence_tick(state.clone()));
+
+    // Forget bans once they run out
+    tokio::spawn(prune_expired_bans(state.clone()));
+
+    // Under systemd with `WatchdogSec=`, keep telling it we're alive
+    systemd::spawn_watchdog(state.clone());
+
+    // Bench players who stop scoring, in rooms that ask for it
+    tokio::spawn(bench_idle_players(state.clone()));
+
+    // Optionally un-ready players who have been sitting ready in an idle lobby for too long
+    if let Some(timeout) = state.config.ready_timeout {
+        tokio::spawn(unready_stale_players(state.clone(), timeout));
+    }
+
+    // Keep copies of rooms shared with other instances fresh, and drop the unused ones
+    if state.bus.shared().is_some() {
+        tokio::spawn(sync_shared_rooms(state.clone()));
+    }
+
+    let assets = assets::router(&state.config).unwrap_or_else(|e| {
+        tracing::error!("cannot serve the frontend: {e:#}");
+        std::process::exit(1);
+    });
+
+    let plan = listen_plan(&state, assets);
+    let listeners = bind_all(&plan).await.unwrap_or_else(|e| {
+        tracing::error!("{e:#}");
+        std::process::exit(1);
+    });
+    let routers = plan.into_iter().map(|(_, router)| router);
+
+    let mut servers = Vec::new();
+    if let Some(paths) = state.config.tls.clone() {
+        // Native TLS: fail fast on bad cert/key files rather than serving broken handshakes
+        let tls_config = tls::load(&paths).unwrap_or_else(|e| {
+            tracing::error!("cannot start TLS: {e:#}");
+            std::process::exit(1);
+        });
+        tokio::spawn(tls::reload_on_sighup(tls_config.clone(), paths));
+
+        let mut handles = Vec::new();
+        for (listener, router) in listeners.into_iter().zip(routers) {
+            let handle = axum_server::Handle::new();
+            handles.push(handle.clone());
+            let listener = listener.into_std().unwrap();
+            let server = axum_server::from_tcp_rustls(listener, tls_config.clone())
+                .handle(handle)
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+            servers.push(tokio::spawn(server));
+        }
+        tokio::spawn(async move {
+            shutdown_signal(state).await;
+            for handle in handles {
+                handle.graceful_shutdown(Some(Duration::from_secs(1)));
+            }
+        });
+    } else {
+        let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+        for (listener, router) in listeners.into_iter().zip(routers) {
+            let mut stop_rx = stop_rx.clone();
+            let server = axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async move {
+                let _ = stop_rx.wait_for(|&stop| stop).await;
+            });
+            servers.push(tokio::spawn(async move { server.await }));
+        }
+        tokio::spawn(async move {
+            shutdown_signal(state).await;
+            stop_tx.send_replace(true);
+        });
+    }
+    systemd::ready();
+
+    for server in servers {
+        if let Ok(Err(e)) = server.await {
+            tracing::error!(error = %e, "server failed");
+        }
+    }
+}
+
+/// The whole app: WebSocket, API and frontend, under `--base-path` if one is set.
+/// `admin` decides whether `/api/admin` and `/api/stats` are routed at all.
+fn app_router(state: &AppState, assets: Router, admin: bool) -> Router {
+    let mut routes = Router::new()
+    // WebSocket route first so it’s not swallowed by fallback
+    .route(&state.config.ws_path, get(ws_handler))
+    .route("/healthz", get(healthz));
+    if admin {
+        routes = routes
+            .route("/api/stats", get(admin::stats))
+            .nest("/api/admin", admin::router());
+    }
+    let routes = routes
+    // Serve static files after WebSocket route
+    .fallback_service(assets)
+    .with_state(state.clone());
+    // behind a shared proxy everything lives under the prefix; anything outside it is a 404
+    let app = match state.config.base_path.as_str() {
+        "" => routes,
+        base => {
+            // `nest` matches `/base` and `/base/x` but not `/base/` itself, which is the URL
+            // people actually visit
+            let index = routes.clone().map_request(|mut req: axum::extract::Request| {
+                let root = match req.uri().query() {
+                    Some(query) => format!("/?{query}"),
+                    None => "/".to_owned(),
+                };
+                *req.uri_mut() = root.parse().unwrap();
+                req
+            });
+            Router::new()
+                .route_service(&format!("{base}/"), index)
+                .nest(base, routes)
+        }
+    };
+
+    app
+    .layer(
+        // path only, so a `?token=` never lands in the logs
+        TraceLayer::new_for_http().make_span_with(|req: &axum::extract::Request| {
+            tracing::debug_span!(
+                "request",
+                method = %req.method(),
+                path = %req.uri().path(),
+                version = ?req.version(),
+                headers = ?req.headers(),
+            )
+        }),
+    )
+    // outermost, so the trace span above already sees these redacted
+    .layer(SetSensitiveRequestHeadersLayer::new([
+        AUTHORIZATION,
+        SEC_WEBSOCKET_PROTOCOL,
+    ]))
+}
+
+/// `GET /healthz` — liveness for load balancers and orchestrators, plus how full the server is.
+async fn healthz(State(state): State<AppState>) -> axum::Json<serde_json::Value> {
+    axum::Json(serde_json::json!({
+        "status": if state.is_draining() { "draining" } else { "ok" },
+        "online": state.online.load(Ordering::Relaxed),
+        "rooms": state.room_count.load(Ordering::Relaxed),
+        "max_rooms": state.config.max_rooms,
+        "games_running": state.timers.running(),
+        "max_running_games": state.config.max_running_games,
+    }))
+}
+
+/// Every listen address with the router it serves. With `--bind-admin`, only those listeners
+/// serve the admin API; otherwise every one does.
+fn listen_plan(state: &AppState, assets: Router) -> Vec<(SocketAddr, Router)> {
+    let full = app_router(state, assets.clone(), true);
+    let public = if state.config.admin_binds.is_empty() {
+        full.clone()
+    } else {
+        app_router(state, assets, false)
+    };
+    state
+        .config
+        .binds
+        .iter()
+        .map(|&addr| (addr, public.clone()))
+        .chain(state.config.admin_binds.iter().map(|&addr| (addr, full.clone())))
+        .collect()
+}
+
+/// Binds every planned address before anything is served, so a typo'd or taken address stops
+/// startup (naming what did bind) instead of leaving the server half-reachable.
+async fn bind_all(plan: &[(SocketAddr, Router)]) -> Result<Vec<tokio::net::TcpListener>> {
+    let mut listeners = Vec::new();
+    for (addr, _) in plan {
+        match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => {
+                tracing::debug!("listening on {}", listener.local_addr().unwrap());
+                listeners.push(listener);
+            }
+            Err(e) => {
+                let bound: Vec<_> = listeners
+                    .iter()
+                    .filter_map(|l| l.local_addr().ok())
+                    .map(|a| a.to_string())
+                    .collect();
+                anyhow::bail!("cannot bind {addr}: {e} (bound so far: {bound:?})");
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+/// Hard limit on how long shutdown may take once a signal arrives.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Resolves once SIGINT or SIGTERM arrives and every running game has recorded its scores.
+/// Returning from here is what lets axum stop serving, so the persistence writes finish first.
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutdown signal received, finishing running games");
+    systemd::stopping();
+
+    // If anything below wedges, don't hang around forever.
+    tokio::spawn(async {
+        tokio::time::sleep(SHUTDOWN_DEADLINE).await;
+        tracing::error!("shutdown deadline exceeded, exiting");
+        std::process::exit(1);
+    });
+
+    shut_down(&state).await;
+}
+
+/// Refuses new games, ends the running ones so their scores are recorded and saved, then
+/// closes every room and socket.
+async fn shut_down(state: &AppState) {
+    state.begin_shutdown();
+    if tokio::time::timeout(SHUTDOWN_DEADLINE, finish_running_games(state))
+        .await
+        .is_err()
+    {
+        tracing::warn!("timed out waiting for running games to finish");
+    }
+
+    // Scores are safe; tell every room why it's ending, then close every socket with a proper
+    // close frame and give them a moment.
+    for (room_id, room) in state.all_rooms() {
+        room.lock().await.close(&room_id, RoomCloseReason::ServerShutdown);
+    }
+    state.disconnect_all();
+    let _ = tokio::time::timeout(Duration::from_secs(2), async {
+        while state.online.load(Ordering::Relaxed) > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    tracing::info!("shutdown complete");
+}
+
+/// Waits for every running game to wind down and record its final scores.
+/// The timer scheduler watches the shutdown flag itself; we only collect each game's
+/// completion and await them, never holding a room's lock while doing so (finalizing needs it).
+async fn finish_running_games(state: &AppState) {
+    let mut games = Vec::new();
+    for (_, room) in state.all_rooms() {
+        if let Some(timer) = &room.lock().await.timer {
+            if timer.is_running() {
+                games.push(timer.finished());
+            }
+        }
+    }
+    tracing::info!(games = games.len(), "waiting for running games");
+    for game in games {
+        game.await;
+    }
+}
+
+/// The handler for the HTTP request that upgrades to WebSocket.
+/// We also log the client address once per connection (resolved through trusted proxies).
+async fn ws_handler(
+    mut ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    uri: Uri,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let client = net::client_ip(peer, &headers, &state.config.trusted_proxies);
+
+    // Banned addresses: check the direct peer as well as whoever the proxy headers name
+    if let Some(ban) = state.bans.check(client).or_else(|| state.bans.check(peer.ip())) {
+        tracing::warn!(client = %client, ban_id = ban.id, "rejected banned address");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Draining before a restart: existing sockets stay, new ones come back later
+    if state.is_draining() {
+        tracing::debug!(client = %client, "refusing websocket while draining");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, DRAIN_RETRY_AFTER_SECS.to_string())],
+        )
+            .into_response();
+    }
+
+    // Refuse cross-site WebSocket hijacking attempts from other pages
+    let origin = headers.get(ORIGIN).and_then(|v| v.to_str().ok());
+    let host = headers.get(HOST).and_then(|v| v.to_str().ok());
+    if !net::origin_allowed(
+        origin,
+        host,
+        &state.config.allowed_origins,
+        state.config.allow_missing_origin,
+    ) {
+        tracing::warn!(client = %client, origin = ?origin, "rejected websocket origin");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Private instances: no shared secret, no socket
+    let mut token_protocol = None;
+    if let Some(expected) = state.config.require_ws_token.as_deref() {
+        if state.ip_limits.auth_blocked(client) {
+            tracing::warn!(client = %client, "too many bad websocket tokens from this address");
+            return StatusCode::TOO_MANY_REQUESTS.into_response();
+        }
+        let params = Query::<WsParams>::try_from_uri(&uri).ok();
+        let query_token = params.as_ref().and_then(|p| p.token.as_deref());
+        match net::find_ws_token(query_token, &headers, expected) {
+            Some(net::TokenSource::Query) => {}
+            Some(net::TokenSource::Subprotocol(protocol)) => token_protocol = Some(protocol),
+            None => {
+                state.ip_limits.record_auth_failure(client);
+                tracing::warn!(client = %client, "rejected websocket without a valid token");
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+        }
+    }
+
+    // Held by the upgrade closure, so the slot is freed however the connection ends
+    let Some(ip_slot) = state.ip_limits.try_connect(client) else {
+        tracing::warn!(client = %client, "too many connections from this address");
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    };
+
+    // Capabilities: echo the subprotocol we'll speak. A token offered as a subprotocol is only
+    // echoed when nothing else matched, since the browser needs one of its offers back.
+    let offered = Subprotocol::ALL.map(|p| Cow::Borrowed(p.name()));
+    ws = ws.protocols(offered.into_iter().chain(token_protocol.map(Cow::Owned)));
+    let protocol = ws
+        .selected_protocol()
+        .and_then(|p| p.to_str().ok())
+        .and_then(Subprotocol::from_name)
+        .unwrap_or(Subprotocol::V1);
+
+    tracing::info!(
+        client = %client,
+        peer = %peer,
+        protocol = protocol.name(),
+        "client connecting"
+    );
+
+    let hard_limit = state.config.max_message_bytes.saturating_mul(2);
+    ws.max_message_size(hard_limit)
+        .max_frame_size(hard_limit)
+        .on_upgrade(move |socket| async move {
+            handle_connection(socket, client, protocol, state).await;
+            drop(ip_slot);
+        })
+        .into_response()
+}
+
+#[cfg(feature = "redis-bus")]
+async fn connect_redis_bus(url: &str) -> Arc<dyn room_bus::RoomBus> {
+    match room_bus::redis_bus::RedisBus::connect(url).await {
+        Ok(bus) => Arc::new(bus),
+        Err(e) => {
+            tracing::error!("redis bus unavailable: {e:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "redis-bus"))]
+async fn connect_redis_bus(_url: &str) -> Arc<dyn room_bus::RoomBus> {
+    tracing::warn!("REDIS_URL is set but this build lacks the redis-bus feature, staying single-instance");
+    Arc::new(room_bus::LocalBus)
+}
+
+/// Close code for sockets dropped by the idle timeout (4000-4999 is left to applications).
+const CLOSE_IDLE: u16 = 4000;
+
+/// Close code for sockets that keep hammering a rate limit.
+const CLOSE_RATE_LIMITED: u16 = 4001;
+
+/// Close code for sockets that keep falling behind their room's broadcasts.
+const CLOSE_TOO_SLOW: u16 = 4002;
+
+/// Close code for a socket whose player started a newer session: over the same seat, or
+/// anywhere with `single_session`.
+const CLOSE_SESSION_REPLACED: u16 = 4003;
+
+/// Rate-limited requests in a row before the socket is closed.
+const MAX_RATE_LIMIT_STRIKES: u32 = 5;
+
+/// How often expired bans are dropped from the list (and the file).
+const BAN_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often each socket checks its idle timeout.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a closed socket waits for the client's side of the close handshake.
+const CLOSE_LINGER: Duration = Duration::from_secs(2);
+
+/// How often running games are checked for players past their room's `idle_kick_secs`.
+const IDLE_KICK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the copies of shared rooms are caught up with the rooms' shared records when
+/// nobody here does anything in them.
+const SHARED_ROOM_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `player` with their name through the profanity filter (and no say in `muted`), or the
+/// error for a name that fails `validate_name`.
+fn check_name(
+    mut player: Player,
+    room_id: Option<&RoomId>,
+    state: &AppState,
+) -> Result<Player, WsServerMsg> {
+    player.validate_name().map_err(|msg| WsServerMsg::Error {
+        room_id: room_id.cloned(),
+        msg,
+        code: Some(ErrorCode::InvalidName {
+            max_len: MAX_NAME_LEN as u32,
+        }),
+    })?;
+    if let Some(masked) = state.deny_list.mask(&player.name) {
+        player.name = masked;
+    }
+    player.muted = false;
+    Ok(player)
+}
+
+/// The error for chat from a member who is muted for `left` longer (`None`: until unmuted).
+fn chat_muted(room_id: Option<&RoomId>, left: Option<Duration>) -> WsServerMsg {
+    let msg = match left {
+        Some(left) => format!("You are muted for {}s", left.as_secs_f64().ceil()),
+        None => "You are muted until the room owner unmutes you".to_string(),
+    };
+    WsServerMsg::Error {
+        room_id: room_id.cloned(),
+        msg,
+        code: Some(ErrorCode::Muted {
+            retry_after_ms: left.map(|left| left.as_millis() as u64),
+        }),
+    }
+}
+
+/// A `ChatMessage` from a socket that isn't in a room: checked like room chat, then sent to
+/// the whole lobby. Spamming it mutes the socket, quietly, as there's no room to announce it to.
+fn lobby_chat(
+    message: String,
+    name: Option<String>,
+    ctx: &mut ConnContext,
+    state: &AppState,
+) -> Result<(), WsServerMsg> {
+    let error = |msg: &str| WsServerMsg::Error {
+        room_id: None,
+        msg: msg.to_string(),
+        code: None,
+    };
+    if !state.config.lobby_chat {
+        return Err(error("Join a room to chat: this server has no lobby chat"));
+    }
+    let name = name.ok_or_else(|| error("Lobby chat needs a name"))?;
+    let player = Player {
+        player_id: String::new(),
+        name,
+        ready: false,
+        muted: false,
+    };
+    let name = check_name(player, None, state)?.name;
+    let now = Instant::now();
+    if let Some(until) = ctx.lobby_muted_until.filter(|&until| until > now) {
+        return Err(chat_muted(None, Some(until - now)));
+    }
+    let message = chat::sanitize(&message, state.config.chat_max_len).map_err(|msg| error(&msg))?;
+    if let Some(limiter) = ctx.lobby_chat_limit.as_mut() {
+        match limiter.check(now) {
+            Ok(()) => {}
+            Err(ChatRefusal::TooFast(wait)) => {
+                return Err(ctx.rate_limited("Chatting too fast", wait));
+            }
+            Err(ChatRefusal::Mute) => {
+                let duration = state.config.chat_mute;
+                ctx.lobby_muted_until = Some(now + duration);
+                tracing::info!(%name, "muted in the lobby for spamming the chat");
+                return Err(chat_muted(None, Some(duration)));
+            }
+        }
+    }
+    let message = state.deny_list.mask(&message).unwrap_or(message);
+    tracing::debug!(%name, len = message.len(), "lobby chat message");
+    let _ = state.lobby_tx.send(WsServerMsg::LobbyChatBroadcast { name, message });
+    Ok(())
+}
+
+/// Where a client message stopped parsing, with the input around that point.
+/// The snippet is cut on character boundaries, so it never exceeds the cap or splits a char.
+fn malformed_json(e: &serde_json::Error, input: &str) -> ErrorCode {
+    // serde counts lines from 1 and columns from 1 in bytes; 0 means the end of the input
+    let line_start = match e.line() {
+        0 | 1 => 0,
+        line => input
+            .match_indices('\n')
+            .nth(line - 2)
+            .map_or(input.len(), |(i, _)| i + 1),
+    };
+    let at = match e.column() {
+        0 => input.len(),
+        column => (line_start + column - 1).min(input.len()),
+    };
+    let half = MALFORMED_JSON_SNIPPET_BYTES / 2;
+    let mut start = at.saturating_sub(half);
+    while !input.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut end = (start + MALFORMED_JSON_SNIPPET_BYTES).min(input.len());
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    ErrorCode::MalformedJson {
+        line: e.line().try_into().unwrap_or(u32::MAX),
+        column: e.column().try_into().unwrap_or(u32::MAX),
+        snippet: input[start..end].to_string(),
+    }
+}
+
+/// What to do about a socket that just fell behind its room's broadcasts.
+enum LagAction {
+    Continue,
+    Resync([Message; 2]),
+    Disconnect,
+}
+
+/// Counts a lag event against this socket for the current game and applies the
+/// `lag_resync_after` / `lag_disconnect_after` policy.
+async fn on_lagged(ctx: &ConnContext, state: &AppState, missed: u64) -> LagAction {
+    let (Some(room_id), Some(player_id)) = (&ctx.joined_room, &ctx.my_player_id) else {
+        return LagAction::Continue;
+    };
+    let Some(mut room_state) = state.lock_room(room_id).await else {
+        return LagAction::Continue;
+    };
+    let lagged = room_state.lagged.entry(player_id.clone()).or_insert(0);
+    *lagged += 1;
+    let lagged = *lagged;
+    tracing::debug!(room_id = %room_id, player_id = %player_id, missed, lagged, "socket lagged");
+
+    let config = &state.config;
+    if config.lag_disconnect_after > 0 && lagged >= config.lag_disconnect_after {
+        tracing::warn!(
+            parent: &room_state.span,
+            event = "too_slow",
+            player_id = %player_id,
+            lagged,
+            "socket keeps falling behind, closing it"
+        );
+        return LagAction::Disconnect;
+    }
+    if config.lag_resync_after > 0 && lagged >= config.lag_resync_after {
+        let seq = room_state.tx.seq();
+        let msgs = room_state.state_sync(room_id, player_id, ctx.follows_spectator_chat);
+        return LagAction::Resync(msgs.map(|msg| ctx.room_snapshot(seq, msg)));
+    }
+    LagAction::Continue
+}
+
+/// A close frame with a status code and a human-readable reason the frontend can show.
+fn close_message(code: u16, reason: &str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: reason.to_owned().into(),
+    }))
+}
+
+async fn prune_expired_bans(state: AppState) {
+    let mut interval = tokio::time::interval(BAN_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        state.bans.prune().await;
+    }
+}
+
+/// Periodically broadcasts `GlobalPresence` to every socket that is not in a room.
+/// Reads only the atomic counters on `AppState`, so it never contends with room traffic.
+async fn presence_tick(state: AppState) {
+    let mut interval = tokio::time::interval(state.config.presence_interval);
+    loop {
+        interval.tick().await;
+        if state.lobby_tx.receiver_count() > 0 {
+            let _ = state.lobby_tx.send(state.presence());
+            let _ = state.lobby_tx.send(state.server_stats());
+        }
+    }
+}
+
+/// Lobby sweep: flips players back to unready once they've been ready for `timeout`
+/// without a game starting, and tells the room. Rooms with a game running are left alone.
+async fn unready_stale_players(state: AppState, timeout: Duration) {
+    let mut interval = tokio::time::interval((timeout / 4).min(Duration::from_secs(30)));
+    loop {
+        interval.tick().await;
+        for (room_id, room) in state.all_rooms() {
+            let mut room_state = room.lock().await;
+            if room_state.game_in_progress() {
+                continue;
+            }
+            let stale: Vec<PlayerId> = room_state
+                .ready_since
+                .iter()
+                .filter(|(pid, since)| {
+                    since.elapsed() >= timeout && seated_here(&state, pid, &room_id)
+                })
+                .map(|(pid, _)| pid.clone())
+                .collect();
+            if stale.is_empty() {
+                continue;
+            }
+            for pid in &stale {
+                room_state.set_ready(pid, false);
+            }
+            tracing::info!(room_id = %room_id, players = ?stale, "un-readied stale players");
+
+            let players: Vec<_> = room_state.player_list();
+            let msg = WsServerMsg::RoomPlayersUpdate {
+                room_id: room_id.clone(),
+                players,
+                owner_id: room_state.owner.clone(),
+            };
+            room_state.tx.send(msg);
+        }
+    }
+}
+
+/// Every few seconds, moves players to the spectators if their room has `idle_kick_secs` and
+/// they haven't scored for that long while its game runs. Disconnected players are left to the
+/// reconnect grace, and players who finished or ran out of moves can't score anyway.
+async fn bench_idle_players(state: AppState) {
+    let mut interval = tokio::time::interval(IDLE_KICK_CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        for (room_id, room) in state.all_rooms() {
+            let mut room_state = room.lock().await;
+            let Some(secs) = room_state.settings.idle_kick_secs else {
+                continue;
+            };
+            if !room_state.game_in_progress() {
+                continue;
+            }
+            let limit = Duration::from_secs(secs.into());
+            let idle: Vec<PlayerId> = room_state
+                .last_score_at
+                .iter()
+                .filter(|(pid, at)| {
+                    at.elapsed() >= limit
+                        && seated_here(&state, pid, &room_id)
+                        && !room_state.disconnected.contains_key(*pid)
+                        && !room_state.finished.contains_key(*pid)
+                        && !room_state.stuck.contains(*pid)
+                })
+                .map(|(pid, _)| pid.clone())
+                .collect();
+            for pid in idle {
+                if room_state.move_to_spectators(&room_id, &pid, &state.audit) {
+                    state.sessions.unseat(&pid, &room_id);
+                    tracing::info!(
+                        parent: &room_state.span,
+                        event = "player_benched",
+                        player_id = %pid,
+                        idle_secs = secs,
+                        "moved idle player to spectators"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Whether `player_id` is this instance's to look after in `room_id`: any seated player, unless
+/// rooms are shared, when only those who took their seat through a socket here are.
+fn seated_here(state: &AppState, player_id: &PlayerId, room_id: &RoomId) -> bool {
+    state.bus.shared().is_none() || state.sessions.is_seated_in(player_id, room_id)
+}
+
+/// Every `SHARED_ROOM_SYNC_INTERVAL`, drops the copies of shared rooms none of the sockets here
+/// are in any more, and catches the others up with their shared record (which lets go of the
+/// ones closed everywhere else).
+async fn sync_shared_rooms(state: AppState) {
+    let mut interval = tokio::time::interval(SHARED_ROOM_SYNC_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        for (room_id, room) in state.all_rooms() {
+            if !state.drop_unused_copy(&room_id, &mut room.lock_owned().await) {
+                state.sync_room(&room_id).await;
+            }
+        }
+    }
+}
+
+/// The “per‐connection” logic, now using a `ConnContext` to group mutable state.
+/// First: send the server info and Top-10 snapshot to the client, then loop reading either:
+///   1) a broadcast message from the room, or
+///   2) a client→server JSON text message.
+#[tracing::instrument(
+    name = "conn",
+    skip_all,
+    fields(client = %client, room_id = tracing::field::Empty, player_id = tracing::field::Empty)
+)]
+async fn handle_connection(
+    ws: WebSocket,
+    client: IpAddr,
+    protocol: Subprotocol,
+    state: AppState,
+) {
+    // initialize our per-connection context
+    let _online = OnlineGuard::new(&state);
+    let (sink, mut stream) = ws.split();
+    let mut out = Outbox::spawn(
+        sink,
+        state.config.send_queue_capacity,
+        state.config.send_stall_timeout,
+    );
+    let mut ctx = ConnContext::new(&state, client, protocol);
+    let mut disconnect_rx = state.disconnect.subscribe();
+    let mut bans_rx = state.bans.subscribe();
+    let mut heartbeat = tokio::time::interval(state.config.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut idle_check = tokio::time::interval(IDLE_CHECK_INTERVAL.min(
+        state.config.idle_timeout.max(Duration::from_secs(1)),
+    ));
+    idle_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // 1) Send the server info and Top-10 scores immediately on connect
+    out.send(ctx.encode(&state.server_info()));
+    let top_10_msg = WsServerMsg::Top10Scores {
+        scores: state.top_10_list.load_full(),
+    };
+    out.send(ctx.encode(&top_10_msg));
+    out.send(ctx.encode(&state.presence()));
+
+    // 2) Enter main event loop:
+    loop {
+        if out.is_closed() {
+            break; // client disconnected
+        }
+        if out.too_slow() {
+            tracing::info!("client stopped reading, dropping connection");
+            out.close_now(close_message(CLOSE_TOO_SLOW, "Connection too slow"));
+            break;
+        }
+        tokio::select! {
+            // (A) If we have a subscription to a room's broadcast channel, wait for it:
+            biased;
+            // (only while the send queue has room: otherwise they wait in the room channel)
+            Some(room_rx_result) = async { if let Some(rx) = ctx.room_rx.as_mut() { Some(rx.recv().await) } else { None } }, if out.has_room() => {
+                match room_rx_result {
+                    Ok(payload) => {
+                        if ctx.wants(&payload) {
+                            out.send(ctx.forward(&payload));
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        // missed some messages → catch up from a snapshot, or give up on a
+                        // socket that keeps falling behind
+                        match on_lagged(&ctx, &state, missed).await {
+                            LagAction::Continue => continue,
+                            LagAction::Resync([players, sync]) => {
+                                out.send(players);
+                                out.send(sync);
+                            }
+                            LagAction::Disconnect => {
+                                out.close_now(close_message(CLOSE_TOO_SLOW, "Connection too slow"));
+                                break;
+                            }
+                        }
+                    }
+                    Err(RecvError::Closed) => {
+                        // room was torn down (the client already got `RoomClosed`) → close the socket
+                        out.send(close_message(close_code::NORMAL, "Room closed"));
+                        break;
+                    }
+                }
+            },
+
+            // (A2) The lobby channel, only while not in a room; falling behind just skips ahead
+            Some(lobby_result) = async { if let Some(rx) = ctx.lobby_rx.as_mut() { Some(rx.recv().await) } else { None } }, if out.has_room() => {
+                match lobby_result {
+                    Ok(server_msg) => out.send(ctx.encode(&server_msg)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        ctx.lobby_rx = None;
+                    }
+                }
+            },
+
+            // (A4) Heartbeat: ping, and give up on sockets that have gone quiet
+            _ = heartbeat.tick() => {
+                if ctx.last_seen.elapsed() > state.config.heartbeat_timeout {
+                    tracing::info!("heartbeat timed out, dropping connection");
+                    break;
+                }
+                out.send(Message::Ping(Default::default()));
+            },
+
+            // (A5) Idle timeout: the client hasn't said anything in a long while
+            _ = idle_check.tick() => {
+                if ctx
+                    .idle_limit(&state.config)
+                    .is_some_and(|limit| ctx.last_activity.elapsed() >= limit)
+                {
+                    tracing::info!("closing idle connection");
+                    out.send(close_message(CLOSE_IDLE, "Closed for inactivity"));
+                    break;
+                }
+            },
+
+            // (A6) A ban was just added: leave if it covers us
+            Ok(()) = bans_rx.changed() => {
+                if let Some(ban) = state.bans.check(client) {
+                    tracing::info!(ban_id = ban.id, "closing connection from newly banned address");
+                    out.send(close_message(close_code::POLICY, "Banned"));
+                    break;
+                }
+            },
+
+            // (A7) A newer socket took over this player's place in the room, or started a
+            // session elsewhere while `single_session` is on
+            seat_taken = ctx.replaced.wait() => {
+                tracing::info!(seat_taken, "session replaced by a newer one");
+                out.send(ctx.encode(&WsServerMsg::SessionReplaced {}));
+                if seat_taken {
+                    // the seat is the new socket's now, so the cleanup below must not free it
+                    ctx.joined_room = None;
+                    out.send(close_message(CLOSE_SESSION_REPLACED, "Superseded by a new connection"));
+                } else {
+                    out.send(close_message(CLOSE_SESSION_REPLACED, "Signed in elsewhere"));
+                }
+                break;
+            },
+
+            // (A3) Server is going away → say goodbye properly
+            _ = async { let _ = disconnect_rx.wait_for(|&close| close).await; } => {
+                out.send(close_message(close_code::AWAY, "Server is restarting"));
+                break;
+            },
+
+            // (B) Read client→server message
+            msg = stream.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => {
+                        ctx.last_seen = Instant::now();
+                        msg
+                    }
+                    // socket errored or ended without a close frame
+                    Some(Err(_)) | None => break,
+                };
+                if let Message::Close(frame) = msg {
+                    tracing::debug!(?frame, "client closed connection");
+                    // tungstenite has already queued the echoed close frame; the writer
+                    // flushes it on its way out
+                    break;
+                }
+                if let Message::Text(txt) = msg {
+                    let limit = state.config.max_message_bytes;
+                    if txt.len() > limit {
+                        tracing::warn!(len = txt.len(), limit, "oversized client message");
+                        let err = WsServerMsg::Error {
+                            room_id: ctx.joined_room.clone(),
+                            msg: format!("Message too large (max {limit} bytes)"),
+                            code: None,
+                        };
+                        state.counters.error(None);
+                        out.send(ctx.encode(&err));
+                        out.send(close_message(close_code::SIZE, "Message too large"));
+                        break;
+                    }
+                    let txt_string = txt.to_string();
+                    ctx.last_activity = Instant::now();
+
+                    let now = Instant::now();
+                    let parsed = serde_json::from_str::<WsClientMsg>(&txt_string);
+                    // the same emote over and over is the point; they have a limit of their own
+                    let is_emote = matches!(parsed, Ok(WsClientMsg::Emote { .. }));
+                    if let Some(last) = &ctx.last_msg_text {
+                        if last == &txt_string && !is_emote {
+                            if let Some(ts) = ctx.last_msg_instant {
+                                if now.duration_since(ts).as_millis() < 800 {
+                                    tracing::debug!("skipping duplicate message");
+                                    continue; 
+                                }
+                            }
+                        }
+                    }
+                    ctx.last_msg_text = Some(txt_string.clone());
+                    ctx.last_msg_instant = Some(now);
+
+                    match parsed {
+                        Ok(client_msg) => {
+                            // inside a room, handler logs hang off the room's span
+                            let kind = client_msg.kind();
+                            state.counters.message(kind);
+                            let msg_span = match &ctx.room_span {
+                                Some(room) => {
+                                    tracing::debug_span!(parent: room, "handle_client_msg", msg = kind)
+                                }
+                                None => tracing::debug_span!("handle_client_msg", msg = kind),
+                            };
+                            let room_before = ctx.joined_room.clone();
+                            let handled = handle_client_msg(client_msg, &mut ctx, &state, &mut out)
+                                .instrument(msg_span)
+                                .await;
+                            match handled {
+                                Ok(()) => ctx.rate_limit_strikes = 0,
+                                Err(err) => {
+                                    tracing::debug!(?err, "client message rejected");
+                                    if let WsServerMsg::Error { code, .. } = &err {
+                                        state.counters.error(code.as_ref());
+                                    }
+                                    out.send(ctx.encode(&err));
+                                }
+                            }
+                            if ctx.rate_limit_strikes >= MAX_RATE_LIMIT_STRIKES {
+                                tracing::warn!("closing connection that keeps hitting rate limits");
+                                out.send(close_message(CLOSE_RATE_LIMITED, "Too many requests"));
+                                break;
+                            }
+                            // tag the connection span once it belongs to a room, so logs are grep-able by room id
+                            // (only on entering: every `record` adds another copy to formatted spans)
+                            let span = tracing::Span::current();
+                            if ctx.joined_room != room_before {
+                                if let (Some(room_id), Some(pid)) = (&ctx.joined_room, &ctx.my_player_id) {
+                                    span.record("room_id", room_id.as_str());
+                                    span.record("player_id", pid.as_str());
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            tracing::debug!(error = %e, "invalid client JSON");
+                            let code = malformed_json(&e, &txt_string);
+                            state.counters.invalid_message();
+                            state.counters.error(Some(&code));
+                            let err = WsServerMsg::Error {
+                                room_id: ctx.joined_room.clone(),
+                                msg: format!("Invalid JSON: {}", e),
+                                code: Some(code),
+                            };
+                            out.send(ctx.encode(&err));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(player_id) = &ctx.my_player_id {
+        state.sessions.release(player_id, ctx.session_id);
+    }
+
+    // Clean up if the client was in a room when they disconnected. A deliberate exit goes
+    // through `LeaveRoom`, so anything still here is a drop: hold the seat for a while.
+    if let (Some(room_id), Some(pid)) = (&ctx.joined_room, &ctx.my_player_id) {
+        if state.config.reconnect_grace.is_zero() || state.is_shutting_down() {
+            remove_player_from_room(room_id, pid, &state).await;
+        } else {
+            hold_seat_for_reconnect(room_id, pid, &state).await;
+        }
+    }
+
+    out.finish().await;
+    // Read on until the client answers the close: dropping the socket with its pongs or close
+    // echo still unread resets the connection, and the client can lose our close frame with it.
+    let _ = tokio::time::timeout(CLOSE_LINGER, async {
+        while let Some(Ok(_)) = stream.next().await {}
+    })
+    .await;
+    tracing::info!("websocket connection closed");
+}
+
+/// One connection's message handling without a socket in front of it, for benches: messages
+/// go straight to `handle_client_msg`, and replies to whatever sink it was made with. Room
+/// broadcasts aren't read.
+pub struct Connection {
+    ctx: ConnContext,
+    out: Outbox,
+}
+
+impl Connection {
+    pub fn new<S>(state: &AppState, client: IpAddr, sink: S) -> Self
+    where
+        S: futures_util::Sink<Message, Error: Send> + Send + Unpin + 'static,
+    {
+        let capacity = state.config.send_queue_capacity;
+        Connection {
+            ctx: ConnContext::new(state, client, Subprotocol::V1),
+            out: Outbox::spawn(sink, capacity, state.config.send_stall_timeout),
+        }
+    }
+
+    /// Handles `msg` as if it had just arrived; the refusal, if any, isn't sent.
+    pub async fn handle(&mut self, msg: WsClientMsg, state: &AppState) -> Result<(), WsServerMsg> {
+        handle_client_msg(msg, &mut self.ctx, state, &mut self.out).await
+    }
+
+    /// The room this connection is in, once it has created or joined one.
+    pub fn room_id(&self) -> Option<&RoomId> {
+        self.ctx.joined_room.as_ref()
+    }
+}
+
+/// Handles a single client→server JSON message.
+/// All mutable per-connection state (joined_room, my_player_id, room_rx) is inside `ctx`.
+async fn handle_client_msg(
+    client_msg: WsClientMsg,
+    ctx: &mut ConnContext,
+    state: &AppState,
+    out: &mut Outbox,
+) -> Result<(), WsServerMsg> {
+    tracing::trace!(?client_msg, "client message");
+    match client_msg {
+        WsClientMsg::CreateRoom { player, settings } => {
+            create_room(player, settings.unwrap_or_default(), ctx, state, out).await?;
+            Ok(())
+        }
+
+        WsClientMsg::JoinRoom { room_id, player, seat_token } => {
+            ctx.join_failures
+                .check()
+                .map_err(|wait| ctx.rate_limited("Too many failed joins", wait))?;
+            let joined = join_room(room_id, player, seat_token, false, ctx, state, out).await;
+            if joined.is_err() {
+                ctx.join_failures.take();
+            }
+            joined
+        }
+
+        WsClientMsg::ReclaimSeat { room_id } => {
+            ctx.join_failures
+                .check()
+                .map_err(|wait| ctx.rate_limited("Too many failed joins", wait))?;
+            // only a seat this socket was offered, in the room it was offered in
+            let joined = match ctx.reclaim_offer.take() {
+                Some((offered_in, player)) if offered_in == room_id => {
+                    join_room(room_id, player, None, true, ctx, state, out).await
+                }
+                _ => Err(WsServerMsg::Error {
+                    room_id: Some(room_id),
+                    msg: "No seat was offered in that room".to_string(),
+                    code: None,
+                }),
+            };
+            if joined.is_err() {
+                ctx.join_failures.take();
+            }
+            joined
+        }
+
+        WsClientMsg::SpectateRoom { room_id, player } => {
+            ctx.join_failures
+                .check()
+                .map_err(|wait| ctx.rate_limited("Too many failed joins", wait))?;
+            let joined = spectate_room(room_id, player, ctx, state, out).await;
+            if joined.is_err() {
+                ctx.join_failures.take();
+            }
+            joined
+        }
+
+        WsClientMsg::FinishRound {} => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let Some(mut room_state) = state.lock_room(room_id).await else {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Room not found".to_string(),
+                    code: None,
+                });
+            };
+            let playing =
+                room_state.game_in_progress() && room_state.boards.contains_key(player_id);
+            let Some(started) = room_state.round_started_at.filter(|_| playing) else {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "No game in progress".to_string(),
+                    code: None,
+                });
+            };
+            if room_state.finished.contains_key(player_id) {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "You already finished this round".to_string(),
+                    code: None,
+                });
+            }
+            let elapsed_ms = started.elapsed().as_millis() as u32;
+            let score = room_state.scores.get(player_id).copied().unwrap_or(0);
+            room_state.finished.insert(player_id.clone(), elapsed_ms);
+            tracing::debug!(
+                room_id = %room_id,
+                player_id = %player_id,
+                score,
+                elapsed_ms,
+                "player finished"
+            );
+            room_state.tx.send(WsServerMsg::PlayerFinished {
+                room_id: room_id.clone(),
+                player_id: player_id.clone(),
+                score,
+                elapsed_ms,
+            });
+            room_state.end_if_all_done(room_id);
+            Ok(())
+        }
+
+        WsClientMsg::ReadyUp { ready } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            // an `auto_start` below goes by everyone's flags, wherever they readied up
+            state.sync_room(room_id).await;
+
+            {
+                // Get the room
+                let Some(mut room_state) = state.lock_room(room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+
+                // Get the player
+                let Some(player) = room_state.players.get(player_id) else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "You are not in a room".to_string(),
+                        code: None,
+                    });
+                };
+
+                // The owner isn't part of the ready check; leave their flag alone
+                if *player_id == room_state.owner {
+                    let ack = WsServerMsg::ReadyAck {
+                        room_id: room_id.clone(),
+                        ready: false,
+                        counted: false,
+                    };
+                    out.send(ctx.encode(&ack));
+                    return Ok(());
+                }
+
+                // Update ready status
+                let player_name = player.name.clone();
+                room_state.set_ready(player_id, ready);
+                tracing::debug!(
+                    room_id = %room_id,
+                    player_id = %player_id,
+                    player_name = %player_name,
+                    ready,
+                    "ready state changed"
+                );
+
+                // Broadcast updated player list + owner ID
+                let players: Vec<_> = room_state.player_list();
+                let msg = WsServerMsg::RoomPlayersUpdate {
+                    room_id: room_id.clone(),
+                    players,
+                    owner_id: room_state.owner.clone(),
+                };
+                room_state.tx.send(msg);
+
+                // the last guest readying up starts an `auto_start` room
+                let auto_start = room_state.settings.auto_start
+                    && ready
+                    && room_state.players.len() > 1
+                    && room_state.all_ready()
+                    && room_state.pending_start.is_none()
+                    && !room_state.game_in_progress()
+                    && !state.is_draining();
+                if auto_start {
+                    tracing::debug!(room_id = %room_id, "everyone is ready, auto-starting");
+                    match state.reserve_game() {
+                        Some(slot) => start_new_round(room_id, &mut room_state, state, slot),
+                        None => room_state
+                            .announce(room_id, Notice::StartCalledOff("the server is busy")),
+                    }
+                }
+            }
+
+            let ack = WsServerMsg::ReadyAck {
+                room_id: room_id.clone(),
+                ready,
+                counted: true,
+            };
+            out.send(ctx.encode(&ack));
+            Ok(())
+        }
+
+        WsClientMsg::StartGame { restart } => {
+            // 1) Only the owner may start
+            let (room_id, _) = ctx.require_room_and_player()?;
+            if state.is_draining() {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Server is restarting, try again shortly".to_string(),
+                    code: Some(ErrorCode::Maintenance),
+                });
+            }
+            state.sync_room(room_id).await;
+            if let Some(mut room_state) = state.lock_room(room_id).await {
+                let caller = ctx.my_player_id.as_ref().unwrap();
+                if *caller != room_state.owner {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Only owner can start".to_string(),
+                        code: None,
+                    });
+                }
+                // a second click (or a stale one) mustn't wipe a running game, only `restart`
+                if restart == Some(true) && room_state.game_in_progress() {
+                    let scores = room_state.scores.iter().map(|(pid, &s)| (pid.clone(), s)).collect();
+                    tracing::info!(
+                        parent: &room_state.span,
+                        event = "game_restarted",
+                        "owner restarted the game"
+                    );
+                    room_state.tx.send(WsServerMsg::GameAborted {
+                        room_id: room_id.clone(),
+                        reason: "The owner restarted the game".to_string(),
+                        scores,
+                    });
+                    // everyone was playing a moment ago, so no countdown or ready checks; the
+                    // new game takes the place of the one being cancelled, so not held to the cap
+                    let slot = state.timers.reserve(0).expect("an uncapped slot is always free");
+                    start_game(room_id, &mut room_state, state, slot);
+                    return Ok(());
+                }
+                if let Some(refusal) = start_refusal(room_id, &room_state, state) {
+                    return Err(refusal);
+                }
+                let Some(slot) = state.reserve_game() else {
+                    return Err(server_busy(Some(room_id.clone()), state));
+                };
+
+                start_new_round(room_id, &mut room_state, state, slot);
+                Ok(())
+            } else {
+                Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Room not found".to_string(),
+                    code: None,
+                })
+            }
+        }
+
+        WsClientMsg::StartSolo { player } => {
+            // alone in the room → no ready checks needed; the slot is taken before the room is
+            // made, so a busy server isn't left holding an empty one, and given back if that fails
+            let Some(slot) = state.reserve_game() else {
+                return Err(server_busy(None, state));
+            };
+            let room_id =
+                create_room(player, RoomSettings::default(), ctx, state, out).await?;
+            let Some(mut room_state) = state.lock_room(&room_id).await else {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id),
+                    msg: "Room not found".to_string(),
+                    code: None,
+                });
+            };
+            start_new_round(&room_id, &mut room_state, state, slot);
+            Ok(())
+        }
+
+        WsClientMsg::ScoreUpdate { cleared_count, turn, cleared_values, rect } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            // the game may have been dealt, and the others' scores added, on another instance
+            state.sync_room(room_id).await;
+            let scored = {
+                let Some(mut room) = state.lock_room(room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+                // reborrowed so the board and the settings can be borrowed side by side
+                let room_state = &mut *room;
+                if !room_state.players.contains_key(player_id) {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Not in room".to_string(),
+                        code: None,
+                    });
+                }
+                // nothing to score in the lobby or the start countdown, and nothing to broadcast
+                if !room_state.game_in_progress() || !room_state.boards.contains_key(player_id) {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "No game in progress".to_string(),
+                        code: Some(ErrorCode::GameNotRunning),
+                    });
+                }
+                if room_state.finished.contains_key(player_id) {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "You already finished this round".to_string(),
+                        code: None,
+                    });
+                }
+                // turns only go up, so a resent or reordered update can't be counted twice
+                let last_turn = room_state.turns.get(player_id).copied().unwrap_or(0);
+                if turn <= last_turn {
+                    tracing::debug!(
+                        room_id = %room_id,
+                        player_id = %player_id,
+                        turn,
+                        last_turn,
+                        "ignoring replayed score update"
+                    );
+                    Scored::Acked(WsServerMsg::ScoreAck {
+                        room_id: room_id.clone(),
+                        turn,
+                        total: room_state.scores.get(player_id).copied().unwrap_or(0),
+                        applied: false,
+                    })
+                } else if turn > last_turn + 1 {
+                    tracing::debug!(
+                        room_id = %room_id,
+                        player_id = %player_id,
+                        turn,
+                        last_turn,
+                        "refusing score update that skips a turn"
+                    );
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: format!("Turn {turn} came before turn {}", last_turn + 1),
+                        code: Some(ErrorCode::TurnOutOfOrder {
+                            expected: last_turn + 1,
+                        }),
+                    });
+                } else {
+                    let delta = match &rect {
+                        Some(rect) => {
+                            let board = room_state.boards.get_mut(player_id).ok_or_else(|| {
+                                WsServerMsg::Error {
+                                    room_id: Some(room_id.clone()),
+                                    msg: "No game in progress".to_string(),
+                                    code: None,
+                                }
+                            })?;
+                            let cleared = clear_on_board(board, rect, cleared_count, &room_state.settings);
+                            if cleared.is_ok() {
+                                room_state.tx.send(WsServerMsg::BoardCleared {
+                                    room_id: room_id.clone(),
+                                    player_id: player_id.clone(),
+                                    rect: rect.clone(),
+                                });
+                            }
+                            if cleared.is_ok()
+                                && board::find_clear(board, room_state.settings.target_sum).is_none()
+                                && room_state.stuck.insert(player_id.clone())
+                            {
+                                tracing::debug!(room_id = %room_id, player_id = %player_id, "no moves left");
+                                room_state.tx.send(WsServerMsg::NoMovesLeft {
+                                    room_id: room_id.clone(),
+                                    player_id: player_id.clone(),
+                                });
+                            }
+                            cleared
+                        }
+                        None => score_for_clear(cleared_count, &cleared_values, &room_state.settings),
+                    }
+                    .map_err(|msg| WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg,
+                        code: None,
+                    })?;
+
+                    if state.bus.shared().is_some() {
+                        room_state.turns.insert(player_id.clone(), turn);
+                        room_state.last_score_at.insert(player_id.clone(), Instant::now());
+                        // counted where every instance counts: see `add_shared_score`
+                        Scored::Shared {
+                            round: room_state.round,
+                            delta,
+                        }
+                    } else {
+                        // 1) Update this player’s score in the room: by the room's scoring mode
+                        let entry = room_state.scores.entry(player_id.clone()).or_insert(0);
+                        *entry += delta;
+                        let total = *entry;
+
+                        room_state.turns.insert(player_id.clone(), turn);
+                        room_state.last_score_at.insert(player_id.clone(), Instant::now());
+                        // 2) Debug print: who scored how much
+                        if let Some(player) = room_state.players.get(player_id) {
+                            tracing::debug!(
+                                room_id = %room_id,
+                                player_id = %player_id,
+                                player_name = %player.name,
+                                turn,
+                                delta,
+                                total,
+                                "score update"
+                            );
+                        }
+
+                        // 3) Broadcast updated leaderboard to all clients in room
+                        let lb_msg = room_state.leaderboard_update(room_id);
+                        room_state.tx.send(lb_msg);
+                        room_state.end_if_all_done(room_id);
+                        Scored::Acked(WsServerMsg::ScoreAck {
+                            room_id: room_id.clone(),
+                            turn,
+                            total,
+                            applied: true,
+                        })
+                    }
+                }
+            };
+            let ack = match scored {
+                Scored::Acked(ack) => ack,
+                Scored::Shared { round, delta } => {
+                    add_shared_score(room_id, player_id, round, turn, delta, state).await?
+                }
+            };
+            out.send(ctx.encode(&ack));
+            Ok(())
+        }
+
+        WsClientMsg::RequestHint {} => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let hint = {
+                let Some(mut room_state) = state.lock_room(room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+                // boards stay around after GameOver, so the game itself has to be checked
+                let board = match room_state.boards.get(player_id) {
+                    Some(board) if room_state.game_in_progress() => board,
+                    _ => {
+                        return Err(WsServerMsg::Error {
+                            room_id: Some(room_id.clone()),
+                            msg: "No game in progress".to_string(),
+                            code: Some(ErrorCode::GameNotRunning),
+                        });
+                    }
+                };
+                if room_state.finished.contains_key(player_id) {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "You already finished this round".to_string(),
+                        code: None,
+                    });
+                }
+                let now = Instant::now();
+                if let Some(last) = room_state.last_hint.get(player_id) {
+                    let wait = state.config.hint_cooldown.saturating_sub(now.duration_since(*last));
+                    if !wait.is_zero() {
+                        return Err(WsServerMsg::Error {
+                            room_id: Some(room_id.clone()),
+                            msg: format!("Next hint in {}s", wait.as_secs() + 1),
+                            code: None,
+                        });
+                    }
+                }
+                let rect = board::find_clear(board, room_state.settings.target_sum);
+                room_state.last_hint.insert(player_id.clone(), now);
+                tracing::debug!(room_id = %room_id, player_id = %player_id, ?rect, "hint requested");
+
+                // only charge for hints that actually point at a move
+                let penalty = room_state.settings.hint_penalty;
+                if rect.is_some() && penalty > 0 {
+                    let score = room_state.scores.entry(player_id.clone()).or_insert(0);
+                    *score = score.saturating_sub(penalty);
+                    let lb_msg = room_state.leaderboard_update(room_id);
+                    room_state.tx.send(lb_msg);
+                }
+                WsServerMsg::Hint {
+                    room_id: room_id.clone(),
+                    rect,
+                }
+            };
+            out.send(ctx.encode(&hint));
+            Ok(())
+        }
+
+        WsClientMsg::ChatMessage { message, name, channel } => {
+            if ctx.joined_room.is_none() {
+                return lobby_chat(message, name, ctx, state);
+            }
+            let follows_spectators = ctx.follows_spectator_chat;
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let (room_id, player_id) = (&room_id.clone(), &player_id.clone());
+
+            // 2) Broadcast the chat to everyone in the room
+            if let Some(mut room_state) = state.lock_room(room_id).await {
+                let sender = match room_state.players.get(player_id) {
+                    Some(player) => Some((player.clone(), false)),
+                    None => room_state.spectators.get(player_id).map(|p| (p.clone(), true)),
+                };
+                if let Some((player, is_spectator)) = sender {
+                    let channel = channel.unwrap_or(if is_spectator {
+                        ChatChannel::Spectators
+                    } else {
+                        ChatChannel::All
+                    });
+                    let refusal = match channel {
+                        ChatChannel::All if is_spectator && !room_state.settings.spectator_chat => {
+                            Some("Spectators can't chat with the players in this room")
+                        }
+                        ChatChannel::Players if is_spectator => {
+                            Some("Only players can post to the players' chat")
+                        }
+                        ChatChannel::Spectators if !is_spectator && !follows_spectators => {
+                            Some("Follow the spectators' chat to post in it")
+                        }
+                        _ => None,
+                    };
+                    if let Some(msg) = refusal {
+                        return Err(WsServerMsg::Error {
+                            room_id: Some(room_id.clone()),
+                            msg: msg.to_string(),
+                            code: None,
+                        });
+                    }
+                    if let Some(left) = room_state.chat_mute_left(player_id) {
+                        return Err(chat_muted(Some(room_id), left));
+                    }
+                    let message = chat::sanitize(&message, state.config.chat_max_len).map_err(
+                        |msg| WsServerMsg::Error {
+                            room_id: Some(room_id.clone()),
+                            msg,
+                            code: None,
+                        },
+                    )?;
+                    // slow mode is the owner's rule, so it comes first and never counts as spam
+                    let now = Instant::now();
+                    let slow_mode = Duration::from_secs(room_state.settings.slow_mode_secs.into());
+                    if let Some(last) = room_state.last_chat_at.get(player_id) {
+                        let wait = slow_mode.saturating_sub(now.duration_since(*last));
+                        if !wait.is_zero() && *player_id != room_state.owner {
+                            let notice = Notice::SlowModeWait(wait.as_secs_f64().ceil() as u64);
+                            let reply = WsServerMsg::SystemMessage {
+                                room_id: room_id.clone(),
+                                kind: notice.kind(),
+                                text: notice.text(),
+                                at_ms: now_ms(),
+                            };
+                            out.send(ctx.encode(&reply));
+                            return Ok(());
+                        }
+                    }
+                    if let Some(limits) = state.config.chat_limits() {
+                        let limiter = room_state
+                            .chat_limits
+                            .entry(player_id.clone())
+                            .or_insert_with(|| ChatLimiter::new(limits));
+                        match limiter.check(now) {
+                            Ok(()) => {}
+                            Err(ChatRefusal::TooFast(wait)) => {
+                                return Err(ctx.rate_limited("Chatting too fast", wait));
+                            }
+                            Err(ChatRefusal::Mute) => {
+                                let duration = state.config.chat_mute;
+                                let why = "for spamming the chat";
+                                let name = &player.name;
+                                room_state.mute_chat(room_id, player_id, name, Some(duration), why);
+                                return Err(chat_muted(Some(room_id), Some(duration)));
+                            }
+                        }
+                    }
+                    // a filtered message still goes out, masked; the third in a game also mutes
+                    let (message, mute) = match state.deny_list.mask(&message) {
+                        Some(masked) => {
+                            let count = room_state.masked_chats.entry(player_id.clone()).or_insert(0);
+                            *count += 1;
+                            (masked, *count >= MASKED_MESSAGES_BEFORE_MUTE)
+                        }
+                        None => (message, false),
+                    };
+                    tracing::debug!(
+                        room_id = %room_id,
+                        player_id = %player_id,
+                        player_name = %player.name,
+                        is_spectator,
+                        ?channel,
+                        len = message.len(),
+                        "chat message"
+                    );
+                    tracing::trace!(room_id = %room_id, %message, "chat message contents");
+                    let name = player.name.clone();
+                    room_state.chat(room_id, player, message, is_spectator, channel);
+                    room_state.last_chat_at.insert(player_id.clone(), now);
+                    if mute {
+                        room_state.masked_chats.remove(player_id);
+                        let duration = state.config.chat_mute;
+                        let why = "for bad language";
+                        room_state.mute_chat(room_id, player_id, &name, Some(duration), why);
+                    }
+                } else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "You are not a player in this room".to_string(),
+                        code: None,
+                    });
+                }
+                Ok(())
+            } else {
+                Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Room not found".to_string(),
+                    code: None,
+                })
+            }
+        }
+
+        WsClientMsg::GetChatHistory { before_id, limit } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let (room_id, player_id) = (room_id.clone(), player_id.clone());
+            if let Err(wait) = ctx.chat_history_pages.try_take() {
+                return Err(ctx.rate_limited("Fetching chat history too fast", wait));
+            }
+            let limit = limit.clamp(1, CHAT_HISTORY_PAGE_MAX) as usize;
+            let (messages, has_more) = {
+                let Some(mut room_state) = state.lock_room(&room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+                let spectating = room_state.spectators.contains_key(&player_id);
+                let follows = ctx.follows_spectator_chat;
+                room_state.log.chat_page(before_id, limit, spectating, follows)
+            };
+            let page = WsServerMsg::ChatHistory {
+                room_id,
+                messages,
+                has_more,
+            };
+            out.send(ctx.encode(&page));
+            Ok(())
+        }
+
+        WsClientMsg::FollowSpectatorChat { follow } => {
+            ctx.require_room_and_player()?;
+            ctx.follows_spectator_chat = follow;
+            Ok(())
+        }
+
+        WsClientMsg::Emote { emote } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let (room_id, player_id) = (room_id.clone(), player_id.clone());
+            // a held key sends a stream of these: extras are dropped, rather than answered
+            // with an error (and a rate-limit strike) each
+            if ctx.emotes.try_take().is_err() {
+                tracing::trace!(room_id = %room_id, "dropping emote over the limit");
+                return Ok(());
+            }
+            let Some(mut room_state) = state.lock_room(&room_id).await else {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Room not found".to_string(),
+                    code: None,
+                });
+            };
+            if let Some(left) = room_state.chat_mute_left(&player_id) {
+                return Err(chat_muted(Some(&room_id), left));
+            }
+            let is_spectator = if room_state.players.contains_key(&player_id) {
+                false
+            } else if room_state.spectators.contains_key(&player_id) {
+                true
+            } else {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "You are not a player in this room".to_string(),
+                    code: None,
+                });
+            };
+            if is_spectator && !room_state.settings.spectator_chat {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Spectators can't send emotes in this room".to_string(),
+                    code: None,
+                });
+            }
+            room_state.tx.send(WsServerMsg::EmoteBroadcast {
+                room_id,
+                player_id,
+                emote,
+            });
+            Ok(())
+        }
+
+        WsClientMsg::MutePlayer { player_id: target, duration_secs } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let error = |msg: &str| WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: msg.to_string(),
+                code: None,
+            };
+            if duration_secs == Some(0) {
+                return Err(error("A mute must last at least a second"));
+            }
+            let Some(mut room_state) = state.lock_room(room_id).await else {
+                return Err(error("Room not found"));
+            };
+            if *player_id != room_state.owner {
+                return Err(error("Only the room owner can mute players"));
+            }
+            if target == room_state.owner {
+                return Err(error("The room owner can't be muted"));
+            }
+            let Some(name) = room_state.member_name(&target) else {
+                return Err(error("No such player in this room"));
+            };
+            let duration = duration_secs.map(Duration::from_secs);
+            room_state.mute_chat(room_id, &target, &name, duration, "by the room owner");
+            state.audit.record(
+                format!("player:{player_id}"),
+                "player_muted",
+                Some(target.to_string()),
+                Some(room_id.clone()),
+            );
+            Ok(())
+        }
+
+        WsClientMsg::UnmutePlayer { player_id: target } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let error = |msg: String| WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg,
+                code: None,
+            };
+            let Some(mut room_state) = state.lock_room(room_id).await else {
+                return Err(error("Room not found".to_string()));
+            };
+            if *player_id != room_state.owner {
+                return Err(error("Only the room owner can unmute players".to_string()));
+            }
+            let Some(name) = room_state.member_name(&target) else {
+                return Err(error("No such player in this room".to_string()));
+            };
+            if !room_state.unmute_chat(room_id, &target, &name) {
+                return Err(error(format!("{name} isn't muted")));
+            }
+            state.audit.record(
+                format!("player:{player_id}"),
+                "player_unmuted",
+                Some(target.to_string()),
+                Some(room_id.clone()),
+            );
+            Ok(())
+        }
+
+        WsClientMsg::SetSlowMode { secs } => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let error = |msg: String| WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg,
+                code: None,
+            };
+            if secs > MAX_SLOW_MODE_SECS {
+                return Err(error(format!("Slow mode can be at most {MAX_SLOW_MODE_SECS}s")));
+            }
+            let Some(mut room_state) = state.lock_room(room_id).await else {
+                return Err(error("Room not found".to_string()));
+            };
+            if *player_id != room_state.owner {
+                return Err(error("Only the room owner can set slow mode".to_string()));
+            }
+            if room_state.settings.slow_mode_secs != secs {
+                room_state.settings.slow_mode_secs = secs;
+                tracing::info!(room_id = %room_id, secs, "slow mode changed");
+                room_state.announce(room_id, Notice::SlowMode(secs));
+            }
+            Ok(())
+        }
+
+        WsClientMsg::LeaveRoom {} => {
+            let (room_id, player_id) = ctx.require_room_and_player()?;
+            let (room_id, player_id) = (room_id.clone(), player_id.clone());
+            remove_player_from_room(&room_id, &player_id, state).await;
+            ctx.return_to_lobby(state);
+            tracing::info!(room_id = %room_id, player_id = %player_id, "player left room on purpose");
+            let left = WsServerMsg::LeftRoom { room_id };
+            out.send(ctx.encode(&left));
+            Ok(())
+        }
+
+        WsClientMsg::GetReadyStatus {} => {
+            let (room_id, _) = ctx.require_room_and_player()?;
+            state.sync_room(room_id).await;
+            let status = {
+                let Some(room_state) = state.lock_room(room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+                let (ready, not_ready) = room_state.readiness();
+                WsServerMsg::ReadyStatus {
+                    room_id: room_id.clone(),
+                    ready,
+                    not_ready,
+                    can_start: start_refusal(room_id, &room_state, state).is_none(),
+                }
+            };
+            out.send(ctx.encode(&status));
+            Ok(())
+        }
+
+        WsClientMsg::GetPresence {} => {
+            out.send(ctx.encode(&state.presence()));
+            Ok(())
+        }
+
+        WsClientMsg::GetPlayerScore { player_id: target } => {
+            let (room_id, _) = ctx.require_room_and_player()?;
+            let reply = {
+                let Some(room_state) = state.lock_room(room_id).await else {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room not found".to_string(),
+                        code: None,
+                    });
+                };
+                if !room_state.players.contains_key(&target) {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "No such player in this room".to_string(),
+                        code: None,
+                    });
+                }
+                WsServerMsg::PlayerScore {
+                    room_id: room_id.clone(),
+                    score: room_state.scores.get(&target).copied().unwrap_or(0),
+                    turns: room_state.turns.get(&target).copied().unwrap_or(0),
+                    player_id: target,
+                }
+            };
+            out.send(ctx.encode(&reply));
+            Ok(())
+        }
+    }
+}
+
+/// Where a `ScoreUpdate` stands once the room lets go of it.
+enum Scored {
+    /// Answered: counted here, or refused as a replay.
+    Acked(WsServerMsg),
+    /// Checked against the player's board here; `delta` is yet to be counted in the room's
+    /// shared record, for game `round`.
+    Shared { round: u64, delta: u32 },
+}
+
+/// Counts a checked `ScoreUpdate` in the room's shared record, where the turn is checked again
+/// in the same step, so a player scoring through two instances can't have one turn counted by
+/// both. Then broadcasts the leaderboard with everyone's total from there.
+async fn add_shared_score(
+    room_id: &RoomId,
+    player_id: &PlayerId,
+    round: u64,
+    turn: u32,
+    delta: u32,
+    state: &AppState,
+) -> Result<WsServerMsg, WsServerMsg> {
+    let totals = match state.bus.shared() {
+        Some(shared) => shared.add_score(room_id, player_id, round, turn, delta).await,
+        None => None,
+    };
+    let Some(mut room_state) = state.lock_room(room_id).await else {
+        return Err(WsServerMsg::Error {
+            room_id: Some(room_id.clone()),
+            msg: "Room not found".to_string(),
+            code: None,
+        });
+    };
+    let Some(totals) = totals else {
+        // counted already through another instance, or the game ended meanwhile
+        tracing::debug!(
+            room_id = %room_id,
+            player_id = %player_id,
+            turn,
+            round,
+            "shared record refused score update"
+        );
+        return Ok(WsServerMsg::ScoreAck {
+            room_id: room_id.clone(),
+            turn,
+            total: room_state.scores.get(player_id).copied().unwrap_or(0),
+            applied: false,
+        });
+    };
+    let total = totals.get(player_id).copied().unwrap_or(0);
+    room_state.scores = totals;
+    tracing::debug!(
+        room_id = %room_id,
+        player_id = %player_id,
+        turn,
+        delta,
+        total,
+        "score update"
+    );
+    let lb_msg = room_state.leaderboard_update(room_id);
+    room_state.tx.send(lb_msg);
+    room_state.end_if_all_done(room_id);
+    Ok(WsServerMsg::ScoreAck {
+        room_id: room_id.clone(),
+        turn,
+        total,
+        applied: true,
+    })
+}
+
+/// How far `join_room` got under the room's lock.
+enum Joining {
+    /// In: the `seq` the replies are numbered from, the replies, and the seat's token.
+    Seated((u64, Vec<WsServerMsg>, String)),
+    /// A new player, to be seated once the shared record has a seat for them.
+    Claim { max_players: u32 },
+}
+
+/// Seats a new `player` in the room `join_room` has checked they may join.
+fn seat_new_player(
+    room_state: &mut RoomGuard,
+    room_id: &RoomId,
+    player: &Player,
+    ctx: &mut ConnContext,
+    state: &AppState,
+) -> (u64, Vec<WsServerMsg>, String) {
+    let player_id = player.player_id.clone();
+    // 2) Insert into room’s player list and reset their score
+    let seq = room_state.tx.seq();
+    let history = room_state.log.snapshot(false, false);
+    room_state.add_player(player.clone());
+    state.sessions.seat(&player_id, room_id);
+    room_state.scores.insert(player_id.clone(), 0);
+    let seat_token = room_state.issue_seat_token(&player_id);
+
+    tracing::trace!(room_id = %room_id, ?room_state, "room state after join");
+
+    // 3) Subscribe to that room’s broadcast channel
+    let (rx, span) = (room_state.tx.subscribe(), room_state.span.clone());
+
+    // 4) Broadcast updated player list
+    let players: Vec<_> = room_state.player_list();
+    let owner_id = room_state.owner.clone();
+    let msg = WsServerMsg::RoomPlayersUpdate {
+        room_id: room_id.clone(),
+        players: players.clone(),
+        owner_id: room_state.owner.clone(),
+    };
+    room_state.tx.send(msg);
+    room_state.announce(room_id, Notice::Joined(&player.name));
+
+    tracing::info!(
+        parent: &span,
+        event = "player_joined",
+        player_id = %player_id,
+        player_name = %player.name,
+        players = players.len(),
+        "player joined room"
+    );
+
+    // 5) Update context
+    ctx.enter_room(state, room_id, &player_id, false, rx, span);
+
+    // 6) Acknowledge to the joining client, then catch them up on the room log
+    let joined_msg = WsServerMsg::RoomPlayersUpdate {
+        room_id: room_id.clone(),
+        players,
+        owner_id,
+    };
+    let mut replies = vec![joined_msg];
+    if !history.is_empty() {
+        replies.push(WsServerMsg::RoomHistory {
+            room_id: room_id.clone(),
+            entries: history,
+        });
+    }
+    (seq, replies, seat_token)
+}
+
+/// Seats `player` in an existing room, or hands a seated player's seat to this socket: after a
+/// drop, or over a connection that's still open (which is then closed). Handing a seat over
+/// takes the `seat_token` it was last issued. Replies with the player list and room history
+/// (plus `Resumed` for a returning player), then `SeatGranted` with the seat's new token.
+///
+/// With `reclaim` (`ReclaimSeat`) only a seat held after a drop, under the same name, is taken.
+/// A new player ID with a held seat's name gets a `ReclaimOffer` rather than a seat.
+async fn join_room(
+    room_id: RoomId,
+    player: Player,
+    seat_token: Option<String>,
+    reclaim: bool,
+    ctx: &mut ConnContext,
+    state: &AppState,
+    out: &mut Outbox,
+) -> Result<(), WsServerMsg> {
+    let player = check_name(player, Some(&room_id), state)?;
+    // a seated socket has to leave first, or its old seat would stay behind with nobody on it
+    if ctx.joined_room.is_some() {
+        return Err(WsServerMsg::Error {
+            room_id: ctx.joined_room.clone(),
+            msg: "Already in a room".to_string(),
+            code: None,
+        });
+    }
+    let player_id = player.player_id.clone();
+    state.sync_room(&room_id).await;
+    let joining = {
+        let Some(mut room_state) = state.lock_room(&room_id).await else {
+            return Err(WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "Room not found".to_string(),
+                code: None,
+            });
+        };
+        let seated = room_state.players.contains_key(&player_id);
+        // every player list carries the IDs, so knowing one proves nothing: taking a seat over
+        // takes its token (a reclaim has its own checks, below)
+        if seated && !reclaim && !room_state.seat_token_matches(&player_id, seat_token.as_deref())
+        {
+            tracing::info!(
+                parent: &room_state.span,
+                player_id = %player_id,
+                "refusing to hand a seat over without its token"
+            );
+            return Err(WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "That player ID already has a seat here".to_string(),
+                code: Some(ErrorCode::SeatTaken),
+            });
+        }
+        if reclaim {
+            let held = state.config.reclaim_by_name
+                && room_state.disconnected.contains_key(&player_id)
+                && room_state.players.get(&player_id).is_some_and(|p| p.name == player.name);
+            if !held {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "That seat is no longer held".to_string(),
+                    code: None,
+                });
+            }
+        }
+        let rejoining = room_state.disconnected.remove(&player_id).is_some();
+        if seated {
+            // a dropped player coming back to their held seat, or a new socket (a reloaded tab,
+            // say) taking the seat over from one that hasn't been reaped yet; `enter_room`
+            // closes that one
+            if rejoining {
+                let name = room_state
+                    .players
+                    .get(&player_id)
+                    .map_or(player.name.clone(), |p| p.name.clone());
+                room_state.announce(&room_id, Notice::Reconnected(&name));
+            } else if let Some(seated) = room_state.players.get_mut(&player_id) {
+                if seated.name != player.name {
+                    seated.name = player.name.clone();
+                    let renamed = seated.clone();
+                    room_state.tx.share(RoomChange::Player(renamed));
+                    let update = WsServerMsg::RoomPlayersUpdate {
+                        room_id: room_id.clone(),
+                        players: room_state.player_list(),
+                        owner_id: room_state.owner.clone(),
+                    };
+                    room_state.tx.send(update);
+                }
+            }
+            let (rx, span) = (room_state.tx.subscribe(), room_state.span.clone());
+            let seq = room_state.tx.seq();
+            let history = room_state.log.snapshot(false, false);
+            let resumed = WsServerMsg::Resumed {
+                room_id: room_id.clone(),
+                board: room_state
+                    .game_in_progress()
+                    .then(|| room_state.boards.get(&player_id).cloned())
+                    .flatten(),
+                scores: room_state
+                    .scores
+                    .iter()
+                    .map(|(pid, &s)| (pid.clone(), s))
+                    .collect(),
+            };
+            let players = WsServerMsg::RoomPlayersUpdate {
+                room_id: room_id.clone(),
+                players: room_state.player_list(),
+                owner_id: room_state.owner.clone(),
+            };
+
+            if rejoining {
+                tracing::info!(
+                    parent: &span,
+                    event = "player_reconnected",
+                    player_id = %player_id,
+                    "player reconnected"
+                );
+            } else {
+                tracing::info!(
+                    parent: &span,
+                    event = "player_taken_over",
+                    player_id = %player_id,
+                    "new connection took over player's seat"
+                );
+            }
+            ctx.enter_room(state, &room_id, &player_id, false, rx, span);
+            let seat_token = room_state.issue_seat_token(&player_id);
+
+            let history_msg = WsServerMsg::RoomHistory {
+                room_id: room_id.clone(),
+                entries: history,
+            };
+            Joining::Seated((seq, vec![players, history_msg, resumed], seat_token))
+        } else {
+            let held_seat = state
+                .config
+                .reclaim_by_name
+                .then(|| room_state.held_seat_named(&player.name))
+                .flatten();
+            if let Some(held) = held_seat {
+                tracing::info!(
+                    parent: &room_state.span,
+                    held_player_id = %held,
+                    player_id = %player_id,
+                    "offering a held seat to a join under the same name"
+                );
+                let offer = WsServerMsg::ReclaimOffer {
+                    room_id: room_id.clone(),
+                    name: player.name.clone(),
+                };
+                out.send(ctx.encode(&offer));
+                let seated = Player {
+                    player_id: held,
+                    ..player
+                };
+                ctx.reclaim_offer = Some((room_id, seated));
+                return Ok(());
+            }
+            if room_state.spectators.contains_key(&player_id) {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "Already in room".to_string(),
+                    code: None,
+                });
+            }
+            // The room may have filled up or started since the client last looked; this is
+            // checked under the same lock as the insert, so two joins can't both take the last seat
+            let max_players = room_state.settings.max_players;
+            let full = || WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "Room is full".to_string(),
+                code: Some(ErrorCode::RoomFull { max_players }),
+            };
+            if max_players > 0 && room_state.players.len() >= max_players as usize {
+                return Err(full());
+            }
+            if room_state.game_in_progress() || room_state.pending_start.is_some() {
+                return Err(WsServerMsg::Error {
+                    room_id: Some(room_id.clone()),
+                    msg: "A game is under way, spectate or join after it".to_string(),
+                    code: Some(ErrorCode::GameInProgress),
+                });
+            }
+            // other instances seat players too, so the last seat is only ours once the shared
+            // record says so; the room can't be held meanwhile
+            if state.bus.shared().is_some() {
+                Joining::Claim { max_players }
+            } else {
+                Joining::Seated(seat_new_player(&mut room_state, &room_id, &player, ctx, state))
+            }
+        }
+    };
+    let (seq, replies, seat_token) = match joining {
+        Joining::Seated(seated) => seated,
+        Joining::Claim { max_players } => {
+            let shared = state.bus.shared().expect("only claimed with shared rooms");
+            let gone = || WsServerMsg::Error {
+                room_id: Some(room_id.clone()),
+                msg: "Room not found".to_string(),
+                code: None,
+            };
+            match shared.take_seat(&room_id, &player, max_players).await {
+                SeatClaim::Seated => {}
+                SeatClaim::Full => {
+                    return Err(WsServerMsg::Error {
+                        room_id: Some(room_id.clone()),
+                        msg: "Room is full".to_string(),
+                        code: Some(ErrorCode::RoomFull { max_players }),
+                    })
+                }
+                SeatClaim::Gone => return Err(gone()),
+            }
+            let Some(mut room_state) = state.lock_room(&room_id).await else {
+                shared.write(&room_id, RoomChange::Unseat(player_id.clone()));
+                return Err(gone());
+            };
+            seat_new_player(&mut room_state, &room_id, &player, ctx, state)
+        }
+    };
+    for msg in replies {
+        out.send(ctx.room_snapshot(seq, msg));
+    }
+    let granted = WsServerMsg::SeatGranted {
+        room_id,
+        player_id,
+        seat_token,
+    };
+    out.send(ctx.encode(&granted));
+    Ok(())
+}
+
+/// Lets `player` watch a room without a seat, and catches them up on the players and history.
+async fn spectate_room(
+    room_id: RoomId,
+    player: Player,
+    ctx: &mut ConnContext,
+    state: &AppState,
+    out: &mut Outbox,
+) -> Result<(), WsServerMsg> {
+    let player = check_name(player, Some(&room_id), state)?;
+    if ctx.joined_room.is_some() {
+        return Err(WsServerMsg::Error {
+            room_id: ctx.joined_room.clone(),
+            msg: "Already in a room".to_string(),
+            code: None,
+        });
+    }
+    state.sync_room(&room_id).await;
+    let (seq, replies) = {
+        let Some(mut room_state) = state.lock_room(&room_id).await else {
+            return Err(WsServerMsg::Error {
+                room_id: Some(room_id),
+                msg: "Room not found".to_string(),
+                code: None,
+            });
+        };
+        let player_id = player.player_id.clone();
+        if room_state.players.contains_key(&player_id)
+            || room_state.spectators.contains_key(&player_id)
+        {
+            return Err(WsServerMsg::Error {
+                room_id: Some(room_id),
+                msg: "Already in room".to_string(),
+                code: None,
+            });
+        }
+        let seq = room_state.tx.seq();
+        let history = room_state.log.snapshot(true, false);
+        room_state.spectators.insert(player_id.clone(), player.clone());
+        let (rx, span) = (room_state.tx.subscribe(), room_state.span.clone());
+        let players_msg = WsServerMsg::RoomPlayersUpdate {
+            room_id: room_id.clone(),
+            players: room_state.player_list(),
+            owner_id: room_state.owner.clone(),
+        };
+
+        tracing::info!(
+            parent: &span,
+            event = "spectator_joined",
+            player_id = %player_id,
+            player_name = %player.name,
+            "spectator joined room"
+        );
+        ctx.enter_room(state, &room_id, &player_id, true, rx, span);
+
+        let history_msg = WsServerMsg::RoomHistory {
+            room_id: room_id.clone(),
+            entries: history,
+        };
+        (seq, [players_msg, history_msg])
+    };
+    for msg in replies {
+        out.send(ctx.room_snapshot(seq, msg));
+    }
+    Ok(())
+}
+
+/// Creates a room owned by `player`, moves this connection into it and sends back
+/// `RoomCreated` plus the initial player list.
+/// `history_len` can only lower the server's `chat_history_len`.
+async fn create_room(
+    player: Player,
+    settings: RoomSettings,
+    ctx: &mut ConnContext,
+    state: &AppState,
+    out: &mut Outbox,
+) -> Result<RoomId, WsServerMsg> {
+    let mut player = check_name(player, None, state)?;
+    if state.is_draining() {
+        return Err(WsServerMsg::Error {
+            room_id: None,
+            msg: "Server is restarting, try again shortly".to_string(),
+            code: Some(ErrorCode::Maintenance),
+        });
+    }
+    if ctx.joined_room.is_some() {
+        return Err(WsServerMsg::Error {
+            room_id: ctx.joined_room.clone(),
+            msg: "Already in a room".to_string(),
+            code: None,
+        });
+    }
+
+    settings.validate().map_err(|errors| WsServerMsg::Error {
+        room_id: None,
+        msg: errors
+            .iter()
+            .map(|e| e.msg.as_str())
+            .collect::<Vec<_>>()
+            .join("; "),
+        code: Some(ErrorCode::InvalidSettings { errors }),
+    })?;
+    let max_rooms = state.config.max_rooms;
+    if max_rooms > 0 && state.rooms.len() >= max_rooms {
+        tracing::warn!(max_rooms, "room limit reached, refusing to create a room");
+        return Err(WsServerMsg::Error {
+            room_id: None,
+            msg: "Server is at capacity, try joining an existing room".to_string(),
+            code: Some(ErrorCode::ServerFull {
+                max_rooms: max_rooms as u32,
+            }),
+        });
+    }
+    // only well-formed requests cost a token, so a typo doesn't lock the player out
+    ctx.create_room_bucket
+        .try_take()
+        .map_err(|wait| ctx.rate_limited("Creating rooms too fast", wait))?;
+    if !state.ip_limits.try_create_room(ctx.client) {
+        tracing::warn!(client = %ctx.client, "room creation rate limit hit");
+        return Err(WsServerMsg::Error {
+            room_id: None,
+            msg: "Too many rooms created, try again in a minute".to_string(),
+            code: None,
+        });
+    }
+
+    let already_seated = || WsServerMsg::Error {
+        room_id: None,
+        msg: "Player ID already present in a room".to_string(),
+        code: None,
+    };
+    // checked for real when the room goes in, but no point finding it an ID first
+    if state.sessions.is_seated(&player.player_id) {
+        return Err(already_seated());
+    }
+
+    // 2) Create a fresh RoomState under an unused 4 digit id and insert it into global AppState.
+    //    Owners start the game instead of readying up, so their flag stays off.
+    player.ready = false;
+    let mut created = None;
+    for _ in 0..ROOM_ID_ATTEMPTS {
+        let room_id = format!("{:04}", rand::random::<u16>() % 10000);
+        // other instances sharing the bus may have the id; asked before the entry below, whose
+        // shard lock can't be held across the round trip
+        if state.rooms.contains_key(&room_id) || !state.bus.reserve(&room_id).await {
+            continue;
+        }
+        // the vacant entry keeps its shard locked, so nobody else can claim the id meanwhile
+        let Entry::Vacant(slot) = state.rooms.entry(room_id.clone()) else {
+            // taken here since the lookup: the reservation is ours to give back, or the id
+            // stays unusable on every instance until it expires
+            state.bus.release(&room_id);
+            continue;
+        };
+        // under the entry's lock, so the seat and the room it's in appear together
+        if !state.sessions.seat_first(&player.player_id, &room_id) {
+            state.bus.release(&room_id);
+            return Err(already_seated());
+        }
+        let mut room_state = state.new_room(&room_id, player.clone(), settings);
+        room_state.scores.insert(player.player_id.clone(), 0);
+        let seat_token = room_state.issue_seat_token(&player.player_id);
+        let (rx, span) = (room_state.tx.subscribe(), room_state.span.clone());
+        let record = state.bus.shared().map(|_| room_state.record());
+        slot.insert(Arc::new(Mutex::new(room_state)));
+        created = Some((room_id, rx, span, seat_token, record));
+        break;
+    }
+    let Some((room_id, rx, span, seat_token, record)) = created else {
+        tracing::warn!("no free room id found");
+        return Err(WsServerMsg::Error {
+            room_id: None,
+            msg: "No free room codes right now, try again".to_string(),
+            code: None,
+        });
+    };
+    state.room_count.fetch_add(1, Ordering::Relaxed);
+    // before anyone hears the code, so every instance can find the room by it
+    if let (Some(shared), Some(record)) = (state.bus.shared(), record) {
+        shared.open(&room_id, &record).await;
+    }
+    let owner_id = player.player_id.clone();
+
+    // 3) Log it
+    tracing::info!(
+        parent: &span,
+        event = "room_created",
+        player_id = %player.player_id,
+        player_name = %player.name,
+        "room created"
+    );
+
+    // 4) Update this connection's context
+    ctx.enter_room(state, &room_id, &player.player_id, false, rx, span);
+
+    // 5) Send back RoomCreated and JoinedRoom
+    let created = WsServerMsg::RoomCreated {
+        room_id: room_id.clone(),
+    };
+    let joined = WsServerMsg::RoomPlayersUpdate {
+        room_id: room_id.clone(),
+        players: vec![player.clone()],
+        owner_id,
+    };
+    // nothing has been broadcast in the new room yet
+    out.send(ctx.room_snapshot(0, created));
+    out.send(ctx.room_snapshot(0, joined));
+    let granted = WsServerMsg::SeatGranted {
+        room_id: room_id.clone(),
+        player_id: player.player_id.clone(),
+        seat_token,
+    };
+    out.send(ctx.encode(&granted));
+    Ok(room_id)
+}
+
+/// Why the owner's `StartGame` (without `restart`) would be refused right now, if it would.
+/// `GetReadyStatus` answers `can_start` from this too, so the two never disagree.
+fn start_refusal(room_id: &RoomId, room_state: &RoomState, state: &AppState) -> Option<WsServerMsg> {
+    let (msg, code) = if state.is_draining() {
+        ("Server is restarting, try again shortly", Some(ErrorCode::Maintenance))
+    } else if room_state.pending_start.is_some() {
+        ("Game is already starting", None)
+    } else if room_state.game_in_progress() {
+        ("A game is already running", Some(ErrorCode::GameAlreadyRunning))
+    } else if !room_state.all_ready() {
+        ("All players must be ready", None)
+    } else if state.games_full() {
+        return Some(server_busy(Some(room_id.clone()), state));
+    } else {
+        return None;
+    };
+    Some(WsServerMsg::Error {
+        room_id: Some(room_id.clone()),
+        msg: msg.to_string(),
+        code,
+    })
+}
+
+/// The refusal for a game that can't start because `max_running_games` are already running.
+fn server_busy(room_id: Option<RoomId>, state: &AppState) -> WsServerMsg {
+    WsServerMsg::Error {
+        room_id,
+        msg: "Server busy, try again shortly".to_string(),
+        code: Some(ErrorCode::ServerBusy {
+            max_games: state.config.max_running_games as u32,
+        }),
+    }
+}
+
+/// How every game starts: broadcasts `GameStarting` and deals the board once
+/// `start_countdown` is up (right away when it's zero). Callers have already checked that
+/// the game may start and reserved its `slot`; readiness is checked again when the countdown
+/// runs out.
+fn start_new_round(
+    room_id: &RoomId,
+    room_state: &mut RoomState,
+    state: &AppState,
+    slot: RunningGuard,
+) {
+    let countdown = state.config.start_countdown;
+    if countdown.is_zero() {
+        start_game(room_id, room_state, state, slot);
+        return;
+    }
+    let at = Instant::now() + countdown;
+    room_state.pending_start = Some(at);
+    room_state.tx.send(WsServerMsg::GameStarting {
+        room_id: room_id.clone(),
+        in_secs: countdown.as_secs(),
+    });
+    tokio::spawn(finish_start_countdown(room_id.clone(), at, state.clone(), slot));
+}
+
+/// Starts the game announced by `start_new_round` at `at`, unless it was superseded, the
+/// room is gone, or someone seated isn't ready any more. A start called off drops its `slot`.
+async fn finish_start_countdown(
+    room_id: RoomId,
+    at: Instant,
+    state: AppState,
+    slot: RunningGuard,
+) {
+    tokio::time::sleep_until(at.into()).await;
+    let Some(mut room_state) = state.lock_room(&room_id).await else {
+        return;
+    };
+    if room_state.pending_start != Some(at) {
+        return;
+    }
+    room_state.pending_start = None;
+    if state.is_draining() {
+        room_state.announce(&room_id, Notice::StartCalledOff("the server is restarting"));
+    } else if !room_state.all_ready() {
+        room_state.announce(&room_id, Notice::StartCalledOff("not everyone is ready"));
+    } else {
+        start_game(&room_id, &mut room_state, &state, slot);
+    }
+}
+
+/// Deals a fresh board in `room_state`, resets scores and ready flags, broadcasts
+/// `GameStarted` and spawns the countdown that records the final scores, which keeps `slot`.
+/// Callers have already checked that the game may start.
+fn start_game(
+    room_id: &RoomId,
+    room_state: &mut RoomState,
+    state: &AppState,
+    slot: RunningGuard,
+) {
+    // 1) If a prior timer was running, cancel it
+    if let Some(timer) = room_state.timer.take() {
+        tracing::debug!(room_id = %room_id, "cancelling previous timer");
+        timer.cancel();
+    }
+
+    // 2) Generate a new random board from a fresh seed, and log the seed for bug reports
+    let seed: u64 = rand::rng().random();
+    let board = Arc::new(board::board_from_seed(&state.combos, seed, &room_state.settings));
+    tracing::info!(
+        parent: &room_state.span,
+        event = "game_started",
+        players = room_state.players.len(),
+        seed,
+        "game started"
+    );
+    state.record_seed(room_id, seed);
+    tracing::trace!(room_id = %room_id, ?board, "generated new board");
+
+    // 3) Reset all players’ scores, turns and boards in this room, and make everyone un ready
+    room_state.deal(Arc::clone(&board));
+    room_state.round += 1;
+    // other instances deal the same boards from the seed; in before `GameStarted` reaches them
+    let duration_secs = room_state.settings.game_secs();
+    room_state.tx.share(RoomChange::RoundStarted {
+        round: room_state.round,
+        game: LiveRound {
+            seed,
+            ends_at_ms: now_ms() + duration_secs * 1000,
+        },
+    });
+
+    // 4) Broadcast GameStarted to everyone in room
+    let start_msg = WsServerMsg::GameStarted {
+        room_id: room_id.clone(),
+        board,
+        duration_secs,
+        settings: room_state.settings.clone(),
+    };
+    let players: Vec<_> = room_state.player_list();
+    let msg = WsServerMsg::RoomPlayersUpdate {
+        room_id: room_id.clone(),
+        players,
+        owner_id: room_state.owner.clone(),
+    };
+    room_state.tx.send(msg);
+    room_state.tx.send(start_msg);
+    room_state.announce(room_id, Notice::GameStarted);
+
+    // 5) Register the countdown; the scheduler records the final scores when it runs out
+    let timer = state.timers.start_game(
+        room_id.clone(),
+        room_state.tx.clone(),
+        room_state.span.clone(),
+        &room_state.settings,
+        slot,
+    );
+    room_state.ends_at = Some(timer.ends_at);
+    room_state.ends_at_ms = Some(timer.ends_at_ms);
+    room_state.timer = Some(timer);
+}
+
+/// Marks a dropped player as disconnected and removes them only if they haven't rejoined
+/// (via `JoinRoom` with the same player ID and its seat token) once `reconnect_grace` has passed.
+async fn hold_seat_for_reconnect(room_id: &RoomId, player_id: &PlayerId, state: &AppState) {
+    let since = Instant::now();
+    let seated = {
+        let Some(mut room_state) = state.lock_room(room_id).await else {
+            return;
+        };
+        match room_state.players.get(player_id) {
+            Some(player) => {
+                let name = player.name.clone();
+                room_state.disconnected.insert(player_id.clone(), since);
+                room_state.announce(room_id, Notice::LostConnection(&name));
+                true
+            }
+            None => false,
+        }
+    };
+    if !seated {
+        // spectators have no seat to hold
+        remove_player_from_room(room_id, player_id, state).await;
+        return;
+    }
+    tracing::info!(room_id = %room_id, player_id = %player_id, "holding seat for reconnect");
+
+    let (room_id, player_id, state) = (room_id.clone(), player_id.clone(), state.clone());
+    tokio::spawn(async move {
+        tokio::time::sleep(state.config.reconnect_grace).await;
+        // a seat taken back through another instance meanwhile isn't held here any more
+        state.sync_room(&room_id).await;
+        let expired = state
+            .lock_room(&room_id)
+            .await
+            .is_some_and(|room| room.disconnected.get(&player_id) == Some(&since));
+        if expired {
+            tracing::info!(room_id = %room_id, player_id = %player_id, "reconnect grace expired");
+            remove_player_from_room(&room_id, &player_id, &state).await;
+        }
+    });
+}
+
+/// If a client disconnects without properly leaving the room, remove them from that room's state.
+/// Broadcasts the updated player list and (new) owner ID to remaining players.
+async fn remove_player_from_room(room_id: &RoomId, player_id: &PlayerId, state: &AppState) {
+    // the room is only empty once nobody is seated on any instance
+    state.sync_room(room_id).await;
+    if let Some(mut room_state) = state.lock_room(room_id).await {
+        room_state.forget_chat(player_id);
+        if let Some(spectator) = room_state.spectators.remove(player_id) {
+            room_state.lagged.remove(player_id);
+            tracing::info!(
+                parent: &room_state.span,
+                event = "spectator_left",
+                player_id = %player_id,
+                player_name = %spectator.name,
+                "spectator left room"
+            );
+            state.drop_unused_copy(room_id, &mut room_state);
+            return;
+        }
+        let player_name = room_state
+            .players
+            .get(player_id)
+            .map_or("Unknown player", |p| p.name.as_str())
+            .to_owned();
+
+        // Remove player from players and scores
+        room_state.remove_player(player_id);
+        room_state.tx.share(RoomChange::Unseat(player_id.clone()));
+        state.sessions.unseat(player_id, room_id);
+        room_state.scores.remove(player_id);
+        room_state.ready_since.remove(player_id);
+        room_state.last_score_at.remove(player_id);
+        room_state.lagged.remove(player_id);
+        room_state.boards.remove(player_id);
+        room_state.last_hint.remove(player_id);
+        room_state.stuck.remove(player_id);
+        room_state.finished.remove(player_id);
+        room_state.disconnected.remove(player_id);
+
+        // If room is now empty, clean up entirely
+        if room_state.players.is_empty() {
+            tracing::info!(
+                parent: &room_state.span,
+                event = "room_destroyed",
+                player_id = %player_id,
+                player_name = %player_name,
+                "last player left, room removed"
+            );
+            state.close_locked_room(room_id, &mut room_state, RoomCloseReason::Empty);
+            return;
+        }
+
+        room_state.announce(room_id, Notice::Left(&player_name));
+
+        // If owner left, hand the room to whoever has been here longest; that broadcasts the
+        // players list itself, already with the new owner ID
+        let successor = (&room_state.owner == player_id)
+            .then(|| room_state.player_list().first().map(|p| p.player_id.clone()))
+            .flatten();
+        if let Some(new_owner) = successor {
+            room_state.transfer_ownership(room_id, new_owner, &state.audit);
+        } else {
+            let update_msg = WsServerMsg::RoomPlayersUpdate {
+                room_id: room_id.clone(),
+                players: room_state.player_list(),
+                owner_id: room_state.owner.clone(),
+            };
+            room_state.tx.send(update_msg);
+        }
+        room_state.end_if_all_done(room_id);
+
+        tracing::info!(
+            parent: &room_state.span,
+            event = "player_left",
+            player_id = %player_id,
+            player_name = %player_name,
+            players = room_state.players.len(),
+            "player left room"
+        );
+        // the room plays on elsewhere
+        state.drop_unused_copy(room_id, &mut room_state);
+    }
+}
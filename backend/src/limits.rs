@@ -0,0 +1,490 @@
+// src/limits.rs
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The sliding window for `CreateRoom` and failed-token rate limiting.
+const ROOM_CREATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many wrong WebSocket tokens an address may send per window before it's refused outright.
+const MAX_AUTH_FAILURES_PER_WINDOW: usize = 10;
+
+/// Past this many tracked addresses, stale creation windows get swept on the next check.
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// Per-client-IP caps on live sockets and on room creation, so one script can't eat the server.
+/// A limit of 0 disables that check. Failed WebSocket token checks are also counted here.
+#[derive(Debug)]
+pub struct IpLimits {
+    max_connections: usize,
+    max_rooms_per_minute: usize,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    room_creations: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+    auth_failures: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl IpLimits {
+    pub fn new(max_connections: usize, max_rooms_per_minute: usize) -> Self {
+        IpLimits {
+            max_connections,
+            max_rooms_per_minute,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            room_creations: Mutex::new(HashMap::new()),
+            auth_failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new socket from `ip`, or `None` if it already has the maximum open.
+    /// The slot is released when the returned guard is dropped.
+    pub fn try_connect(&self, ip: IpAddr) -> Option<IpConnGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        if self.max_connections > 0 && *count >= self.max_connections {
+            return None;
+        }
+        *count += 1;
+        Some(IpConnGuard {
+            ip,
+            connections: self.connections.clone(),
+        })
+    }
+
+    /// Record a room creation from `ip` if it's still under the per-minute cap.
+    pub fn try_create_room(&self, ip: IpAddr) -> bool {
+        if self.max_rooms_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut creations = self.room_creations.lock().unwrap();
+        let times = window(&mut creations, ip, now);
+        if times.len() >= self.max_rooms_per_minute {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Whether `ip` has sent too many wrong WebSocket tokens lately to be worth checking again.
+    pub fn auth_blocked(&self, ip: IpAddr) -> bool {
+        let mut failures = self.auth_failures.lock().unwrap();
+        window(&mut failures, ip, Instant::now()).len() >= MAX_AUTH_FAILURES_PER_WINDOW
+    }
+
+    /// Record a wrong or missing WebSocket token from `ip`.
+    pub fn record_auth_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut failures = self.auth_failures.lock().unwrap();
+        window(&mut failures, ip, now).push_back(now);
+    }
+}
+
+/// `ip`'s events within the last minute, oldest first, after dropping expired ones (and sweeping
+/// other stale addresses once the map grows large).
+fn window(
+    events: &mut HashMap<IpAddr, VecDeque<Instant>>,
+    ip: IpAddr,
+    now: Instant,
+) -> &mut VecDeque<Instant> {
+    if events.len() > SWEEP_THRESHOLD {
+        events.retain(|_, times| {
+            times
+                .back()
+                .is_some_and(|&t| now.duration_since(t) < ROOM_CREATION_WINDOW)
+        });
+    }
+    let times = events.entry(ip).or_default();
+    while times
+        .front()
+        .is_some_and(|&t| now.duration_since(t) >= ROOM_CREATION_WINDOW)
+    {
+        times.pop_front();
+    }
+    times
+}
+
+/// Size and refill rate of a `TokenBucket`.
+#[derive(Debug, Clone, Copy)]
+pub struct BucketConfig {
+    /// Tokens available at once (the allowed burst).
+    pub capacity: u32,
+    /// How long it takes to earn one token back.
+    pub refill_every: Duration,
+}
+
+/// One `CreateRoom` (or `StartSolo`) per connection every 10 seconds.
+pub const CREATE_ROOM_BUCKET: BucketConfig = BucketConfig {
+    capacity: 1,
+    refill_every: Duration::from_secs(10),
+};
+
+/// Ten failed `JoinRoom`/`SpectateRoom` attempts per connection per minute.
+pub const JOIN_FAILURE_BUCKET: BucketConfig = BucketConfig {
+    capacity: 10,
+    refill_every: Duration::from_secs(6),
+};
+
+/// Emotes per connection: a burst of eight, then four a second.
+pub const EMOTE_BUCKET: BucketConfig = BucketConfig {
+    capacity: 8,
+    refill_every: Duration::from_millis(250),
+};
+
+/// `GetChatHistory` pages per connection: five at once, then one a second.
+pub const CHAT_HISTORY_BUCKET: BucketConfig = BucketConfig {
+    capacity: 5,
+    refill_every: Duration::from_secs(1),
+};
+
+/// A classic token bucket, owned by whoever it throttles (no locking).
+#[derive(Debug)]
+pub struct TokenBucket {
+    config: BucketConfig,
+    tokens: u32,
+    // when the bucket was last full, or the last refill was credited
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(config: BucketConfig) -> Self {
+        TokenBucket {
+            config,
+            tokens: config.capacity,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// `Ok` if a token is available right now, otherwise how long until one is.
+    pub fn check(&mut self) -> Result<(), Duration> {
+        self.refill(Instant::now());
+        if self.tokens > 0 {
+            return Ok(());
+        }
+        let next = self.refilled_at + self.config.refill_every;
+        Err(next.saturating_duration_since(Instant::now()))
+    }
+
+    /// Spend a token if one is available (never goes below empty).
+    pub fn take(&mut self) {
+        self.refill(Instant::now());
+        if self.tokens == self.config.capacity {
+            // the refill clock starts when the bucket stops being full
+            self.refilled_at = Instant::now();
+        }
+        self.tokens = self.tokens.saturating_sub(1);
+    }
+
+    /// `check` and `take` in one go, for actions that always cost a token.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        self.check()?;
+        self.take();
+        Ok(())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if self.tokens >= self.config.capacity || self.config.refill_every.is_zero() {
+            return;
+        }
+        let elapsed = now.duration_since(self.refilled_at);
+        let earned = (elapsed.as_nanos() / self.config.refill_every.as_nanos()) as u32;
+        if earned > 0 {
+            self.tokens = self.tokens.saturating_add(earned).min(self.config.capacity);
+            self.refilled_at += self.config.refill_every * earned;
+        }
+    }
+}
+
+/// How far back chat rate-limit violations are counted towards a mute.
+const CHAT_STRIKE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Chat throttling for one room member: a `TokenBucket`, and how often running it dry earns
+/// a mute (muting itself is up to the room).
+#[derive(Debug, Clone, Copy)]
+pub struct ChatLimitConfig {
+    pub bucket: BucketConfig,
+    /// Violations within `CHAT_STRIKE_WINDOW` that earn a mute (0 never mutes).
+    pub mute_after: u32,
+}
+
+/// Why a chat message was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRefusal {
+    /// Over the rate; a token is back after this long.
+    TooFast(Duration),
+    /// Over the rate once too often: the sender has earned a mute.
+    Mute,
+}
+
+/// One member's chat limiter, kept on the room for as long as they're in it.
+#[derive(Debug)]
+pub struct ChatLimiter {
+    config: ChatLimitConfig,
+    bucket: TokenBucket,
+    violations: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    pub fn new(config: ChatLimitConfig) -> Self {
+        ChatLimiter {
+            config,
+            bucket: TokenBucket::new(config.bucket),
+            violations: VecDeque::new(),
+        }
+    }
+
+    /// Spend a token for a message sent at `now`, or say why it can't be sent.
+    pub fn check(&mut self, now: Instant) -> Result<(), ChatRefusal> {
+        let Err(wait) = self.bucket.try_take() else {
+            return Ok(());
+        };
+        while self
+            .violations
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= CHAT_STRIKE_WINDOW)
+        {
+            self.violations.pop_front();
+        }
+        self.violations.push_back(now);
+        if self.config.mute_after > 0 && self.violations.len() >= self.config.mute_after as usize {
+            self.violations.clear();
+            return Err(ChatRefusal::Mute);
+        }
+        Err(ChatRefusal::TooFast(wait))
+    }
+}
+
+/// Holds one of an IP's connection slots; dropping it frees the slot, however the socket ends.
+#[derive(Debug)]
+pub struct IpConnGuard {
+    ip: IpAddr,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for IpConnGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    const TEN_PER_MINUTE: BucketConfig = BucketConfig {
+        capacity: 10,
+        refill_every: Duration::from_secs(6),
+    };
+
+    /// Winds `bucket`'s refill clock back by `by`, as if that much time had passed.
+    fn age(bucket: &mut TokenBucket, by: Duration) {
+        bucket.refilled_at -= by;
+    }
+
+    #[test]
+    fn a_bucket_allows_its_burst_then_says_how_long_to_wait() {
+        let mut bucket = TokenBucket::new(TEN_PER_MINUTE);
+        for _ in 0..10 {
+            assert!(bucket.try_take().is_ok());
+        }
+        let wait = bucket.try_take().unwrap_err();
+        assert!(wait > Duration::from_secs(5) && wait <= Duration::from_secs(6), "{wait:?}");
+    }
+
+    #[test]
+    fn a_bucket_earns_tokens_back_one_interval_at_a_time() {
+        let mut bucket = TokenBucket::new(TEN_PER_MINUTE);
+        for _ in 0..10 {
+            bucket.take();
+        }
+        age(&mut bucket, Duration::from_secs(13));
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_ok());
+        assert!(bucket.try_take().is_err());
+        // the second left over from 13s still counts towards the next token
+        let wait = bucket.check().unwrap_err();
+        assert!(wait <= Duration::from_secs(5), "{wait:?}");
+    }
+
+    #[test]
+    fn a_bucket_never_fills_past_its_capacity() {
+        let mut bucket = TokenBucket::new(TEN_PER_MINUTE);
+        bucket.take();
+        age(&mut bucket, Duration::from_secs(3600));
+        for _ in 0..10 {
+            assert!(bucket.try_take().is_ok());
+        }
+        assert!(bucket.try_take().is_err());
+    }
+
+    #[test]
+    fn a_full_bucket_starts_its_refill_clock_on_the_first_take() {
+        let mut bucket = TokenBucket::new(CREATE_ROOM_BUCKET);
+        // however long it sat full, that's no credit towards after the take
+        age(&mut bucket, Duration::from_secs(3600));
+        assert!(bucket.try_take().is_ok());
+        let wait = bucket.try_take().unwrap_err();
+        assert!(wait > Duration::from_secs(9), "{wait:?}");
+    }
+
+    #[test]
+    fn checking_an_empty_bucket_costs_nothing() {
+        let mut bucket = TokenBucket::new(CREATE_ROOM_BUCKET);
+        bucket.take();
+        for _ in 0..5 {
+            assert!(bucket.check().is_err());
+        }
+        age(&mut bucket, Duration::from_secs(10));
+        assert!(bucket.check().is_ok());
+        assert!(bucket.check().is_ok(), "check took the token");
+        // and taking from an empty bucket stays at empty
+        bucket.take();
+        bucket.take();
+        age(&mut bucket, Duration::from_secs(10));
+        assert!(bucket.check().is_ok());
+    }
+
+    const CHAT: ChatLimitConfig = ChatLimitConfig {
+        bucket: BucketConfig {
+            capacity: 3,
+            refill_every: Duration::from_secs(2),
+        },
+        mute_after: 3,
+    };
+
+    /// A limiter that has just sent its burst.
+    fn drained_chat() -> (ChatLimiter, Instant) {
+        let (mut chat, now) = (ChatLimiter::new(CHAT), Instant::now());
+        for _ in 0..3 {
+            assert_eq!(chat.check(now), Ok(()));
+        }
+        (chat, now)
+    }
+
+    #[test]
+    fn chat_past_the_burst_is_told_when_to_try_again() {
+        let (mut chat, now) = drained_chat();
+        match chat.check(now) {
+            Err(ChatRefusal::TooFast(wait)) => assert!(wait <= Duration::from_secs(2), "{wait:?}"),
+            other => panic!("not too fast: {other:?}"),
+        }
+        age(&mut chat.bucket, Duration::from_secs(2));
+        assert_eq!(chat.check(now), Ok(()));
+    }
+
+    #[test]
+    fn the_third_violation_within_a_minute_earns_a_mute() {
+        let (mut chat, now) = drained_chat();
+        assert!(matches!(chat.check(now), Err(ChatRefusal::TooFast(_))));
+        assert!(matches!(chat.check(now), Err(ChatRefusal::TooFast(_))));
+        assert_eq!(chat.check(now), Err(ChatRefusal::Mute));
+        // the count starts over after a mute
+        assert!(matches!(chat.check(now), Err(ChatRefusal::TooFast(_))));
+    }
+
+    #[test]
+    fn violations_older_than_a_minute_are_forgotten() {
+        let (mut chat, now) = drained_chat();
+        for secs in [0, 30, 61] {
+            let at = now + Duration::from_secs(secs);
+            assert!(matches!(chat.check(at), Err(ChatRefusal::TooFast(_))), "at {secs}s");
+        }
+        // the one at 0s is gone, the ones at 30s and 61s still count
+        assert_eq!(chat.check(now + Duration::from_secs(62)), Err(ChatRefusal::Mute));
+    }
+
+    #[test]
+    fn a_limiter_without_mutes_only_ever_says_too_fast() {
+        let mut chat = ChatLimiter::new(ChatLimitConfig { mute_after: 0, ..CHAT });
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(chat.check(now), Ok(()));
+        }
+        for _ in 0..20 {
+            assert!(matches!(chat.check(now), Err(ChatRefusal::TooFast(_))));
+        }
+    }
+
+    #[test]
+    fn connections_are_capped_per_address_and_freed_on_drop() {
+        let limits = IpLimits::new(2, 0);
+        let a = limits.try_connect(ip("203.0.113.7")).unwrap();
+        let _b = limits.try_connect(ip("203.0.113.7")).unwrap();
+        assert!(limits.try_connect(ip("203.0.113.7")).is_none());
+        // someone else's cap is their own
+        assert!(limits.try_connect(ip("203.0.113.8")).is_some());
+
+        drop(a);
+        assert!(limits.try_connect(ip("203.0.113.7")).is_some());
+    }
+
+    #[test]
+    fn the_last_guard_forgets_the_address() {
+        let limits = IpLimits::new(2, 0);
+        let guards: Vec<_> = (0..2)
+            .map(|_| limits.try_connect(ip("203.0.113.7")).unwrap())
+            .collect();
+        drop(guards);
+        assert!(limits.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_zero_limit_lets_everything_through() {
+        let limits = IpLimits::new(0, 0);
+        let guards: Vec<_> = (0..100)
+            .filter_map(|_| limits.try_connect(ip("203.0.113.7")))
+            .collect();
+        assert_eq!(guards.len(), 100);
+        assert!((0..100).all(|_| limits.try_create_room(ip("203.0.113.7"))));
+    }
+
+    #[test]
+    fn room_creations_are_capped_per_address_per_minute() {
+        let limits = IpLimits::new(0, 3);
+        assert!((0..3).all(|_| limits.try_create_room(ip("203.0.113.7"))));
+        assert!(!limits.try_create_room(ip("203.0.113.7")));
+        assert!(limits.try_create_room(ip("203.0.113.8")));
+    }
+
+    #[test]
+    fn creations_older_than_the_window_no_longer_count() {
+        let now = Instant::now();
+        let long_ago = now - ROOM_CREATION_WINDOW - Duration::from_secs(1);
+        let mut events = HashMap::from([(ip("203.0.113.7"), VecDeque::from([long_ago, now]))]);
+        assert_eq!(window(&mut events, ip("203.0.113.7"), now).len(), 1);
+    }
+
+    #[test]
+    fn a_large_map_sweeps_addresses_that_went_quiet() {
+        let now = Instant::now();
+        let long_ago = now - ROOM_CREATION_WINDOW;
+        let mut events: HashMap<_, _> = (0..=SWEEP_THRESHOLD as u32)
+            .map(|i| (IpAddr::from(i.to_be_bytes()), VecDeque::from([long_ago])))
+            .collect();
+        events.insert(ip("203.0.113.8"), VecDeque::from([now]));
+        window(&mut events, ip("203.0.113.7"), now);
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn wrong_tokens_block_an_address_after_the_limit() {
+        let limits = IpLimits::new(0, 0);
+        for _ in 0..MAX_AUTH_FAILURES_PER_WINDOW {
+            assert!(!limits.auth_blocked(ip("203.0.113.7")));
+            limits.record_auth_failure(ip("203.0.113.7"));
+        }
+        assert!(limits.auth_blocked(ip("203.0.113.7")));
+        assert!(!limits.auth_blocked(ip("203.0.113.8")));
+    }
+}
@@ -0,0 +1,218 @@
+// src/logging.rs
+use crate::config::{LogFormat, LogOptions};
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::{field::Field, Event, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    fmt::{
+        format::{JsonFields, Writer},
+        time::{FormatTime, SystemTime},
+        writer::BoxMakeWriter,
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// Installs the global subscriber: `RUST_LOG` filtering, the chosen format, stdout or a file.
+/// Keep the returned guard alive for the life of the process, or buffered file lines are lost.
+pub fn init(options: &LogOptions) -> Result<Option<WorkerGuard>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        format!("{}=debug,tower_http=warn", env!("CARGO_CRATE_NAME")).into()
+    });
+    let (layer, guard) = layer(options)?;
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .init();
+    Ok(guard)
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The formatting layer `options` ask for, and the file writer's guard if there is one.
+fn layer(options: &LogOptions) -> Result<(BoxedLayer, Option<WorkerGuard>)> {
+    let (writer, guard) = match &options.file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("cannot open log file {}", path.display()))?;
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let ansi = options.file.is_none();
+
+    let layer: BoxedLayer = match options.format {
+        LogFormat::Full => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed(),
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .with_writer(writer)
+            .boxed(),
+    };
+    Ok((layer, guard))
+}
+
+/// One JSON object per line, with the event's fields and those of every enclosing span
+/// (`room_id`, `player_id`, `client`, ...) merged in as top-level keys; inner spans win.
+struct FlatJson;
+
+impl<S, N> FormatEvent<S, N> for FlatJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut line = Map::new();
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        line.insert("timestamp".into(), timestamp.into());
+        line.insert("level".into(), event.metadata().level().as_str().into());
+        line.insert("target".into(), event.metadata().target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            let mut names = Vec::new();
+            for span in scope.from_root() {
+                names.push(span.name());
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(fields) {
+                    line.extend(fields);
+                }
+            }
+            line.insert("span".into(), names.join(":").into());
+        }
+
+        event.record(&mut JsonVisitor(&mut line));
+
+        let text = serde_json::to_string(&line).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{text}")
+    }
+}
+
+/// Copies event fields into the JSON line, keeping numbers and booleans typed.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl tracing::field::Visit for JsonVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().into(), format!("{value:?}").into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Logs `events` through a JSON file layer and returns the lines written.
+    fn json_lines(events: impl FnOnce()) -> Vec<Map<String, Value>> {
+        let name = format!("log-{}.jsonl", uuid::Uuid::new_v4().simple());
+        let path = std::env::temp_dir().join(name);
+        let options = LogOptions {
+            format: LogFormat::Json,
+            file: Some(path.clone()),
+        };
+        let (layer, guard) = layer(&options).unwrap();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), events);
+        // flushes the writer thread
+        drop(guard);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{line}: {e}")))
+            .collect()
+    }
+
+    #[test]
+    fn events_land_in_the_file_as_json_lines() {
+        let lines = json_lines(|| {
+            tracing::info!(players = 3, ranked = true, "game started");
+            tracing::warn!(error = %"disk full", "save failed");
+        });
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "game started");
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["players"], 3);
+        assert_eq!(lines[0]["ranked"], true);
+        assert!(lines[0]["timestamp"].is_string());
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["error"], "disk full");
+    }
+
+    #[test]
+    fn span_fields_are_top_level_keys() {
+        let lines = json_lines(|| {
+            let room = tracing::info_span!("room", room_id = "4821");
+            let _room = room.enter();
+            let conn = tracing::info_span!("conn", player_id = "p-1", client = "203.0.113.7");
+            let _conn = conn.enter();
+            tracing::info!(score = 12, "score update");
+        });
+        let line = &lines[0];
+        assert_eq!(line["room_id"], "4821");
+        assert_eq!(line["player_id"], "p-1");
+        assert_eq!(line["client"], "203.0.113.7");
+        assert_eq!(line["score"], 12);
+        assert_eq!(line["span"], "room:conn");
+    }
+
+    #[test]
+    fn inner_spans_and_the_event_win_a_clash() {
+        let lines = json_lines(|| {
+            let outer = tracing::info_span!("lobby", room_id = "outer", player_id = "outer");
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("room", room_id = "inner");
+            let _inner = inner.enter();
+            tracing::info!(player_id = "event", "joined");
+        });
+        assert_eq!(lines[0]["room_id"], "inner");
+        assert_eq!(lines[0]["player_id"], "event");
+    }
+}
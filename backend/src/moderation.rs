@@ -0,0 +1,149 @@
+// src/moderation.rs
+use aho_corasick::AhoCorasick;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Masked chat messages in one game that earn a mute.
+pub const MASKED_MESSAGES_BEFORE_MUTE: u32 = 3;
+
+/// The look-alikes `--leet-map` starts from: `from=to` pairs, applied after lowercasing.
+pub const DEFAULT_LEET_MAP: &str = "0=o,1=i,3=e,4=a,5=s,7=t,8=b,@=a,$=s,!=i,|=l";
+
+/// Words masked out of chat and player names when `profanity_filter` is on, read from a text
+/// file with one word or phrase per line (blank lines and `#` comments are skipped).
+///
+/// The list can be reloaded while running; the compiled filter is swapped whole, so a message
+/// being checked meanwhile sees either the old list or the new one.
+#[derive(Debug)]
+pub struct DenyList {
+    path: Option<PathBuf>,
+    substitutions: HashMap<char, char>,
+    filter: ArcSwap<WordFilter>,
+}
+
+impl Default for DenyList {
+    /// An empty list that masks nothing and has no file to reload.
+    fn default() -> Self {
+        DenyList {
+            path: None,
+            substitutions: HashMap::new(),
+            filter: ArcSwap::from_pointee(WordFilter::new(&[], HashMap::new())),
+        }
+    }
+}
+
+impl DenyList {
+    /// Reads `path` if it exists (a missing file is an empty list). Letters are matched
+    /// case-insensitively, after replacing each `substitutions` key with its value.
+    pub fn load(path: PathBuf, substitutions: HashMap<char, char>) -> Result<Self> {
+        let filter = WordFilter::new(&read_words(&path)?, substitutions.clone());
+        Ok(DenyList {
+            path: Some(path),
+            substitutions,
+            filter: ArcSwap::from_pointee(filter),
+        })
+    }
+
+    /// Rereads the file; on failure the current list stays. Returns how many entries it has.
+    pub fn reload(&self) -> Result<usize> {
+        let path = self.path.as_ref().context("no deny list file is in use")?;
+        let filter = WordFilter::new(&read_words(path)?, self.substitutions.clone());
+        let words = filter.words;
+        self.filter.store(filter.into());
+        Ok(words)
+    }
+
+    /// `text` with every listed word replaced by as many `*`s, or `None` if nothing matched.
+    pub fn mask(&self, text: &str) -> Option<String> {
+        self.filter.load().mask(text)
+    }
+}
+
+fn read_words(path: &Path) -> Result<Vec<String>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!(path = %path.display(), "deny list file not found, nothing will be masked");
+            String::new()
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("cannot read deny list {}", path.display()))
+        }
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// A deny list compiled into one automaton, so a message is scanned once whatever its length.
+#[derive(Debug)]
+struct WordFilter {
+    words: usize,
+    automaton: Option<AhoCorasick>,
+    substitutions: HashMap<char, char>,
+}
+
+impl WordFilter {
+    fn new(words: &[String], substitutions: HashMap<char, char>) -> Self {
+        let mut filter = WordFilter {
+            words: 0,
+            automaton: None,
+            substitutions,
+        };
+        let patterns: Vec<String> = words
+            .iter()
+            .map(|word| word.chars().map(|c| filter.normalize(c)).collect())
+            .collect();
+        filter.words = patterns.len();
+        if !patterns.is_empty() {
+            // plain strings can't exceed the automaton's limits at any realistic list size
+            filter.automaton = Some(AhoCorasick::new(&patterns).expect("deny list too large"));
+        }
+        filter
+    }
+
+    /// What `c` is matched as: lowercased (when that's still one char), then substituted.
+    fn normalize(&self, c: char) -> char {
+        let mut lower = c.to_lowercase();
+        let c = match (lower.next(), lower.next()) {
+            (Some(l), None) => l,
+            _ => c,
+        };
+        self.substitutions.get(&c).copied().unwrap_or(c)
+    }
+
+    /// Masks whole-word matches only, so a listed word inside a longer one (the classic
+    /// “Scunthorpe” problem) is left alone.
+    fn mask(&self, text: &str) -> Option<String> {
+        let automaton = self.automaton.as_ref()?;
+        let mut chars: Vec<char> = text.chars().collect();
+        let normalized: Vec<char> = chars.iter().map(|&c| self.normalize(c)).collect();
+        let haystack: String = normalized.iter().collect();
+        // byte offset in `haystack` → index of the char there
+        let mut char_at = Vec::with_capacity(haystack.len());
+        for (i, c) in normalized.iter().enumerate() {
+            char_at.extend(std::iter::repeat_n(i, c.len_utf8()));
+        }
+
+        // judged on what was typed, so punctuation read as a letter (`heck!`) still ends a word
+        let word_chars: Vec<bool> = chars.iter().map(|c| c.is_alphanumeric()).collect();
+        let is_word = |i: usize| word_chars.get(i).copied().unwrap_or(false);
+        let mut masked = false;
+        for m in automaton.find_overlapping_iter(&haystack) {
+            let (start, end) = (char_at[m.start()], char_at[m.end() - 1] + 1);
+            if (start > 0 && is_word(start - 1)) || is_word(end) {
+                continue;
+            }
+            chars[start..end].fill('*');
+            masked = true;
+        }
+        masked.then(|| chars.into_iter().collect())
+    }
+}
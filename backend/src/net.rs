@@ -0,0 +1,339 @@
+// src/net.rs
+use crate::admin::constant_time_eq;
+use axum::http::{
+    header::{FORWARDED, SEC_WEBSOCKET_PROTOCOL},
+    HeaderMap,
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Works out the real client address for a request.
+///
+/// Proxy headers are only honored when the direct peer is one of our `trusted` proxies; otherwise
+/// anyone could claim any address. When they are honored we walk the hop list from the right
+/// (closest to us) and return the first address that isn't itself a trusted proxy. `Forwarded`
+/// takes precedence over `X-Forwarded-For`. A malformed hop stops the walk, and we fall back to the
+/// last address we could vouch for.
+pub fn client_ip(peer: SocketAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let peer_ip = peer.ip().to_canonical();
+    if !is_trusted(peer_ip, trusted) {
+        return peer_ip;
+    }
+
+    let hops = if headers.contains_key(FORWARDED) {
+        forwarded_hops(headers)
+    } else {
+        x_forwarded_for_hops(headers)
+    };
+
+    let mut last_good = peer_ip;
+    for hop in hops.iter().rev() {
+        let Some(ip) = parse_hop(hop) else {
+            break;
+        };
+        if !is_trusted(ip, trusted) {
+            return ip;
+        }
+        last_good = ip;
+    }
+    last_good
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[IpNet]) -> bool {
+    trusted.iter().any(|net| net.contains(&ip))
+}
+
+/// All `X-Forwarded-For` entries, across repeated headers, in order.
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|hop| hop.trim().to_string())
+        .collect()
+}
+
+/// The `for=` value of every element in the RFC 7239 `Forwarded` headers, in order.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<String> {
+    let mut hops = Vec::new();
+    for value in headers.get_all(FORWARDED).iter() {
+        let Ok(value) = value.to_str() else {
+            // an unreadable header means we can't trust anything to its left
+            hops.push(String::new());
+            continue;
+        };
+        for element in value.split(',') {
+            let hop = element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, v)| v.trim().trim_matches('"').to_string())
+                .unwrap_or_default();
+            hops.push(hop);
+        }
+    }
+    hops
+}
+
+/// Parses one hop: a bare IP, `ip:port`, or `[v6]:port`. Obfuscated or `unknown` hops give `None`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = hop.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    hop.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|inner| inner.parse::<IpAddr>().ok())
+}
+
+/// Decides whether a WebSocket upgrade from `origin` may proceed.
+///
+/// Allowed: origins on the same host the request was addressed to (`Host` header), localhost
+/// on any port for development, and anything in `allowed` (exact match, or `*`). A missing
+/// `Origin` means a non-browser client and is allowed only when `allow_missing` is set.
+pub fn origin_allowed(
+    origin: Option<&str>,
+    host: Option<&str>,
+    allowed: &[String],
+    allow_missing: bool,
+) -> bool {
+    let Some(origin) = origin else {
+        return allow_missing;
+    };
+    if allowed
+        .iter()
+        .any(|a| a == "*" || a.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    {
+        return true;
+    }
+    let Some((_, authority)) = origin.split_once("://") else {
+        // e.g. the opaque "null" origin
+        return false;
+    };
+    if host.is_some_and(|host| host.eq_ignore_ascii_case(authority)) {
+        return true;
+    }
+    is_localhost(authority)
+}
+
+/// `localhost`, `127.0.0.1` or `[::1]`, with or without a port.
+fn is_localhost(authority: &str) -> bool {
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => authority.split(':').next().unwrap_or(""),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Where the WebSocket token was presented.
+pub enum TokenSource {
+    /// `?token=...` on the upgrade URL.
+    Query,
+    /// One of the offered `Sec-WebSocket-Protocol` values, which must be echoed back to the
+    /// browser for the handshake to complete.
+    Subprotocol(String),
+}
+
+/// The WebSocket subprotocols the server speaks, for clients to declare what they support.
+///
+/// A client lists the ones it knows in `Sec-WebSocket-Protocol`; the server picks the first of
+/// `ALL` among them and echoes it back. A client that offers none gets `V1`, the protocol every
+/// client spoke before negotiation existed. New encodings or message versions get a variant
+/// here, and the connection branches on the one it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subprotocol {
+    /// JSON text frames, the messages in `ws_messages`.
+    V1,
+}
+
+impl Subprotocol {
+    /// Most preferred first.
+    pub const ALL: [Subprotocol; 1] = [Subprotocol::V1];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subprotocol::V1 => "fruitbox.v1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Looks for `expected` in the `token` query parameter, then among the offered subprotocols.
+/// Every comparison is constant-time; `None` means the upgrade carries no matching token.
+pub fn find_ws_token(
+    query_token: Option<&str>,
+    headers: &HeaderMap,
+    expected: &str,
+) -> Option<TokenSource> {
+    if query_token.is_some_and(|t| constant_time_eq(t.as_bytes(), expected.as_bytes())) {
+        return Some(TokenSource::Query);
+    }
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .find(|p| constant_time_eq(p.as_bytes(), expected.as_bytes()))
+        .map(|p| TokenSource::Subprotocol(p.to_owned()))
+}
+
+/// Parses a comma-separated list of CIDRs or bare addresses (e.g. `10.0.0.0/8,127.0.0.1`).
+/// Returns the networks that parsed and the entries that didn't.
+pub fn parse_ip_nets(list: &str) -> (Vec<IpNet>, Vec<String>) {
+    let mut nets = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.parse::<IpNet>() {
+            Ok(net) => nets.push(net),
+            Err(_) => match entry.parse::<IpAddr>() {
+                Ok(ip) => nets.push(IpNet::from(ip)),
+                Err(_) => invalid.push(entry.to_string()),
+            },
+        }
+    }
+    (nets, invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn trusted() -> Vec<IpNet> {
+        parse_ip_nets("10.0.0.0/8,::1").0
+    }
+
+    fn peer(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), 443)
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn headers_from_an_untrusted_peer_are_ignored() {
+        let h = headers(&[("x-forwarded-for", "203.0.113.7")]);
+        assert_eq!(client_ip(peer("198.51.100.1"), &h, &trusted()), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn the_rightmost_untrusted_hop_is_the_client() {
+        // the client can prepend whatever it likes; only what our proxies appended counts
+        let h = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.0.0.2")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn repeated_headers_are_read_as_one_list() {
+        let h = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-for", "10.0.0.3, 10.0.0.2"),
+        ]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn a_malformed_hop_stops_at_the_last_trusted_one() {
+        let h = headers(&[("x-forwarded-for", "203.0.113.7, garbage, 10.0.0.2")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.2"));
+        let h = headers(&[("x-forwarded-for", "")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn all_trusted_hops_give_the_leftmost() {
+        let h = headers(&[("x-forwarded-for", "10.0.0.9, 10.0.0.2")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.9"));
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        let h = headers(&[
+            ("x-forwarded-for", "198.51.100.9"),
+            ("forwarded", r#"for=203.0.113.7;proto=https, For="[2001:db8::1]:4711""#),
+        ]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("2001:db8::1"));
+        let h = headers(&[("forwarded", "for=203.0.113.7;proto=https;by=10.0.0.1")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn an_obfuscated_or_missing_for_is_not_trusted_past() {
+        let h = headers(&[("forwarded", "for=203.0.113.7, for=_hidden, for=10.0.0.2")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.2"));
+        let h = headers(&[("forwarded", "for=203.0.113.7, proto=https")]);
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn an_unreadable_forwarded_header_stops_the_walk() {
+        let mut h = headers(&[("forwarded", "for=203.0.113.7")]);
+        h.append(FORWARDED, HeaderValue::from_bytes(b"for=\xff").unwrap());
+        assert_eq!(client_ip(peer("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn mapped_v4_peers_are_matched_as_v4() {
+        let h = headers(&[("x-forwarded-for", "::ffff:203.0.113.7")]);
+        assert_eq!(client_ip(peer("::ffff:10.0.0.1"), &h, &trusted()), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn hops_parse_with_and_without_ports() {
+        assert_eq!(parse_hop("203.0.113.7"), Some(ip("203.0.113.7")));
+        assert_eq!(parse_hop("203.0.113.7:8080"), Some(ip("203.0.113.7")));
+        assert_eq!(parse_hop("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_hop("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_hop("[2001:db8::1]:4711"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_hop("unknown"), None);
+        assert_eq!(parse_hop("_hidden"), None);
+        assert_eq!(parse_hop("[203.0.113.7"), None);
+        assert_eq!(parse_hop(""), None);
+    }
+
+    #[test]
+    fn the_token_is_found_in_the_query_or_among_the_subprotocols() {
+        let none = HeaderMap::new();
+        assert!(matches!(find_ws_token(Some("s3cret"), &none, "s3cret"), Some(TokenSource::Query)));
+        assert!(find_ws_token(Some("s3cre"), &none, "s3cret").is_none());
+        assert!(find_ws_token(Some(""), &none, "s3cret").is_none());
+        assert!(find_ws_token(None, &none, "s3cret").is_none());
+
+        let offered = headers(&[("sec-websocket-protocol", "fruitbox.v1, s3cret")]);
+        match find_ws_token(None, &offered, "s3cret") {
+            Some(TokenSource::Subprotocol(p)) => assert_eq!(p, "s3cret"),
+            _ => panic!("token among the subprotocols not found"),
+        }
+        let wrong = headers(&[("sec-websocket-protocol", "fruitbox.v1, s3cret2")]);
+        assert!(find_ws_token(Some("nope"), &wrong, "s3cret").is_none());
+    }
+
+    #[test]
+    fn ip_nets_take_cidrs_and_bare_addresses() {
+        let (nets, invalid) = parse_ip_nets(" 10.0.0.0/8, 127.0.0.1,,fd00::/8, nope ,10.0.0.0/33");
+        assert_eq!(nets.len(), 3);
+        assert!(is_trusted(ip("10.200.0.1"), &nets));
+        assert!(is_trusted(ip("127.0.0.1"), &nets));
+        assert!(!is_trusted(ip("127.0.0.2"), &nets));
+        assert_eq!(invalid, ["nope", "10.0.0.0/33"]);
+    }
+}
@@ -0,0 +1,297 @@
+// src/outbox.rs
+use axum::extract::ws::Message;
+use futures_util::{FutureExt, Sink, SinkExt};
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
+
+/// Slots kept free for direct replies: broadcasts are only taken while more than this many are open.
+const REPLY_HEADROOM: usize = 8;
+
+/// How long a closing socket gets to write out what's still queued.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A connection's outgoing frames, written to the socket by a task of its own.
+///
+/// Nothing here waits on the network: `send` queues the frame and returns, so a client that
+/// stops reading only stalls its writer, never the loop that reads its messages and pings it.
+/// Room broadcasts aren't pushed while the queue is nearly full (see `has_room`); they wait in
+/// the room's channel, where falling too far behind is handled like any other lag.
+pub struct Outbox {
+    frames: mpsc::Sender<Message>,
+    abort: Option<oneshot::Sender<Message>>,
+    writer: JoinHandle<()>,
+    stall_limit: Duration,
+    // When the queue last filled up past `REPLY_HEADROOM`; cleared once it drains.
+    full_since: Option<Instant>,
+}
+
+impl Outbox {
+    /// Spawns the writer for `sink` (the socket's write half). A queue of `capacity` frames
+    /// that stays too full for `stall_limit` makes the connection `too_slow`.
+    pub fn spawn<S>(sink: S, capacity: usize, stall_limit: Duration) -> Self
+    where
+        S: Sink<Message, Error: Send> + Send + Unpin + 'static,
+    {
+        let (frames, rx) = mpsc::channel(capacity.max(REPLY_HEADROOM + 1));
+        let (abort, abort_rx) = oneshot::channel();
+        Outbox {
+            frames,
+            abort: Some(abort),
+            writer: tokio::spawn(write(sink, rx, abort_rx)),
+            stall_limit,
+            full_since: None,
+        }
+    }
+
+    /// Queues a frame. A full queue drops it; by then the connection is about to be closed
+    /// as too slow anyway.
+    pub fn send(&mut self, frame: Message) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.frames.try_send(frame) {
+            self.full_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Whether there's space for a room broadcast, leaving enough for the replies.
+    pub fn has_room(&self) -> bool {
+        self.frames.capacity() > REPLY_HEADROOM
+    }
+
+    /// The writer has stopped: the socket is broken or closed.
+    pub fn is_closed(&self) -> bool {
+        self.writer.is_finished()
+    }
+
+    /// Whether the client has kept the queue too full for longer than the stall limit.
+    /// Call it regularly: the stall is only timed from the first call that sees it full.
+    pub fn too_slow(&mut self) -> bool {
+        if self.has_room() {
+            self.full_since = None;
+            return false;
+        }
+        self.full_since.get_or_insert_with(Instant::now).elapsed() >= self.stall_limit
+    }
+
+    /// Skips the queue: the writer sends `close` as soon as it can and stops, dropping
+    /// whatever was still queued.
+    pub fn close_now(&mut self, close: Message) {
+        if let Some(abort) = self.abort.take() {
+            let _ = abort.send(close);
+        }
+    }
+
+    /// Lets the writer flush what's queued (a close frame included), up to `DRAIN_TIMEOUT`.
+    pub async fn finish(self) {
+        // `abort` lives until the writer is done, so it never sees the channel drop
+        let Outbox { frames, mut writer, abort, .. } = self;
+        drop(frames);
+        if tokio::time::timeout(DRAIN_TIMEOUT, &mut writer).await.is_err() {
+            tracing::debug!("socket didn't drain in time, dropping it");
+            writer.abort();
+        }
+        drop(abort);
+    }
+}
+
+async fn write<S: Sink<Message> + Unpin>(
+    mut sink: S,
+    mut frames: mpsc::Receiver<Message>,
+    abort: oneshot::Receiver<Message>,
+) {
+    // an `Outbox` dropped without `finish` drops `abort` with frames still queued; fused, the
+    // dropped channel is seen once and then left alone while the rest go out
+    let mut abort = abort.fuse();
+    loop {
+        // a close_now cuts in even while a frame is stuck on its way out
+        let sent = tokio::select! {
+            biased;
+            Ok(close) = &mut abort => {
+                let _ = sink.send(close).await;
+                return;
+            }
+            frame = frames.recv() => match frame {
+                Some(frame) => tokio::select! {
+                    biased;
+                    Ok(close) = &mut abort => {
+                        let _ = sink.send(close).await;
+                        return;
+                    }
+                    sent = sink.send(frame) => sent,
+                },
+                None => break,
+            },
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+    // everything queued is out; this also flushes the echo of a client's close frame
+    let _ = sink.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::sink;
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+    use tokio::sync::Semaphore;
+
+    const CAPACITY: usize = 16;
+    const STALL_LIMIT: Duration = Duration::from_secs(10);
+
+    /// The client's end of the socket: it only takes frames off when the test lets it.
+    #[derive(Clone)]
+    struct Reader {
+        gate: Arc<Semaphore>,
+        read: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl Reader {
+        fn stalled() -> Self {
+            Reader {
+                gate: Arc::new(Semaphore::new(0)),
+                read: Arc::default(),
+            }
+        }
+
+        fn sink(&self) -> Pin<Box<dyn Sink<Message, Error = ()> + Send>> {
+            Box::pin(sink::unfold(self.clone(), |reader, frame| async move {
+                reader.gate.acquire().await.unwrap().forget();
+                reader.read.lock().unwrap().push(frame);
+                Ok(reader)
+            }))
+        }
+
+        /// Lets `frames` more through, and gives the writer a moment to hand them over.
+        async fn read(&self, frames: usize) -> Vec<Message> {
+            self.gate.add_permits(frames);
+            settle().await;
+            self.read.lock().unwrap().clone()
+        }
+    }
+
+    fn outbox(reader: &Reader) -> Outbox {
+        Outbox::spawn(reader.sink(), CAPACITY, STALL_LIMIT)
+    }
+
+    fn frame(i: usize) -> Message {
+        Message::Text(format!("frame {i}").into())
+    }
+
+    fn frames(range: std::ops::Range<usize>) -> Vec<Message> {
+        range.map(frame).collect()
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn frames_past_a_full_queue_are_dropped_without_waiting() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        out.send(frame(0));
+        settle().await;
+        // the writer is stuck on frame 0; sending never is, however far behind the reader is
+        for i in 1..1000 {
+            out.send(frame(i));
+        }
+        assert!(!out.has_room());
+        assert_eq!(reader.read(1000).await, frames(0..CAPACITY + 1));
+        assert!(out.has_room());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_queue_that_stays_full_is_too_slow_once_the_stall_limit_passes() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        for i in 0..CAPACITY * 2 {
+            out.send(frame(i));
+        }
+        settle().await;
+        assert!(!out.too_slow());
+        tokio::time::advance(STALL_LIMIT - Duration::from_millis(10)).await;
+        assert!(!out.too_slow());
+        tokio::time::advance(Duration::from_millis(10)).await;
+        assert!(out.too_slow());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_reader_that_catches_up_starts_the_stall_over() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        for i in 0..CAPACITY {
+            out.send(frame(i));
+        }
+        settle().await;
+        assert!(!out.too_slow());
+        tokio::time::advance(STALL_LIMIT / 2).await;
+        reader.read(CAPACITY).await;
+        assert!(!out.too_slow());
+
+        // stalled again: timed from now, not from the first stall
+        for i in 0..CAPACITY {
+            out.send(frame(i));
+        }
+        settle().await;
+        assert!(!out.too_slow());
+        tokio::time::advance(STALL_LIMIT * 3 / 4).await;
+        assert!(!out.too_slow());
+        tokio::time::advance(STALL_LIMIT / 4).await;
+        assert!(out.too_slow());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_now_goes_out_ahead_of_the_queue() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        for i in 0..CAPACITY {
+            out.send(frame(i));
+        }
+        settle().await;
+        let close = Message::Close(None);
+        out.close_now(close.clone());
+        // frame 0 was already on its way out; everything still queued behind it is dropped
+        assert_eq!(reader.read(CAPACITY).await, vec![frame(0), close]);
+        assert!(out.is_closed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finish_flushes_the_queue_but_gives_up_on_a_stalled_reader() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        for i in 0..5 {
+            out.send(frame(i));
+        }
+        reader.gate.add_permits(5);
+        out.finish().await;
+        assert_eq!(*reader.read.lock().unwrap(), frames(0..5));
+
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        out.send(frame(0));
+        let started = Instant::now();
+        out.finish().await;
+        assert_eq!(started.elapsed(), DRAIN_TIMEOUT);
+        assert!(reader.read.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_dropped_outbox_still_writes_out_its_queue() {
+        let reader = Reader::stalled();
+        let mut out = outbox(&reader);
+        for i in 0..5 {
+            out.send(frame(i));
+        }
+        let writer = out.writer.abort_handle();
+        drop(out);
+        assert_eq!(reader.read(5).await, frames(0..5));
+        assert!(writer.is_finished());
+    }
+}
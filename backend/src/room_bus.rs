@@ -0,0 +1,986 @@
+// src/room_bus.rs
+use crate::{
+    stats::Counters,
+    ws_messages::{Player, PlayerId, RoomEvent, RoomId, RoomSettings, WsServerMsg},
+};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::broadcast;
+
+/// A room broadcast as it goes out on the wire: a `RoomEvent` serialized once by `RoomTx::send`
+/// and shared by every subscriber, so a room of 16 doesn't encode the same message 16 times.
+pub type RoomPayload = Arc<str>;
+
+/// The delivery path for room broadcasts.
+///
+/// Each room always has a local `broadcast` channel that its sockets on this instance subscribe
+/// to. A bus decides how a published message reaches those channels: `LocalBus` sends straight
+/// into it, while the Redis bus (feature `redis-bus`) round-trips through a `room:{id}` pub/sub
+/// channel so every instance relays it to its own subscribers.
+///
+/// A bus also owns room IDs across instances: `reserve` before creating a room under an ID,
+/// `release` once the room is gone. One that keeps rooms' membership and scores where every
+/// instance can reach them hands out its `SharedRooms`.
+pub trait RoomBus: Send + Sync + fmt::Debug {
+    /// A room's local channel was created. Buses that deliver from elsewhere keep a weak handle.
+    fn attach(&self, _room_id: &RoomId, _local: &broadcast::Sender<RoomPayload>) {}
+
+    /// Claims `room_id` for a room on this instance; `false` if another room (here or on any
+    /// instance sharing the bus) has it. With only this instance, `AppState::rooms` is
+    /// already the whole picture, so this always succeeds by default.
+    fn reserve<'a>(&'a self, _room_id: &'a RoomId) -> BoxFuture<'a, bool> {
+        Box::pin(async { true })
+    }
+
+    /// The room under `room_id` is gone, so the ID may be handed out again. With `SharedRooms`
+    /// that's only once nobody is seated in it on any instance.
+    fn release(&self, _room_id: &RoomId) {}
+
+    /// Where rooms' membership and scores live, if not just in this instance's `RoomState`s.
+    fn shared(&self) -> Option<&dyn SharedRooms> {
+        None
+    }
+
+    /// Deliver `payload` to everyone in the room.
+    fn publish(
+        &self,
+        room_id: &RoomId,
+        local: &broadcast::Sender<RoomPayload>,
+        payload: RoomPayload,
+    );
+}
+
+/// Single-instance bus: messages go directly to the room's local channel.
+#[derive(Debug, Default)]
+pub struct LocalBus;
+
+impl RoomBus for LocalBus {
+    fn publish(
+        &self,
+        _room_id: &RoomId,
+        local: &broadcast::Sender<RoomPayload>,
+        payload: RoomPayload,
+    ) {
+        let _ = local.send(payload);
+    }
+}
+
+/// Room membership and scores kept where every instance sharing the bus can reach them, so any
+/// instance can serve any room.
+///
+/// Each instance with sockets in a room keeps its own `RoomState` for it, a working copy:
+/// `AppState::sync_room` refreshes it from the record before membership or scores are acted
+/// on, and every change to them goes into the record as well. Taking a seat and counting a
+/// turn happen in the record itself, so two instances can't both take the last seat or both
+/// count one turn.
+pub trait SharedRooms: Send + Sync + fmt::Debug {
+    /// Records a room just made under a reserved ID.
+    fn open<'a>(&'a self, room_id: &'a RoomId, record: &'a RoomRecord) -> BoxFuture<'a, ()>;
+
+    /// The room as last written, or `None` if it's gone.
+    fn load<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+    ) -> BoxFuture<'a, anyhow::Result<Option<RoomRecord>>>;
+
+    /// Seats `player` on a score of 0, unless the room is gone or has `max_players` (0: any
+    /// number) seated already. A player already seated keeps their seat.
+    fn take_seat<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+        player: &'a Player,
+        max_players: u32,
+    ) -> BoxFuture<'a, SeatClaim>;
+
+    /// Adds `delta` to the player's score if `round` is the game being played and `turn` is
+    /// the one after their last, all in one step. Every seated player's total once it's
+    /// counted, or `None` if it isn't.
+    fn add_score<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+        player_id: &'a PlayerId,
+        round: u64,
+        turn: u32,
+        delta: u32,
+    ) -> BoxFuture<'a, Option<HashMap<PlayerId, u32>>>;
+
+    /// Applies `change` in the background. One instance's changes land in the order it makes
+    /// them, and before any broadcast it publishes after them.
+    fn write(&self, room_id: &RoomId, change: RoomChange);
+}
+
+/// A room's shared membership and scores: what `SharedRooms` keeps.
+#[derive(Debug, Clone)]
+pub struct RoomRecord {
+    pub owner: PlayerId,
+    pub settings: RoomSettings,
+    /// Seated players, in the order they sat down.
+    pub players: Vec<Player>,
+    /// The token each seat was last issued, so a seat can be taken back through any instance.
+    pub seat_tokens: HashMap<PlayerId, String>,
+    pub scores: HashMap<PlayerId, u32>,
+    /// Per player, the last turn counted this game.
+    pub turns: HashMap<PlayerId, u32>,
+    /// Games dealt so far; the latest is `round`.
+    pub round: u64,
+    /// The game being played, if any.
+    pub game: Option<LiveRound>,
+}
+
+/// The game a room is playing, as dealt by whichever instance started it (and times it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveRound {
+    /// Every board this game is dealt from this, with `board::board_from_seed`.
+    pub seed: u64,
+    /// When time runs out, as Unix ms.
+    pub ends_at_ms: u64,
+}
+
+/// How `SharedRooms::take_seat` went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeatClaim {
+    Seated,
+    Full,
+    Gone,
+}
+
+/// A change to a room's `RoomRecord`, for `SharedRooms::write`.
+#[derive(Debug, Clone)]
+pub enum RoomChange {
+    /// A seated player's name or flags; dropped if they've left in the meantime.
+    Player(Player),
+    SeatToken { player_id: PlayerId, token: String },
+    /// The player gave up their seat, and their score with it.
+    Unseat(PlayerId),
+    Owner(PlayerId),
+    /// Game `round` was dealt: every seated player starts it on 0.
+    RoundStarted { round: u64, game: LiveRound },
+    /// Game `round` is over (a later one may already have started, and stays).
+    RoundEnded { round: u64 },
+    /// The room is closed for everyone, seated or not.
+    Closed,
+}
+
+/// A room's sending half: numbers and serializes each broadcast, publishes it through the bus,
+/// subscribes locally. The counter is shared by every clone, so the game timer's broadcasts are
+/// numbered in the same sequence as those sent under the rooms lock.
+/// Dropping every clone closes the local channel, which is how sockets learn the room is gone.
+#[derive(Clone, Debug)]
+pub struct RoomTx {
+    room_id: RoomId,
+    local: broadcast::Sender<RoomPayload>,
+    bus: Arc<dyn RoomBus>,
+    seq: Arc<AtomicU64>,
+    counters: Arc<Counters>,
+}
+
+impl RoomTx {
+    /// `capacity` is how many broadcasts each local subscriber can fall behind by.
+    pub fn new(
+        room_id: RoomId,
+        bus: Arc<dyn RoomBus>,
+        counters: Arc<Counters>,
+        capacity: usize,
+    ) -> Self {
+        let (local, _) = broadcast::channel(capacity);
+        bus.attach(&room_id, &local);
+        RoomTx {
+            room_id,
+            local,
+            bus,
+            seq: Arc::new(AtomicU64::new(0)),
+            counters,
+        }
+    }
+
+    pub fn send(&self, msg: WsServerMsg) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters.broadcast();
+        let payload = serde_json::to_string(&RoomEvent { seq, msg }).unwrap();
+        self.bus.publish(&self.room_id, &self.local, payload.into());
+    }
+
+    pub fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+
+    /// The `seq` of the latest broadcast (0 before the first), i.e. how many this room has sent.
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RoomPayload> {
+        self.local.subscribe()
+    }
+
+    /// Writes `change` into the room's shared record, when the bus keeps one. Sent before a
+    /// broadcast, it lands before that broadcast reaches any instance.
+    pub fn share(&self, change: RoomChange) {
+        if let Some(shared) = self.bus.shared() {
+            shared.write(&self.room_id, change);
+        }
+    }
+}
+
+/// Redis pub/sub fan-out and shared rooms, for running several instances behind one load
+/// balancer. Any instance can serve any room, so the balancer needn't be sticky.
+///
+/// What is shared through Redis:
+/// - Broadcasts: one published on any instance reaches that room's sockets on every instance.
+/// - Room IDs: each live room holds a `room-id:{id}` key (`SET NX`, owned by the instance that
+///   made the room), so two instances never hand out the same code. The keys expire after
+///   `ROOM_ID_TTL` unless an instance with the room open refreshes them, so a crashed
+///   instance's codes come back.
+/// - Membership and scores (`SharedRooms`): a `room-state:{id}` hash per room with its owner,
+///   settings, seats and their tokens, scores, turns and the game being played. A seat is
+///   taken optimistically: `WATCH` the hash, check there's room, `MULTI`/`EXEC` the seat, and
+///   read again if anyone wrote in between. A turn is checked and its points `HINCRBY`'d by
+///   one script, so nothing gets in between those either. Every other change goes out on the
+///   connection the broadcasts do, in order.
+///
+/// What isn't: sockets, and in an instance's copy of a room the boards its own players clear,
+/// the chat log and mutes, and the timer of a game it started (it records that game's result).
+/// An instance drops its copy of a room once none of its sockets are in it.
+#[cfg(feature = "redis-bus")]
+pub mod redis_bus {
+    use super::{
+        LiveRound, RoomBus, RoomChange, RoomPayload, RoomRecord, SeatClaim, SharedRooms,
+    };
+    use crate::ws_messages::{Player, PlayerId, RoomId};
+    use anyhow::{Context, Result};
+    use futures_util::{future::BoxFuture, StreamExt};
+    use redis::{aio::MultiplexedConnection, AsyncCommands};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::sync::{broadcast, mpsc};
+
+    type LocalRooms = Arc<Mutex<HashMap<RoomId, broadcast::WeakSender<RoomPayload>>>>;
+
+    /// How long a room ID (and the room's record) stays without a refresh; instances with the
+    /// room open refresh at a third of this, so only a room nobody has open runs out.
+    const ROOM_ID_TTL: Duration = Duration::from_secs(10 * 60);
+
+    /// How often a seat is tried for in a room that keeps changing under the `WATCH`.
+    const SEAT_ATTEMPTS: usize = 8;
+
+    /// Deletes a reservation and the room's record once nobody is seated in the room, if this
+    /// instance owns the reservation or the room was opened at all.
+    const RELEASE_SCRIPT: &str = r#"
+        for _, field in ipairs(redis.call('HKEYS', KEYS[2])) do
+            if string.sub(field, 1, 7) == 'player:' then
+                return 0
+            end
+        end
+        if redis.call('GET', KEYS[1]) == ARGV[1] or redis.call('EXISTS', KEYS[2]) == 1 then
+            return redis.call('DEL', KEYS[1], KEYS[2])
+        end
+        return 0
+    "#;
+
+    /// Counts turn `ARGV[3]` of player `ARGV[1]` in round `ARGV[2]`: adds `ARGV[4]` points if
+    /// the round is being played and the turn is the one after their last. Returns every
+    /// seated player's total as `player_id, score` pairs, or nil.
+    const SCORE_SCRIPT: &str = r#"
+        local pid = ARGV[1]
+        if redis.call('HEXISTS', KEYS[1], 'player:' .. pid) == 0
+            or redis.call('HGET', KEYS[1], 'round') ~= ARGV[2]
+            or redis.call('HEXISTS', KEYS[1], 'ends_at_ms') == 0 then
+            return false
+        end
+        local last = tonumber(redis.call('HGET', KEYS[1], 'turn:' .. pid) or '0')
+        if tonumber(ARGV[3]) ~= last + 1 then
+            return false
+        end
+        redis.call('HSET', KEYS[1], 'turn:' .. pid, ARGV[3])
+        redis.call('HINCRBY', KEYS[1], 'score:' .. pid, ARGV[4])
+        local totals = {}
+        local fields = redis.call('HGETALL', KEYS[1])
+        for i = 1, #fields, 2 do
+            if string.sub(fields[i], 1, 6) == 'score:' then
+                totals[#totals + 1] = string.sub(fields[i], 7)
+                totals[#totals + 1] = fields[i + 1]
+            end
+        end
+        return totals
+    "#;
+
+    /// Sets field `ARGV[2]` to `ARGV[3]` if player `ARGV[1]` is still seated.
+    const SEATED_SCRIPT: &str = r#"
+        if redis.call('HEXISTS', KEYS[1], 'player:' .. ARGV[1]) == 1 then
+            redis.call('HSET', KEYS[1], ARGV[2], ARGV[3])
+        end
+    "#;
+
+    /// Sets field `ARGV[1]` to `ARGV[2]` if the room is still open.
+    const OPEN_SCRIPT: &str = r#"
+        if redis.call('EXISTS', KEYS[1]) == 1 then
+            redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+        end
+    "#;
+
+    /// Deals round `ARGV[1]` (seed `ARGV[2]`, ending at `ARGV[3]`): every score back to 0,
+    /// every turn forgotten, nobody ready.
+    const ROUND_STARTED_SCRIPT: &str = r#"
+        if redis.call('EXISTS', KEYS[1]) == 0 then
+            return
+        end
+        local fields = redis.call('HGETALL', KEYS[1])
+        for i = 1, #fields, 2 do
+            local field = fields[i]
+            if string.sub(field, 1, 6) == 'score:' then
+                redis.call('HSET', KEYS[1], field, 0)
+            elseif string.sub(field, 1, 5) == 'turn:' then
+                redis.call('HDEL', KEYS[1], field)
+            elseif string.sub(field, 1, 7) == 'player:' then
+                local player = cjson.decode(fields[i + 1])
+                player.ready = false
+                redis.call('HSET', KEYS[1], field, cjson.encode(player))
+            end
+        end
+        redis.call('HSET', KEYS[1], 'round', ARGV[1], 'seed', ARGV[2], 'ends_at_ms', ARGV[3])
+    "#;
+
+    /// Ends round `ARGV[1]`, unless a later one has been dealt since.
+    const ROUND_ENDED_SCRIPT: &str = r#"
+        if redis.call('HGET', KEYS[1], 'round') == ARGV[1] then
+            redis.call('HDEL', KEYS[1], 'seed', 'ends_at_ms')
+        end
+    "#;
+
+    fn room_id_key(room_id: &RoomId) -> String {
+        format!("room-id:{room_id}")
+    }
+
+    fn room_state_key(room_id: &RoomId) -> String {
+        format!("room-state:{room_id}")
+    }
+
+    /// `script` run on the room's record, for the write queue; add its `ARGV` after.
+    fn eval(script: &str, room_id: &RoomId) -> redis::Cmd {
+        let mut cmd = redis::cmd("EVAL");
+        cmd.arg(script).arg(1).arg(room_state_key(room_id));
+        cmd
+    }
+
+    /// The command that makes `change` to the room's record.
+    fn change_cmd(room_id: &RoomId, change: RoomChange) -> redis::Cmd {
+        match change {
+            RoomChange::Player(player) => {
+                let mut cmd = eval(SEATED_SCRIPT, room_id);
+                let json = serde_json::to_string(&player).unwrap();
+                let field = format!("player:{}", player.player_id);
+                cmd.arg(player.player_id).arg(field).arg(json);
+                cmd
+            }
+            RoomChange::SeatToken { player_id, token } => {
+                let mut cmd = eval(SEATED_SCRIPT, room_id);
+                cmd.arg(&player_id).arg(format!("token:{player_id}")).arg(token);
+                cmd
+            }
+            RoomChange::Unseat(player_id) => {
+                let mut cmd = redis::cmd("HDEL");
+                cmd.arg(room_state_key(room_id));
+                for prefix in ["player", "order", "token", "score", "turn"] {
+                    cmd.arg(format!("{prefix}:{player_id}"));
+                }
+                cmd
+            }
+            RoomChange::Owner(player_id) => {
+                let mut cmd = eval(OPEN_SCRIPT, room_id);
+                cmd.arg("owner").arg(player_id);
+                cmd
+            }
+            RoomChange::RoundStarted { round, game } => {
+                let mut cmd = eval(ROUND_STARTED_SCRIPT, room_id);
+                cmd.arg(round).arg(game.seed).arg(game.ends_at_ms);
+                cmd
+            }
+            RoomChange::RoundEnded { round } => {
+                let mut cmd = eval(ROUND_ENDED_SCRIPT, room_id);
+                cmd.arg(round);
+                cmd
+            }
+            RoomChange::Closed => {
+                let mut cmd = redis::cmd("DEL");
+                cmd.arg(room_id_key(room_id)).arg(room_state_key(room_id));
+                cmd
+            }
+        }
+    }
+
+    /// The fields `open` writes for `record`.
+    fn record_fields(record: &RoomRecord) -> Vec<(String, String)> {
+        let mut fields = vec![
+            ("owner".to_owned(), record.owner.clone()),
+            ("settings".to_owned(), serde_json::to_string(&record.settings).unwrap()),
+            ("seats".to_owned(), record.players.len().to_string()),
+            ("round".to_owned(), record.round.to_string()),
+        ];
+        for (seat, player) in record.players.iter().enumerate() {
+            let pid = &player.player_id;
+            let score = record.scores.get(pid).copied().unwrap_or(0);
+            fields.push((format!("player:{pid}"), serde_json::to_string(player).unwrap()));
+            fields.push((format!("order:{pid}"), (seat + 1).to_string()));
+            fields.push((format!("score:{pid}"), score.to_string()));
+            if let Some(token) = record.seat_tokens.get(pid) {
+                fields.push((format!("token:{pid}"), token.clone()));
+            }
+        }
+        fields
+    }
+
+    /// Reads a room's record back out of its hash; `None` if there's no such room.
+    fn parse_record(fields: &HashMap<String, String>) -> Option<RoomRecord> {
+        let number = |field: &str| fields.get(field).and_then(|v| v.parse::<u64>().ok());
+        let mut seated = Vec::new();
+        let (mut seat_tokens, mut scores, mut turns) =
+            (HashMap::new(), HashMap::new(), HashMap::new());
+        for (field, value) in fields {
+            let Some((kind, pid)) = field.split_once(':') else {
+                continue;
+            };
+            match kind {
+                "player" => {
+                    if let Ok(player) = serde_json::from_str::<Player>(value) {
+                        seated.push((number(&format!("order:{pid}")), player));
+                    }
+                }
+                "token" => {
+                    seat_tokens.insert(pid.to_owned(), value.clone());
+                }
+                "score" => {
+                    scores.insert(pid.to_owned(), value.parse().unwrap_or(0));
+                }
+                "turn" => {
+                    turns.insert(pid.to_owned(), value.parse().unwrap_or(0));
+                }
+                _ => {}
+            }
+        }
+        seated.sort_by_key(|(seat, _)| seat.unwrap_or(u64::MAX));
+        let game = match (number("seed"), number("ends_at_ms")) {
+            (Some(seed), Some(ends_at_ms)) => Some(LiveRound { seed, ends_at_ms }),
+            _ => None,
+        };
+        Some(RoomRecord {
+            owner: fields.get("owner")?.clone(),
+            settings: serde_json::from_str(fields.get("settings")?).ok()?,
+            players: seated.into_iter().map(|(_, player)| player).collect(),
+            seat_tokens,
+            scores,
+            turns,
+            round: number("round").unwrap_or(0),
+            game,
+        })
+    }
+
+    #[derive(Debug)]
+    pub struct RedisBus {
+        // Broadcasts and record changes, sent one after the other on one connection.
+        outgoing: mpsc::UnboundedSender<(RoomId, redis::Cmd)>,
+        rooms: LocalRooms,
+        // For the connections of seat transactions: a `WATCH` is per connection.
+        client: redis::Client,
+        conn: MultiplexedConnection,
+        // What this instance writes into its reservations, so it only ever releases its own.
+        instance_id: String,
+    }
+
+    impl RedisBus {
+        /// Connects to `url`, subscribes to `room:*` and spawns the publish and relay tasks.
+        pub async fn connect(url: &str) -> Result<Self> {
+            let client = redis::Client::open(url).context("invalid Redis URL")?;
+            let mut publisher = client
+                .get_multiplexed_async_connection()
+                .await
+                .context("cannot connect to Redis")?;
+            let mut pubsub = client
+                .get_async_pubsub()
+                .await
+                .context("cannot open Redis pub/sub connection")?;
+            pubsub.psubscribe("room:*").await?;
+
+            let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<(RoomId, redis::Cmd)>();
+            tokio::spawn(async move {
+                while let Some((room_id, cmd)) = outgoing_rx.recv().await {
+                    let sent: redis::RedisResult<()> = cmd.query_async(&mut publisher).await;
+                    if let Err(e) = sent {
+                        tracing::warn!(room_id = %room_id, error = %e, "redis write failed");
+                    }
+                }
+            });
+
+            let rooms = LocalRooms::default();
+            let relay_rooms = rooms.clone();
+            tokio::spawn(async move {
+                let mut messages = pubsub.into_on_message();
+                while let Some(msg) = messages.next().await {
+                    let Some(room_id) = msg.get_channel_name().strip_prefix("room:") else {
+                        continue;
+                    };
+                    let Ok(payload) = msg.get_payload::<String>() else {
+                        continue;
+                    };
+                    // already a serialized `RoomEvent`, relayed to the sockets as-is
+                    let mut rooms = relay_rooms.lock().unwrap();
+                    match rooms.get(room_id).and_then(|weak| weak.upgrade()) {
+                        Some(local) => {
+                            let _ = local.send(payload.into());
+                        }
+                        // not open here, or the room has been closed
+                        None => {
+                            rooms.remove(room_id);
+                        }
+                    }
+                }
+                tracing::error!("redis pub/sub stream ended, cross-instance messages stopped");
+            });
+
+            let instance_id = uuid::Uuid::new_v4().simple().to_string();
+            tokio::spawn(refresh_reservations(
+                client.get_multiplexed_async_connection().await?,
+                rooms.clone(),
+            ));
+
+            tracing::info!(instance_id = %instance_id, "rooms are shared through redis");
+            Ok(RedisBus {
+                outgoing,
+                rooms,
+                conn: client.get_multiplexed_async_connection().await?,
+                client,
+                instance_id,
+            })
+        }
+
+        async fn try_take_seat(
+            &self,
+            room_id: &RoomId,
+            player: &Player,
+            max_players: u32,
+        ) -> redis::RedisResult<SeatClaim> {
+            let key = room_state_key(room_id);
+            let pid = &player.player_id;
+            let json = serde_json::to_string(player).unwrap();
+            let mut conn = self.client.get_multiplexed_async_connection().await?;
+            for _ in 0..SEAT_ATTEMPTS {
+                redis::cmd("WATCH").arg(&key).query_async::<()>(&mut conn).await?;
+                let fields: HashMap<String, String> = conn.hgetall(&key).await?;
+                if fields.is_empty() {
+                    return Ok(SeatClaim::Gone);
+                }
+                if fields.contains_key(&format!("player:{pid}")) {
+                    return Ok(SeatClaim::Seated);
+                }
+                let seated = fields.keys().filter(|f| f.starts_with("player:")).count();
+                if max_players > 0 && seated >= max_players as usize {
+                    return Ok(SeatClaim::Full);
+                }
+                let seats = fields.get("seats").and_then(|n| n.parse::<u64>().ok());
+                let seat = seats.unwrap_or(0) + 1;
+                // nil if the hash changed since the WATCH: someone else got in first
+                let taken: Option<()> = redis::pipe()
+                    .atomic()
+                    .hset(&key, format!("player:{pid}"), &json)
+                    .ignore()
+                    .hset(&key, format!("order:{pid}"), seat)
+                    .ignore()
+                    .hset(&key, format!("score:{pid}"), 0)
+                    .ignore()
+                    .hset(&key, "seats", seat)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await?;
+                if taken.is_some() {
+                    return Ok(SeatClaim::Seated);
+                }
+            }
+            // still losing the race this many reads later: as good as full
+            tracing::warn!(room_id = %room_id, "gave up on a seat in a room that kept changing");
+            Ok(SeatClaim::Full)
+        }
+    }
+
+    /// Keeps the reservations and records of the rooms still open here from expiring.
+    async fn refresh_reservations(mut conn: MultiplexedConnection, rooms: LocalRooms) {
+        let mut ticks = tokio::time::interval(ROOM_ID_TTL / 3);
+        loop {
+            ticks.tick().await;
+            let open: Vec<RoomId> = {
+                let mut rooms = rooms.lock().unwrap();
+                rooms.retain(|_, weak| weak.strong_count() > 0);
+                rooms.keys().cloned().collect()
+            };
+            for room_id in open {
+                let ttl = ROOM_ID_TTL.as_secs() as i64;
+                let refreshed: redis::RedisResult<()> = redis::pipe()
+                    .expire(room_id_key(&room_id), ttl)
+                    .ignore()
+                    .expire(room_state_key(&room_id), ttl)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                if let Err(e) = refreshed {
+                    tracing::warn!(
+                        room_id = %room_id,
+                        error = %e,
+                        "cannot refresh room id reservation"
+                    );
+                }
+            }
+        }
+    }
+
+    impl RoomBus for RedisBus {
+        fn attach(&self, room_id: &RoomId, local: &broadcast::Sender<RoomPayload>) {
+            self.rooms
+                .lock()
+                .unwrap()
+                .insert(room_id.clone(), local.downgrade());
+        }
+
+        fn reserve<'a>(&'a self, room_id: &'a RoomId) -> BoxFuture<'a, bool> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let set: redis::RedisResult<Option<String>> = redis::cmd("SET")
+                    .arg(room_id_key(room_id))
+                    .arg(&self.instance_id)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(ROOM_ID_TTL.as_secs())
+                    .query_async(&mut conn)
+                    .await;
+                match set {
+                    Ok(reply) => reply.is_some(),
+                    // refusing is safe: the caller just tries another code, or gives up
+                    Err(e) => {
+                        tracing::warn!(room_id = %room_id, error = %e, "cannot reserve room id");
+                        false
+                    }
+                }
+            })
+        }
+
+        // queued behind this instance's writes, so it sees the seats they gave up
+        fn release(&self, room_id: &RoomId) {
+            let mut cmd = redis::cmd("EVAL");
+            cmd.arg(RELEASE_SCRIPT)
+                .arg(2)
+                .arg(room_id_key(room_id))
+                .arg(room_state_key(room_id))
+                .arg(&self.instance_id);
+            let _ = self.outgoing.send((room_id.clone(), cmd));
+        }
+
+        fn shared(&self) -> Option<&dyn SharedRooms> {
+            Some(self)
+        }
+
+        fn publish(
+            &self,
+            room_id: &RoomId,
+            _local: &broadcast::Sender<RoomPayload>,
+            payload: RoomPayload,
+        ) {
+            let mut cmd = redis::cmd("PUBLISH");
+            cmd.arg(format!("room:{room_id}")).arg(&*payload);
+            let _ = self.outgoing.send((room_id.clone(), cmd));
+        }
+    }
+
+    impl SharedRooms for RedisBus {
+        fn open<'a>(&'a self, room_id: &'a RoomId, record: &'a RoomRecord) -> BoxFuture<'a, ()> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let key = room_state_key(room_id);
+                let opened: redis::RedisResult<()> = redis::pipe()
+                    .atomic()
+                    .hset_multiple(&key, &record_fields(record))
+                    .ignore()
+                    .expire(&key, ROOM_ID_TTL.as_secs() as i64)
+                    .ignore()
+                    .query_async(&mut conn)
+                    .await;
+                // the room still works here; other instances just can't find it
+                if let Err(e) = opened {
+                    tracing::warn!(room_id = %room_id, error = %e, "cannot share room");
+                }
+            })
+        }
+
+        fn load<'a>(&'a self, room_id: &'a RoomId) -> BoxFuture<'a, Result<Option<RoomRecord>>> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let fields: HashMap<String, String> = conn
+                    .hgetall(room_state_key(room_id))
+                    .await
+                    .context("cannot load room from Redis")?;
+                Ok(parse_record(&fields))
+            })
+        }
+
+        fn take_seat<'a>(
+            &'a self,
+            room_id: &'a RoomId,
+            player: &'a Player,
+            max_players: u32,
+        ) -> BoxFuture<'a, SeatClaim> {
+            Box::pin(async move {
+                match self.try_take_seat(room_id, player, max_players).await {
+                    Ok(claim) => claim,
+                    // as if the room were gone: the join is refused, and can be tried again
+                    Err(e) => {
+                        tracing::warn!(room_id = %room_id, error = %e, "cannot take seat");
+                        SeatClaim::Gone
+                    }
+                }
+            })
+        }
+
+        fn add_score<'a>(
+            &'a self,
+            room_id: &'a RoomId,
+            player_id: &'a PlayerId,
+            round: u64,
+            turn: u32,
+            delta: u32,
+        ) -> BoxFuture<'a, Option<HashMap<PlayerId, u32>>> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let totals: redis::RedisResult<Option<HashMap<PlayerId, u32>>> =
+                    redis::Script::new(SCORE_SCRIPT)
+                        .key(room_state_key(room_id))
+                        .arg(player_id)
+                        .arg(round)
+                        .arg(turn)
+                        .arg(delta)
+                        .invoke_async(&mut conn)
+                        .await;
+                totals.unwrap_or_else(|e| {
+                    tracing::warn!(room_id = %room_id, error = %e, "cannot add score");
+                    None
+                })
+            })
+        }
+
+        fn write(&self, room_id: &RoomId, change: RoomChange) {
+            let _ = self.outgoing.send((room_id.clone(), change_cmd(room_id, change)));
+        }
+    }
+
+    /// These need a real Redis and are skipped without one. To run them against a container:
+    /// `docker run --rm -p 6379:6379 redis:7`, then
+    /// `REDIS_TEST_URL=redis://127.0.0.1/ cargo test --features redis-bus redis_bus`.
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Two buses on one Redis, standing in for two instances.
+        async fn instances() -> Option<(RedisBus, RedisBus)> {
+            let Ok(url) = std::env::var("REDIS_TEST_URL") else {
+                eprintln!("REDIS_TEST_URL is not set, skipping");
+                return None;
+            };
+            let a = RedisBus::connect(&url).await.unwrap();
+            let b = RedisBus::connect(&url).await.unwrap();
+            Some((a, b))
+        }
+
+        fn fresh_room_id() -> RoomId {
+            format!("test-{}", uuid::Uuid::new_v4().simple())
+        }
+
+        fn player(id: &str) -> Player {
+            Player {
+                player_id: id.to_owned(),
+                name: id.to_owned(),
+                ready: false,
+                muted: false,
+            }
+        }
+
+        /// Opens a room on `a` under a fresh ID, with `owner` seated.
+        async fn open_room(a: &RedisBus, owner: &str) -> RoomId {
+            let room_id = fresh_room_id();
+            assert!(a.reserve(&room_id).await);
+            let record = RoomRecord {
+                owner: owner.to_owned(),
+                settings: Default::default(),
+                players: vec![player(owner)],
+                seat_tokens: HashMap::new(),
+                scores: HashMap::from([(owner.to_owned(), 0)]),
+                turns: HashMap::new(),
+                round: 0,
+                game: None,
+            };
+            a.open(&room_id, &record).await;
+            room_id
+        }
+
+        /// Deals round 1 of `room_id` through `bus`, and waits until it's recorded.
+        async fn start_round(bus: &RedisBus, room_id: &RoomId) {
+            let game = LiveRound { seed: 7, ends_at_ms: u64::MAX / 2 };
+            bus.write(room_id, RoomChange::RoundStarted { round: 1, game });
+            until(bus, room_id, |record| record.as_ref().is_some_and(|r| r.game.is_some())).await;
+        }
+
+        /// Reloads `room_id` through `bus` until `done` holds for it; what it was then.
+        async fn until(
+            bus: &RedisBus,
+            room_id: &RoomId,
+            done: impl Fn(&Option<RoomRecord>) -> bool,
+        ) -> Option<RoomRecord> {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            loop {
+                let record = bus.load(room_id).await.unwrap();
+                if done(&record) {
+                    return record;
+                }
+                assert!(tokio::time::Instant::now() < deadline, "never got there: {record:?}");
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        #[tokio::test]
+        async fn a_seat_and_a_score_land_through_the_other_instance() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = open_room(&a, "host").await;
+
+            assert_eq!(b.take_seat(&room_id, &player("guest"), 0).await, SeatClaim::Seated);
+            assert_eq!(b.take_seat(&room_id, &player("guest"), 0).await, SeatClaim::Seated);
+            let seen = a.load(&room_id).await.unwrap().unwrap();
+            let seated: Vec<_> = seen.players.iter().map(|p| p.player_id.as_str()).collect();
+            assert_eq!(seated, ["host", "guest"], "seated once, after the owner");
+
+            start_round(&a, &room_id).await;
+            let totals = b.add_score(&room_id, &"guest".to_owned(), 1, 1, 12).await.unwrap();
+            assert_eq!(totals["guest"], 12);
+            assert_eq!(totals["host"], 0);
+            let seen = a.load(&room_id).await.unwrap().unwrap();
+            assert_eq!(seen.scores["guest"], 12);
+            assert_eq!(seen.turns["guest"], 1);
+
+            a.write(&room_id, RoomChange::Closed);
+            until(&b, &room_id, Option::is_none).await;
+            assert_eq!(b.take_seat(&room_id, &player("late"), 0).await, SeatClaim::Gone);
+        }
+
+        #[tokio::test]
+        async fn the_last_seat_goes_to_one_instance_only() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = open_room(&a, "host").await;
+            let (first, second) = (player("first"), player("second"));
+            let (on_a, on_b) =
+                tokio::join!(a.take_seat(&room_id, &first, 2), b.take_seat(&room_id, &second, 2));
+            let mut claims = [on_a, on_b];
+            claims.sort_by_key(|claim| *claim as u8);
+            assert_eq!(claims, [SeatClaim::Seated, SeatClaim::Full]);
+            assert_eq!(a.load(&room_id).await.unwrap().unwrap().players.len(), 2);
+            a.write(&room_id, RoomChange::Closed);
+        }
+
+        #[tokio::test]
+        async fn a_turn_counts_once_whichever_instance_sends_it() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = open_room(&a, "host").await;
+            let host = "host".to_owned();
+            // not yet dealt: nothing counts
+            assert_eq!(a.add_score(&room_id, &host, 1, 1, 5).await, None);
+            start_round(&b, &room_id).await;
+
+            let (on_a, on_b) = tokio::join!(
+                a.add_score(&room_id, &host, 1, 1, 5),
+                b.add_score(&room_id, &host, 1, 1, 5)
+            );
+            assert!(on_a.is_some() ^ on_b.is_some(), "exactly one instance counts the turn");
+            // a turn skipped, or one from a round that isn't being played
+            assert_eq!(b.add_score(&room_id, &host, 1, 3, 5).await, None);
+            assert_eq!(b.add_score(&room_id, &host, 0, 2, 5).await, None);
+            assert_eq!(a.add_score(&room_id, &host, 1, 2, 3).await.unwrap()["host"], 8);
+
+            b.write(&room_id, RoomChange::RoundEnded { round: 1 });
+            until(&a, &room_id, |record| record.as_ref().unwrap().game.is_none()).await;
+            assert_eq!(a.add_score(&room_id, &host, 1, 3, 5).await, None);
+            a.write(&room_id, RoomChange::Closed);
+        }
+
+        #[tokio::test]
+        async fn a_room_is_released_only_once_nobody_is_seated_in_it() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = open_room(&a, "host").await;
+            assert_eq!(b.take_seat(&room_id, &player("guest"), 0).await, SeatClaim::Seated);
+
+            // the host leaves through the instance that made the room; the guest is still in
+            a.write(&room_id, RoomChange::Unseat("host".to_owned()));
+            a.release(&room_id);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let seen = a.load(&room_id).await.unwrap().expect("released with a guest in it");
+            assert_eq!(seen.players.len(), 1);
+            assert!(!a.reserve(&room_id).await, "the room id is still taken");
+
+            // then the guest leaves through the other one
+            b.write(&room_id, RoomChange::Unseat("guest".to_owned()));
+            b.release(&room_id);
+            until(&a, &room_id, Option::is_none).await;
+            assert!(a.reserve(&room_id).await, "the room id is free again");
+            a.release(&room_id);
+        }
+
+        #[tokio::test]
+        async fn a_broadcast_reaches_the_room_on_the_other_instance() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = fresh_room_id();
+            let (local_b, mut rx_b) = broadcast::channel(8);
+            b.attach(&room_id, &local_b);
+            let (local_a, _) = broadcast::channel(8);
+            a.publish(&room_id, &local_a, RoomPayload::from(r#"{"seq":1}"#));
+
+            let got = tokio::time::timeout(Duration::from_secs(5), rx_b.recv()).await;
+            assert_eq!(&*got.unwrap().unwrap(), r#"{"seq":1}"#);
+        }
+
+        #[tokio::test]
+        async fn a_room_id_belongs_to_one_instance_until_it_releases_it() {
+            let Some((a, b)) = instances().await else {
+                return;
+            };
+            let room_id = fresh_room_id();
+            let (first, second) = tokio::join!(a.reserve(&room_id), b.reserve(&room_id));
+            assert!(first ^ second, "exactly one instance gets the id");
+            let (owner, other) = if first { (&a, &b) } else { (&b, &a) };
+            assert!(!owner.reserve(&room_id).await, "not even its owner gets it twice");
+
+            // only the owner's release counts
+            other.release(&room_id);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert!(!other.reserve(&room_id).await);
+
+            owner.release(&room_id);
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            while !other.reserve(&room_id).await {
+                assert!(tokio::time::Instant::now() < deadline, "release never landed");
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            other.release(&room_id);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// src/score_store.rs
+use crate::server_state::TopScores;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BinaryHeap,
+    fmt,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::{fs, sync::Semaphore};
+
+/// Where the all-time top 10 is kept between games.
+///
+/// `FileStore` is what the server runs with; `MemoryStore` keeps everything in the process, so
+/// a state built with `AppState::new` never touches the disk when a game ends.
+pub trait ScoreStore: Send + Sync + fmt::Debug {
+    /// The saved top 10; empty if nothing was saved yet or it can't be read.
+    fn load(&self) -> BoxFuture<'_, TopScores>;
+
+    /// Replace the saved top 10. Failures are logged, not returned: a game must end either way.
+    fn save<'a>(&'a self, top_10: &'a TopScores) -> BoxFuture<'a, ()>;
+}
+
+/// One top-10 entry as written to the scores file.
+#[derive(Serialize, Deserialize)]
+pub struct TopScoreEntry {
+    pub score: u32,
+    pub name: String,
+}
+
+/// The top 10 as a JSON file, gzipped when the path ends in `.gz`.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> Self {
+        FileStore { path }
+    }
+}
+
+impl ScoreStore for FileStore {
+    fn load(&self) -> BoxFuture<'_, TopScores> {
+        Box::pin(async move {
+            let path = &self.path;
+            if let Ok(data) = fs::read(path).await {
+                let data = if is_gzip(path) { gunzip(&data) } else { Ok(data) };
+                let Ok(data) = data else {
+                    tracing::error!(path = %path.display(), "top-10 file is not valid gzip");
+                    return BinaryHeap::new();
+                };
+                if let Ok(entries) = serde_json::from_slice::<Vec<TopScoreEntry>>(&data) {
+                    let mut heap = BinaryHeap::new();
+                    for entry in entries {
+                        heap.push((std::cmp::Reverse(entry.score), entry.name));
+                    }
+                    return heap;
+                }
+            }
+            BinaryHeap::new()
+        })
+    }
+
+    fn save<'a>(&'a self, top_10: &'a TopScores) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let path = &self.path;
+            let vec: Vec<_> = top_10
+                .iter()
+                .map(|r| TopScoreEntry {
+                    score: r.0 .0,
+                    name: r.1.clone(),
+                })
+                .collect();
+            let mut data = serde_json::to_vec_pretty(&vec).unwrap();
+            if is_gzip(path) {
+                data = gzip(&data);
+            }
+            tracing::debug!(entries = vec.len(), "saving top-10");
+            tracing::trace!(?top_10, "top-10 contents");
+            // write to a sibling file and rename, so a kill mid-write never leaves a torn scores file
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            if let Err(e) = fs::write(&tmp, data).await {
+                tracing::error!(error = %e, path = ?tmp, "failed to write top-10 temp file");
+                return;
+            }
+            if let Err(e) = fs::rename(&tmp, path).await {
+                tracing::error!(error = %e, path = %path.display(), "failed to replace top-10 file");
+            }
+        })
+    }
+}
+
+/// A top 10 that lives only as long as the process, for tests and throwaway instances.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    saved: Mutex<TopScores>,
+}
+
+impl ScoreStore for MemoryStore {
+    fn load(&self) -> BoxFuture<'_, TopScores> {
+        let saved = self.saved.lock().unwrap().clone();
+        Box::pin(async move { saved })
+    }
+
+    fn save<'a>(&'a self, top_10: &'a TopScores) -> BoxFuture<'a, ()> {
+        *self.saved.lock().unwrap() = top_10.clone();
+        Box::pin(async {})
+    }
+}
+
+/// A copy of the top 10 taken under its lock, numbered in the order the copies were taken.
+#[derive(Debug)]
+pub struct Snapshot {
+    seq: u64,
+    top_10: TopScores,
+}
+
+/// Saves top-10 snapshots with the live top 10 already unlocked, one write at a time. A snapshot
+/// that lost the race to a newer one is dropped rather than written over it.
+#[derive(Debug)]
+pub struct SaveQueue {
+    taken: AtomicU64,
+    written: AtomicU64,
+    writing: Semaphore,
+}
+
+impl Default for SaveQueue {
+    fn default() -> Self {
+        SaveQueue {
+            taken: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            writing: Semaphore::new(1),
+        }
+    }
+}
+
+impl SaveQueue {
+    /// Call while holding the `top_10` lock, so snapshot order is update order.
+    pub fn snapshot(&self, top_10: &TopScores) -> Snapshot {
+        Snapshot {
+            seq: self.taken.fetch_add(1, Ordering::Relaxed) + 1,
+            top_10: top_10.clone(),
+        }
+    }
+
+    pub async fn save(&self, store: &dyn ScoreStore, snapshot: Snapshot) {
+        // never closed
+        let _permit = self.writing.acquire().await.unwrap();
+        if snapshot.seq <= self.written.load(Ordering::Relaxed) {
+            tracing::debug!(seq = snapshot.seq, "skipping superseded top-10 save");
+            return;
+        }
+        store.save(&snapshot.top_10).await;
+        self.written.store(snapshot.seq, Ordering::Relaxed);
+    }
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    // writing into a Vec can't fail
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Reverse;
+
+    fn top(entries: &[(u32, &str)]) -> TopScores {
+        entries.iter().map(|&(score, name)| (Reverse(score), name.to_owned())).collect()
+    }
+
+    fn sorted(top_10: TopScores) -> Vec<(Reverse<u32>, String)> {
+        top_10.into_sorted_vec()
+    }
+
+    fn scores_path(ext: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("top10-{}.{ext}", uuid::Uuid::new_v4().simple()))
+    }
+
+    #[tokio::test]
+    async fn the_memory_store_keeps_the_last_save() {
+        let store = MemoryStore::default();
+        assert!(store.load().await.is_empty());
+        store.save(&top(&[(40, "Ada"), (12, "Bo")])).await;
+        store.save(&top(&[(41, "Cy")])).await;
+        assert_eq!(sorted(store.load().await), sorted(top(&[(41, "Cy")])));
+    }
+
+    #[tokio::test]
+    async fn the_file_store_round_trips_plain_and_gzipped() {
+        let scores = top(&[(151, "Ada"), (97, "Bo"), (97, "Cy")]);
+        for ext in ["json", "json.gz"] {
+            let path = scores_path(ext);
+            let store = FileStore::new(path.clone());
+            store.save(&scores).await;
+            assert_eq!(sorted(store.load().await), sorted(scores.clone()), "{ext}");
+
+            let data = std::fs::read(&path).unwrap();
+            assert_eq!(data.starts_with(b"\x1f\x8b"), ext.ends_with(".gz"), "{ext}");
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            assert!(!Path::new(&tmp).exists(), "the temp file was left behind");
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn the_scores_file_is_a_plain_list_of_entries() {
+        let path = scores_path("json");
+        std::fs::write(&path, r#"[{"score": 88, "name": "Ada"}, {"score": 9, "name": "Bo"}]"#)
+            .unwrap();
+        let loaded = FileStore::new(path.clone()).load().await;
+        assert_eq!(sorted(loaded), sorted(top(&[(88, "Ada"), (9, "Bo")])));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_missing_or_unreadable_scores_file_loads_as_empty() {
+        assert!(FileStore::new(scores_path("json")).load().await.is_empty());
+        for (ext, junk) in [("json", &b"{not json"[..]), ("json.gz", &b"not gzip"[..])] {
+            let path = scores_path(ext);
+            std::fs::write(&path, junk).unwrap();
+            assert!(FileStore::new(path.clone()).load().await.is_empty(), "{ext}");
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn a_snapshot_overtaken_by_a_newer_one_is_not_written() {
+        let (queue, store) = (SaveQueue::default(), MemoryStore::default());
+        let older = queue.snapshot(&top(&[(40, "Ada")]));
+        let newer = queue.snapshot(&top(&[(40, "Ada"), (55, "Bo")]));
+        queue.save(&store, newer).await;
+        queue.save(&store, older).await;
+        assert_eq!(sorted(store.load().await), sorted(top(&[(40, "Ada"), (55, "Bo")])));
+
+        // in order, each is written
+        queue.save(&store, queue.snapshot(&top(&[(60, "Cy")]))).await;
+        assert_eq!(sorted(store.load().await), sorted(top(&[(60, "Cy")])));
+    }
+}
@@ -1,117 +1,1606 @@
 // src/server_state.rs
-use crate::ws_messages::{BoardData, Player, PlayerId, RoomId, WsServerMsg};
-use serde::{Deserialize, Serialize};
+use crate::admin::constant_time_eq;
+use crate::audit::AuditLog;
+use crate::bans::BanList;
+use crate::board::{self, PlayerBoard};
+use crate::config::Config;
+use crate::game_timer::{GameTimer, GameTimers, RunningGuard};
+use crate::limits::{ChatLimiter, IpLimits};
+use crate::moderation::DenyList;
+use crate::room_bus::{LiveRound, LocalBus, RoomBus, RoomChange, RoomRecord, RoomTx};
+use crate::score_store::{MemoryStore, SaveQueue, ScoreStore};
+use crate::sessions::Sessions;
+use crate::stats::Counters;
+use crate::webhooks::Webhooks;
+use crate::ws_messages::{
+    BoardData, ChatChannel, Player, PlayerId, RoomCloseReason, RoomId, RoomLogEntry, RoomSettings,
+    SystemMessageKind, WsServerMsg,
+};
+use arc_swap::ArcSwap;
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::Serialize;
 use std::{
     cmp::Reverse,
-    collections::{BinaryHeap, HashMap},
-    path::Path,
-    sync::Arc,
-};
-use tokio::{
-    fs,
-    sync::{broadcast, Mutex, MutexGuard},
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use tokio::sync::{broadcast, watch, Mutex, OwnedMutexGuard};
 
-/// How long (in seconds) the game runs after StartGame.
-pub const GAME_DURATION_SECS: u64 = 120;
+/// How many lobby broadcasts a socket can fall behind by before it skips ahead.
+const LOBBY_CAPACITY: usize = 64;
 
 /// Represents everything the server needs to know about a single lobby/room.
 #[derive(Debug)]
 pub struct RoomState {
+    // Parent of everything logged about this room (handlers, the game timer, lifecycle events),
+    // so `RUST_LOG='fruitbox_fsg[room{room_id=1234}]=trace'` follows one room.
+    pub span: tracing::Span,
+
     pub owner: PlayerId,
     pub players: HashMap<PlayerId, Player>,
+    // `players` keys in the order they joined, so player lists don't reshuffle on every update.
+    // Go through `add_player`/`remove_player` to keep the two in step.
+    join_order: Vec<PlayerId>,
+
+    // Game rules for this room.
+    pub settings: RoomSettings,
 
     // broadcast channel so we can send WsServerMsg to *all* participants.
-    pub tx: broadcast::Sender<WsServerMsg>,
+    pub tx: RoomTx,
 
     // After the game starts:
-    pub board: Option<BoardData>,
+    pub board: Option<Arc<BoardData>>,
     pub scores: HashMap<PlayerId, u32>,
 
-    // Track number of turns per player
+    // Highest `ScoreUpdate::turn` applied per player this game; anything at or below it is a replay.
     pub turns: HashMap<PlayerId, u32>,
 
-    // so we can cancel a running timer if needed (e.g. room closed).
-    // For simplicity, we’ll store a handle to the tokio::JoinHandle.
-    pub timer_handle: Option<tokio::task::JoinHandle<()>>,
+    // Each player's board as they've cleared it this game, for hints and clear checks.
+    pub boards: HashMap<PlayerId, PlayerBoard>,
+    pub last_hint: HashMap<PlayerId, Instant>,
+
+    // Watching, not playing: they get room broadcasts but never a board or a score.
+    pub spectators: HashMap<PlayerId, Player>,
+
+    // Players whose connection dropped, and since when; their seat is held for a reconnect.
+    pub disconnected: HashMap<PlayerId, Instant>,
+
+    // Players whose board has no moves left.
+    pub stuck: HashSet<PlayerId>,
+
+    // When each player last scored this round (or the round started), for `idle_kick_secs`.
+    pub last_score_at: HashMap<PlayerId, Instant>,
+
+    // How often each subscriber (player or spectator) fell behind the broadcasts this game.
+    pub lagged: HashMap<PlayerId, u32>,
+
+    // When the current round started, and how many ms in each player sent `FinishRound`.
+    pub round_started_at: Option<Instant>,
+    pub finished: HashMap<PlayerId, u32>,
+
+    // When the current round's clock runs out (its timer's deadline, on the timer's clock), and
+    // the same as Unix ms.
+    pub ends_at: Option<tokio::time::Instant>,
+    pub ends_at_ms: Option<u64>,
+
+    // When each currently-ready player readied up, for the stale-ready sweep.
+    pub ready_since: HashMap<PlayerId, Instant>,
+
+    // Chat throttling for each member (players and spectators) who has chatted, until they leave.
+    pub chat_limits: HashMap<PlayerId, ChatLimiter>,
+
+    // When each member last got a chat message out, for the room's slow mode.
+    pub last_chat_at: HashMap<PlayerId, Instant>,
+
+    // Members who may not chat until the given time (or until unmuted); see `mute_chat`.
+    chat_muted_until: HashMap<PlayerId, Option<Instant>>,
+
+    // How many of each member's chat messages the profanity filter masked this game.
+    pub masked_chats: HashMap<PlayerId, u32>,
+
+    // The `message_id` of the next chat message; never reset while the room lives.
+    next_message_id: u64,
+
+    // Chat and system notices, replayed to joiners.
+    pub log: RoomLog,
+
+    // The current game's countdown, so it can be ended early or cancelled (e.g. room closed).
+    pub timer: Option<GameTimer>,
+
+    // When the announced `GameStarting` runs out; cleared once the game starts or is called off.
+    pub pending_start: Option<Instant>,
+
+    // Games dealt in this room so far; the latest is the one being played, if any.
+    pub round: u64,
+
+    // A game another instance sharing the room dealt, and times; see `apply_record`.
+    pub remote_game: bool,
+
+    // Set once the room is out of `AppState::rooms`, for anyone who got hold of it just before.
+    closed: bool,
+
+    // The token each seat was last issued (`SeatGranted`); taking the seat over needs it.
+    seat_tokens: HashMap<PlayerId, SeatToken>,
+}
+
+/// The secret behind one seat. Kept out of `Debug`, which the room's trace logs go through.
+struct SeatToken(String);
+
+impl std::fmt::Debug for SeatToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SeatToken(..)")
+    }
 }
 
 impl RoomState {
-    pub fn new(owner: Player) -> Self {
-        let (tx, _) = broadcast::channel(32);
-        let mut players = HashMap::new();
-        players.insert(owner.player_id.clone(), owner.clone());
-        RoomState {
-            owner: owner.player_id,
-            players,
-            tx,
-            board: None,
-            scores: HashMap::new(),
-            turns: HashMap::new(),
-            timer_handle: None,
+    pub fn add_player(&mut self, player: Player) {
+        if self.players.insert(player.player_id.clone(), player.clone()).is_none() {
+            self.join_order.push(player.player_id);
+        }
+    }
+
+    pub fn remove_player(&mut self, player_id: &PlayerId) -> Option<Player> {
+        self.join_order.retain(|id| id != player_id);
+        self.seat_tokens.remove(player_id);
+        self.players.remove(player_id)
+    }
+
+    /// Gives `player_id`'s seat a fresh token, voiding the one it was issued before.
+    pub fn issue_seat_token(&mut self, player_id: &PlayerId) -> String {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.seat_tokens.insert(player_id.clone(), SeatToken(token.clone()));
+        self.tx.share(RoomChange::SeatToken {
+            player_id: player_id.clone(),
+            token: token.clone(),
+        });
+        token
+    }
+
+    /// Whether `token` is the one `player_id`'s seat was last issued.
+    pub fn seat_token_matches(&self, player_id: &PlayerId, token: Option<&str>) -> bool {
+        match (self.seat_tokens.get(player_id), token) {
+            (Some(SeatToken(issued)), Some(token)) => {
+                constant_time_eq(issued.as_bytes(), token.as_bytes())
+            }
+            _ => false,
+        }
+    }
+
+    /// Everyone seated, in join order (what `RoomPlayersUpdate` carries).
+    pub fn player_list(&self) -> Vec<Player> {
+        let now = Instant::now();
+        self.join_order
+            .iter()
+            .filter_map(|id| self.players.get(id))
+            .map(|p| Player {
+                muted: self
+                    .chat_muted_until
+                    .get(&p.player_id)
+                    .is_some_and(|until| until.is_none_or(|until| until > now)),
+                ..p.clone()
+            })
+            .collect()
+    }
+
+    /// Everyone's score and last applied turn, as a `LeaderboardUpdate`.
+    pub fn leaderboard_update(&self, room_id: &RoomId) -> WsServerMsg {
+        WsServerMsg::LeaderboardUpdate {
+            room_id: room_id.clone(),
+            scores: self.scores.iter().map(|(pid, &s)| (pid.clone(), s)).collect(),
+            turns: self.turns.iter().map(|(pid, &t)| (pid.clone(), t)).collect(),
+        }
+    }
+
+    /// A player called `name` whose seat is being held after a drop, if there is one.
+    pub fn held_seat_named(&self, name: &str) -> Option<PlayerId> {
+        self.disconnected
+            .keys()
+            .find(|id| self.players.get(*id).is_some_and(|p| p.name == name))
+            .cloned()
+    }
+
+    /// The display name of a player or spectator in this room.
+    pub fn member_name(&self, player_id: &PlayerId) -> Option<String> {
+        self.players
+            .get(player_id)
+            .or_else(|| self.spectators.get(player_id))
+            .map(|p| p.name.clone())
+    }
+
+    /// Whether every player but the owner is ready, which is all `StartGame` asks of them.
+    pub fn all_ready(&self) -> bool {
+        self.guests().all(|p| p.ready)
+    }
+
+    /// Every player but the owner, split into the ready ones and the rest, in join order.
+    pub fn readiness(&self) -> (Vec<PlayerId>, Vec<PlayerId>) {
+        let (ready, not_ready): (Vec<_>, Vec<_>) = self.guests().partition(|p| p.ready);
+        let ids = |players: Vec<&Player>| players.into_iter().map(|p| p.player_id.clone()).collect();
+        (ids(ready), ids(not_ready))
+    }
+
+    // The seated players the ready check covers.
+    fn guests(&self) -> impl Iterator<Item = &Player> {
+        self.join_order
+            .iter()
+            .filter(|&id| *id != self.owner)
+            .filter_map(|id| self.players.get(id))
+    }
+
+    /// Whether a game timer is currently counting down in this room (here, or on the instance
+    /// that dealt the game).
+    pub fn game_in_progress(&self) -> bool {
+        self.remote_game || self.timer.as_ref().is_some_and(GameTimer::is_running)
+    }
+
+    /// Whether everyone playing this round is done, by the measures the room ends early on:
+    /// finished (`end_when_finished`) or out of moves (`end_when_stuck`).
+    pub fn all_done(&self) -> bool {
+        let done = |p: &PlayerId| {
+            (self.settings.end_when_finished && self.finished.contains_key(p))
+                || (self.settings.end_when_stuck && self.stuck.contains(p))
+        };
+        !self.boards.is_empty() && self.boards.keys().all(done)
+    }
+
+    /// Stop the countdown early if the room wants that and nobody is playing any more.
+    pub fn end_if_all_done(&self, room_id: &RoomId) {
+        if let Some(timer) = self.timer.as_ref().filter(|t| t.is_running()) {
+            if self.all_done() {
+                tracing::info!(room_id = %room_id, "everyone is done, ending game");
+                timer.end_early();
+            }
+        }
+    }
+
+    /// Broadcast a chat line and keep it in the room log.
+    pub fn chat(
+        &mut self,
+        room_id: &RoomId,
+        player: Player,
+        message: String,
+        is_spectator: bool,
+        channel: ChatChannel,
+    ) {
+        let (message_id, at_ms) = (self.next_message_id, now_ms());
+        self.next_message_id += 1;
+        self.log.push(RoomLogEntry::Chat {
+            message_id,
+            channel,
+            player: player.clone(),
+            message: message.clone(),
+            is_spectator,
+            at_ms,
+        });
+        self.tx.send(WsServerMsg::ChatBroadcast {
+            room_id: room_id.clone(),
+            message_id,
+            channel,
+            player,
+            message,
+            is_spectator,
+            at_ms,
+        });
+    }
+
+    /// Stop the timer and tell everyone still subscribed why the room is going away.
+    /// Dropping the state afterwards closes the channel, which disconnects them.
+    pub fn close(&mut self, room_id: &RoomId, reason: RoomCloseReason) {
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+        self.tx.send(WsServerMsg::RoomClosed {
+            room_id: room_id.clone(),
+            reason,
+        });
+    }
+
+    /// Hand the room to `new_owner`, then broadcast the player list with the new `owner_id`,
+    /// `OwnerChanged` and a notice, in that order. Remove a departing owner before calling this.
+    /// The handover goes in the audit log under the previous owner.
+    pub fn transfer_ownership(&mut self, room_id: &RoomId, new_owner: PlayerId, audit: &AuditLog) {
+        let previous = std::mem::replace(&mut self.owner, new_owner.clone());
+        audit.record(
+            format!("player:{previous}"),
+            "owner_transferred",
+            Some(new_owner.to_string()),
+            Some(room_id.clone()),
+        );
+        // the owner isn't counted as ready or not, so don't show a stale flag
+        self.set_ready(&new_owner, false);
+        // nor muted: the owner can't be
+        self.chat_muted_until.remove(&new_owner);
+        let name = self
+            .players
+            .get(&new_owner)
+            .map_or("Unknown player", |p| p.name.as_str())
+            .to_owned();
+        tracing::info!(
+            parent: &self.span,
+            event = "owner_changed",
+            previous_owner_id = %previous,
+            owner_id = %new_owner,
+            owner_name = %name,
+            "room ownership transferred"
+        );
+        self.tx.send(WsServerMsg::RoomPlayersUpdate {
+            room_id: room_id.clone(),
+            players: self.player_list(),
+            owner_id: new_owner.clone(),
+        });
+        self.tx.share(RoomChange::Owner(new_owner.clone()));
+        self.tx.send(WsServerMsg::OwnerChanged {
+            room_id: room_id.clone(),
+            owner_id: new_owner,
+            previous_owner_id: previous,
+        });
+        self.announce(room_id, Notice::NewOwner(&name));
+    }
+
+    /// Take a player's seat away mid-round and let them keep watching as a spectator: their
+    /// score and board are dropped, and the room passes on if they owned it. Refused for the
+    /// last player, who would leave nobody to play.
+    pub fn move_to_spectators(
+        &mut self,
+        room_id: &RoomId,
+        player_id: &PlayerId,
+        audit: &AuditLog,
+    ) -> bool {
+        if self.players.len() <= 1 {
+            return false;
+        }
+        let Some(mut player) = self.remove_player(player_id) else {
+            return false;
+        };
+        self.tx.share(RoomChange::Unseat(player_id.clone()));
+        player.ready = false;
+        self.scores.remove(player_id);
+        self.turns.remove(player_id);
+        self.boards.remove(player_id);
+        self.last_hint.remove(player_id);
+        self.stuck.remove(player_id);
+        self.finished.remove(player_id);
+        self.ready_since.remove(player_id);
+        self.last_score_at.remove(player_id);
+        let name = player.name.clone();
+        self.spectators.insert(player_id.clone(), player);
 
+        self.tx.send(WsServerMsg::MovedToSpectators {
+            room_id: room_id.clone(),
+            player_id: player_id.clone(),
+        });
+        let successor = (&self.owner == player_id)
+            .then(|| self.player_list().first().map(|p| p.player_id.clone()))
+            .flatten();
+        if let Some(new_owner) = successor {
+            self.transfer_ownership(room_id, new_owner, audit);
+        } else {
+            self.tx.send(WsServerMsg::RoomPlayersUpdate {
+                room_id: room_id.clone(),
+                players: self.player_list(),
+                owner_id: self.owner.clone(),
+            });
         }
+        self.announce(room_id, Notice::MovedToSpectators(&name));
+        self.end_if_all_done(room_id);
+        true
     }
+
+    /// What a subscriber that missed broadcasts needs to catch up: the player list, then
+    /// `StateSync` (with the chat `player_id` gets, following the spectators' or not).
+    pub fn state_sync(
+        &mut self,
+        room_id: &RoomId,
+        player_id: &PlayerId,
+        follows_spectators: bool,
+    ) -> [WsServerMsg; 2] {
+        let spectating = self.spectators.contains_key(player_id);
+        let in_game = self.game_in_progress();
+        let on_the_clock = in_game && !self.settings.is_endless();
+        let players = WsServerMsg::RoomPlayersUpdate {
+            room_id: room_id.clone(),
+            players: self.player_list(),
+            owner_id: self.owner.clone(),
+        };
+        let sync = WsServerMsg::StateSync {
+            room_id: room_id.clone(),
+            scores: self
+                .scores
+                .iter()
+                .map(|(pid, &s)| (pid.clone(), s))
+                .collect(),
+            board: in_game
+                .then(|| self.boards.get(player_id).cloned().map(Vec::into_boxed_slice))
+                .flatten(),
+            // rounded up, as `TimerTick` has it
+            remaining_secs: self.ends_at.filter(|_| on_the_clock).map(|at| {
+                let left = at.saturating_duration_since(tokio::time::Instant::now());
+                left.as_millis().div_ceil(1000) as u64
+            }),
+            ends_at_ms: self.ends_at_ms.filter(|_| on_the_clock),
+            entries: self.log.snapshot(spectating, follows_spectators),
+        };
+        [players, sync]
+    }
+
+    /// Broadcast a server notice and keep it in the room log.
+    pub fn announce(&mut self, room_id: &RoomId, notice: Notice) {
+        let (kind, text, at_ms) = (notice.kind(), notice.text(), now_ms());
+        self.log.push(RoomLogEntry::System {
+            kind,
+            text: text.clone(),
+            at_ms,
+        });
+        self.tx.send(WsServerMsg::SystemMessage {
+            room_id: room_id.clone(),
+            kind,
+            text,
+            at_ms,
+        });
+    }
+
+    /// Keep `player_id` out of the chat for `duration` (`None` until `unmute_chat`) and tell
+    /// the room, naming them as `name` and giving `why` ("for spamming the chat"). A zero
+    /// `duration` does nothing.
+    pub fn mute_chat(
+        &mut self,
+        room_id: &RoomId,
+        player_id: &PlayerId,
+        name: &str,
+        duration: Option<Duration>,
+        why: &str,
+    ) {
+        if duration.is_some_and(|d| d.is_zero()) {
+            return;
+        }
+        let secs = duration.map(|d| d.as_secs());
+        tracing::info!(
+            parent: &self.span,
+            event = "chat_muted",
+            player_id = %player_id,
+            player_name = %name,
+            mute_secs = ?secs,
+            why,
+            "muted in chat"
+        );
+        let until = duration.map(|d| Instant::now() + d);
+        self.chat_muted_until.insert(player_id.clone(), until);
+        self.tx.send(WsServerMsg::RoomPlayersUpdate {
+            room_id: room_id.clone(),
+            players: self.player_list(),
+            owner_id: self.owner.clone(),
+        });
+        self.announce(room_id, Notice::Muted { name, secs, why });
+    }
+
+    /// Lift `player_id`'s mute, if they have one that hasn't run out, and tell the room.
+    pub fn unmute_chat(&mut self, room_id: &RoomId, player_id: &PlayerId, name: &str) -> bool {
+        if self.chat_mute_left(player_id).is_none() {
+            return false;
+        }
+        tracing::info!(
+            parent: &self.span,
+            event = "chat_unmuted",
+            player_id = %player_id,
+            player_name = %name,
+            "unmuted in chat"
+        );
+        self.chat_muted_until.remove(player_id);
+        self.tx.send(WsServerMsg::RoomPlayersUpdate {
+            room_id: room_id.clone(),
+            players: self.player_list(),
+            owner_id: self.owner.clone(),
+        });
+        self.announce(room_id, Notice::Unmuted(name));
+        true
+    }
+
+    /// Whether `player_id` is muted in chat: `Some(left)`, with `left` `None` for a mute that
+    /// lasts until lifted. A mute that ran out is dropped here.
+    pub fn chat_mute_left(&mut self, player_id: &PlayerId) -> Option<Option<Duration>> {
+        let until = *self.chat_muted_until.get(player_id)?;
+        let Some(until) = until else {
+            return Some(None);
+        };
+        let left = until.checked_duration_since(Instant::now()).filter(|d| !d.is_zero());
+        if left.is_none() {
+            self.chat_muted_until.remove(player_id);
+        }
+        left.map(Some)
+    }
+
+    /// Forget a departing member's chat limiter, mute and filter strikes.
+    pub fn forget_chat(&mut self, player_id: &PlayerId) {
+        self.chat_limits.remove(player_id);
+        self.chat_muted_until.remove(player_id);
+        self.masked_chats.remove(player_id);
+    }
+
+    /// Set a player's ready flag, keeping `ready_since` in step.
+    pub fn set_ready(&mut self, player_id: &PlayerId, ready: bool) {
+        if let Some(player) = self.players.get_mut(player_id) {
+            player.ready = ready;
+            let shared = player.clone();
+            self.note_ready(player_id, ready);
+            self.tx.share(RoomChange::Player(shared));
+        }
+    }
+
+    fn note_ready(&mut self, player_id: &PlayerId, ready: bool) {
+        if ready {
+            self.ready_since.entry(player_id.clone()).or_insert_with(Instant::now);
+        } else {
+            self.ready_since.remove(player_id);
+        }
+    }
+
+    /// Deals `board` to everyone seated: fresh boards, scores and turns at 0, nobody ready,
+    /// and everything else kept per game cleared out.
+    pub fn deal(&mut self, board: Arc<BoardData>) {
+        self.boards.clear();
+        self.last_hint.clear();
+        self.masked_chats.clear();
+        self.stuck.clear();
+        self.finished.clear();
+        self.last_score_at.clear();
+        self.lagged.clear();
+        self.round_started_at = Some(Instant::now());
+        for pid in self.players.keys() {
+            self.last_score_at.insert(pid.clone(), Instant::now());
+            self.scores.insert(pid.clone(), 0);
+            *self.turns.entry(pid.clone()).or_insert(0) = 0;
+            self.boards.insert(pid.clone(), board::player_board(&board));
+        }
+        self.board = Some(board);
+        for player in self.players.values_mut() {
+            player.ready = false;
+        }
+        self.ready_since.clear();
+    }
+
+    /// What other instances need of this room to serve it, for `SharedRooms::open`.
+    pub fn record(&self) -> RoomRecord {
+        RoomRecord {
+            owner: self.owner.clone(),
+            settings: self.settings.clone(),
+            players: self.player_list(),
+            seat_tokens: self
+                .seat_tokens
+                .iter()
+                .map(|(pid, SeatToken(token))| (pid.clone(), token.clone()))
+                .collect(),
+            scores: self.scores.clone(),
+            turns: self.turns.clone(),
+            round: self.round,
+            game: None,
+        }
+    }
+
+    /// Catches this instance's copy of a shared room up with its `record`: the owner, who is
+    /// seated (with their flags and seat tokens), scores and turns, and a game dealt somewhere
+    /// else since, whose boards are dealt here from its seed. Returns the players whose held
+    /// seat was taken back through another instance, so it's no longer held here.
+    pub fn apply_record(&mut self, record: RoomRecord, combos: &[[u8; 8]]) -> Vec<PlayerId> {
+        self.owner = record.owner;
+        let seated: HashSet<&PlayerId> = record.players.iter().map(|p| &p.player_id).collect();
+        let gone: Vec<PlayerId> =
+            self.players.keys().filter(|pid| !seated.contains(pid)).cloned().collect();
+        for pid in &gone {
+            self.remove_player(pid);
+            self.ready_since.remove(pid);
+            self.last_score_at.remove(pid);
+            self.lagged.remove(pid);
+            self.boards.remove(pid);
+            self.last_hint.remove(pid);
+            self.stuck.remove(pid);
+            self.finished.remove(pid);
+            self.disconnected.remove(pid);
+        }
+        for player in record.players {
+            let pid = player.player_id.clone();
+            let ready = player.ready;
+            match self.players.get_mut(&pid) {
+                Some(here) => *here = player,
+                None => self.add_player(player),
+            }
+            self.note_ready(&pid, ready);
+        }
+        let mut retaken = Vec::new();
+        for (pid, token) in record.seat_tokens {
+            if !self.seat_token_matches(&pid, Some(&token)) {
+                if self.disconnected.remove(&pid).is_some() {
+                    retaken.push(pid.clone());
+                }
+                self.seat_tokens.insert(pid, SeatToken(token));
+            }
+        }
+        self.scores = record.scores;
+        self.turns = record.turns;
+
+        if record.round > self.round {
+            self.round = record.round;
+            // whatever ran here was dealt over
+            if let Some(timer) = self.timer.take() {
+                timer.cancel();
+            }
+            if let Some(LiveRound { seed, ends_at_ms }) = record.game {
+                let board = board::board_from_seed(combos, seed, &self.settings);
+                let scores = std::mem::take(&mut self.scores);
+                let turns = std::mem::take(&mut self.turns);
+                self.deal(Arc::new(board));
+                (self.scores, self.turns) = (scores, turns);
+                let left = Duration::from_millis(ends_at_ms.saturating_sub(now_ms()));
+                self.ends_at = Some(tokio::time::Instant::now() + left);
+                self.ends_at_ms = Some(ends_at_ms);
+                self.pending_start = None;
+                self.remote_game = true;
+            }
+        } else if self.remote_game && record.game.is_none() {
+            // the instance that dealt it has recorded the result
+            self.remote_game = false;
+            self.board = None;
+            self.boards.clear();
+            self.ends_at = None;
+            self.ends_at_ms = None;
+        }
+        retaken
+    }
+}
+
+/// Something that happened in a room, told to it as a `SystemMessage`. All notice wording
+/// lives in `text`.
+#[derive(Debug, Clone, Copy)]
+pub enum Notice<'a> {
+    Joined(&'a str),
+    Left(&'a str),
+    LostConnection(&'a str),
+    Reconnected(&'a str),
+    NewOwner(&'a str),
+    MovedToSpectators(&'a str),
+    /// `why` completes the sentence: "for spamming the chat". `secs` is `None` for a mute
+    /// that lasts until lifted.
+    Muted { name: &'a str, secs: Option<u64>, why: &'a str },
+    Unmuted(&'a str),
+    GameStarted,
+    /// The best score of the game and who made it, if anyone played.
+    GameEnded { best: Option<(&'a str, u32)> },
+    /// What stopped the start: "not everyone is ready".
+    StartCalledOff(&'a str),
+    /// The room's new `slow_mode_secs`, 0 when it was turned off.
+    SlowMode(u32),
+    /// How many more seconds the sender has to wait under slow mode.
+    SlowModeWait(u64),
+}
+
+impl Notice<'_> {
+    pub fn kind(&self) -> SystemMessageKind {
+        match self {
+            Notice::Joined(_) => SystemMessageKind::PlayerJoined,
+            Notice::Left(_) => SystemMessageKind::PlayerLeft,
+            Notice::LostConnection(_) => SystemMessageKind::PlayerDisconnected,
+            Notice::Reconnected(_) => SystemMessageKind::PlayerReconnected,
+            Notice::NewOwner(_) => SystemMessageKind::OwnerChanged,
+            Notice::MovedToSpectators(_) => SystemMessageKind::MovedToSpectators,
+            Notice::Muted { .. } => SystemMessageKind::Muted,
+            Notice::Unmuted(_) => SystemMessageKind::Unmuted,
+            Notice::GameStarted => SystemMessageKind::GameStarted,
+            Notice::GameEnded { .. } => SystemMessageKind::GameEnded,
+            Notice::StartCalledOff(_) => SystemMessageKind::StartCalledOff,
+            Notice::SlowMode(_) | Notice::SlowModeWait(_) => SystemMessageKind::SlowMode,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Notice::Joined(name) => format!("{name} joined"),
+            Notice::Left(name) => format!("{name} left"),
+            Notice::LostConnection(name) => format!("{name} lost connection"),
+            Notice::Reconnected(name) => format!("{name} reconnected"),
+            Notice::NewOwner(name) => format!("{name} is now the room owner"),
+            Notice::MovedToSpectators(name) => {
+                format!("{name} was moved to spectators for not playing")
+            }
+            Notice::Muted { name, secs: Some(secs), why } => {
+                format!("{name} was muted for {secs}s {why}")
+            }
+            Notice::Muted { name, secs: None, why } => format!("{name} was muted {why}"),
+            Notice::Unmuted(name) => format!("{name} can chat again"),
+            Notice::GameStarted => "The game has started".to_string(),
+            Notice::GameEnded { best: Some((name, score)) } => {
+                format!("Game over: {name} has the top score with {score}")
+            }
+            Notice::GameEnded { best: None } => "Game over".to_string(),
+            Notice::StartCalledOff(why) => format!("Start called off: {why}"),
+            Notice::SlowMode(0) => "Slow mode is off".to_string(),
+            Notice::SlowMode(secs) => format!("Slow mode is on: one message every {secs}s"),
+            Notice::SlowModeWait(secs) => {
+                format!("Slow mode is on: wait {secs}s before your next message")
+            }
+        }
+    }
+}
+
+/// How many of the newest log entries `RoomHistory` and `StateSync` replay; older chat is there
+/// for `GetChatHistory`.
+pub const HISTORY_REPLAY_LEN: usize = 50;
+
+/// A room's recent chat and system notices, bounded by count and by the bytes of text they
+/// hold, and optionally by age. It lives and dies with the room.
+#[derive(Debug)]
+pub struct RoomLog {
+    entries: VecDeque<(Instant, RoomLogEntry)>,
+    capacity: usize,
+    max_bytes: usize,
+    // Text held by `entries`, as counted by `text_bytes`.
+    bytes: usize,
+    max_age: Option<Duration>,
+}
+
+impl RoomLog {
+    /// `max_bytes` of 0 doesn't cap the text.
+    pub fn new(capacity: usize, max_bytes: usize, max_age: Option<Duration>) -> Self {
+        RoomLog {
+            entries: VecDeque::with_capacity(capacity.min(HISTORY_REPLAY_LEN)),
+            capacity,
+            max_bytes,
+            bytes: 0,
+            max_age,
+        }
+    }
+
+    /// Append an entry, evicting the oldest ones past the limits.
+    pub fn push(&mut self, entry: RoomLogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let size = text_bytes(&entry);
+        while self.entries.len() >= self.capacity
+            || (self.max_bytes > 0 && !self.entries.is_empty() && self.bytes + size > self.max_bytes)
+        {
+            self.pop_front();
+        }
+        self.bytes += size;
+        self.entries.push_back((Instant::now(), entry));
+        self.prune();
+    }
+
+    /// The newest `HISTORY_REPLAY_LEN` entries a member gets (see `RoomLogEntry::reaches`),
+    /// oldest first.
+    pub fn snapshot(&mut self, spectating: bool, follows_spectators: bool) -> Vec<RoomLogEntry> {
+        self.prune();
+        let mut recent: Vec<_> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|(_, e)| e.reaches(spectating, follows_spectators))
+            .take(HISTORY_REPLAY_LEN)
+            .map(|(_, e)| e.clone())
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    /// Up to `limit` of the chat messages a member gets from before `before_id` (or the
+    /// newest, without one), oldest first, and whether there are older ones still.
+    pub fn chat_page(
+        &mut self,
+        before_id: Option<u64>,
+        limit: usize,
+        spectating: bool,
+        follows_spectators: bool,
+    ) -> (Vec<RoomLogEntry>, bool) {
+        self.prune();
+        let mut older = self.entries.iter().rev().filter(|(_, e)| match e {
+            RoomLogEntry::Chat { message_id, .. } => {
+                before_id.is_none_or(|before| *message_id < before)
+                    && e.reaches(spectating, follows_spectators)
+            }
+            RoomLogEntry::System { .. } => false,
+        });
+        let mut page: Vec<_> = older.by_ref().take(limit).map(|(_, e)| e.clone()).collect();
+        let has_more = older.next().is_some();
+        page.reverse();
+        (page, has_more)
+    }
+
+    fn prune(&mut self) {
+        let Some(max_age) = self.max_age else {
+            return;
+        };
+        while self
+            .entries
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > max_age)
+        {
+            self.pop_front();
+        }
+    }
+
+    fn pop_front(&mut self) {
+        if let Some((_, entry)) = self.entries.pop_front() {
+            self.bytes -= text_bytes(&entry);
+        }
+    }
+}
+
+/// What a log entry counts towards `RoomLog`'s byte cap: the text in it.
+fn text_bytes(entry: &RoomLogEntry) -> usize {
+    match entry {
+        RoomLogEntry::Chat { player, message, .. } => player.name.len() + message.len(),
+        RoomLogEntry::System { text, .. } => text.len(),
+    }
+}
+
+/// Min-heap of `(score, name)` so the lowest top-10 entry is always at the top.
+pub type TopScores = BinaryHeap<(Reverse<u32>, String)>;
+
+/// The top 10 as `(score, name)`, best first: what `Top10Scores` carries.
+pub type TopScoresList = Vec<(u32, String)>;
+
+fn sorted_scores(top_10: &TopScores) -> TopScoresList {
+    top_10
+        .clone()
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(Reverse(score), name)| (score, name))
+        .collect()
 }
 
+/// One room behind its own lock, so busy rooms don't hold each other up.
+pub type SharedRoom = Arc<Mutex<RoomState>>;
+
+/// A locked room, as handed out by `AppState::lock_room`.
+pub type RoomGuard = OwnedMutexGuard<RoomState>;
+
 /// Global application state: all rooms, keyed by ID.
 #[derive(Clone)]
 pub struct AppState {
-    /// Mutex so we can add/remove rooms, modify players, etc.
-    pub rooms: Arc<Mutex<HashMap<RoomId, RoomState>>>,
-    pub top_10: Arc<Mutex<BinaryHeap<(Reverse<u32>, String)>>>,
+    /// Every room, each behind its own lock. The map is sharded, so looking up different rooms
+    /// never contends; `lock_room` is the usual way in.
+    ///
+    /// Lock order, to stay clear of deadlocks:
+    /// - hold at most one room's lock at a time;
+    /// - never hold a map entry (`Ref`, `Entry`, an iterator) across an `.await`: its shard lock
+    ///   blocks the thread, so clone the `Arc` out first;
+    /// - `top_10` comes last: never wait for a room while holding it. Only game results take it;
+    ///   readers load `top_10_list` instead;
+    /// - release a room or `top_10` guard before any other `.await` (socket sends, saves): take
+    ///   what you need inside a block and do the I/O after it. `clippy.toml` flags violations.
+    ///
+    /// A room can be removed while someone who already cloned its `Arc` waits for its lock; they
+    /// find it `closed` (which `lock_room` checks) and treat it as gone.
+    pub rooms: Arc<DashMap<RoomId, SharedRoom>>,
+    pub top_10: Arc<Mutex<TopScores>>,
+    // A sorted copy of `top_10`, replaced whole after each change, so a new connection gets the
+    // list without taking any lock. Kept in step by `publish_top_10`.
+    pub top_10_list: Arc<ArcSwap<TopScoresList>>,
+    // Where `top_10` is saved after each game; in memory unless built with `with_score_store`.
+    pub scores: Arc<dyn ScoreStore>,
+    // Orders those saves, which run once the `top_10` lock is released.
+    pub score_saves: Arc<SaveQueue>,
+    pub config: Arc<Config>,
+
+    // Cheap counters for the lobby presence tick, so it never has to lock `rooms`.
+    pub online: Arc<AtomicUsize>,
+    pub room_count: Arc<AtomicUsize>,
+
+    // Everything for sockets that are not in a room: presence ticks, `ServerInfo` when the
+    // MOTD changes, and lobby chat. A socket swaps it for its room's channel on joining.
+    pub lobby_tx: broadcast::Sender<WsServerMsg>,
+
+    // Flips to `true` once a shutdown signal arrives; timers and handlers watch it.
+    pub shutdown: Arc<watch::Sender<bool>>,
+
+    // Set through the admin API before a deploy; see `is_draining`.
+    pub draining: Arc<AtomicBool>,
+
+    // Flips to `true` at the very end of shutdown, telling every socket to send a close frame.
+    pub disconnect: Arc<watch::Sender<bool>>,
+
+    // Rolling log of recent board seeds, so reported boards can be regenerated.
+    pub recent_seeds: Arc<StdMutex<VecDeque<SeedRecord>>>,
+
+    // Per-client-IP socket counts and room-creation windows.
+    pub ip_limits: Arc<IpLimits>,
+
+    // How room broadcasts are delivered (in-process, or across instances).
+    pub bus: Arc<dyn RoomBus>,
+
+    // Addresses refused at the upgrade; replaced by the persisted list at startup.
+    pub bans: Arc<BanList>,
+
+    // Admin and moderation actions; replaced by the file-backed log at startup.
+    pub audit: Arc<AuditLog>,
+
+    // Words masked out of chat and names; replaced by the loaded list when the filter is on.
+    pub deny_list: Arc<DenyList>,
+
+    // Message of the day, seeded from the config and replaceable through the admin API.
+    pub motd: Arc<StdMutex<Option<String>>>,
+
+    // Monotonic message/broadcast/error/game totals for the stats API.
+    pub counters: Arc<Counters>,
+
+    // Finished-game notifications; replaced by the configured sender at startup.
+    pub webhooks: Arc<Webhooks>,
+
+    // Every running game's countdown; replaced by the running scheduler at startup.
+    pub timers: Arc<GameTimers>,
+
+    // Which sockets act as which player, for `single_session` and the admin API.
+    pub sessions: Arc<Sessions>,
+
+    // What classic boards are dealt from (the `combos_*.json` files); loaded once at startup.
+    pub combos: Arc<[[u8; 8]]>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::new_with_top_10(BinaryHeap::new(), Config::default())
+    }
+
+    /// A state whose top 10 is loaded from, and saved back to, `store`.
+    pub async fn with_score_store(store: Arc<dyn ScoreStore>, config: Config) -> Self {
+        let top_10 = store.load().await;
         AppState {
-            rooms: Arc::new(Mutex::new(HashMap::new())),
-            top_10: Arc::new(Mutex::new(BinaryHeap::new())),
+            scores: store,
+            ..Self::new_with_top_10(top_10, config)
         }
     }
-    pub fn new_with_top_10(top_10: BinaryHeap<(Reverse<u32>, String)>) -> Self {
+
+    pub fn new_with_top_10(top_10: TopScores, config: Config) -> Self {
+        let (lobby_tx, _) = broadcast::channel(LOBBY_CAPACITY);
+        let ip_limits = IpLimits::new(
+            config.max_connections_per_ip,
+            config.max_rooms_per_ip_per_minute,
+        );
+        let motd = config.motd.clone();
         AppState {
-            rooms: Arc::new(Mutex::new(HashMap::new())),
+            rooms: Arc::new(DashMap::new()),
+            top_10_list: Arc::new(ArcSwap::from_pointee(sorted_scores(&top_10))),
             top_10: Arc::new(Mutex::new(top_10)),
+            scores: Arc::new(MemoryStore::default()),
+            score_saves: Arc::new(SaveQueue::default()),
+            config: Arc::new(config),
+            online: Arc::new(AtomicUsize::new(0)),
+            room_count: Arc::new(AtomicUsize::new(0)),
+            lobby_tx,
+            shutdown: Arc::new(watch::channel(false).0),
+            disconnect: Arc::new(watch::channel(false).0),
+            draining: Arc::new(AtomicBool::new(false)),
+            recent_seeds: Arc::new(StdMutex::new(VecDeque::new())),
+            ip_limits: Arc::new(ip_limits),
+            bus: Arc::new(LocalBus),
+            bans: Arc::new(BanList::default()),
+            audit: Arc::new(AuditLog::default()),
+            deny_list: Arc::new(DenyList::default()),
+            motd: Arc::new(StdMutex::new(motd)),
+            counters: Arc::new(Counters::default()),
+            webhooks: Arc::new(Webhooks::default()),
+            timers: Arc::new(GameTimers::default()),
+            sessions: Arc::new(Sessions::default()),
+            combos: Arc::from([]),
+        }
+    }
+
+    /// Swap a fresh copy of `top_10` into `top_10_list`. Call while holding the `top_10` lock,
+    /// so an older list is never swapped in over a newer one.
+    pub fn publish_top_10(&self, top_10: &TopScores) {
+        self.top_10_list.store(Arc::new(sorted_scores(top_10)));
+    }
+
+    /// Remember the seed a room's board was generated from, dropping the oldest entry when full.
+    pub fn record_seed(&self, room_id: &RoomId, seed: u64) {
+        let capacity = self.config.seed_log_capacity;
+        if capacity == 0 {
+            return;
+        }
+        let mut seeds = self.recent_seeds.lock().unwrap();
+        while seeds.len() >= capacity {
+            seeds.pop_front();
+        }
+        seeds.push_back(SeedRecord {
+            room_id: room_id.clone(),
+            seed,
+            started_at_ms: now_ms(),
+        });
+    }
+
+    /// Whether the server has started shutting down and should refuse new games.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Whether new sockets, rooms and games are refused. Games already running carry on to the
+    /// end and record their scores. Always true once shutdown has begun.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed) || self.is_shutting_down()
+    }
+
+    /// Whether `max_running_games` games already hold a slot, so no more may start. Only an
+    /// answer for right now: a game that starts takes its slot with `reserve_game`.
+    pub fn games_full(&self) -> bool {
+        let max = self.config.max_running_games;
+        max > 0 && self.timers.running() >= max
+    }
+
+    /// A slot for one more game, or `None` if `max_running_games` already hold one. The game
+    /// keeps it from its start countdown until it's over; dropping it unused gives it back.
+    pub fn reserve_game(&self) -> Option<RunningGuard> {
+        self.timers.reserve(self.config.max_running_games)
+    }
+
+    /// Start or stop draining; a shutdown in progress keeps draining regardless.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
+    /// Mark the server as shutting down, waking every timer waiting on the flag.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// A room of just `owner`, under `room_id` and playing by `settings` (which are taken as
+    /// already validated). It isn't in `rooms` yet: inserting it is up to the caller.
+    pub fn new_room(&self, room_id: &RoomId, owner: Player, settings: RoomSettings) -> RoomState {
+        let config = &self.config;
+        let log_len = settings
+            .history_len
+            .map_or(config.chat_history_len, |len| (len as usize).min(config.chat_history_len));
+        let log = RoomLog::new(log_len, config.chat_history_max_bytes, config.chat_history_max_age);
+        let tx = RoomTx::new(
+            room_id.clone(),
+            self.bus.clone(),
+            self.counters.clone(),
+            config.room_channel_capacity,
+        );
+        let mut players = HashMap::new();
+        players.insert(owner.player_id.clone(), owner.clone());
+        let span = tracing::info_span!(
+            parent: None,
+            "room",
+            room_id = %room_id,
+            owner = %owner.player_id
+        );
+        RoomState {
+            span,
+            join_order: vec![owner.player_id.clone()],
+            owner: owner.player_id,
+            players,
+            settings,
+            tx,
+            board: None,
+            scores: HashMap::new(),
+            turns: HashMap::new(),
+            boards: HashMap::new(),
+            last_hint: HashMap::new(),
+            spectators: HashMap::new(),
+            disconnected: HashMap::new(),
+            stuck: HashSet::new(),
+            last_score_at: HashMap::new(),
+            lagged: HashMap::new(),
+            round_started_at: None,
+            ends_at: None,
+            ends_at_ms: None,
+            finished: HashMap::new(),
+            ready_since: HashMap::new(),
+            chat_limits: HashMap::new(),
+            last_chat_at: HashMap::new(),
+            chat_muted_until: HashMap::new(),
+            masked_chats: HashMap::new(),
+            next_message_id: 1,
+            log,
+            timer: None,
+            pending_start: None,
+            round: 0,
+            remote_game: false,
+            closed: false,
+            seat_tokens: HashMap::new(),
         }
     }
-    /// Load the top 10 from file asynchronously
-    pub async fn load_top_10() -> BinaryHeap<(Reverse<u32>, String)> {
-        let path = Path::new("top10.json");
-        if let Ok(data) = fs::read_to_string(path).await {
-            if let Ok(entries) = serde_json::from_str::<Vec<TopScoreEntry>>(&data) {
-                let mut heap = BinaryHeap::new();
-                for entry in entries {
-                    heap.push((std::cmp::Reverse(entry.score), entry.name));
+
+    /// Lock room `room_id`. `None` if there is no such room, or it was closed while we waited.
+    pub async fn lock_room(&self, room_id: &str) -> Option<RoomGuard> {
+        let room = self.rooms.get(room_id)?.value().clone();
+        let room_state = room.lock_owned().await;
+        (!room_state.closed).then_some(room_state)
+    }
+
+    /// Every room there is right now, to be locked one at a time.
+    pub fn all_rooms(&self) -> Vec<(RoomId, SharedRoom)> {
+        self.rooms
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Remove a room, closing it for `reason` first; `false` if there was no such room.
+    pub async fn close_room(&self, room_id: &RoomId, reason: RoomCloseReason) -> bool {
+        match self.lock_room(room_id).await {
+            Some(mut room_state) => self.close_locked_room(room_id, &mut room_state, reason),
+            None => false,
+        }
+    }
+
+    /// `close_room` for a caller already holding the room's lock.
+    ///
+    /// Only this room leaves the map: if its ID has somehow been reused, the newer room stays.
+    pub fn close_locked_room(
+        &self,
+        room_id: &RoomId,
+        room_state: &mut RoomGuard,
+        reason: RoomCloseReason,
+    ) -> bool {
+        if room_state.closed {
+            return false;
+        }
+        // an emptied room is let go of once nobody is seated on any instance either
+        if reason == RoomCloseReason::Empty {
+            self.bus.release(room_id);
+        } else {
+            room_state.tx.share(RoomChange::Closed);
+        }
+        self.drop_locked_room(room_id, room_state);
+        room_state.close(room_id, reason);
+        true
+    }
+
+    /// Takes the room out of `rooms` here, and lets its sockets go without a word: what's left
+    /// of `close_locked_room` for a copy of a shared room that lives on elsewhere, or is
+    /// already closed there.
+    fn drop_locked_room(&self, room_id: &RoomId, room_state: &mut RoomGuard) {
+        let this = RoomGuard::mutex(room_state);
+        self.rooms.remove_if(room_id, |_, room| Arc::ptr_eq(room, this));
+        for player_id in room_state.players.keys() {
+            self.sessions.unseat(player_id, room_id);
+        }
+        if let Some(timer) = room_state.timer.take() {
+            timer.cancel();
+        }
+        room_state.closed = true;
+        self.room_count.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Catches this instance's copy of a shared room up with the room's shared record: opens one
+    /// if there's none here yet, and drops it if the room is gone. Nothing when the bus doesn't
+    /// share rooms. Called before acting on a room's membership or scores.
+    pub async fn sync_room(&self, room_id: &RoomId) {
+        let Some(shared) = self.bus.shared() else {
+            return;
+        };
+        let record = match shared.load(room_id).await {
+            Ok(record) => record,
+            // the copy here is the best there is for now
+            Err(e) => {
+                tracing::warn!(room_id = %room_id, error = %e, "cannot sync room");
+                return;
+            }
+        };
+        let room = self.rooms.get(room_id).map(|room| room.value().clone());
+        let Some(record) = record else {
+            if let Some(room) = room {
+                let mut room_state = room.lock_owned().await;
+                if !room_state.closed {
+                    tracing::info!(parent: &room_state.span, "room is gone everywhere else");
+                    self.drop_locked_room(room_id, &mut room_state);
+                }
+            }
+            return;
+        };
+        let room = match room {
+            Some(room) => room,
+            None => {
+                let owner = record.players.iter().find(|p| p.player_id == record.owner);
+                // nobody seated: about to be let go of
+                let Some(owner) = owner.or(record.players.first()).cloned() else {
+                    return;
+                };
+                match self.rooms.entry(room_id.clone()) {
+                    Entry::Occupied(entry) => entry.get().clone(),
+                    Entry::Vacant(slot) => {
+                        tracing::debug!(room_id = %room_id, "opening a copy of a shared room");
+                        let copy = self.new_room(room_id, owner, record.settings.clone());
+                        self.room_count.fetch_add(1, Ordering::Relaxed);
+                        slot.insert(Arc::new(Mutex::new(copy))).clone()
+                    }
                 }
-                return heap;
             }
+        };
+        let mut room_state = room.lock_owned().await;
+        if room_state.closed {
+            return;
+        }
+        let retaken = room_state.apply_record(record, &self.combos);
+        for player_id in &retaken {
+            self.sessions.unseat(player_id, room_id);
+        }
+        if !retaken.is_empty() {
+            self.drop_unused_copy(room_id, &mut room_state);
         }
-        BinaryHeap::new()
     }
 
-    /// Save the top 10 to file asynchronously
-    pub async fn save_top_10(heap: &MutexGuard<'_, BinaryHeap<(Reverse<u32>, String)>>) {
-        let vec: Vec<_> = heap
-            .iter()
-            .map(|r| TopScoreEntry {
-                score: r.0 .0,
-                name: r.1.clone(),
-            })
-            .collect();
-        let data = serde_json::to_string_pretty(&vec).unwrap();
-        println!("saving top 10 {:#?}", heap);
-        let _ = fs::write("top10.json", data).await;
+    /// Drops this instance's copy of a shared room if none of its sockets are in it and no game
+    /// it dealt is running; `true` if it did.
+    pub fn drop_unused_copy(&self, room_id: &RoomId, room_state: &mut RoomGuard) -> bool {
+        let used = !room_state.spectators.is_empty()
+            || room_state.timer.as_ref().is_some_and(GameTimer::is_running)
+            || room_state.players.keys().any(|pid| self.sessions.is_seated_in(pid, room_id));
+        if used || room_state.closed || self.bus.shared().is_none() {
+            return false;
+        }
+        tracing::debug!(parent: &room_state.span, "no sockets here are in the room any more");
+        self.drop_locked_room(room_id, room_state);
+        true
+    }
+
+    /// Ask every connected socket to close itself with a "going away" frame.
+    pub fn disconnect_all(&self) {
+        self.disconnect.send_replace(true);
     }
+
+    /// Snapshot of how many sockets are connected and how many rooms exist.
+    pub fn presence(&self) -> WsServerMsg {
+        WsServerMsg::GlobalPresence {
+            online: self.online.load(Ordering::Relaxed) as u32,
+            rooms: self.room_count.load(Ordering::Relaxed) as u32,
+        }
+    }
+
+    pub fn server_stats(&self) -> WsServerMsg {
+        WsServerMsg::ServerStats {
+            messages_handled: self.counters.total_messages(),
+            broadcasts: self.counters.total_broadcasts(),
+            games_completed: self.counters.total_games_completed(),
+        }
+    }
+
+    pub fn server_info(&self) -> WsServerMsg {
+        WsServerMsg::ServerInfo {
+            server_name: self.config.server_name.clone(),
+            motd: self.motd.lock().unwrap().clone(),
+        }
+    }
+
+    /// Replaces the MOTD and pushes the new `ServerInfo` to every socket in the lobby.
+    pub fn set_motd(&self, motd: Option<String>) {
+        *self.motd.lock().unwrap() = motd;
+        let _ = self.lobby_tx.send(self.server_info());
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct TopScoreEntry {
-    pub score: u32,
-    pub name: String,
+/// One generated board: which room, the RNG seed, and when the game started.
+#[derive(Serialize, Debug, Clone)]
+pub struct SeedRecord {
+    pub room_id: RoomId,
+    pub seed: u64,
+    pub started_at_ms: u64,
 }
 
 pub struct TurnsUpdate {
     pub room_id: RoomId,
-   pub turns: HashMap<PlayerId, u32>,
-}
\ No newline at end of file
+    pub turns: HashMap<PlayerId, u32>,
+}
+
+/// Counts a live socket in `AppState::online` for as long as it is held.
+pub struct OnlineGuard {
+    online: Arc<AtomicUsize>,
+}
+
+impl OnlineGuard {
+    pub fn new(state: &AppState) -> Self {
+        state.online.fetch_add(1, Ordering::Relaxed);
+        OnlineGuard {
+            online: state.online.clone(),
+        }
+    }
+}
+
+impl Drop for OnlineGuard {
+    fn drop(&mut self) {
+        self.online.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::Mutex;
+
+    /// Puts a one-player room under `room_id` in `state`, the way `CreateRoom` does.
+    fn open_room(state: &AppState, room_id: &str) -> SharedRoom {
+        let owner = Player {
+            player_id: "owner".to_owned(),
+            name: "Owner".to_owned(),
+            ready: false,
+            muted: false,
+        };
+        let room = state.new_room(&room_id.to_owned(), owner, RoomSettings::default());
+        let room = Arc::new(Mutex::new(room));
+        state.rooms.insert(room_id.to_owned(), room.clone());
+        state.room_count.fetch_add(1, Ordering::Relaxed);
+        room
+    }
+
+    fn state() -> AppState {
+        AppState::new_with_top_10(BinaryHeap::new(), Config::default())
+    }
+
+    #[test]
+    fn a_room_keeps_the_history_its_settings_ask_for_up_to_the_servers() {
+        let state = state();
+        let owner = Player {
+            player_id: "owner".to_owned(),
+            name: "Owner".to_owned(),
+            ready: false,
+            muted: false,
+        };
+        let kept = |history_len| {
+            let settings = RoomSettings {
+                history_len,
+                ..RoomSettings::default()
+            };
+            state.new_room(&"4821".to_owned(), owner.clone(), settings).log.capacity
+        };
+        let most = state.config.chat_history_len;
+        assert_eq!(kept(None), most);
+        assert_eq!(kept(Some(20)), 20);
+        assert_eq!(kept(Some(0)), 0);
+        assert_eq!(kept(Some(most as u32 + 1)), most);
+    }
+
+    #[tokio::test]
+    async fn a_state_starts_from_its_score_store() {
+        let store = Arc::new(MemoryStore::default());
+        store.save(&[(Reverse(88), "Ada".to_owned())].into_iter().collect()).await;
+        let state = AppState::with_score_store(store, Config::default()).await;
+        assert_eq!(state.top_10.lock().await.len(), 1);
+        assert_eq!(state.top_10_list.load().first(), Some(&(88, "Ada".to_owned())));
+    }
+
+    #[test]
+    fn an_owner_moved_to_the_spectators_hands_over_after_the_new_list() {
+        let state = state();
+        let room = open_room(&state, "4821");
+        let mut room = room.try_lock().unwrap();
+        for id in ["first", "second"] {
+            room.add_player(Player {
+                player_id: id.to_owned(),
+                name: id.to_owned(),
+                ready: false,
+                muted: false,
+            });
+        }
+        let mut rx = room.tx.subscribe();
+        assert!(room.move_to_spectators(&"4821".to_owned(), &"owner".to_owned(), &state.audit));
+
+        let mut next = || {
+            let payload = rx.try_recv().expect("nothing more was broadcast");
+            serde_json::from_str::<crate::ws_messages::RoomEvent>(&payload).unwrap().msg
+        };
+        assert!(matches!(next(), WsServerMsg::MovedToSpectators { .. }));
+        match next() {
+            WsServerMsg::RoomPlayersUpdate { players, owner_id, .. } => {
+                assert_eq!(owner_id, "first");
+                assert!(players.iter().all(|p| p.player_id != "owner"), "{players:?}");
+            }
+            other => panic!("not the player list: {other:?}"),
+        }
+        match next() {
+            WsServerMsg::OwnerChanged { owner_id, previous_owner_id, .. } => {
+                assert_eq!((owner_id.as_str(), previous_owner_id.as_str()), ("first", "owner"));
+            }
+            other => panic!("not OwnerChanged: {other:?}"),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn a_room_closed_from_many_tasks_at_once_is_closed_once() {
+        let state = state();
+        for round in 0..50 {
+            let room_id = format!("{round:04}");
+            open_room(&state, &room_id);
+            let closers: Vec<_> = (0..8)
+                .map(|_| {
+                    let (state, room_id) = (state.clone(), room_id.clone());
+                    tokio::spawn(async move {
+                        state.close_room(&room_id, RoomCloseReason::Empty).await
+                    })
+                })
+                .collect();
+            let mut closed = 0;
+            for closer in closers {
+                closed += closer.await.unwrap() as usize;
+            }
+            assert_eq!(closed, 1, "round {round}");
+        }
+        assert!(state.rooms.is_empty());
+        assert_eq!(state.room_count.load(Ordering::Relaxed), 0);
+    }
+
+    // the lock is held across the yield on purpose: that's what the waiter is waiting on
+    #[allow(clippy::await_holding_invalid_type)]
+    #[tokio::test]
+    async fn whoever_was_waiting_on_a_closed_room_does_not_get_it() {
+        let state = state();
+        open_room(&state, "4821");
+        let mut owner = state.lock_room("4821").await.unwrap();
+        // looked the room up before the close, and now waits for its lock
+        let waiter = tokio::spawn({
+            let state = state.clone();
+            async move { state.lock_room("4821").await.is_none() }
+        });
+        tokio::task::yield_now().await;
+
+        assert!(state.close_locked_room(&"4821".to_owned(), &mut owner, RoomCloseReason::Empty));
+        drop(owner);
+        assert!(waiter.await.unwrap(), "got a closed room");
+        assert!(state.lock_room("4821").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn closing_a_room_leaves_a_newer_one_under_the_same_id() {
+        let state = state();
+        let old = open_room(&state, "4821");
+        let new = {
+            let mut old = old.lock_owned().await;
+            // the ID went to another room meanwhile
+            let new = open_room(&state, "4821");
+            assert!(state.close_locked_room(&"4821".to_owned(), &mut old, RoomCloseReason::Empty));
+            new
+        };
+        let left = state.rooms.get("4821").expect("the newer room went too").value().clone();
+        assert!(Arc::ptr_eq(&left, &new));
+        assert!(state.lock_room("4821").await.is_some());
+    }
+
+    #[test]
+    fn the_published_top_10_is_best_first() {
+        let state = state();
+        let top_10 = [(40, "Bo"), (88, "Ada"), (55, "Cy")]
+            .map(|(score, name)| (Reverse(score), name.to_owned()))
+            .into_iter()
+            .collect();
+        state.publish_top_10(&top_10);
+        let best_first = [(88, "Ada"), (55, "Cy"), (40, "Bo")].map(|(s, n)| (s, n.to_owned()));
+        assert_eq!(**state.top_10_list.load(), best_first);
+    }
+
+    /// Top 10s recorded the way a finishing game does it: one better score each time, published
+    /// under the writers' lock.
+    async fn record_scores(state: &AppState, scores: std::ops::RangeInclusive<u32>) {
+        for score in scores {
+            let mut top_10 = state.top_10.lock().await;
+            top_10.push((Reverse(score), format!("player {score}")));
+            if top_10.len() > 10 {
+                top_10.pop();
+            }
+            state.publish_top_10(&top_10);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn readers_only_ever_see_whole_and_newer_top_10s() {
+        const WRITES: u32 = 2000;
+        let state = state();
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let mut last_best = 0;
+                    loop {
+                        let list = state.top_10_list.load_full();
+                        let best = list.first().map_or(0, |&(score, _)| score);
+                        // exactly the ten best recorded so far, never a list part-way built
+                        let expected: Vec<_> = (best.saturating_sub(9).max(1)..=best)
+                            .rev()
+                            .map(|score| (score, format!("player {score}")))
+                            .collect();
+                        assert_eq!(*list, expected);
+                        assert!(best >= last_best, "went back from {last_best} to {best}");
+                        last_best = best;
+                        if best == WRITES {
+                            return;
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        record_scores(&state, 1..=WRITES).await;
+        // every reader gets to the last write and stops there
+        for reader in readers {
+            tokio::time::timeout(Duration::from_secs(5), reader).await.unwrap().unwrap();
+        }
+    }
+
+    fn chat_entry(message_id: u64, channel: ChatChannel, message: &str) -> RoomLogEntry {
+        RoomLogEntry::Chat {
+            message_id,
+            channel,
+            player: Player {
+                player_id: "p".to_owned(),
+                name: "P".to_owned(),
+                ready: false,
+                muted: false,
+            },
+            message: message.to_owned(),
+            is_spectator: false,
+            at_ms: 0,
+        }
+    }
+
+    /// The `message_id`s in a page.
+    fn ids(page: &[RoomLogEntry]) -> Vec<u64> {
+        page.iter()
+            .map(|entry| match entry {
+                RoomLogEntry::Chat { message_id, .. } => *message_id,
+                RoomLogEntry::System { .. } => panic!("a system notice in a chat page"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chat_pages_run_back_past_where_the_log_wrapped() {
+        let mut log = RoomLog::new(6, 0, None);
+        for id in 1..=9 {
+            log.push(chat_entry(id, ChatChannel::All, "hi"));
+        }
+        log.push(RoomLogEntry::System {
+            kind: SystemMessageKind::PlayerJoined,
+            text: "P joined".to_owned(),
+            at_ms: 0,
+        });
+        // 1 to 4 are gone; the notice takes a slot but no place on a page
+        let (page, has_more) = log.chat_page(None, 3, false, false);
+        assert_eq!((ids(&page), has_more), (vec![7, 8, 9], true));
+        let (page, has_more) = log.chat_page(Some(7), 3, false, false);
+        assert_eq!((ids(&page), has_more), (vec![5, 6], false));
+        let (page, has_more) = log.chat_page(Some(5), 3, false, false);
+        assert_eq!((ids(&page), has_more), (vec![], false));
+    }
+
+    #[test]
+    fn chat_pages_leave_out_channels_the_member_does_not_get() {
+        let mut log = RoomLog::new(16, 0, None);
+        log.push(chat_entry(1, ChatChannel::All, "everyone"));
+        log.push(chat_entry(2, ChatChannel::Players, "players"));
+        log.push(chat_entry(3, ChatChannel::Spectators, "spectators"));
+        assert_eq!(ids(&log.chat_page(None, 10, false, false).0), [1, 2]);
+        assert_eq!(ids(&log.chat_page(None, 10, false, true).0), [1, 2, 3]);
+        assert_eq!(ids(&log.chat_page(None, 10, true, false).0), [1, 3]);
+        // a page is full with what the member gets, not what is in the log
+        let (page, has_more) = log.chat_page(None, 1, true, false);
+        assert_eq!((ids(&page), has_more), (vec![3], true));
+    }
+
+    #[test]
+    fn the_log_drops_its_oldest_text_to_stay_under_its_byte_cap() {
+        // each entry holds 1 byte of name and 9 of message
+        let mut log = RoomLog::new(100, 25, None);
+        for id in 1..=4 {
+            log.push(chat_entry(id, ChatChannel::All, "123456789"));
+        }
+        assert_eq!(ids(&log.chat_page(None, 10, false, false).0), [3, 4]);
+        // one entry over the cap on its own is still kept, alone
+        log.push(chat_entry(5, ChatChannel::All, &"x".repeat(40)));
+        assert_eq!(ids(&log.chat_page(None, 10, false, false).0), [5]);
+    }
+}
@@ -0,0 +1,162 @@
+// src/sessions.rs
+use crate::ws_messages::{PlayerId, RoomId};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::Notify;
+
+/// One socket acting as a player, as listed by `GET /api/admin/sessions/{player_id}`.
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionInfo {
+    /// Unique for the life of the process.
+    pub id: u64,
+    pub client: IpAddr,
+    pub room_id: RoomId,
+    pub spectating: bool,
+    /// When the socket took on this player ID (Unix ms).
+    pub since_ms: u64,
+}
+
+#[derive(Debug)]
+struct Session {
+    info: SessionInfo,
+    // Woken when a newer session takes over; the socket sends `SessionReplaced` and closes.
+    replaced: Arc<Replacement>,
+}
+
+/// How a socket learns that a newer session of its player replaced it.
+#[derive(Debug, Default)]
+pub struct Replacement {
+    notify: Notify,
+    seat_taken: AtomicBool,
+}
+
+impl Replacement {
+    fn replace(&self, seat_taken: bool) {
+        self.seat_taken.store(seat_taken, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// Waits until the session is replaced. Returns `true` if the new session took over this
+    /// one's place in its room, which the old socket must then leave alone.
+    pub async fn wait(&self) -> bool {
+        self.notify.notified().await;
+        self.seat_taken.load(Ordering::Relaxed)
+    }
+}
+
+/// Which sockets are acting as which player, so one player's sessions can be listed and an
+/// older one closed when a new one takes its seat (or, with `single_session`, starts at all).
+///
+/// Player IDs are generated by the clients, so they are the identity here: a socket takes one
+/// on when it enters a room and gives it up when it leaves or disconnects.
+///
+/// Also which rooms each player has a seat in, socket or not (a dropped player's seat is held
+/// for them), so that can be answered without locking every room.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    next_id: AtomicU64,
+    by_player: DashMap<PlayerId, Vec<Session>>,
+    // Kept in step with each room's `players` by whoever changes them, under that room's lock.
+    seats: DashMap<PlayerId, Vec<RoomId>>,
+}
+
+impl Sessions {
+    /// A fresh session ID for a new socket.
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Records `info` as a session of `player_id`, replacing the socket's earlier entry there if
+    /// it had one. Any other session of the player in the same room has just had its place
+    /// taken over, and with `single` so has every other session of the player; those are
+    /// dropped and woken through their `Replacement`, and their IDs are returned.
+    ///
+    /// Callers hold the room's lock, so of two sockets racing for one place the one that got
+    /// the lock last keeps it.
+    pub fn claim(
+        &self,
+        player_id: &PlayerId,
+        info: SessionInfo,
+        replaced: &Arc<Replacement>,
+        single: bool,
+    ) -> Vec<u64> {
+        let mut sessions = self.by_player.entry(player_id.clone()).or_default();
+        let mut displaced = Vec::new();
+        sessions.retain(|s| {
+            if s.info.id == info.id {
+                return false;
+            }
+            let seat_taken = s.info.room_id == info.room_id;
+            if seat_taken || single {
+                s.replaced.replace(seat_taken);
+                displaced.push(s.info.id);
+                return false;
+            }
+            true
+        });
+        sessions.push(Session {
+            info,
+            replaced: Arc::clone(replaced),
+        });
+        displaced
+    }
+
+    /// Forgets session `id` of `player_id`; does nothing if it was already gone.
+    pub fn release(&self, player_id: &PlayerId, id: u64) {
+        self.by_player.remove_if_mut(player_id, |_, sessions| {
+            sessions.retain(|s| s.info.id != id);
+            sessions.is_empty()
+        });
+    }
+
+    /// `player_id` took a seat in `room_id`.
+    pub fn seat(&self, player_id: &PlayerId, room_id: &RoomId) {
+        self.seats.entry(player_id.clone()).or_default().push(room_id.clone());
+    }
+
+    /// Seats `player_id` in `room_id` unless they already have a seat somewhere; `false` if they
+    /// do. Two calls for one player can't both succeed.
+    pub fn seat_first(&self, player_id: &PlayerId, room_id: &RoomId) -> bool {
+        let mut rooms = self.seats.entry(player_id.clone()).or_default();
+        if !rooms.is_empty() {
+            return false;
+        }
+        rooms.push(room_id.clone());
+        true
+    }
+
+    /// `player_id` gave up their seat in `room_id`; does nothing if they had none there.
+    pub fn unseat(&self, player_id: &PlayerId, room_id: &RoomId) {
+        self.seats.remove_if_mut(player_id, |_, rooms| {
+            if let Some(i) = rooms.iter().position(|r| r == room_id) {
+                rooms.swap_remove(i);
+            }
+            rooms.is_empty()
+        });
+    }
+
+    /// Whether `player_id` has a seat in any room.
+    pub fn is_seated(&self, player_id: &PlayerId) -> bool {
+        self.seats.contains_key(player_id)
+    }
+
+    /// Whether `player_id` took their seat in `room_id` here, and hasn't left it.
+    pub fn is_seated_in(&self, player_id: &PlayerId, room_id: &RoomId) -> bool {
+        self.seats.get(player_id).is_some_and(|rooms| rooms.contains(room_id))
+    }
+
+    /// Every live session of `player_id`, oldest first.
+    pub fn list(&self, player_id: &PlayerId) -> Vec<SessionInfo> {
+        self.by_player
+            .get(player_id)
+            .map(|sessions| sessions.iter().map(|s| s.info.clone()).collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,197 @@
+// src/stats.rs
+use crate::ws_messages::{ErrorCode, WsClientMsg};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Error kinds we count separately; everything without a code lands in `Other`.
+const ERROR_KINDS: [&str; 15] = [
+    "RateLimited",
+    "InvalidName",
+    "ServerFull",
+    "Maintenance",
+    "MalformedJson",
+    "InvalidSettings",
+    "RoomFull",
+    "GameInProgress",
+    "Muted",
+    "TurnOutOfOrder",
+    "GameAlreadyRunning",
+    "GameNotRunning",
+    "ServerBusy",
+    "SeatTaken",
+    "Other",
+];
+
+/// Cheap in-process counters for the stats API and the `ServerStats` broadcast.
+///
+/// Plain relaxed atomics, so bumping one never takes a lock. They only ever go up (nothing
+/// resets them while the process lives); consumers diff two readings to get rates.
+#[derive(Debug, Default)]
+pub struct Counters {
+    messages: [AtomicU64; WsClientMsg::KINDS.len()],
+    invalid_messages: AtomicU64,
+    broadcasts: AtomicU64,
+    errors: [AtomicU64; ERROR_KINDS.len()],
+    games_completed: AtomicU64,
+}
+
+/// A point-in-time copy of `Counters`, as returned by `GET /api/stats`.
+#[derive(Serialize, Debug, Clone)]
+pub struct CountersSnapshot {
+    /// Client messages handled, by `WsClientMsg` variant.
+    pub messages: BTreeMap<&'static str, u64>,
+    /// Client messages that weren't valid JSON for any variant.
+    pub invalid_messages: u64,
+    /// Room broadcasts sent, across every room.
+    pub broadcasts: u64,
+    /// `Error`s sent to clients, by `ErrorCode` variant.
+    pub errors: BTreeMap<&'static str, u64>,
+    pub games_completed: u64,
+}
+
+impl Counters {
+    /// A parsed client message of this `WsClientMsg::kind()` is about to be handled.
+    pub fn message(&self, kind: &str) {
+        if let Some(i) = WsClientMsg::KINDS.iter().position(|&k| k == kind) {
+            self.messages[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn invalid_message(&self) {
+        self.invalid_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn broadcast(&self) {
+        self.broadcasts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// An `Error` with this code went out to a client.
+    pub fn error(&self, code: Option<&ErrorCode>) {
+        let i = match code {
+            Some(ErrorCode::RateLimited { .. }) => 0,
+            Some(ErrorCode::InvalidName { .. }) => 1,
+            Some(ErrorCode::ServerFull { .. }) => 2,
+            Some(ErrorCode::Maintenance) => 3,
+            Some(ErrorCode::MalformedJson { .. }) => 4,
+            Some(ErrorCode::InvalidSettings { .. }) => 5,
+            Some(ErrorCode::RoomFull { .. }) => 6,
+            Some(ErrorCode::GameInProgress) => 7,
+            Some(ErrorCode::Muted { .. }) => 8,
+            Some(ErrorCode::TurnOutOfOrder { .. }) => 9,
+            Some(ErrorCode::GameAlreadyRunning) => 10,
+            Some(ErrorCode::GameNotRunning) => 11,
+            Some(ErrorCode::ServerBusy { .. }) => 12,
+            Some(ErrorCode::SeatTaken) => 13,
+            None => 14,
+        };
+        self.errors[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_completed(&self) {
+        self.games_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn total_messages(&self) -> u64 {
+        self.messages.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn total_broadcasts(&self) -> u64 {
+        self.broadcasts.load(Ordering::Relaxed)
+    }
+
+    pub fn total_games_completed(&self) -> u64 {
+        self.games_completed.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> CountersSnapshot {
+        let read = |names: &[&'static str], counters: &[AtomicU64]| {
+            names
+                .iter()
+                .zip(counters)
+                .map(|(&name, c)| (name, c.load(Ordering::Relaxed)))
+                .collect()
+        };
+        CountersSnapshot {
+            messages: read(&WsClientMsg::KINDS, &self.messages),
+            invalid_messages: self.invalid_messages.load(Ordering::Relaxed),
+            broadcasts: self.total_broadcasts(),
+            errors: read(&ERROR_KINDS, &self.errors),
+            games_completed: self.total_games_completed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_error_code_has_its_own_bucket() {
+        let counters = Counters::default();
+        counters.error(Some(&ErrorCode::SeatTaken));
+        counters.error(Some(&ErrorCode::TurnOutOfOrder { expected: 3 }));
+        counters.error(Some(&ErrorCode::TurnOutOfOrder { expected: 4 }));
+        counters.error(None);
+
+        let errors = counters.snapshot().errors;
+        assert_eq!(errors.len(), ERROR_KINDS.len());
+        assert_eq!(errors["SeatTaken"], 1);
+        assert_eq!(errors["TurnOutOfOrder"], 2);
+        assert_eq!(errors["Other"], 1);
+        assert_eq!(errors.values().sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn messages_are_counted_by_kind_and_unknown_kinds_ignored() {
+        let counters = Counters::default();
+        counters.message("CreateRoom");
+        counters.message("ScoreUpdate");
+        counters.message("ScoreUpdate");
+        counters.message("NotAMessage");
+        counters.invalid_message();
+
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.messages.len(), WsClientMsg::KINDS.len());
+        assert_eq!(snapshot.messages["CreateRoom"], 1);
+        assert_eq!(snapshot.messages["ScoreUpdate"], 2);
+        assert_eq!(snapshot.invalid_messages, 1);
+        // unparseable messages aren't "handled"
+        assert_eq!(counters.total_messages(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn no_increment_is_lost_under_contention() {
+        const TASKS: u64 = 16;
+        const EACH: u64 = 5_000;
+        let counters = Arc::new(Counters::default());
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|t| {
+                let counters = counters.clone();
+                tokio::spawn(async move {
+                    for i in 0..EACH {
+                        counters.message(WsClientMsg::KINDS[(t + i) as usize % 3]);
+                        counters.broadcast();
+                        counters.error(None);
+                        if i % 100 == 0 {
+                            counters.game_completed();
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let snapshot = counters.snapshot();
+        assert_eq!(counters.total_messages(), TASKS * EACH);
+        assert_eq!(snapshot.broadcasts, TASKS * EACH);
+        assert_eq!(snapshot.errors["Other"], TASKS * EACH);
+        assert_eq!(snapshot.games_completed, TASKS * EACH / 100);
+    }
+}
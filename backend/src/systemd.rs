@@ -0,0 +1,77 @@
+// src/systemd.rs
+use crate::server_state::AppState;
+use std::time::Duration;
+
+// `Type=notify` support. Outside systemd (no `NOTIFY_SOCKET` / `WATCHDOG_USEC`, or not Linux)
+// every function here does nothing.
+
+/// The listener is bound and startup state is loaded.
+pub fn ready() {
+    notify(Notify::Ready);
+}
+
+/// Graceful shutdown has begun.
+pub fn stopping() {
+    notify(Notify::Stopping);
+}
+
+/// Starts pinging the watchdog if the unit has `WatchdogSec=` set.
+///
+/// Pings go out at half the configured interval, but only after every room's lock could be
+/// taken within a quarter of it; a wedged lock stops the pings, so systemd restarts the service.
+pub fn spawn_watchdog(state: AppState) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tracing::info!(interval_ms = interval.as_millis() as u64, "systemd watchdog enabled");
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval / 2);
+        loop {
+            ticks.tick().await;
+            let lock_every_room = async {
+                for (_, room) in state.all_rooms() {
+                    drop(room.lock().await);
+                }
+            };
+            match tokio::time::timeout(interval / 4, lock_every_room).await {
+                Ok(()) => notify(Notify::Watchdog),
+                Err(_) => tracing::error!("rooms lock wedged, skipping watchdog ping"),
+            }
+        }
+    });
+}
+
+enum Notify {
+    Ready,
+    Stopping,
+    Watchdog,
+}
+
+#[cfg(target_os = "linux")]
+fn notify(what: Notify) {
+    use sd_notify::NotifyState;
+    let state = match what {
+        Notify::Ready => NotifyState::Ready,
+        Notify::Stopping => NotifyState::Stopping,
+        Notify::Watchdog => NotifyState::Watchdog,
+    };
+    // a no-op (Ok) when NOTIFY_SOCKET isn't set
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        tracing::warn!(error = %e, "failed to notify systemd");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify(_: Notify) {}
+
+#[cfg(target_os = "linux")]
+fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    (sd_notify::watchdog_enabled(false, &mut usec) && usec > 0)
+        .then(|| Duration::from_micros(usec))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watchdog_interval() -> Option<Duration> {
+    None
+}
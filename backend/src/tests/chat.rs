@@ -0,0 +1,259 @@
+// src/tests/chat.rs
+//! Room and lobby chat both go out cleaned up, and neither takes a message with nothing in it.
+//! Room chat reaches only the members its channel is for.
+use super::support::{player, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{ChatChannel, RoomLogEntry, RoomSettings, WsClientMsg, WsServerMsg},
+};
+
+async fn chat(client: &mut Client, message: &str, name: Option<&str>) {
+    client
+        .send(&WsClientMsg::ChatMessage {
+            message: message.to_owned(),
+            name: name.map(str::to_owned),
+            channel: None,
+        })
+        .await;
+}
+
+/// Posts `message` to the room on `channel` (the sender's default without one) and waits for
+/// `witness`, who gets every channel, to hear it: whatever is posted next goes out after it.
+async fn post(
+    client: &mut Client,
+    witness: &mut Client,
+    message: &str,
+    channel: Option<ChatChannel>,
+) {
+    client
+        .send(&WsClientMsg::ChatMessage {
+            message: message.to_owned(),
+            name: None,
+            channel,
+        })
+        .await;
+    witness
+        .expect(|msg| match msg {
+            WsServerMsg::ChatBroadcast { message: heard, .. } => (heard == message).then_some(()),
+            _ => None,
+        })
+        .await;
+}
+
+/// Follows the spectators' chat as a player. No reply comes for that, so a `ReadyUp` goes
+/// after it: once that is acked, the follow is in.
+async fn follow(client: &mut Client) {
+    client.send(&WsClientMsg::FollowSpectatorChat { follow: true }).await;
+    client.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::ReadyAck { .. }).then_some(()))
+        .await;
+}
+
+/// The room chat `client` gets, with its channel, up to and including `last`.
+async fn heard_until(client: &mut Client, last: &str) -> Vec<(ChatChannel, String)> {
+    let mut heard = Vec::new();
+    loop {
+        if let Some(WsServerMsg::ChatBroadcast { channel, message, .. }) = client.recv().await {
+            let done = message == last;
+            heard.push((channel, message));
+            if done {
+                return heard;
+            }
+        }
+    }
+}
+
+async fn post_refused(client: &mut Client, channel: ChatChannel, why: &str) {
+    client
+        .send(&WsClientMsg::ChatMessage {
+            message: format!("to {channel:?}"),
+            name: None,
+            channel: Some(channel),
+        })
+        .await;
+    assert_eq!(refusal(client).await, why);
+}
+
+/// Spectates `room_id` as `id`, and returns the room chat the history replay had in it.
+async fn spectate(client: &mut Client, room_id: &str, id: &str) -> Vec<String> {
+    client
+        .send(&WsClientMsg::SpectateRoom {
+            room_id: room_id.to_owned(),
+            player: player(id, id),
+        })
+        .await;
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::RoomHistory { entries, .. } => Some(entries),
+            WsServerMsg::Error { msg, .. } => panic!("spectating refused: {msg}"),
+            _ => None,
+        })
+        .await
+        .into_iter()
+        .filter_map(|entry| match entry {
+            RoomLogEntry::Chat { message, .. } => Some(message),
+            RoomLogEntry::System { .. } => None,
+        })
+        .collect()
+}
+
+async fn refusal(client: &mut Client) -> String {
+    match client.expect_error().await {
+        WsServerMsg::Error { msg, .. } => msg,
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn room_chat_is_broadcast_cleaned_up() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+
+    chat(&mut guest, " \u{200B}\n\n", None).await;
+    assert_eq!(refusal(&mut guest).await, "Message can't be empty");
+    chat(&mut guest, "x".repeat(501).as_str(), None).await;
+    assert_eq!(refusal(&mut guest).await, "Message is longer than 500 characters");
+
+    chat(&mut guest, "  gg\u{202E}\n\n\n\nwp \u{1F44D}\u{1F3FD}  ", None).await;
+    let heard = host
+        .expect(|msg| match msg {
+            WsServerMsg::ChatBroadcast { message, .. } => Some(message),
+            _ => None,
+        })
+        .await;
+    assert_eq!(heard, "gg\n\nwp \u{1F44D}\u{1F3FD}");
+}
+
+#[tokio::test]
+async fn lobby_chat_is_cleaned_up_the_same_way() {
+    let server = TestServer::with_config(Config {
+        lobby_chat: true,
+        ..test_config()
+    })
+    .await;
+    let mut listener = server.connect().await;
+    let mut talker = server.connect().await;
+
+    chat(&mut talker, "\t\u{7}", Some("Talker")).await;
+    assert_eq!(refusal(&mut talker).await, "Message can't be empty");
+    chat(&mut talker, "anyone\u{0}\n\n\nup for a round?", Some("Talker")).await;
+    let heard = listener
+        .expect(|msg| match msg {
+            WsServerMsg::LobbyChatBroadcast { message, .. } => Some(message),
+            _ => None,
+        })
+        .await;
+    assert_eq!(heard, "anyone\n\nup for a round?");
+}
+
+#[tokio::test]
+async fn each_channel_reaches_only_its_members() {
+    use ChatChannel::{All, Players, Spectators};
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut follower = server.connect().await;
+    follower.join(&room_id, &player("follower", "Follower"), None).await;
+    let mut witness = server.connect().await;
+    witness.join(&room_id, &player("witness", "Witness"), None).await;
+    let mut spectator = server.connect().await;
+    spectate(&mut spectator, &room_id, "spectator").await;
+
+    post_refused(&mut host, Spectators, "Follow the spectators' chat to post in it").await;
+    post_refused(&mut spectator, Players, "Only players can post to the players' chat").await;
+
+    follow(&mut follower).await;
+    follow(&mut witness).await;
+    let w = &mut witness;
+    post(&mut follower, w, "follower to spectators", Some(Spectators)).await;
+    post(&mut host, w, "host to all", None).await;
+    post(&mut host, w, "host to players", Some(Players)).await;
+    post(&mut spectator, w, "spectator to spectators", None).await;
+    post(&mut spectator, w, "spectator to all", Some(All)).await;
+    post(&mut host, w, "end", None).await;
+
+    let said = |heard: &[(ChatChannel, &str)]| -> Vec<(ChatChannel, String)> {
+        heard.iter().map(|&(channel, m)| (channel, m.to_owned())).collect()
+    };
+    assert_eq!(
+        heard_until(&mut host, "end").await,
+        said(&[
+            (All, "host to all"),
+            (Players, "host to players"),
+            (All, "spectator to all"),
+            (All, "end"),
+        ])
+    );
+    assert_eq!(
+        heard_until(&mut follower, "end").await,
+        said(&[
+            (Spectators, "follower to spectators"),
+            (All, "host to all"),
+            (Players, "host to players"),
+            (Spectators, "spectator to spectators"),
+            (All, "spectator to all"),
+            (All, "end"),
+        ])
+    );
+    assert_eq!(
+        heard_until(&mut spectator, "end").await,
+        said(&[
+            (Spectators, "follower to spectators"),
+            (All, "host to all"),
+            (Spectators, "spectator to spectators"),
+            (All, "spectator to all"),
+            (All, "end"),
+        ])
+    );
+
+    // the history replay goes by the same rules
+    let mut late = server.connect().await;
+    assert_eq!(
+        spectate(&mut late, &room_id, "late").await,
+        [
+            "follower to spectators",
+            "host to all",
+            "spectator to spectators",
+            "spectator to all",
+            "end"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn spectators_can_be_kept_out_of_the_players_chat() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    host.send(&WsClientMsg::CreateRoom {
+        player: player("host", "Host"),
+        settings: Some(RoomSettings {
+            spectator_chat: false,
+            ..RoomSettings::default()
+        }),
+    })
+    .await;
+    let room_id = host
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { room_id, .. } => Some(room_id),
+            _ => None,
+        })
+        .await;
+    let mut spectator = server.connect().await;
+    spectate(&mut spectator, &room_id, "spectator").await;
+
+    let refusal = "Spectators can't chat with the players in this room";
+    post_refused(&mut spectator, ChatChannel::All, refusal).await;
+    chat(&mut spectator, "among ourselves", None).await;
+    let heard = spectator
+        .expect(|msg| match msg {
+            WsServerMsg::ChatBroadcast { channel, .. } => Some(channel),
+            WsServerMsg::Error { msg, .. } => panic!("chat refused: {msg}"),
+            _ => None,
+        })
+        .await;
+    assert_eq!(heard, ChatChannel::Spectators);
+}
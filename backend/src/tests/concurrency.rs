@@ -0,0 +1,530 @@
+// src/tests/concurrency.rs
+use super::support::{player, start_two_player_game, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    room_bus::{RoomBus, RoomPayload},
+    score_store::{MemoryStore, ScoreStore},
+    server_state::{AppState, SharedRoom, TopScores},
+    ws_messages::{ErrorCode, RoomId, RoomSettings, WsClientMsg, WsServerMsg},
+};
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use std::{
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, Notify},
+    time::timeout,
+};
+
+const FLOOD: u32 = 200;
+
+/// Updates sent ahead of their acks. The outbox only keeps eight slots free for direct
+/// replies, so a client that runs further ahead than that can have acks dropped.
+const WINDOW: u32 = 8;
+
+/// A room with a game running, and the guest's socket to score from.
+async fn room_in_play(server: &TestServer, tag: &str) -> (RoomId, Client, Client) {
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player(&format!("host-{tag}"), "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player(&format!("guest-{tag}"), "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    (room_id, host, guest)
+}
+
+/// Sends turns `1..=FLOOD`, keeping `WINDOW` of them ahead of their acks; returns the last total.
+async fn flood(guest: &mut Client) -> u32 {
+    let mut total = 0;
+    for turn in 1..=FLOOD {
+        guest
+            .send(&WsClientMsg::ScoreUpdate {
+                cleared_count: 2,
+                turn,
+                cleared_values: vec![1, 9],
+                rect: None,
+            })
+            .await;
+        if turn >= WINDOW {
+            total = next_ack(guest).await;
+        }
+    }
+    for _ in 1..WINDOW {
+        total = next_ack(guest).await;
+    }
+    total
+}
+
+async fn next_ack(guest: &mut Client) -> u32 {
+    guest
+        .expect(|msg| match msg {
+            WsServerMsg::ScoreAck { total, applied: true, .. } => Some(total),
+            WsServerMsg::ScoreAck { turn, .. } => panic!("turn {turn} not applied"),
+            WsServerMsg::Error { msg, .. } => panic!("score refused: {msg}"),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn two_rooms_flooded_at_once_both_count_every_update() {
+    let server = TestServer::start().await;
+    let (a, _host_a, mut guest_a) = room_in_play(&server, "a").await;
+    let (b, _host_b, mut guest_b) = room_in_play(&server, "b").await;
+
+    let (total_a, total_b) = tokio::join!(flood(&mut guest_a), flood(&mut guest_b));
+    assert_eq!((total_a, total_b), (2 * FLOOD, 2 * FLOOD));
+    for (room_id, guest) in [(a, "guest-a"), (b, "guest-b")] {
+        let room = server.state.lock_room(&room_id).await.unwrap();
+        assert_eq!(room.scores[guest], 2 * FLOOD);
+        assert_eq!(room.turns[guest], FLOOD);
+    }
+}
+
+// Holding the room lock across the flood is the point: it stands in for a stuck handler.
+#[allow(clippy::await_holding_invalid_type)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn a_stuck_room_does_not_hold_up_another() {
+    let server = TestServer::start().await;
+    let (a, _host_a, mut guest_a) = room_in_play(&server, "a").await;
+    let (_b, _host_b, mut guest_b) = room_in_play(&server, "b").await;
+
+    let stuck = server.state.lock_room(&a).await.unwrap();
+    guest_a
+        .send(&WsClientMsg::ScoreUpdate {
+            cleared_count: 2,
+            turn: 1,
+            cleared_values: vec![1, 9],
+            rect: None,
+        })
+        .await;
+    assert_eq!(flood(&mut guest_b).await, 2 * FLOOD);
+
+    // room A's update was waiting on its own lock all along, and goes through once it's free
+    drop(stuck);
+    let total = guest_a
+        .expect(|msg| match msg {
+            WsServerMsg::ScoreAck { total, .. } => Some(total),
+            _ => None,
+        })
+        .await;
+    assert_eq!(total, 2);
+}
+
+/// Whether the `JoinRoom` `guest` sent got a seat, rather than a refusal.
+async fn joined(guest: &mut Client) -> bool {
+    guest
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { .. } => Some(true),
+            WsServerMsg::Error { .. } => Some(false),
+            _ => None,
+        })
+        .await
+}
+
+async fn leave(client: &mut Client) {
+    client.send(&WsClientMsg::LeaveRoom {}).await;
+    left(client).await;
+}
+
+async fn left(client: &mut Client) {
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::LeftRoom { .. }).then_some(()))
+        .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn joins_racing_the_last_leave_never_land_in_a_dead_room() {
+    const ROUNDS: usize = 24;
+    const GUESTS: usize = 3;
+    // a fresh room every round, all from one address
+    let server = TestServer::with_config(Config {
+        max_rooms_per_ip_per_minute: 0,
+        ..test_config()
+    })
+    .await;
+    let (mut seated, mut refused) = (0, 0);
+    for round in 0..ROUNDS {
+        let mut host = server.connect().await;
+        let (room_id, _) = host.create_room(&player(&format!("host-{round}"), "Host")).await;
+        let mut guests = Vec::new();
+        for _ in 0..GUESTS {
+            guests.push(server.connect().await);
+        }
+
+        // the owner walks out of the room as the guests walk in, somewhere among them (each
+        // socket is handled on its own task, so the server sees them in any order)
+        for (i, guest) in guests.iter_mut().enumerate() {
+            if i == round % (GUESTS + 1) {
+                host.send(&WsClientMsg::LeaveRoom {}).await;
+            }
+            guest
+                .send(&WsClientMsg::JoinRoom {
+                    room_id: room_id.clone(),
+                    player: player(&format!("guest-{round}-{i}"), "Guest"),
+                    seat_token: None,
+                })
+                .await;
+        }
+        if round % (GUESTS + 1) == GUESTS {
+            host.send(&WsClientMsg::LeaveRoom {}).await;
+        }
+        left(&mut host).await;
+        let mut in_room = Vec::new();
+        for guest in &mut guests {
+            in_room.push(joined(guest).await);
+        }
+        let count = in_room.iter().filter(|&&j| j).count();
+        seated += count;
+        refused += GUESTS - count;
+
+        // everyone who got a seat is in a room that's still there
+        match server.state.lock_room(&room_id).await {
+            Some(room) => assert_eq!(room.players.len(), count, "round {round}"),
+            None => assert_eq!(count, 0, "round {round}: seated in a closed room"),
+        }
+        for (guest, joined) in guests.iter_mut().zip(in_room) {
+            if joined {
+                leave(guest).await;
+            }
+        }
+    }
+    assert!(seated > 0 && refused > 0, "no race: {seated} seated, {refused} refused");
+    // either way, every room went once its last player did
+    assert!(server.state.rooms.is_empty(), "{} rooms left over", server.state.rooms.len());
+    assert_eq!(server.state.room_count.load(std::sync::atomic::Ordering::Relaxed), 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn creates_racing_under_one_player_id_make_one_room() {
+    const ROUNDS: usize = 16;
+    let server = TestServer::with_config(Config {
+        max_rooms_per_ip_per_minute: 0,
+        ..test_config()
+    })
+    .await;
+    for round in 0..ROUNDS {
+        // two tabs of one player, both asking for a room at once
+        let twin = player(&format!("twin-{round}"), "Twin");
+        let mut tabs = [server.connect().await, server.connect().await];
+        for tab in &mut tabs {
+            tab.send(&WsClientMsg::CreateRoom {
+                player: twin.clone(),
+                settings: None,
+            })
+            .await;
+        }
+        let mut created = Vec::new();
+        for tab in &mut tabs {
+            created.push(joined(tab).await);
+        }
+        assert_eq!(created.iter().filter(|&&c| c).count(), 1, "round {round}");
+        let seated: Vec<_> = server
+            .state
+            .all_rooms()
+            .into_iter()
+            .filter(|(_, room)| room.try_lock().unwrap().players.contains_key(&twin.player_id))
+            .collect();
+        assert_eq!(seated.len(), 1, "round {round}");
+
+        // once out of it, the player can have a room again (from a new tab: each gets one
+        // create per `CREATE_ROOM_BUCKET` refill)
+        let winner = created.iter().position(|&c| c).unwrap();
+        leave(&mut tabs[winner]).await;
+        let mut tab = server.connect().await;
+        tab.create_room(&twin).await;
+        leave(&mut tab).await;
+    }
+    assert!(server.state.rooms.is_empty(), "{} rooms left over", server.state.rooms.len());
+}
+
+/// How long a join may take while something else on the server is stuck.
+const PROMPT: Duration = Duration::from_secs(1);
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn a_client_that_stops_reading_does_not_hold_up_joins_elsewhere() {
+    let server = TestServer::start().await;
+    // room A's guest never reads again, while room A broadcasts an update for every turn
+    let (_a, mut host_a, _silent) = room_in_play(&server, "a").await;
+    let mut host_b = server.connect().await;
+    let (b, _) = host_b.create_room(&player("host-b", "Host")).await;
+
+    let newcomers = async {
+        for i in 0..5 {
+            let mut guest = server.connect().await;
+            let newcomer = player(&format!("guest-b-{i}"), "Guest");
+            timeout(PROMPT, guest.join(&b, &newcomer, None))
+                .await
+                .expect("join to room B stalled");
+            leave(&mut guest).await;
+        }
+    };
+    let (total, ()) = tokio::join!(flood(&mut host_a), newcomers);
+    assert_eq!(total, 2 * FLOOD);
+}
+
+/// A store whose saves wait until the test lets them through.
+#[derive(Debug, Default)]
+struct StalledStore {
+    saving: Notify,
+    release: Notify,
+    inner: MemoryStore,
+}
+
+impl ScoreStore for StalledStore {
+    fn load(&self) -> BoxFuture<'_, TopScores> {
+        self.inner.load()
+    }
+
+    fn save<'a>(&'a self, top_10: &'a TopScores) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.saving.notify_one();
+            self.release.notified().await;
+            self.inner.save(top_10).await;
+        })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn a_slow_top_10_save_holds_up_neither_the_room_nor_the_top_10() {
+    let store = Arc::new(StalledStore::default());
+    let state = AppState::with_score_store(store.clone(), test_config()).await;
+    let server = TestServer::serve(state).await;
+    let (room_id, mut host, mut guest) = room_in_play(&server, "a").await;
+    guest.score_pair(1).await;
+    for client in [&mut host, &mut guest] {
+        client.send(&WsClientMsg::FinishRound {}).await;
+    }
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::GameOver { .. }).then_some(()))
+        .await;
+    timeout(PROMPT, store.saving.notified()).await.expect("the save never started");
+
+    // the save is stuck, and the room and the top 10 carry on without it (seats wait until
+    // the game is recorded, but anyone can come in to watch)
+    let mut newcomer = server.connect().await;
+    newcomer
+        .send(&WsClientMsg::SpectateRoom {
+            room_id: room_id.clone(),
+            player: player("newcomer", "Newcomer"),
+        })
+        .await;
+    let watching = newcomer.expect(|msg| match msg {
+        WsServerMsg::RoomHistory { .. } => Some(()),
+        WsServerMsg::Error { msg, .. } => panic!("spectating refused: {msg}"),
+        _ => None,
+    });
+    timeout(PROMPT, watching).await.expect("spectating stalled behind the save");
+    assert!(server.state.top_10.try_lock().is_ok(), "top 10 locked across the save");
+    assert!(store.inner.load().await.is_empty());
+
+    store.release.notify_one();
+    let deadline = tokio::time::Instant::now() + PROMPT;
+    while store.inner.load().await.is_empty() {
+        assert!(tokio::time::Instant::now() < deadline, "the save never finished");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(store.inner.load().await.into_vec().len(), 1);
+}
+
+/// Seats in the rooms the last-seat race is run on, the owner's included.
+const MAX_PLAYERS: u32 = 4;
+
+/// The reply to a `JoinRoom` sent earlier: `Ok` for a seat, the error code for a refusal.
+async fn join_reply(guest: &mut Client) -> Result<(), Option<ErrorCode>> {
+    guest
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { .. } => Some(Ok(())),
+            WsServerMsg::Error { code, .. } => Some(Err(code)),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn joins_racing_for_the_last_seats_never_overfill_a_room() {
+    const ROUNDS: usize = 10;
+    const GUESTS: usize = 8;
+    let server = TestServer::with_config(Config {
+        max_rooms_per_ip_per_minute: 0,
+        ..test_config()
+    })
+    .await;
+    for round in 0..ROUNDS {
+        let mut host = server.connect().await;
+        host.send(&WsClientMsg::CreateRoom {
+            player: player(&format!("host-{round}"), "Host"),
+            settings: Some(RoomSettings {
+                max_players: MAX_PLAYERS,
+                ..RoomSettings::default()
+            }),
+        })
+        .await;
+        let room_id = host
+            .expect(|msg| match msg {
+                WsServerMsg::SeatGranted { room_id, .. } => Some(room_id),
+                _ => None,
+            })
+            .await;
+        let mut guests = Vec::new();
+        for _ in 0..GUESTS {
+            guests.push(server.connect().await);
+        }
+
+        // every guest asks before any of them hears back
+        for (i, guest) in guests.iter_mut().enumerate() {
+            guest
+                .send(&WsClientMsg::JoinRoom {
+                    room_id: room_id.clone(),
+                    player: player(&format!("guest-{round}-{i}"), "Guest"),
+                    seat_token: None,
+                })
+                .await;
+        }
+        let mut seated = 0;
+        for guest in &mut guests {
+            match join_reply(guest).await {
+                Ok(()) => seated += 1,
+                Err(code) => {
+                    assert_eq!(code, Some(ErrorCode::RoomFull { max_players: MAX_PLAYERS }));
+                }
+            }
+        }
+        assert_eq!(seated, MAX_PLAYERS as usize - 1, "round {round}");
+        let room = server.state.lock_room(&room_id).await.unwrap();
+        assert_eq!(room.players.len(), MAX_PLAYERS as usize, "round {round}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn starts_racing_for_the_last_game_slots_never_overshoot_the_cap() {
+    const ROUNDS: usize = 10;
+    const PLAYERS: usize = 8;
+    let server = TestServer::with_config(Config {
+        max_rooms_per_ip_per_minute: 0,
+        max_running_games: PLAYERS - 1,
+        ..test_config()
+    })
+    .await;
+    for round in 0..ROUNDS {
+        let mut solos = Vec::new();
+        for _ in 0..PLAYERS {
+            solos.push(server.connect().await);
+        }
+
+        // every player asks before any of them hears back
+        for (i, solo) in solos.iter_mut().enumerate() {
+            solo.send(&WsClientMsg::StartSolo {
+                player: player(&format!("solo-{round}-{i}"), "Solo"),
+            })
+            .await;
+        }
+        let mut started = Vec::new();
+        for mut solo in solos {
+            let reply = solo
+                .expect(|msg| match msg {
+                    WsServerMsg::GameStarted { .. } => Some(Ok(())),
+                    WsServerMsg::Error { code, .. } => Some(Err(code)),
+                    _ => None,
+                })
+                .await;
+            match reply {
+                Ok(()) => started.push(solo),
+                Err(code) => {
+                    let busy = ErrorCode::ServerBusy { max_games: PLAYERS as u32 - 1 };
+                    assert_eq!(code, Some(busy));
+                }
+            }
+        }
+        assert_eq!(started.len(), PLAYERS - 1, "round {round}");
+        assert_eq!(server.state.timers.running(), PLAYERS - 1, "round {round}");
+
+        // a refused start leaves no room behind, and leaving gives the slots back
+        assert_eq!(server.state.rooms.len(), PLAYERS - 1, "round {round}");
+        for solo in &mut started {
+            leave(solo).await;
+        }
+        while server.state.timers.running() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_join_into_a_running_game_is_refused_as_in_progress() {
+    let server = TestServer::start().await;
+    let (room_id, _host, _guest) = room_in_play(&server, "a").await;
+    let mut late = server.connect().await;
+    late.send(&WsClientMsg::JoinRoom {
+        room_id: room_id.clone(),
+        player: player("late", "Late"),
+        seat_token: None,
+    })
+    .await;
+    assert_eq!(join_reply(&mut late).await, Err(Some(ErrorCode::GameInProgress)));
+    assert!(!server.state.lock_room(&room_id).await.unwrap().players.contains_key("late"));
+}
+
+/// A bus shared with other instances, one of which creates a room under each ID this one
+/// reserves for a second room, just after the reservation.
+#[derive(Debug)]
+struct RacingBus {
+    rooms: Arc<DashMap<RoomId, SharedRoom>>,
+    raced: Mutex<Option<RoomId>>,
+    released: Mutex<Vec<RoomId>>,
+}
+
+impl RoomBus for RacingBus {
+    fn reserve<'a>(&'a self, room_id: &'a RoomId) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            let mut raced = self.raced.lock().unwrap();
+            // the iterator holds its shard's lock, so it's done with before the insert
+            let first = self.rooms.iter().next().map(|room| room.value().clone());
+            if let (None, Some(room)) = (&*raced, first) {
+                self.rooms.insert(room_id.clone(), room);
+                *raced = Some(room_id.clone());
+            }
+            true
+        })
+    }
+
+    fn release(&self, room_id: &RoomId) {
+        self.released.lock().unwrap().push(room_id.clone());
+    }
+
+    fn publish(
+        &self,
+        _room_id: &RoomId,
+        local: &broadcast::Sender<RoomPayload>,
+        payload: RoomPayload,
+    ) {
+        let _ = local.send(payload);
+    }
+}
+
+#[tokio::test]
+async fn a_room_id_taken_after_it_was_reserved_is_given_back() {
+    let config = Config {
+        max_rooms_per_ip_per_minute: 0,
+        ..test_config()
+    };
+    let mut state = AppState::new_with_top_10(BinaryHeap::new(), config);
+    let bus = Arc::new(RacingBus {
+        rooms: state.rooms.clone(),
+        raced: Mutex::new(None),
+        released: Mutex::new(Vec::new()),
+    });
+    state.bus = bus.clone();
+    let server = TestServer::serve(state).await;
+    let mut first = server.connect().await;
+    first.create_room(&player("first", "First")).await;
+
+    // the second create loses its first ID to the race and carries on with another
+    let mut second = server.connect().await;
+    let (room_id, _) = second.create_room(&player("second", "Second")).await;
+    let raced = bus.raced.lock().unwrap().clone().expect("no race was run");
+    assert_ne!(room_id, raced);
+    assert_eq!(*bus.released.lock().unwrap(), [raced]);
+}
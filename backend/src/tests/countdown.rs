@@ -0,0 +1,196 @@
+// src/tests/countdown.rs
+//! `GameStarting` ahead of every game, whoever starts it, and no scoring until it's over.
+use super::support::{player, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{ErrorCode, RoomId, RoomSettings, SystemMessageKind, WsClientMsg, WsServerMsg},
+};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const COUNTDOWN: Duration = Duration::from_secs(1);
+
+async fn counting_down() -> TestServer {
+    TestServer::with_config(Config {
+        start_countdown: COUNTDOWN,
+        ..test_config()
+    })
+    .await
+}
+
+/// A room with `settings`, owned by "host", with "guest" joined and not ready yet.
+async fn room(server: &TestServer, settings: RoomSettings) -> (RoomId, Client, Client) {
+    let mut host = server.connect().await;
+    host.send(&WsClientMsg::CreateRoom {
+        player: player("host", "Host"),
+        settings: Some(settings),
+    })
+    .await;
+    let room_id = host
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { room_id, .. } => Some(room_id),
+            _ => None,
+        })
+        .await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    (room_id, host, guest)
+}
+
+/// What `client` hears of the start: `GameStarting`'s seconds, then how long `GameStarted`
+/// took after it.
+async fn countdown_then_start(client: &mut Client) -> (u64, Duration) {
+    let in_secs = client
+        .expect(|msg| match msg {
+            WsServerMsg::GameStarting { in_secs, .. } => Some(in_secs),
+            WsServerMsg::GameStarted { .. } => panic!("GameStarted without a countdown"),
+            _ => None,
+        })
+        .await;
+    let counting = Instant::now();
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+        .await;
+    (in_secs, counting.elapsed())
+}
+
+async fn both_counted_down(host: &mut Client, guest: &mut Client) {
+    let (host, guest) = tokio::join!(countdown_then_start(host), countdown_then_start(guest));
+    for (in_secs, took) in [host, guest] {
+        assert_eq!(in_secs, COUNTDOWN.as_secs());
+        // a little slack for the announcement being read a moment after it went out
+        assert!(took >= COUNTDOWN - Duration::from_millis(100), "started after {took:?}");
+    }
+}
+
+#[tokio::test]
+async fn an_auto_start_counts_down_like_a_manual_one() {
+    let server = counting_down().await;
+    let settings = RoomSettings {
+        auto_start: true,
+        ..RoomSettings::default()
+    };
+    let (_, mut host, mut guest) = room(&server, settings).await;
+    // the last guest readying up is all it takes
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    both_counted_down(&mut host, &mut guest).await;
+}
+
+#[tokio::test]
+async fn a_manual_start_counts_down() {
+    let server = counting_down().await;
+    let (_, mut host, mut guest) = room(&server, RoomSettings::default()).await;
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::ReadyAck { .. }).then_some(()))
+        .await;
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    both_counted_down(&mut host, &mut guest).await;
+}
+
+#[tokio::test]
+async fn a_guest_unreadying_during_the_countdown_calls_the_start_off() {
+    let server = counting_down().await;
+    let settings = RoomSettings {
+        auto_start: true,
+        ..RoomSettings::default()
+    };
+    let (room_id, mut host, mut guest) = room(&server, settings).await;
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    host.expect(|msg| matches!(msg, WsServerMsg::GameStarting { .. }).then_some(()))
+        .await;
+
+    // starting again meanwhile is refused
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    match host.expect_error().await {
+        WsServerMsg::Error { msg, .. } => assert_eq!(msg, "Game is already starting"),
+        _ => unreachable!(),
+    }
+    // and so is joining, which would seat someone who isn't ready
+    let mut late = server.connect().await;
+    late.send(&WsClientMsg::JoinRoom {
+        room_id: room_id.clone(),
+        player: player("late", "Late"),
+        seat_token: None,
+    })
+    .await;
+    match late.expect_error().await {
+        WsServerMsg::Error { msg, .. } => {
+            assert_eq!(msg, "A game is under way, spectate or join after it")
+        }
+        _ => unreachable!(),
+    }
+
+    guest.send(&WsClientMsg::ReadyUp { ready: false }).await;
+    let called_off = host
+        .expect(|msg| match msg {
+            WsServerMsg::GameStarted { .. } => panic!("started with an unready player"),
+            WsServerMsg::SystemMessage { kind: SystemMessageKind::StartCalledOff, text, .. } => {
+                Some(text)
+            }
+            _ => None,
+        })
+        .await;
+    assert_eq!(called_off, "Start called off: not everyone is ready");
+    assert!(server.state.lock_room(&room_id).await.unwrap().pending_start.is_none());
+}
+
+/// Reports a two-apple clear as turn 1, with the apples in `values`' order so consecutive
+/// reports aren't dropped as repeats; the `ScoreAck` total, or the refusal's code.
+async fn first_clear(client: &mut Client, values: [u8; 2]) -> Result<u32, Option<ErrorCode>> {
+    client
+        .send(&WsClientMsg::ScoreUpdate {
+            cleared_count: 2,
+            turn: 1,
+            cleared_values: values.to_vec(),
+            rect: None,
+        })
+        .await;
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::ScoreAck { total, .. } => Some(Ok(total)),
+            WsServerMsg::Error { code, .. } => Some(Err(code)),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test]
+async fn nothing_is_scored_before_the_game_starts() {
+    let server = counting_down().await;
+    let (room_id, mut host, mut guest) = room(&server, RoomSettings::default()).await;
+    let not_running = Err(Some(ErrorCode::GameNotRunning));
+    assert_eq!(first_clear(&mut guest, [1, 9]).await, not_running, "scored in the lobby");
+
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::ReadyAck { .. }).then_some(()))
+        .await;
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::GameStarting { .. }).then_some(()))
+        .await;
+    assert_eq!(first_clear(&mut guest, [9, 1]).await, not_running, "scored in the countdown");
+    let score = server.state.lock_room(&room_id).await.unwrap().scores.get("guest").copied();
+    assert_eq!(score.unwrap_or(0), 0);
+
+    // the room heard nothing of either, and the first clear once it's on counts
+    host.expect(|msg| match msg {
+        WsServerMsg::LeaderboardUpdate { .. } => panic!("a refused update was broadcast"),
+        WsServerMsg::GameStarted { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+        .await;
+    assert_eq!(first_clear(&mut guest, [1, 9]).await, Ok(2));
+    let scores = host
+        .expect(|msg| match msg {
+            WsServerMsg::LeaderboardUpdate { scores, .. } => Some(scores),
+            _ => None,
+        })
+        .await;
+    assert!(scores.contains(&("guest".to_owned(), 2)));
+}
+
@@ -0,0 +1,121 @@
+// test bundle: enough repetition for the compressed copies to come out much smaller
+export const apple0 = { value: 1, row: 0, col: 0 };
+export const apple1 = { value: 2, row: 0, col: 1 };
+export const apple2 = { value: 3, row: 0, col: 2 };
+export const apple3 = { value: 4, row: 0, col: 3 };
+export const apple4 = { value: 5, row: 0, col: 4 };
+export const apple5 = { value: 6, row: 0, col: 5 };
+export const apple6 = { value: 7, row: 0, col: 6 };
+export const apple7 = { value: 8, row: 0, col: 7 };
+export const apple8 = { value: 9, row: 0, col: 8 };
+export const apple9 = { value: 1, row: 0, col: 9 };
+export const apple10 = { value: 2, row: 0, col: 10 };
+export const apple11 = { value: 3, row: 0, col: 11 };
+export const apple12 = { value: 4, row: 0, col: 12 };
+export const apple13 = { value: 5, row: 0, col: 13 };
+export const apple14 = { value: 6, row: 0, col: 14 };
+export const apple15 = { value: 7, row: 0, col: 15 };
+export const apple16 = { value: 8, row: 0, col: 16 };
+export const apple17 = { value: 9, row: 1, col: 0 };
+export const apple18 = { value: 1, row: 1, col: 1 };
+export const apple19 = { value: 2, row: 1, col: 2 };
+export const apple20 = { value: 3, row: 1, col: 3 };
+export const apple21 = { value: 4, row: 1, col: 4 };
+export const apple22 = { value: 5, row: 1, col: 5 };
+export const apple23 = { value: 6, row: 1, col: 6 };
+export const apple24 = { value: 7, row: 1, col: 7 };
+export const apple25 = { value: 8, row: 1, col: 8 };
+export const apple26 = { value: 9, row: 1, col: 9 };
+export const apple27 = { value: 1, row: 1, col: 10 };
+export const apple28 = { value: 2, row: 1, col: 11 };
+export const apple29 = { value: 3, row: 1, col: 12 };
+export const apple30 = { value: 4, row: 1, col: 13 };
+export const apple31 = { value: 5, row: 1, col: 14 };
+export const apple32 = { value: 6, row: 1, col: 15 };
+export const apple33 = { value: 7, row: 1, col: 16 };
+export const apple34 = { value: 8, row: 2, col: 0 };
+export const apple35 = { value: 9, row: 2, col: 1 };
+export const apple36 = { value: 1, row: 2, col: 2 };
+export const apple37 = { value: 2, row: 2, col: 3 };
+export const apple38 = { value: 3, row: 2, col: 4 };
+export const apple39 = { value: 4, row: 2, col: 5 };
+export const apple40 = { value: 5, row: 2, col: 6 };
+export const apple41 = { value: 6, row: 2, col: 7 };
+export const apple42 = { value: 7, row: 2, col: 8 };
+export const apple43 = { value: 8, row: 2, col: 9 };
+export const apple44 = { value: 9, row: 2, col: 10 };
+export const apple45 = { value: 1, row: 2, col: 11 };
+export const apple46 = { value: 2, row: 2, col: 12 };
+export const apple47 = { value: 3, row: 2, col: 13 };
+export const apple48 = { value: 4, row: 2, col: 14 };
+export const apple49 = { value: 5, row: 2, col: 15 };
+export const apple50 = { value: 6, row: 2, col: 16 };
+export const apple51 = { value: 7, row: 3, col: 0 };
+export const apple52 = { value: 8, row: 3, col: 1 };
+export const apple53 = { value: 9, row: 3, col: 2 };
+export const apple54 = { value: 1, row: 3, col: 3 };
+export const apple55 = { value: 2, row: 3, col: 4 };
+export const apple56 = { value: 3, row: 3, col: 5 };
+export const apple57 = { value: 4, row: 3, col: 6 };
+export const apple58 = { value: 5, row: 3, col: 7 };
+export const apple59 = { value: 6, row: 3, col: 8 };
+export const apple60 = { value: 7, row: 3, col: 9 };
+export const apple61 = { value: 8, row: 3, col: 10 };
+export const apple62 = { value: 9, row: 3, col: 11 };
+export const apple63 = { value: 1, row: 3, col: 12 };
+export const apple64 = { value: 2, row: 3, col: 13 };
+export const apple65 = { value: 3, row: 3, col: 14 };
+export const apple66 = { value: 4, row: 3, col: 15 };
+export const apple67 = { value: 5, row: 3, col: 16 };
+export const apple68 = { value: 6, row: 4, col: 0 };
+export const apple69 = { value: 7, row: 4, col: 1 };
+export const apple70 = { value: 8, row: 4, col: 2 };
+export const apple71 = { value: 9, row: 4, col: 3 };
+export const apple72 = { value: 1, row: 4, col: 4 };
+export const apple73 = { value: 2, row: 4, col: 5 };
+export const apple74 = { value: 3, row: 4, col: 6 };
+export const apple75 = { value: 4, row: 4, col: 7 };
+export const apple76 = { value: 5, row: 4, col: 8 };
+export const apple77 = { value: 6, row: 4, col: 9 };
+export const apple78 = { value: 7, row: 4, col: 10 };
+export const apple79 = { value: 8, row: 4, col: 11 };
+export const apple80 = { value: 9, row: 4, col: 12 };
+export const apple81 = { value: 1, row: 4, col: 13 };
+export const apple82 = { value: 2, row: 4, col: 14 };
+export const apple83 = { value: 3, row: 4, col: 15 };
+export const apple84 = { value: 4, row: 4, col: 16 };
+export const apple85 = { value: 5, row: 5, col: 0 };
+export const apple86 = { value: 6, row: 5, col: 1 };
+export const apple87 = { value: 7, row: 5, col: 2 };
+export const apple88 = { value: 8, row: 5, col: 3 };
+export const apple89 = { value: 9, row: 5, col: 4 };
+export const apple90 = { value: 1, row: 5, col: 5 };
+export const apple91 = { value: 2, row: 5, col: 6 };
+export const apple92 = { value: 3, row: 5, col: 7 };
+export const apple93 = { value: 4, row: 5, col: 8 };
+export const apple94 = { value: 5, row: 5, col: 9 };
+export const apple95 = { value: 6, row: 5, col: 10 };
+export const apple96 = { value: 7, row: 5, col: 11 };
+export const apple97 = { value: 8, row: 5, col: 12 };
+export const apple98 = { value: 9, row: 5, col: 13 };
+export const apple99 = { value: 1, row: 5, col: 14 };
+export const apple100 = { value: 2, row: 5, col: 15 };
+export const apple101 = { value: 3, row: 5, col: 16 };
+export const apple102 = { value: 4, row: 6, col: 0 };
+export const apple103 = { value: 5, row: 6, col: 1 };
+export const apple104 = { value: 6, row: 6, col: 2 };
+export const apple105 = { value: 7, row: 6, col: 3 };
+export const apple106 = { value: 8, row: 6, col: 4 };
+export const apple107 = { value: 9, row: 6, col: 5 };
+export const apple108 = { value: 1, row: 6, col: 6 };
+export const apple109 = { value: 2, row: 6, col: 7 };
+export const apple110 = { value: 3, row: 6, col: 8 };
+export const apple111 = { value: 4, row: 6, col: 9 };
+export const apple112 = { value: 5, row: 6, col: 10 };
+export const apple113 = { value: 6, row: 6, col: 11 };
+export const apple114 = { value: 7, row: 6, col: 12 };
+export const apple115 = { value: 8, row: 6, col: 13 };
+export const apple116 = { value: 9, row: 6, col: 14 };
+export const apple117 = { value: 1, row: 6, col: 15 };
+export const apple118 = { value: 2, row: 6, col: 16 };
+export const apple119 = { value: 3, row: 7, col: 0 };
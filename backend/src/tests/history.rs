@@ -0,0 +1,113 @@
+// src/tests/history.rs
+//! `GetChatHistory` pages back through a room's chat, answers only the one who asked, and is
+//! rate limited.
+use super::support::{player, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{ErrorCode, RoomLogEntry, WsClientMsg, WsServerMsg},
+};
+
+/// Asks for a page; returns its messages with their IDs, and `has_more`.
+async fn history(
+    client: &mut Client,
+    before_id: Option<u64>,
+    limit: u8,
+) -> (Vec<(u64, String)>, bool) {
+    client.send(&WsClientMsg::GetChatHistory { before_id, limit }).await;
+    let (messages, has_more) = client
+        .expect(|msg| match msg {
+            WsServerMsg::ChatHistory { messages, has_more, .. } => Some((messages, has_more)),
+            WsServerMsg::Error { msg, .. } => panic!("history refused: {msg}"),
+            _ => None,
+        })
+        .await;
+    let messages = messages
+        .into_iter()
+        .map(|entry| match entry {
+            RoomLogEntry::Chat { message_id, message, .. } => (message_id, message),
+            RoomLogEntry::System { .. } => panic!("a system notice in a chat page"),
+        })
+        .collect();
+    (messages, has_more)
+}
+
+async fn say(client: &mut Client, message: &str) {
+    client
+        .send(&WsClientMsg::ChatMessage {
+            message: message.to_owned(),
+            name: None,
+            channel: None,
+        })
+        .await;
+}
+
+/// The chat `client` gets up to and including `last`, and whether a `ChatHistory` came too.
+async fn heard_until(client: &mut Client, last: &str) -> (Vec<String>, bool) {
+    let (mut heard, mut got_history) = (Vec::new(), false);
+    loop {
+        match client.recv().await.expect("socket closed") {
+            WsServerMsg::ChatBroadcast { message, .. } => {
+                let done = message == last;
+                heard.push(message);
+                if done {
+                    return (heard, got_history);
+                }
+            }
+            WsServerMsg::ChatHistory { .. } => got_history = true,
+            _ => {}
+        }
+    }
+}
+
+fn lines(range: std::ops::RangeInclusive<u64>) -> Vec<(u64, String)> {
+    range.map(|n| (n, format!("line {n}"))).collect()
+}
+
+#[tokio::test]
+async fn pages_go_back_fifty_at_a_time_to_the_requester_alone() {
+    // no chat limit, so the backlog can be written in one go
+    let server = TestServer::with_config(Config {
+        chat_burst: 0,
+        ..test_config()
+    })
+    .await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    for n in 1..=60 {
+        say(&mut host, &format!("line {n}")).await;
+    }
+    let (heard, _) = heard_until(&mut guest, "line 60").await;
+    assert_eq!(heard.len(), 60);
+
+    // however many are asked for, a page holds 50; the join notice is on none of them
+    assert_eq!(history(&mut host, None, u8::MAX).await, (lines(11..=60), true));
+    assert_eq!(history(&mut host, Some(11), u8::MAX).await, (lines(1..=10), false));
+    assert_eq!(history(&mut host, Some(31), 3).await, (lines(28..=30), true));
+
+    say(&mut host, "after").await;
+    let (heard, got_history) = heard_until(&mut guest, "after").await;
+    assert_eq!(heard, ["after"]);
+    assert!(!got_history, "the guest got the host's pages");
+}
+
+#[tokio::test]
+async fn asking_for_pages_too_fast_is_refused() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    host.create_room(&player("host", "Host")).await;
+    say(&mut host, "hello").await;
+
+    // five at once, each a different frame so none is dropped as a repeat
+    for limit in 1..=5 {
+        assert_eq!(history(&mut host, None, limit).await.0.len(), 1);
+    }
+    host.send(&WsClientMsg::GetChatHistory { before_id: None, limit: 6 }).await;
+    match host.expect_error().await {
+        WsServerMsg::Error { code: Some(ErrorCode::RateLimited { retry_after_ms }), .. } => {
+            assert!(retry_after_ms <= 1000, "one page a second comes back, not {retry_after_ms}ms");
+        }
+        other => panic!("not rate limited: {other:?}"),
+    }
+}
@@ -0,0 +1,65 @@
+// src/tests/idle.rs
+use super::support::{player, test_config, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{WsClientMsg, WsServerMsg},
+};
+use std::time::{Duration, Instant};
+
+const IDLE: Duration = Duration::from_secs(1);
+const ROOM_IDLE: Duration = Duration::from_secs(3);
+/// How late the close may come: the idle check runs every `IDLE` here.
+const CHECK_SLACK: Duration = Duration::from_millis(1500);
+const CLOSE_IDLE: u16 = 4000;
+
+async fn server() -> TestServer {
+    TestServer::with_config(Config {
+        idle_timeout: IDLE,
+        room_idle_timeout: ROOM_IDLE,
+        ..test_config()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn a_socket_that_never_joins_is_closed_once_idle() {
+    let server = server().await;
+    let connected = Instant::now();
+    let mut client = server.connect().await;
+
+    assert_eq!(client.closed_within(IDLE + CHECK_SLACK).await, Some(CLOSE_IDLE));
+    assert!(connected.elapsed() >= IDLE);
+}
+
+#[tokio::test]
+async fn each_message_restarts_the_idle_timer() {
+    let server = server().await;
+    let mut client = server.connect().await;
+
+    tokio::time::sleep(IDLE * 3 / 4).await;
+    let last_message = Instant::now();
+    client.send(&WsClientMsg::GetPresence {}).await;
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::GlobalPresence { .. }).then_some(()))
+        .await;
+
+    assert_eq!(client.closed_within(IDLE + CHECK_SLACK).await, Some(CLOSE_IDLE));
+    assert!(last_message.elapsed() >= IDLE);
+}
+
+#[tokio::test]
+async fn a_socket_in_a_room_gets_the_longer_limit() {
+    let server = server().await;
+    let mut host = server.connect().await;
+    host.create_room(&player("host", "Host")).await;
+
+    // well past the lobby limit, the socket still answers
+    tokio::time::sleep(IDLE + CHECK_SLACK).await;
+    let last_message = Instant::now();
+    host.send(&WsClientMsg::GetPresence {}).await;
+    host.expect(|msg| matches!(msg, WsServerMsg::GlobalPresence { .. }).then_some(()))
+        .await;
+
+    assert_eq!(host.closed_within(ROOM_IDLE + CHECK_SLACK).await, Some(CLOSE_IDLE));
+    assert!(last_message.elapsed() >= ROOM_IDLE);
+}
@@ -0,0 +1,209 @@
+// src/tests/instances.rs
+//! Two instances sharing their rooms: a room made on one is joined and played on the other.
+use super::support::{player, start_two_player_game, test_config, TestServer};
+use crate::{
+    room_bus::{RoomBus, RoomChange, RoomPayload, RoomRecord, SeatClaim, SharedRooms},
+    server_state::AppState,
+    ws_messages::{Player, PlayerId, RoomId, WsServerMsg},
+};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+
+/// What Redis is to the instances, in memory: broadcasts go to the room's channel on every
+/// instance, and room records are kept and changed under one lock.
+#[derive(Debug, Default)]
+struct SharedBus {
+    locals: Mutex<Vec<(RoomId, broadcast::WeakSender<RoomPayload>)>>,
+    reserved: Mutex<HashSet<RoomId>>,
+    records: Mutex<HashMap<RoomId, RoomRecord>>,
+}
+
+impl RoomBus for SharedBus {
+    fn attach(&self, room_id: &RoomId, local: &broadcast::Sender<RoomPayload>) {
+        let mut locals = self.locals.lock().unwrap();
+        locals.retain(|(_, weak)| weak.strong_count() > 0);
+        locals.push((room_id.clone(), local.downgrade()));
+    }
+
+    fn reserve<'a>(&'a self, room_id: &'a RoomId) -> BoxFuture<'a, bool> {
+        Box::pin(async { self.reserved.lock().unwrap().insert(room_id.clone()) })
+    }
+
+    fn release(&self, room_id: &RoomId) {
+        let mut records = self.records.lock().unwrap();
+        if records.get(room_id).is_none_or(|record| record.players.is_empty()) {
+            records.remove(room_id);
+            self.reserved.lock().unwrap().remove(room_id);
+        }
+    }
+
+    fn shared(&self) -> Option<&dyn SharedRooms> {
+        Some(self)
+    }
+
+    fn publish(
+        &self,
+        room_id: &RoomId,
+        _local: &broadcast::Sender<RoomPayload>,
+        payload: RoomPayload,
+    ) {
+        for (id, weak) in self.locals.lock().unwrap().iter() {
+            if let Some(local) = weak.upgrade().filter(|_| id == room_id) {
+                let _ = local.send(payload.clone());
+            }
+        }
+    }
+}
+
+impl SharedRooms for SharedBus {
+    fn open<'a>(&'a self, room_id: &'a RoomId, record: &'a RoomRecord) -> BoxFuture<'a, ()> {
+        self.records.lock().unwrap().insert(room_id.clone(), record.clone());
+        Box::pin(async {})
+    }
+
+    fn load<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+    ) -> BoxFuture<'a, anyhow::Result<Option<RoomRecord>>> {
+        let record = self.records.lock().unwrap().get(room_id).cloned();
+        Box::pin(async { Ok(record) })
+    }
+
+    fn take_seat<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+        player: &'a Player,
+        max_players: u32,
+    ) -> BoxFuture<'a, SeatClaim> {
+        let mut records = self.records.lock().unwrap();
+        let claim = match records.get_mut(room_id) {
+            None => SeatClaim::Gone,
+            Some(record) if record.players.iter().any(|p| p.player_id == player.player_id) => {
+                SeatClaim::Seated
+            }
+            Some(record) if max_players > 0 && record.players.len() >= max_players as usize => {
+                SeatClaim::Full
+            }
+            Some(record) => {
+                record.players.push(player.clone());
+                record.scores.insert(player.player_id.clone(), 0);
+                SeatClaim::Seated
+            }
+        };
+        Box::pin(async move { claim })
+    }
+
+    fn add_score<'a>(
+        &'a self,
+        room_id: &'a RoomId,
+        player_id: &'a PlayerId,
+        round: u64,
+        turn: u32,
+        delta: u32,
+    ) -> BoxFuture<'a, Option<HashMap<PlayerId, u32>>> {
+        let mut records = self.records.lock().unwrap();
+        let totals = records.get_mut(room_id).and_then(|record| {
+            let last = record.turns.get(player_id).copied().unwrap_or(0);
+            let seated = record.players.iter().any(|p| &p.player_id == player_id);
+            if !seated || record.round != round || record.game.is_none() || turn != last + 1 {
+                return None;
+            }
+            record.turns.insert(player_id.clone(), turn);
+            *record.scores.entry(player_id.clone()).or_default() += delta;
+            Some(record.scores.clone())
+        });
+        Box::pin(async move { totals })
+    }
+
+    fn write(&self, room_id: &RoomId, change: RoomChange) {
+        let mut records = self.records.lock().unwrap();
+        if let RoomChange::Closed = change {
+            records.remove(room_id);
+            self.reserved.lock().unwrap().remove(room_id);
+            return;
+        }
+        let Some(record) = records.get_mut(room_id) else {
+            return;
+        };
+        match change {
+            RoomChange::Player(player) => {
+                if let Some(seated) =
+                    record.players.iter_mut().find(|p| p.player_id == player.player_id)
+                {
+                    *seated = player;
+                }
+            }
+            RoomChange::SeatToken { player_id, token } => {
+                if record.players.iter().any(|p| p.player_id == player_id) {
+                    record.seat_tokens.insert(player_id, token);
+                }
+            }
+            RoomChange::Unseat(player_id) => {
+                record.players.retain(|p| p.player_id != player_id);
+                record.seat_tokens.remove(&player_id);
+                record.scores.remove(&player_id);
+                record.turns.remove(&player_id);
+            }
+            RoomChange::Owner(player_id) => record.owner = player_id,
+            RoomChange::RoundStarted { round, game } => {
+                record.round = round;
+                record.game = Some(game);
+                record.scores.values_mut().for_each(|score| *score = 0);
+                record.turns.clear();
+                record.players.iter_mut().for_each(|p| p.ready = false);
+            }
+            RoomChange::RoundEnded { round } => {
+                if record.round == round {
+                    record.game = None;
+                }
+            }
+            RoomChange::Closed => unreachable!(),
+        }
+    }
+}
+
+/// Two servers on one `SharedBus`.
+async fn instances() -> (TestServer, TestServer) {
+    let bus: Arc<dyn RoomBus> = Arc::new(SharedBus::default());
+    let instance = || {
+        let mut state = AppState::new_with_top_10(BinaryHeap::new(), test_config());
+        state.bus = bus.clone();
+        TestServer::serve(state)
+    };
+    (instance().await, instance().await)
+}
+
+#[tokio::test]
+async fn a_room_made_on_one_instance_is_joined_and_scored_in_on_the_other() {
+    let (a, b) = instances().await;
+    let mut host = a.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = b.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    // the join went out from the other instance, and reached the host
+    let players = host
+        .expect(|msg| match msg {
+            WsServerMsg::RoomPlayersUpdate { players, .. } if players.len() == 2 => Some(players),
+            _ => None,
+        })
+        .await;
+    assert_eq!(players[1].player_id, "guest");
+
+    start_two_player_game(&mut host, &mut guest).await;
+    assert_eq!(guest.score_pair(1).await, 2);
+    let scores = host
+        .expect(|msg| match msg {
+            WsServerMsg::LeaderboardUpdate { scores, .. } => Some(scores),
+            _ => None,
+        })
+        .await;
+    assert!(scores.contains(&("guest".to_owned(), 2)), "leaderboard: {scores:?}");
+
+    // and the room's first instance has it too
+    a.state.sync_room(&room_id).await;
+    assert_eq!(a.state.lock_room(&room_id).await.unwrap().scores["guest"], 2);
+}
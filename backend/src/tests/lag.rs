@@ -0,0 +1,116 @@
+// src/tests/lag.rs
+//! Sockets that fall behind their room's broadcasts: resynced, or closed if it keeps happening.
+//!
+//! With a room channel of 2, starting a game is enough: it broadcasts the player list,
+//! `GameStarted` and a notice in one go, before either socket's task gets to forward them (the
+//! host's is busy handling `StartGame`), so both fall behind.
+use super::support::{player, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{RoomId, WsClientMsg, WsServerMsg, DEFAULT_DURATION_SECS},
+};
+
+/// What `CLOSE_TOO_SLOW` closes a socket with.
+const TOO_SLOW: u16 = 4002;
+
+fn tiny_channel(resync_after: u32, disconnect_after: u32) -> Config {
+    Config {
+        room_channel_capacity: 2,
+        lag_resync_after: resync_after,
+        lag_disconnect_after: disconnect_after,
+        ..test_config()
+    }
+}
+
+/// Starts a game between a host and a guest, without waiting on a `GameStarted` neither may see.
+async fn start_game(server: &TestServer) -> (RoomId, Client, Client) {
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::ReadyAck { .. }).then_some(()))
+        .await;
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    (room_id, host, guest)
+}
+
+async fn times_lagged(server: &TestServer, room_id: &RoomId, player_id: &str) -> Option<u32> {
+    let room = server.state.lock_room(room_id).await.expect("room is gone");
+    room.lagged.get(player_id).copied()
+}
+
+async fn state_sync(client: &mut Client) -> WsServerMsg {
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::StateSync { .. }).then_some(msg))
+        .await
+}
+
+#[tokio::test]
+async fn a_socket_that_falls_behind_gets_a_state_sync() {
+    let server = TestServer::with_config(tiny_channel(1, 0)).await;
+    let (room_id, mut host, mut guest) = start_game(&server).await;
+
+    server.until_room(&room_id, |room| room.ends_at_ms.is_some()).await;
+    let ends_at_ms = server.state.lock_room(&room_id).await.unwrap().ends_at_ms;
+    for (client, id) in [(&mut host, "host"), (&mut guest, "guest")] {
+        match state_sync(client).await {
+            WsServerMsg::StateSync { board, remaining_secs, ends_at_ms: synced, .. } => {
+                assert!(board.is_some(), "{id} resynced without its board");
+                // the deadline the timer was given, not one worked out again
+                assert_eq!(synced, ends_at_ms, "{id} resynced with another deadline");
+                let left = remaining_secs.expect("resynced without the clock");
+                assert!((1..=u64::from(DEFAULT_DURATION_SECS)).contains(&left), "{left} s left");
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(times_lagged(&server, &room_id, id).await, Some(1));
+    }
+}
+
+#[tokio::test]
+async fn a_socket_that_keeps_falling_behind_is_closed() {
+    let server = TestServer::with_config(tiny_channel(1, 1)).await;
+    let (_, mut host, mut guest) = start_game(&server).await;
+    assert_eq!(host.expect_closed().await, Some(TOO_SLOW));
+    assert_eq!(guest.expect_closed().await, Some(TOO_SLOW));
+}
+
+#[tokio::test]
+async fn with_the_policy_off_a_lagging_socket_just_skips_ahead() {
+    let server = TestServer::with_config(tiny_channel(0, 0)).await;
+    let (room_id, mut host, mut guest) = start_game(&server).await;
+    host.expect(|msg| matches!(msg, WsServerMsg::TimerTick { .. }).then_some(()))
+        .await;
+
+    // no resync, and the socket carries on with whatever comes next
+    host.send(&WsClientMsg::ScoreUpdate {
+        cleared_count: 2,
+        turn: 1,
+        cleared_values: vec![1, 9],
+        rect: None,
+    })
+    .await;
+    guest
+        .expect(|msg| match msg {
+            WsServerMsg::StateSync { .. } => panic!("resynced with the policy off"),
+            WsServerMsg::LeaderboardUpdate { .. } => Some(()),
+            _ => None,
+        })
+        .await;
+    assert_eq!(times_lagged(&server, &room_id, "guest").await, Some(1));
+}
+
+#[tokio::test]
+async fn the_default_channel_is_deep_enough_for_a_game_start() {
+    let server = TestServer::start().await;
+    let (room_id, mut host, mut guest) = start_game(&server).await;
+    for (client, id) in [(&mut host, "host"), (&mut guest, "guest")] {
+        client
+            .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+            .await;
+        assert_eq!(times_lagged(&server, &room_id, id).await, None);
+    }
+}
+
@@ -0,0 +1,91 @@
+// src/tests/listeners.rs
+//! Several listen addresses serving one state, with the admin API only on `--bind-admin` ones.
+use super::support::test_config;
+use crate::{bind_all, config::Config, listen_plan, server_state::AppState};
+use axum::Router;
+use std::{
+    collections::BinaryHeap,
+    net::{SocketAddr, TcpListener},
+};
+
+const ADMIN_TOKEN: &str = "listeners-admin";
+
+fn any_port(ip: &str) -> SocketAddr {
+    SocketAddr::new(ip.parse().unwrap(), 0)
+}
+
+/// Binds and serves everything `config` asks for; returns the addresses in plan order.
+async fn serve(config: Config) -> Vec<SocketAddr> {
+    let config = Config {
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        ..config
+    };
+    let state = AppState::new_with_top_10(BinaryHeap::new(), config);
+    let plan = listen_plan(&state, Router::new());
+    let listeners = bind_all(&plan).await.unwrap();
+    let mut addrs = Vec::new();
+    for (listener, (_, router)) in listeners.into_iter().zip(plan) {
+        addrs.push(listener.local_addr().unwrap());
+        let app = router.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+    }
+    addrs
+}
+
+async fn status(addr: SocketAddr, path: &str) -> u16 {
+    reqwest::Client::new()
+        .get(format!("http://{addr}{path}"))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn every_bind_address_serves_the_app() {
+    let addrs = serve(Config {
+        binds: vec![any_port("127.0.0.1"), any_port("::1")],
+        ..test_config()
+    })
+    .await;
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[1].is_ipv6());
+    for addr in addrs {
+        assert_eq!(status(addr, "/healthz").await, 200, "{addr}");
+        // with no admin-only listener, the admin API is everywhere
+        assert_eq!(status(addr, "/api/stats").await, 200, "{addr}");
+    }
+}
+
+#[tokio::test]
+async fn only_admin_listeners_serve_the_admin_api() {
+    let addrs = serve(Config {
+        binds: vec![any_port("::1")],
+        admin_binds: vec![any_port("127.0.0.1")],
+        ..test_config()
+    })
+    .await;
+    let (public, admin) = (addrs[0], addrs[1]);
+    assert_eq!(status(public, "/healthz").await, 200);
+    assert_eq!(status(admin, "/healthz").await, 200);
+    assert_eq!(status(public, "/api/stats").await, 404);
+    assert_eq!(status(public, "/api/admin/drain").await, 404);
+    assert_eq!(status(admin, "/api/stats").await, 200);
+}
+
+#[tokio::test]
+async fn one_address_that_cannot_be_bound_fails_them_all() {
+    let holder = TcpListener::bind("127.0.0.1:0").unwrap();
+    let taken = holder.local_addr().unwrap();
+    let config = Config {
+        binds: vec![any_port("::1"), taken],
+        ..test_config()
+    };
+    let state = AppState::new_with_top_10(BinaryHeap::new(), config);
+    let err = bind_all(&listen_plan(&state, Router::new())).await.unwrap_err();
+    let err = err.to_string();
+    assert!(err.contains(&format!("cannot bind {taken}")), "{err}");
+    assert!(err.contains("[::1]:"), "doesn't say what did bind: {err}");
+}
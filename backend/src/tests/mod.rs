@@ -0,0 +1,24 @@
+// src/tests/mod.rs
+//! Protocol tests: a real server on a loopback port, driven over WebSockets.
+mod chat;
+mod concurrency;
+mod countdown;
+mod history;
+mod idle;
+mod instances;
+mod lag;
+mod listeners;
+mod owner;
+mod ready;
+mod restart;
+mod routing;
+mod seats;
+mod settings;
+mod scoring;
+mod shutdown;
+mod stats;
+mod support;
+mod throttle;
+mod tls;
+mod top_10;
+mod upgrade;
@@ -0,0 +1,106 @@
+// src/tests/owner.rs
+//! Who owns a room once its owner is gone, and the order the others hear about it in.
+use super::support::{player, test_config, Client, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{RoomId, SystemMessageKind, WsClientMsg, WsServerMsg},
+};
+use std::time::Duration;
+
+/// A room owned by "host", with "first" and then "second" joined, both caught up on the joins.
+async fn room_of_three(server: &TestServer) -> (RoomId, Client, Client, Client) {
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut first = server.connect().await;
+    first.join(&room_id, &player("first", "First"), None).await;
+    let mut second = server.connect().await;
+    second.join(&room_id, &player("second", "Second"), None).await;
+    for guest in [&mut first, &mut second] {
+        guest
+            .expect(|msg| match msg {
+                WsServerMsg::RoomPlayersUpdate { players, .. } => {
+                    (players.len() == 3).then_some(())
+                }
+                _ => None,
+            })
+            .await;
+    }
+    (room_id, host, first, second)
+}
+
+/// What `client` hears from now until the room's new owner is announced.
+async fn until_new_owner_announced(client: &mut Client) -> Vec<WsServerMsg> {
+    let mut seen = Vec::new();
+    loop {
+        let msg = client.recv().await.expect("socket closed");
+        let done = matches!(
+            msg,
+            WsServerMsg::SystemMessage { kind: SystemMessageKind::OwnerChanged, .. }
+        );
+        seen.push(msg);
+        if done {
+            return seen;
+        }
+    }
+}
+
+/// The player list (without the old owner, naming the new one) comes before `OwnerChanged`,
+/// which comes before the notice, and no list in between still names the old owner.
+fn assert_handed_over(seen: &[WsServerMsg], from: &str, to: &str) {
+    let changed = seen
+        .iter()
+        .position(|msg| matches!(msg, WsServerMsg::OwnerChanged { .. }))
+        .unwrap_or_else(|| panic!("no OwnerChanged in {seen:?}"));
+    match &seen[changed] {
+        WsServerMsg::OwnerChanged { owner_id, previous_owner_id, .. } => {
+            assert_eq!((owner_id.as_str(), previous_owner_id.as_str()), (to, from));
+        }
+        _ => unreachable!(),
+    }
+    let lists: Vec<_> = seen
+        .iter()
+        .enumerate()
+        .filter_map(|(i, msg)| match msg {
+            WsServerMsg::RoomPlayersUpdate { players, owner_id, .. } => {
+                Some((i, players, owner_id))
+            }
+            _ => None,
+        })
+        .collect();
+    let (at, players, owner_id) = lists.last().expect("no player list");
+    assert!(*at < changed, "the player list came after OwnerChanged: {seen:?}");
+    assert_eq!(owner_id.as_str(), to);
+    assert!(players.iter().all(|p| p.player_id != from), "{players:?}");
+    for (_, players, owner_id) in &lists {
+        assert_ne!(owner_id.as_str(), from, "a list still named the old owner: {seen:?}");
+        assert!(players.iter().all(|p| p.player_id != from), "{players:?}");
+    }
+}
+
+#[tokio::test]
+async fn an_owner_who_leaves_hands_the_room_to_the_longest_seated() {
+    let server = TestServer::start().await;
+    let (room_id, mut host, mut first, mut second) = room_of_three(&server).await;
+    host.send(&WsClientMsg::LeaveRoom {}).await;
+
+    for client in [&mut first, &mut second] {
+        let seen = until_new_owner_announced(client).await;
+        assert_handed_over(&seen, "host", "first");
+    }
+    let room = server.state.lock_room(&room_id).await.unwrap();
+    assert_eq!(room.owner, "first");
+}
+
+#[tokio::test]
+async fn an_owner_who_drops_hands_the_room_over_once_their_seat_is_given_up() {
+    let server = TestServer::with_config(Config {
+        reconnect_grace: Duration::from_millis(200),
+        ..test_config()
+    })
+    .await;
+    let (_, host, mut first, _second) = room_of_three(&server).await;
+    host.close().await;
+
+    let seen = until_new_owner_announced(&mut first).await;
+    assert_handed_over(&seen, "host", "first");
+}
@@ -0,0 +1,75 @@
+// src/tests/ready.rs
+//! The owner starts the game instead of readying up, and their ready flag stays out of it.
+use super::support::{player, Client, TestServer};
+use crate::ws_messages::{Player, WsClientMsg, WsServerMsg};
+
+async fn ready_up(client: &mut Client, ready: bool) -> (bool, bool) {
+    client.send(&WsClientMsg::ReadyUp { ready }).await;
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::ReadyAck { ready, counted, .. } => Some((ready, counted)),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test]
+async fn the_owner_readying_up_changes_nothing() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+
+    assert_eq!(ready_up(&mut host, true).await, (false, false));
+    assert!(!server.state.lock_room(&room_id).await.unwrap().players["host"].ready);
+
+    // readying up didn't make the owner count: the guest still holds the start up...
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    match host.expect_error().await {
+        WsServerMsg::Error { msg, .. } => assert_eq!(msg, "All players must be ready"),
+        _ => unreachable!(),
+    }
+    // ...and once they're ready nothing else does, the owner's flag being off
+    assert_eq!(ready_up(&mut guest, true).await, (true, true));
+    // (not the same frame again, which the server would take for a double send)
+    host.send(&WsClientMsg::StartGame { restart: Some(false) }).await;
+    host.expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+        .await;
+}
+
+#[tokio::test]
+async fn a_creator_who_arrives_ready_is_listed_as_not_ready() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host
+        .create_room(&Player {
+            ready: true,
+            ..player("host", "Host")
+        })
+        .await;
+    assert!(!server.state.lock_room(&room_id).await.unwrap().players["host"].ready);
+}
+
+#[tokio::test]
+async fn a_guest_who_inherits_the_room_loses_their_ready_flag() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    assert_eq!(ready_up(&mut guest, true).await, (true, true));
+
+    host.send(&WsClientMsg::LeaveRoom {}).await;
+    let players = guest
+        .expect(|msg| match msg {
+            WsServerMsg::RoomPlayersUpdate { players, owner_id, .. } if owner_id == "guest" => {
+                Some(players)
+            }
+            _ => None,
+        })
+        .await;
+    assert!(players.iter().all(|p| !p.ready), "stale ready flag in {players:?}");
+    // as the owner now, their ReadyUp isn't counted either
+    assert_eq!(ready_up(&mut guest, false).await, (false, false));
+}
@@ -0,0 +1,81 @@
+// src/tests/restart.rs
+//! A `StartGame` while a game runs leaves it alone, unless it asks to restart: then the room
+//! hears the game was aborted, with its scores, before the new one starts.
+use super::support::{player, start_two_player_game, Client, TestServer};
+use crate::ws_messages::{ErrorCode, RoomId, WsClientMsg, WsServerMsg};
+use std::sync::Arc;
+
+/// A game between a host and a guest, both past `GameStarted`, with the guest 2 points up.
+async fn game(server: &TestServer) -> (RoomId, Client, Client) {
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    assert_eq!(guest.score_pair(1).await, 2);
+    (room_id, host, guest)
+}
+
+#[tokio::test]
+async fn a_second_start_mid_game_changes_nothing() {
+    let server = TestServer::start().await;
+    let (room_id, mut host, mut guest) = game(&server).await;
+    let board = server.state.lock_room(&room_id).await.unwrap().board.clone().unwrap();
+
+    // one that says outright it isn't a restart, and a plain second click (sent first, it
+    // would be the same frame as the start and be dropped as a repeat)
+    for restart in [Some(false), None] {
+        host.send(&WsClientMsg::StartGame { restart }).await;
+        match host.expect_error().await {
+            WsServerMsg::Error { code, .. } => {
+                assert_eq!(code, Some(ErrorCode::GameAlreadyRunning))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // same board, same score, and the turns carry on
+    assert_eq!(guest.score_pair(2).await, 4);
+    let room = server.state.lock_room(&room_id).await.unwrap();
+    assert!(Arc::ptr_eq(room.board.as_ref().unwrap(), &board), "the board was dealt again");
+    assert_eq!(server.state.timers.running(), 1);
+}
+
+#[tokio::test]
+async fn a_restart_is_announced_with_the_scores_it_throws_away() {
+    let server = TestServer::start().await;
+    let (_, mut host, mut guest) = game(&server).await;
+
+    host.send(&WsClientMsg::StartGame { restart: Some(true) }).await;
+    let aborted = guest
+        .expect(|msg| match msg {
+            WsServerMsg::GameAborted { scores, .. } => Some(scores),
+            WsServerMsg::GameStarted { .. } => panic!("the new game came before the abort"),
+            _ => None,
+        })
+        .await;
+    assert!(aborted.contains(&("guest".to_owned(), 2)));
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+        .await;
+
+    // the new game starts from nothing: turn 1 again, its apples the other way round so the
+    // frame isn't the last game's turn 1 repeated
+    guest
+        .send(&WsClientMsg::ScoreUpdate {
+            cleared_count: 2,
+            turn: 1,
+            cleared_values: vec![9, 1],
+            rect: None,
+        })
+        .await;
+    let total = guest
+        .expect(|msg| match msg {
+            WsServerMsg::ScoreAck { total, .. } => Some(total),
+            WsServerMsg::Error { msg, .. } => panic!("score refused: {msg}"),
+            _ => None,
+        })
+        .await;
+    assert_eq!(total, 2);
+    assert_eq!(server.state.timers.running(), 1);
+}
@@ -0,0 +1,91 @@
+// src/tests/routing.rs
+//! Where the app is mounted: at the root, or under `--base-path`, with `--ws-path` for the socket.
+use super::support::{test_config, TestServer};
+use crate::{app_router, assets, config::Config, server_state::AppState};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use std::collections::BinaryHeap;
+use tower::ServiceExt;
+
+/// The whole app for `config`, with the API-only frontend (its status page names the socket URL).
+fn app(config: Config) -> Router {
+    let config = Config { no_assets: true, ..config };
+    let assets = assets::router(&config).unwrap();
+    app_router(&AppState::new_with_top_10(BinaryHeap::new(), config), assets, true)
+}
+
+/// The status and body `GET path` gets.
+async fn get(app: &Router, path: &str) -> (StatusCode, String) {
+    let request = Request::get(path).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+fn prefixed() -> Config {
+    Config {
+        base_path: "/fruitbox".to_owned(),
+        ..test_config()
+    }
+}
+
+#[tokio::test]
+async fn unprefixed_everything_is_at_the_root() {
+    let app = app(test_config());
+    assert_eq!(get(&app, "/healthz").await.0, StatusCode::OK);
+    let (status, page) = get(&app, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("<code>/ws</code>"), "{page}");
+    assert_eq!(get(&app, "/api/nope").await.0, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_base_path_moves_everything_under_it() {
+    let app = app(prefixed());
+    for path in ["/fruitbox/healthz", "/fruitbox/", "/fruitbox/?room=4821", "/fruitbox"] {
+        assert_eq!(get(&app, path).await.0, StatusCode::OK, "{path}");
+    }
+    let (_, page) = get(&app, "/fruitbox/").await;
+    assert!(page.contains("<code>/fruitbox/ws</code>"), "the socket URL ignores the prefix");
+
+    let (status, body) = get(&app, "/fruitbox/api/nope").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.starts_with('{'), "not the API's JSON 404: {body}");
+}
+
+#[tokio::test]
+async fn nothing_is_served_outside_the_base_path() {
+    let app = app(prefixed());
+    for path in ["/", "/healthz", "/ws", "/api/stats", "/fruitboxes/healthz"] {
+        assert_eq!(get(&app, path).await.0, StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn the_socket_is_under_the_base_path() {
+    let server = TestServer::with_config(prefixed()).await;
+    let mut client = server.connect().await;
+    assert!(client.recv().await.is_some());
+}
+
+#[tokio::test]
+async fn the_socket_route_can_be_renamed() {
+    let config = Config {
+        ws_path: "/socket".to_owned(),
+        ..prefixed()
+    };
+    let server = TestServer::with_config(config.clone()).await;
+    let mut client = server.connect().await;
+    assert!(client.recv().await.is_some());
+
+    // the old name is no socket any more, and the new one is kept off the frontend
+    let app = app(config);
+    assert_eq!(get(&app, "/fruitbox/ws").await.0, StatusCode::NOT_FOUND);
+    let (status, body) = get(&app, "/fruitbox/socket/x").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body.starts_with('{'), "{body}");
+}
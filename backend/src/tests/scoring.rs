@@ -0,0 +1,136 @@
+// src/tests/scoring.rs
+//! `ScoreUpdate` turns: replays are acknowledged without scoring twice, gaps are refused, and
+//! every game counts from turn 1 again.
+use super::support::{player, start_two_player_game, Client, TestServer};
+use crate::ws_messages::{ErrorCode, WsClientMsg, WsServerMsg};
+
+/// A game between a host and a guest, both past `GameStarted`.
+async fn game(server: &TestServer) -> (Client, Client) {
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    (host, guest)
+}
+
+/// Reports a two-apple clear as `turn` and returns the `ScoreAck` or `Error` it got.
+///
+/// Each call sends a slightly different frame (the apples swap places) so that a resend isn't
+/// caught by the duplicate filter before it reaches the turn check.
+async fn report(client: &mut Client, turn: u32, values: [u8; 2]) -> WsServerMsg {
+    client
+        .send(&WsClientMsg::ScoreUpdate {
+            cleared_count: 2,
+            turn,
+            cleared_values: values.to_vec(),
+            rect: None,
+        })
+        .await;
+    client
+        .expect(|msg| {
+            matches!(msg, WsServerMsg::ScoreAck { .. } | WsServerMsg::Error { .. }).then_some(msg)
+        })
+        .await
+}
+
+fn ack(msg: WsServerMsg) -> (u32, u32, bool) {
+    match msg {
+        WsServerMsg::ScoreAck { turn, total, applied, .. } => (turn, total, applied),
+        other => panic!("not a ScoreAck: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn a_resent_turn_is_acknowledged_but_only_scored_once() {
+    let server = TestServer::start().await;
+    let (mut host, _guest) = game(&server).await;
+    assert_eq!(ack(report(&mut host, 1, [1, 9]).await), (1, 2, true));
+    assert_eq!(ack(report(&mut host, 1, [9, 1]).await), (1, 2, false));
+    assert_eq!(ack(report(&mut host, 2, [1, 9]).await), (2, 4, true));
+}
+
+#[tokio::test]
+async fn an_older_turn_arriving_late_is_ignored() {
+    let server = TestServer::start().await;
+    let (mut host, _guest) = game(&server).await;
+    for turn in 1..=3 {
+        report(&mut host, turn, [1, 9]).await;
+    }
+    assert_eq!(ack(report(&mut host, 2, [9, 1]).await), (2, 6, false));
+    assert_eq!(ack(report(&mut host, 4, [9, 1]).await), (4, 8, true));
+}
+
+#[tokio::test]
+async fn a_skipped_turn_is_refused_with_the_one_expected() {
+    let server = TestServer::start().await;
+    let (mut host, _guest) = game(&server).await;
+    report(&mut host, 1, [1, 9]).await;
+    match report(&mut host, 3, [1, 9]).await {
+        WsServerMsg::Error { code, .. } => {
+            assert_eq!(code, Some(ErrorCode::TurnOutOfOrder { expected: 2 }))
+        }
+        other => panic!("skipped turn accepted: {other:?}"),
+    }
+    // nothing was counted, and resending from the expected turn carries on
+    assert_eq!(ack(report(&mut host, 2, [9, 1]).await), (2, 4, true));
+    assert_eq!(ack(report(&mut host, 3, [1, 9]).await), (3, 6, true));
+}
+
+#[tokio::test]
+async fn each_player_counts_their_own_turns() {
+    let server = TestServer::start().await;
+    let (mut host, mut guest) = game(&server).await;
+    for turn in 1..=3 {
+        report(&mut host, turn, [1, 9]).await;
+    }
+    assert_eq!(ack(report(&mut guest, 1, [1, 9]).await), (1, 2, true));
+    assert_eq!(ack(report(&mut guest, 1, [9, 1]).await), (1, 2, false));
+}
+
+#[tokio::test]
+async fn the_leaderboard_says_how_far_each_player_has_got() {
+    let server = TestServer::start().await;
+    let (mut host, mut guest) = game(&server).await;
+    for turn in 1..=2 {
+        report(&mut host, turn, [1, 9]).await;
+    }
+    report(&mut guest, 1, [1, 9]).await;
+    // the update for the guest's turn, the last one scored
+    let guest_turn = ("guest".to_owned(), 1);
+    let (mut scores, mut turns) = host
+        .expect(|msg| match msg {
+            WsServerMsg::LeaderboardUpdate { scores, turns, .. } if turns.contains(&guest_turn) => {
+                Some((scores, turns))
+            }
+            _ => None,
+        })
+        .await;
+    scores.sort();
+    turns.sort();
+    assert_eq!(scores, [("guest".to_owned(), 2), ("host".to_owned(), 4)]);
+    assert_eq!(turns, [("guest".to_owned(), 1), ("host".to_owned(), 2)]);
+}
+
+#[tokio::test]
+async fn a_restarted_game_counts_turns_from_one_again() {
+    let server = TestServer::start().await;
+    let (mut host, mut guest) = game(&server).await;
+    for turn in 1..=3 {
+        report(&mut host, turn, [1, 9]).await;
+    }
+    host.send(&WsClientMsg::StartGame { restart: Some(true) }).await;
+    for client in [&mut host, &mut guest] {
+        client
+            .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+            .await;
+    }
+    assert_eq!(ack(report(&mut host, 1, [9, 1]).await), (1, 2, true));
+    match report(&mut host, 4, [1, 9]).await {
+        WsServerMsg::Error { code, .. } => {
+            assert_eq!(code, Some(ErrorCode::TurnOutOfOrder { expected: 2 }))
+        }
+        other => panic!("the last game's turns carried over: {other:?}"),
+    }
+}
+
@@ -0,0 +1,206 @@
+// src/tests/seats.rs
+use super::support::{player, start_two_player_game, test_config, TestServer};
+use crate::{
+    ws_messages::{ErrorCode, WsClientMsg, WsServerMsg},
+    CLOSE_SESSION_REPLACED,
+};
+
+fn seat_taken(msg: &WsServerMsg) -> bool {
+    matches!(msg, WsServerMsg::Error { code: Some(ErrorCode::SeatTaken), .. })
+}
+
+#[tokio::test]
+async fn a_seat_is_not_handed_over_without_its_token() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, token) = host.create_room(&player("host", "Host")).await;
+
+    let mut intruder = server.connect().await;
+    for guess in [None, Some("not-the-token".to_owned()), Some(token.to_uppercase())] {
+        intruder
+            .send(&WsClientMsg::JoinRoom {
+                room_id: room_id.clone(),
+                player: player("host", "Host"),
+                seat_token: guess,
+            })
+            .await;
+        assert!(seat_taken(&intruder.expect_error().await));
+    }
+
+    // the owner kept the seat and the socket
+    assert_eq!(server.state.sessions.list(&"host".to_owned()).len(), 1);
+    host.send(&WsClientMsg::GetReadyStatus {}).await;
+    host.expect(|msg| matches!(msg, WsServerMsg::ReadyStatus { .. }).then_some(()))
+        .await;
+}
+
+#[tokio::test]
+async fn reconnecting_mid_game_keeps_the_score() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    let token = guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    assert_eq!(guest.score_pair(1).await, 2);
+    assert_eq!(guest.score_pair(2).await, 4);
+
+    guest.close().await;
+    server
+        .until_room(&room_id, |room| room.disconnected.contains_key("guest"))
+        .await;
+
+    let mut back = server.connect().await;
+    back.send(&WsClientMsg::JoinRoom {
+        room_id: room_id.clone(),
+        player: player("guest", "Guest"),
+        seat_token: Some(token.clone()),
+    })
+    .await;
+    let (board, scores) = back
+        .expect(|msg| match msg {
+            WsServerMsg::Resumed { board, scores, .. } => Some((board, scores)),
+            _ => None,
+        })
+        .await;
+    assert!(board.is_some(), "the board comes back while the game runs");
+    assert!(scores.contains(&("guest".to_owned(), 4)));
+    let fresh = back
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { seat_token, .. } => Some(seat_token),
+            _ => None,
+        })
+        .await;
+    assert_ne!(fresh, token);
+
+    // turns carry on from where they were
+    assert_eq!(back.score_pair(3).await, 6);
+
+    // and the token from before the drop is spent
+    let mut stale = server.connect().await;
+    stale
+        .send(&WsClientMsg::JoinRoom {
+            room_id,
+            player: player("guest", "Guest"),
+            seat_token: Some(token),
+        })
+        .await;
+    assert!(seat_taken(&stale.expect_error().await));
+}
+
+#[tokio::test]
+async fn a_reload_takes_over_a_live_seat_mid_game() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    let token = guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    assert_eq!(guest.score_pair(1).await, 2);
+
+    // the old tab's socket is still up, and the new one comes back under a new name
+    let mut reloaded = server.connect().await;
+    reloaded
+        .send(&WsClientMsg::JoinRoom {
+            room_id: room_id.clone(),
+            player: player("guest", "Renamed"),
+            seat_token: Some(token),
+        })
+        .await;
+    let scores = reloaded
+        .expect(|msg| match msg {
+            WsServerMsg::Resumed { scores, .. } => Some(scores),
+            WsServerMsg::Error { msg, .. } => panic!("takeover refused: {msg}"),
+            _ => None,
+        })
+        .await;
+    assert!(scores.contains(&("guest".to_owned(), 2)));
+    assert_eq!(guest.expect_closed().await, Some(CLOSE_SESSION_REPLACED));
+    assert_eq!(reloaded.score_pair(2).await, 4);
+    let room = server.state.lock_room(&room_id).await.unwrap();
+    assert_eq!(room.players["guest"].name, "Renamed");
+}
+
+#[tokio::test]
+async fn racing_takeovers_leave_exactly_one_winner() {
+    let config = crate::config::Config {
+        max_rooms_per_ip_per_minute: 100,
+        ..test_config()
+    };
+    let server = TestServer::with_config(config).await;
+    for round in 0..10 {
+        let host_id = format!("host-{round}");
+        let mut host = server.connect().await;
+        let (room_id, token) = host.create_room(&player(&host_id, "Host")).await;
+
+        let (mut a, mut b) = (server.connect().await, server.connect().await);
+        let join = WsClientMsg::JoinRoom {
+            room_id: room_id.clone(),
+            player: player(&host_id, "Host"),
+            seat_token: Some(token),
+        };
+        tokio::join!(a.send(&join), b.send(&join));
+        let outcome = |msg: WsServerMsg| match msg {
+            WsServerMsg::SeatGranted { .. } => Some(true),
+            ref err if seat_taken(err) => Some(false),
+            WsServerMsg::Error { msg, .. } => panic!("unexpected refusal: {msg}"),
+            _ => None,
+        };
+        let (won_a, won_b) = tokio::join!(a.expect(outcome), b.expect(outcome));
+        assert!(won_a ^ won_b, "round {round}: exactly one socket gets the seat");
+
+        // the original socket is told and closed, and the winner is the only session left
+        assert_eq!(host.expect_closed().await, Some(CLOSE_SESSION_REPLACED));
+        let sessions = server.state.sessions.list(&host_id);
+        assert_eq!(sessions.len(), 1);
+        let room = server.state.lock_room(&room_id).await.unwrap();
+        assert!(room.players.contains_key(&host_id));
+        assert!(!room.disconnected.contains_key(&host_id));
+    }
+}
+
+#[tokio::test]
+async fn a_reclaim_takes_the_offered_seat_without_learning_its_id_first() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    guest.close().await;
+    server
+        .until_room(&room_id, |room| room.disconnected.contains_key("guest"))
+        .await;
+
+    // someone who never got an offer can't claim anything
+    let mut other = server.connect().await;
+    other.send(&WsClientMsg::ReclaimSeat { room_id: room_id.clone() }).await;
+    other.expect_error().await;
+
+    let mut back = server.connect().await;
+    back.send(&WsClientMsg::JoinRoom {
+        room_id: room_id.clone(),
+        player: player("lost-my-id", "Guest"),
+        seat_token: None,
+    })
+    .await;
+    let offered = back
+        .expect(|msg| match msg {
+            WsServerMsg::ReclaimOffer { name, .. } => Some(name),
+            WsServerMsg::SeatGranted { .. } => panic!("joined instead of being offered the seat"),
+            _ => None,
+        })
+        .await;
+    assert_eq!(offered, "Guest");
+    back.send(&WsClientMsg::ReclaimSeat { room_id: room_id.clone() }).await;
+    let seat = back
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { player_id, .. } => Some(player_id),
+            WsServerMsg::Error { msg, .. } => panic!("reclaim refused: {msg}"),
+            _ => None,
+        })
+        .await;
+    assert_eq!(seat, "guest");
+    let room = server.state.lock_room(&room_id).await.unwrap();
+    assert!(!room.disconnected.contains_key("guest"));
+    assert!(!room.players.contains_key("lost-my-id"));
+}
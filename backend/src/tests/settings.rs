@@ -0,0 +1,48 @@
+// src/tests/settings.rs
+//! `CreateRoom` settings are checked up front, and every broken rule is named by field.
+use super::support::{player, TestServer};
+use crate::ws_messages::{ErrorCode, RoomSettings, WsClientMsg, WsServerMsg};
+
+#[tokio::test]
+async fn broken_settings_are_refused_field_by_field() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client
+        .send(&WsClientMsg::CreateRoom {
+            player: player("host", "Host"),
+            settings: Some(RoomSettings {
+                max_value: 4,
+                idle_kick_secs: Some(1),
+                duration_secs: Some(0),
+                ..RoomSettings::default()
+            }),
+        })
+        .await;
+    match client.expect_error().await {
+        WsServerMsg::Error { msg, code: Some(ErrorCode::InvalidSettings { errors }), .. } => {
+            let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+            assert_eq!(fields, ["target_sum", "idle_kick_secs", "duration_secs"]);
+            // the message sums them all up, for clients that don't look at the fields
+            for error in &errors {
+                assert!(msg.contains(&error.msg), "{msg:?} leaves out {:?}", error.msg);
+            }
+        }
+        other => panic!("not InvalidSettings: {other:?}"),
+    }
+    assert!(server.state.rooms.is_empty());
+
+    // and nothing was held against the connection: fixed settings go through
+    client
+        .send(&WsClientMsg::CreateRoom {
+            player: player("host", "Host"),
+            settings: Some(RoomSettings { max_value: 8, ..RoomSettings::default() }),
+        })
+        .await;
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::SeatGranted { .. } => Some(()),
+            WsServerMsg::Error { msg, .. } => panic!("refused: {msg}"),
+            _ => None,
+        })
+        .await;
+}
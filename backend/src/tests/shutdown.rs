@@ -0,0 +1,58 @@
+// src/tests/shutdown.rs
+use super::support::{player, start_two_player_game, test_config, TestServer};
+use crate::{
+    score_store::{MemoryStore, ScoreStore},
+    server_state::AppState,
+    shut_down,
+    ws_messages::{ErrorCode, WsClientMsg, WsServerMsg},
+};
+use std::{cmp::Reverse, sync::Arc};
+
+#[tokio::test]
+async fn a_game_in_progress_is_scored_and_saved_before_the_server_stops() {
+    let store = Arc::new(MemoryStore::default());
+    let state = AppState::with_score_store(store.clone(), test_config()).await;
+    let server = TestServer::serve(state).await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    guest.score_pair(1).await;
+    assert_eq!(guest.score_pair(2).await, 4);
+
+    shut_down(&server.state).await;
+
+    let scores = guest
+        .expect(|msg| match msg {
+            WsServerMsg::GameOver { scores, .. } => Some(scores),
+            _ => None,
+        })
+        .await;
+    assert!(scores.contains(&("guest".to_owned(), 4)));
+    let saved = store.load().await;
+    assert!(saved.iter().any(|entry| *entry == (Reverse(4), "Guest".to_owned())));
+    assert!(guest.expect_closed().await.is_some());
+}
+
+#[tokio::test]
+async fn no_new_rooms_or_games_once_shutdown_has_begun() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    host.create_room(&player("host", "Host")).await;
+    let mut late = server.connect().await;
+
+    server.state.begin_shutdown();
+
+    late.send(&WsClientMsg::CreateRoom {
+        player: player("late", "Late"),
+        settings: None,
+    })
+    .await;
+    let WsServerMsg::Error { code, .. } = late.expect_error().await else { unreachable!() };
+    assert_eq!(code, Some(ErrorCode::Maintenance));
+
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    let WsServerMsg::Error { code, .. } = host.expect_error().await else { unreachable!() };
+    assert_eq!(code, Some(ErrorCode::Maintenance));
+}
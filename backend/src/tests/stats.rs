@@ -0,0 +1,80 @@
+// src/tests/stats.rs
+//! The running counters, as `GET /api/stats` and the `ServerStats` broadcast report them.
+use super::support::{player, test_config, TestServer};
+use crate::{config::Config, ws_messages::WsServerMsg};
+use serde_json::Value;
+
+const ADMIN_TOKEN: &str = "stats-admin";
+
+async fn get_stats(server: &TestServer, token: Option<&str>) -> Result<Value, u16> {
+    let mut request = reqwest::Client::new().get(format!("http://{}/api/stats", server.host()));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.unwrap();
+    if !response.status().is_success() {
+        return Err(response.status().as_u16());
+    }
+    Ok(response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn the_stats_api_counts_messages_errors_and_broadcasts() {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        ..test_config()
+    })
+    .await;
+    let mut client = server.connect().await;
+    let (room_id, _) = client.create_room(&player("host", "Host")).await;
+    client.send_text("{\"type\":\"ChatMessage\"".to_owned()).await;
+    client.expect_error().await;
+    // a join is broadcast to the room
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::RoomPlayersUpdate { players, .. } => (players.len() == 2).then_some(()),
+            _ => None,
+        })
+        .await;
+
+    let stats = get_stats(&server, Some(ADMIN_TOKEN)).await.unwrap();
+    let counters = &stats["counters"];
+    assert_eq!(counters["messages"]["CreateRoom"], 1);
+    assert_eq!(counters["messages"]["JoinRoom"], 1);
+    assert_eq!(counters["messages"]["ScoreUpdate"], 0);
+    assert_eq!(counters["invalid_messages"], 1);
+    assert_eq!(counters["errors"]["MalformedJson"], 1);
+
+    let room = &stats["rooms"][0];
+    assert_eq!(room["room_id"], room_id.as_str());
+    assert_eq!(room["players"], 2);
+    let room_broadcasts = room["broadcasts"].as_u64().unwrap();
+    assert!(room_broadcasts > 0);
+    assert_eq!(counters["broadcasts"].as_u64(), Some(room_broadcasts));
+
+    // the lobby's broadcast carries the same totals
+    match server.state.server_stats() {
+        WsServerMsg::ServerStats { messages_handled, broadcasts, .. } => {
+            assert_eq!(messages_handled, 2);
+            assert_eq!(broadcasts, room_broadcasts);
+        }
+        other => panic!("not ServerStats: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn the_stats_api_is_for_admins_only() {
+    let server = TestServer::with_config(Config {
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        ..test_config()
+    })
+    .await;
+    assert_eq!(get_stats(&server, None).await.unwrap_err(), 401);
+    assert_eq!(get_stats(&server, Some("guess")).await.unwrap_err(), 401);
+
+    // without an admin token there's no admin API to find
+    let closed = TestServer::start().await;
+    assert_eq!(get_stats(&closed, Some(ADMIN_TOKEN)).await.unwrap_err(), 404);
+}
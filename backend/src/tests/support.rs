@@ -0,0 +1,258 @@
+// src/tests/support.rs
+use crate::{
+    app_router,
+    config::Config,
+    game_timer::GameTimers,
+    server_state::{AppState, RoomState},
+    ws_messages::{Player, RoomEvent, RoomId, WsClientMsg, WsServerMsg},
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{collections::BinaryHeap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Error as WsError, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+/// How long a test waits for a reply it expects before failing.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One combo, padded out with 9s like the real ones: every classic board has the same values.
+const TEST_COMBOS: [[u8; 8]; 1] = [[19; 8]];
+
+/// The config tests start from: games start without the countdown.
+pub fn test_config() -> Config {
+    Config {
+        start_countdown: Duration::ZERO,
+        ..Config::default()
+    }
+}
+
+/// A server on a loopback port with its own state, stopped when the test's runtime ends.
+pub struct TestServer {
+    pub state: AppState,
+    url: String,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::with_config(test_config()).await
+    }
+
+    pub async fn with_config(config: Config) -> Self {
+        Self::serve(AppState::new_with_top_10(BinaryHeap::new(), config)).await
+    }
+
+    /// Serves `state` as it is, apart from the test combos and the timer scheduler.
+    pub async fn serve(mut state: AppState) -> Self {
+        state.combos = Arc::new(TEST_COMBOS);
+        state.timers = Arc::new(GameTimers::start(state.clone()));
+        let app = app_router(&state, axum::Router::new(), true);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+        });
+        let url = format!("ws://{addr}{}{}", state.config.base_path, state.config.ws_path);
+        TestServer { state, url }
+    }
+
+    /// Waits (up to `REPLY_TIMEOUT`) for `room_id` to get into a state `check` accepts, for
+    /// things the server does on its own time, like noticing a dropped socket.
+    pub async fn until_room(&self, room_id: &RoomId, check: impl Fn(&RoomState) -> bool) {
+        let deadline = tokio::time::Instant::now() + REPLY_TIMEOUT;
+        loop {
+            if check(&self.state.lock_room(room_id).await.expect("room is gone")) {
+                return;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "room never got there");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    pub async fn connect(&self) -> Client {
+        self.upgrade("", &[]).await.expect("upgrade refused")
+    }
+
+    /// Attempts an upgrade with `query` on the URL and extra `headers`; the HTTP status if the
+    /// server refused it.
+    pub async fn upgrade(
+        &self,
+        query: &str,
+        headers: &[(&'static str, &str)],
+    ) -> Result<Client, u16> {
+        let mut request = format!("{}{query}", self.url).into_client_request().unwrap();
+        for &(name, value) in headers {
+            request.headers_mut().append(name, value.parse().unwrap());
+        }
+        match tokio_tungstenite::connect_async(request).await {
+            Ok((ws, _)) => Ok(Client { ws, close_code: None }),
+            Err(WsError::Http(response)) => Err(response.status().as_u16()),
+            Err(e) => panic!("upgrade failed: {e}"),
+        }
+    }
+
+    /// The `Host` the test clients send, for same-origin requests.
+    pub fn host(&self) -> &str {
+        let authority = self.url.trim_start_matches("ws://");
+        &authority[..authority.find('/').unwrap()]
+    }
+}
+
+/// A test's end of one socket.
+pub struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// The code the server closed the socket with, once it has.
+    pub close_code: Option<u16>,
+}
+
+impl Client {
+    pub async fn send(&mut self, msg: &WsClientMsg) {
+        self.send_text(serde_json::to_string(msg).unwrap()).await;
+    }
+
+    /// Sends `text` as it is, for frames a well-behaved client wouldn't send.
+    pub async fn send_text(&mut self, text: String) {
+        self.ws.send(Message::text(text)).await.unwrap();
+    }
+
+    /// The next server message, broadcast or direct; `None` once the socket is closed.
+    pub async fn recv(&mut self) -> Option<WsServerMsg> {
+        self.recv_within(REPLY_TIMEOUT).await
+    }
+
+    /// `recv`, for messages that take longer than a reply: waits up to `limit`.
+    pub async fn recv_within(&mut self, limit: Duration) -> Option<WsServerMsg> {
+        loop {
+            let frame = tokio::time::timeout(limit, self.ws.next())
+                .await
+                .expect("timed out waiting for the server");
+            match frame {
+                Some(Ok(Message::Text(text))) => {
+                    // room broadcasts come wrapped with their `seq`, direct replies don't
+                    let msg = match serde_json::from_str::<RoomEvent>(&text) {
+                        Ok(event) => event.msg,
+                        Err(_) => serde_json::from_str::<WsServerMsg>(&text)
+                            .unwrap_or_else(|e| panic!("unparseable server message {text}: {e}")),
+                    };
+                    return Some(msg);
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    self.close_code = frame.map(|f| f.code.into());
+                    return None;
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return None,
+            }
+        }
+    }
+
+    /// Skips messages until `pick` takes one. Panics if the socket closes first.
+    pub async fn expect<T>(&mut self, mut pick: impl FnMut(WsServerMsg) -> Option<T>) -> T {
+        loop {
+            let msg = self.recv().await.expect("socket closed while waiting for a reply");
+            if let Some(found) = pick(msg) {
+                return found;
+            }
+        }
+    }
+
+    /// The next `Error`, skipping anything else.
+    pub async fn expect_error(&mut self) -> WsServerMsg {
+        self.expect(|msg| matches!(msg, WsServerMsg::Error { .. }).then_some(msg))
+            .await
+    }
+
+    /// Reads until the server closes the socket, and returns the close code.
+    pub async fn expect_closed(&mut self) -> Option<u16> {
+        while self.recv().await.is_some() {}
+        self.close_code
+    }
+
+    /// Reads until the server closes the socket, for closes it makes on its own time: fails
+    /// if that takes longer than `limit` from now.
+    pub async fn closed_within(&mut self, limit: Duration) -> Option<u16> {
+        let deadline = tokio::time::Instant::now() + limit;
+        loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if self.recv_within(left).await.is_none() {
+                return self.close_code;
+            }
+        }
+    }
+
+    /// Creates a room as `player` and returns its ID and the owner's seat token.
+    pub async fn create_room(&mut self, player: &Player) -> (RoomId, String) {
+        self.send(&WsClientMsg::CreateRoom {
+            player: player.clone(),
+            settings: None,
+        })
+        .await;
+        self.expect(|msg| match msg {
+            WsServerMsg::SeatGranted { room_id, seat_token, .. } => Some((room_id, seat_token)),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Joins `room_id` as `player` and returns the seat token (panics on a refusal).
+    pub async fn join(&mut self, room_id: &RoomId, player: &Player, token: Option<&str>) -> String {
+        self.send(&WsClientMsg::JoinRoom {
+            room_id: room_id.clone(),
+            player: player.clone(),
+            seat_token: token.map(str::to_owned),
+        })
+        .await;
+        self.expect(|msg| match msg {
+            WsServerMsg::SeatGranted { seat_token, .. } => Some(seat_token),
+            WsServerMsg::Error { msg, .. } => panic!("join refused: {msg}"),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Reports a two-apple clear (1 + 9) as `turn`, worth 2 points, and waits for the ack.
+    pub async fn score_pair(&mut self, turn: u32) -> u32 {
+        self.send(&WsClientMsg::ScoreUpdate {
+            cleared_count: 2,
+            turn,
+            cleared_values: vec![1, 9],
+            rect: None,
+        })
+        .await;
+        self.expect(|msg| match msg {
+            WsServerMsg::ScoreAck { total, .. } => Some(total),
+            WsServerMsg::Error { msg, .. } => panic!("score refused: {msg}"),
+            _ => None,
+        })
+        .await
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.ws.close(None).await;
+    }
+}
+
+pub fn player(id: &str, name: &str) -> Player {
+    Player {
+        player_id: id.to_owned(),
+        name: name.to_owned(),
+        ready: false,
+        muted: false,
+    }
+}
+
+/// Starts a game between `host`, who owns the room, and `guest`, who has joined it: the guest
+/// gets ready, the host starts, and both see `GameStarted`.
+pub async fn start_two_player_game(host: &mut Client, guest: &mut Client) {
+    guest.send(&WsClientMsg::ReadyUp { ready: true }).await;
+    guest
+        .expect(|msg| matches!(msg, WsServerMsg::ReadyAck { .. }).then_some(()))
+        .await;
+    host.send(&WsClientMsg::StartGame { restart: None }).await;
+    for client in [host, guest] {
+        client
+            .expect(|msg| matches!(msg, WsServerMsg::GameStarted { .. }).then_some(()))
+            .await;
+    }
+}
@@ -0,0 +1,175 @@
+// src/tests/throttle.rs
+//! Per-connection throttles on creating and joining rooms, and per-member ones on chat.
+use super::support::{player, Client, TestServer};
+use crate::ws_messages::{ErrorCode, SystemMessageKind, WsClientMsg, WsServerMsg};
+
+/// What `CLOSE_RATE_LIMITED` closes a socket with.
+const RATE_LIMITED: u16 = 4001;
+
+fn retry_after(msg: &WsServerMsg) -> Option<u64> {
+    match msg {
+        WsServerMsg::Error { code: Some(ErrorCode::RateLimited { retry_after_ms }), .. } => {
+            Some(*retry_after_ms)
+        }
+        _ => None,
+    }
+}
+
+/// A join to a room that doesn't exist, varied by `n` so the duplicate filter lets it through.
+async fn join_missing(client: &mut Client, n: u32) -> WsServerMsg {
+    client
+        .send(&WsClientMsg::JoinRoom {
+            room_id: format!("9{n:03}"),
+            player: player("prober", "Prober"),
+            seat_token: None,
+        })
+        .await;
+    client.expect_error().await
+}
+
+#[tokio::test]
+async fn a_second_room_within_ten_seconds_is_refused_with_a_retry_hint() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    client.create_room(&player("host", "Host")).await;
+    client.send(&WsClientMsg::LeaveRoom {}).await;
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::LeftRoom { .. }).then_some(()))
+        .await;
+
+    client
+        .send(&WsClientMsg::CreateRoom {
+            player: player("host", "Host"),
+            settings: None,
+        })
+        .await;
+    let err = client.expect_error().await;
+    let wait = retry_after(&err).unwrap_or_else(|| panic!("not rate limited: {err:?}"));
+    assert!(wait > 0 && wait <= 10_000, "{wait}ms");
+
+    // the limit is the connection's, not the address's
+    let mut other = server.connect().await;
+    other.create_room(&player("other", "Other")).await;
+}
+
+#[tokio::test]
+async fn ten_failed_joins_a_minute_then_rate_limited() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    for n in 0..10 {
+        let err = join_missing(&mut client, n).await;
+        assert_eq!(retry_after(&err), None, "failed join {n} was throttled");
+    }
+    let err = join_missing(&mut client, 10).await;
+    let wait = retry_after(&err).unwrap_or_else(|| panic!("not rate limited: {err:?}"));
+    assert!(wait > 0 && wait <= 6_000, "{wait}ms");
+}
+
+#[tokio::test]
+async fn successful_joins_are_not_counted() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+
+    let mut hopper = server.connect().await;
+    for _ in 0..12 {
+        hopper.join(&room_id, &player("hopper", "Hopper"), None).await;
+        hopper.send(&WsClientMsg::LeaveRoom {}).await;
+        hopper
+            .expect(|msg| matches!(msg, WsServerMsg::LeftRoom { .. }).then_some(()))
+            .await;
+    }
+    // the failures it's allowed are all still there
+    for n in 0..10 {
+        let err = join_missing(&mut hopper, n).await;
+        assert_eq!(retry_after(&err), None, "failed join {n} was throttled");
+    }
+}
+
+#[tokio::test]
+async fn keeping_on_past_the_limit_closes_the_socket() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    for n in 0..10 {
+        join_missing(&mut client, n).await;
+    }
+    for n in 10..14 {
+        let err = join_missing(&mut client, n).await;
+        assert!(retry_after(&err).is_some(), "not rate limited: {err:?}");
+    }
+    // the fifth refusal in a row is the last
+    client
+        .send(&WsClientMsg::JoinRoom {
+            room_id: "9999".to_owned(),
+            player: player("prober", "Prober"),
+            seat_token: None,
+        })
+        .await;
+    assert_eq!(client.expect_closed().await, Some(RATE_LIMITED));
+}
+
+/// Room chat, varied by `n` so the duplicate filter lets it through.
+async fn chat(client: &mut Client, n: u32) {
+    client
+        .send(&WsClientMsg::ChatMessage {
+            message: format!("message {n}"),
+            name: None,
+            channel: None,
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn chatting_past_the_limit_is_refused_then_muted() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut spammer = server.connect().await;
+    spammer.join(&room_id, &player("spammer", "Spammer"), None).await;
+
+    // the burst of three goes out...
+    for n in 0..3 {
+        chat(&mut spammer, n).await;
+        let heard = host
+            .expect(|msg| match msg {
+                WsServerMsg::ChatBroadcast { message, .. } => Some(message),
+                _ => None,
+            })
+            .await;
+        assert_eq!(heard, format!("message {n}"));
+    }
+    // ...the next two are refused with a retry hint...
+    for n in 3..5 {
+        chat(&mut spammer, n).await;
+        let err = spammer.expect_error().await;
+        let wait = retry_after(&err).unwrap_or_else(|| panic!("not rate limited: {err:?}"));
+        assert!(wait <= 2_000, "{wait}ms");
+    }
+    // ...and the third violation mutes, which the room hears about
+    chat(&mut spammer, 5).await;
+    match spammer.expect_error().await {
+        WsServerMsg::Error { code: Some(ErrorCode::Muted { retry_after_ms }), .. } => {
+            assert_eq!(retry_after_ms, Some(60_000));
+        }
+        other => panic!("not muted: {other:?}"),
+    }
+    let notice = host
+        .expect(|msg| match msg {
+            WsServerMsg::ChatBroadcast { message, .. } => panic!("{message} got through"),
+            WsServerMsg::SystemMessage { kind: SystemMessageKind::Muted, text, .. } => Some(text),
+            _ => None,
+        })
+        .await;
+    assert!(notice.contains("Spammer"), "{notice}");
+    chat(&mut spammer, 6).await;
+    let err = spammer.expect_error().await;
+    assert!(matches!(err, WsServerMsg::Error { code: Some(ErrorCode::Muted { .. }), .. }));
+
+    // the limiter goes with its member
+    spammer.send(&WsClientMsg::LeaveRoom {}).await;
+    spammer
+        .expect(|msg| matches!(msg, WsServerMsg::LeftRoom { .. }).then_some(()))
+        .await;
+    let room = server.state.lock_room(&room_id).await.unwrap();
+    assert!(!room.chat_limits.contains_key("spammer"));
+}
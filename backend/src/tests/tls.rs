@@ -0,0 +1,100 @@
+// src/tests/tls.rs
+use crate::{app_router, config::TlsPaths, server_state::AppState, tls, ws_messages::WsServerMsg};
+use futures_util::StreamExt;
+use rcgen::CertifiedKey;
+use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::Message;
+
+fn self_signed() -> CertifiedKey {
+    rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap()
+}
+
+/// Writes `cert` and `key` as PEM files in the temp directory.
+fn pem_files(cert: &str, key: &str) -> TlsPaths {
+    let stem = std::env::temp_dir().join(format!("tls-{}", uuid::Uuid::new_v4().simple()));
+    let paths = TlsPaths {
+        cert: PathBuf::from(format!("{}.crt", stem.display())),
+        key: PathBuf::from(format!("{}.key", stem.display())),
+    };
+    std::fs::write(&paths.cert, cert).unwrap();
+    std::fs::write(&paths.key, key).unwrap();
+    paths
+}
+
+fn remove(paths: TlsPaths) {
+    let _ = std::fs::remove_file(paths.cert);
+    let _ = std::fs::remove_file(paths.key);
+}
+
+fn load_error(paths: &TlsPaths) -> String {
+    format!("{:#}", tls::build_server_config(paths).unwrap_err())
+}
+
+#[tokio::test]
+async fn a_websocket_connects_over_tls_with_a_self_signed_cert() {
+    let CertifiedKey { cert, key_pair } = self_signed();
+    let paths = pem_files(&cert.pem(), &key_pair.serialize_pem());
+    let state = AppState::new();
+    let app = app_router(&state, axum::Router::new(), false);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum_server::from_tcp_rustls(listener, tls::load(&paths).unwrap())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    tokio::spawn(server);
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    // a browser offers h2 as well; the upgrade only works if the server settles on 1.1
+    client.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(client))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp)
+        .await
+        .unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    let url = format!("wss://localhost:{}{}", addr.port(), state.config.ws_path);
+    let (mut ws, _) = tokio_tungstenite::client_async(url, stream).await.unwrap();
+    let Some(Ok(Message::Text(first))) = ws.next().await else {
+        panic!("no greeting over TLS");
+    };
+    let greeting: WsServerMsg = serde_json::from_str(&first).unwrap();
+    assert!(matches!(greeting, WsServerMsg::ServerInfo { .. }));
+    remove(paths);
+}
+
+#[test]
+fn a_key_from_another_cert_is_refused() {
+    let (ours, theirs) = (self_signed(), self_signed());
+    let paths = pem_files(&ours.cert.pem(), &theirs.key_pair.serialize_pem());
+    let error = load_error(&paths);
+    assert!(error.contains("does not match certificate"), "{error}");
+    remove(paths);
+}
+
+#[test]
+fn missing_and_empty_files_name_the_file() {
+    let CertifiedKey { cert, key_pair } = self_signed();
+    let paths = pem_files("", &key_pair.serialize_pem());
+    let error = load_error(&paths);
+    assert!(error.contains(&format!("no certificates found in {}", paths.cert.display())));
+
+    std::fs::write(&paths.cert, cert.pem()).unwrap();
+    std::fs::remove_file(&paths.key).unwrap();
+    let error = load_error(&paths);
+    assert!(error.contains(&format!("cannot read TLS private key {}", paths.key.display())));
+
+    std::fs::remove_file(&paths.cert).unwrap();
+    let error = load_error(&paths);
+    assert!(error.contains(&format!("cannot read TLS certificate {}", paths.cert.display())));
+}
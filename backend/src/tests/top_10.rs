@@ -0,0 +1,34 @@
+// src/tests/top_10.rs
+//! The all-time top 10 every socket is sent on connecting.
+use super::support::{player, start_two_player_game, Client, TestServer};
+use crate::ws_messages::{WsClientMsg, WsServerMsg};
+
+async fn welcome_top_10(client: &mut Client) -> Vec<(u32, String)> {
+    client
+        .expect(|msg| match msg {
+            WsServerMsg::Top10Scores { scores } => Some((*scores).clone()),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test]
+async fn a_finished_game_is_in_the_next_sockets_top_10() {
+    let server = TestServer::start().await;
+    let mut host = server.connect().await;
+    assert_eq!(welcome_top_10(&mut host).await, []);
+    let (room_id, _) = host.create_room(&player("host", "Host")).await;
+    let mut guest = server.connect().await;
+    guest.join(&room_id, &player("guest", "Guest"), None).await;
+    start_two_player_game(&mut host, &mut guest).await;
+    guest.score_pair(1).await;
+    for client in [&mut host, &mut guest] {
+        client.send(&WsClientMsg::FinishRound {}).await;
+    }
+    // the game only counts as over once its scores are recorded
+    server.until_room(&room_id, |room| !room.game_in_progress()).await;
+
+    // the host never scored, so didn't make the list
+    let mut newcomer = server.connect().await;
+    assert_eq!(welcome_top_10(&mut newcomer).await, [(2, "Guest".to_owned())]);
+}
@@ -0,0 +1,259 @@
+// src/tests/upgrade.rs
+//! What the server checks before it accepts a socket at all.
+use super::support::{player, test_config, TestServer};
+use crate::{
+    config::Config,
+    ws_messages::{WsClientMsg, WsServerMsg},
+};
+
+async fn expect_ws_ok(server: &TestServer, headers: &[(&'static str, &str)]) {
+    if let Err(status) = server.upgrade("", headers).await {
+        panic!("upgrade refused with {status}");
+    }
+}
+
+async fn expect_ws_status(
+    server: &TestServer,
+    query: &str,
+    headers: &[(&'static str, &str)],
+) -> u16 {
+    match server.upgrade(query, headers).await {
+        Ok(_) => panic!("upgrade was accepted"),
+        Err(status) => status,
+    }
+}
+
+#[tokio::test]
+async fn same_host_and_localhost_origins_are_let_in() {
+    let server = TestServer::start().await;
+    let same_host = format!("http://{}", server.host());
+    expect_ws_ok(&server, &[("origin", &same_host)]).await;
+    expect_ws_ok(&server, &[("origin", "http://localhost:5173")]).await;
+    expect_ws_ok(&server, &[("origin", "http://[::1]:5173")]).await;
+}
+
+#[tokio::test]
+async fn other_sites_are_refused() {
+    let server = TestServer::start().await;
+    assert_eq!(expect_ws_status(&server, "", &[("origin", "https://evil.example")]).await, 403);
+    assert_eq!(expect_ws_status(&server, "", &[("origin", "null")]).await, 403);
+    // a lookalike of localhost is not localhost
+    let lookalike = [("origin", "http://localhost.evil.example")];
+    assert_eq!(expect_ws_status(&server, "", &lookalike).await, 403);
+}
+
+#[tokio::test]
+async fn listed_origins_are_let_in() {
+    let server = TestServer::with_config(Config {
+        allowed_origins: vec!["https://fruit.example/".to_owned()],
+        ..test_config()
+    })
+    .await;
+    expect_ws_ok(&server, &[("origin", "https://fruit.example")]).await;
+    let other = [("origin", "https://fruit.example.org")];
+    assert_eq!(expect_ws_status(&server, "", &other).await, 403);
+}
+
+#[tokio::test]
+async fn a_missing_origin_is_let_in_only_when_configured() {
+    let server = TestServer::start().await;
+    expect_ws_ok(&server, &[]).await;
+
+    let strict = TestServer::with_config(Config {
+        allow_missing_origin: false,
+        ..test_config()
+    })
+    .await;
+    assert_eq!(expect_ws_status(&strict, "", &[]).await, 403);
+}
+
+#[tokio::test]
+async fn upgrades_past_the_per_address_cap_get_429() {
+    let server = TestServer::with_config(Config {
+        max_connections_per_ip: 5,
+        ..test_config()
+    })
+    .await;
+    let attempts = futures_util::future::join_all((0..20).map(|_| server.upgrade("", &[]))).await;
+    let (mut open, refused): (Vec<_>, Vec<_>) = attempts.into_iter().partition(Result::is_ok);
+    assert_eq!(open.len(), 5);
+    assert!(refused.iter().all(|r| matches!(r, Err(429))));
+
+    // a closed socket hands its slot back
+    open.pop().unwrap().unwrap().close().await;
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+    while server.upgrade("", &[]).await.is_err() {
+        assert!(tokio::time::Instant::now() < deadline, "slot never came back");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn room_creation_is_capped_per_address_across_connections() {
+    let server = TestServer::with_config(Config {
+        max_rooms_per_ip_per_minute: 2,
+        ..test_config()
+    })
+    .await;
+    for i in 0..2 {
+        server.connect().await.create_room(&player(&format!("host-{i}"), "Host")).await;
+    }
+    let mut third = server.connect().await;
+    third
+        .send(&WsClientMsg::CreateRoom {
+            player: player("host-2", "Host"),
+            settings: None,
+        })
+        .await;
+    let WsServerMsg::Error { msg, .. } = third.expect_error().await else { unreachable!() };
+    assert_eq!(msg, "Too many rooms created, try again in a minute");
+}
+
+const SIZE_LIMIT: usize = 1024;
+
+async fn size_limited() -> TestServer {
+    TestServer::with_config(Config {
+        max_message_bytes: SIZE_LIMIT,
+        ..test_config()
+    })
+    .await
+}
+
+/// A `CreateRoom`, padded with whitespace to `len` bytes.
+fn create_room_of_len(len: usize) -> String {
+    let msg = WsClientMsg::CreateRoom {
+        player: player("host", "Host"),
+        settings: None,
+    };
+    let text = serde_json::to_string(&msg).unwrap();
+    format!("{text:<len$}")
+}
+
+#[tokio::test]
+async fn a_message_right_at_the_limit_is_handled() {
+    let server = size_limited().await;
+    let mut client = server.connect().await;
+    client.send_text(create_room_of_len(SIZE_LIMIT)).await;
+    client
+        .expect(|msg| matches!(msg, WsServerMsg::SeatGranted { .. }).then_some(()))
+        .await;
+}
+
+#[tokio::test]
+async fn an_oversized_message_is_refused_and_the_socket_closed() {
+    let server = size_limited().await;
+    let mut client = server.connect().await;
+    client.send_text(create_room_of_len(SIZE_LIMIT + 1)).await;
+    let WsServerMsg::Error { msg, .. } = client.expect_error().await else { unreachable!() };
+    assert_eq!(msg, format!("Message too large (max {SIZE_LIMIT} bytes)"));
+    assert_eq!(client.expect_closed().await, Some(1009));
+    assert_eq!(server.state.rooms.len(), 0);
+}
+
+#[tokio::test]
+async fn a_frame_past_the_hard_limit_is_never_read() {
+    let server = size_limited().await;
+    let mut client = server.connect().await;
+    client.send_text("x".repeat(4 * SIZE_LIMIT)).await;
+    // the frame is cut off before it's buffered: the socket is dropped without a reply
+    while let Some(msg) = client.recv().await {
+        assert!(!matches!(msg, WsServerMsg::Error { .. }), "got {msg:?}");
+    }
+    server.connect().await.create_room(&player("host", "Host")).await;
+}
+
+const TOKEN: &str = "0f5c3b1e9a7d4c2e";
+
+async fn private() -> TestServer {
+    TestServer::with_config(Config {
+        require_ws_token: Some(TOKEN.to_owned()),
+        ..test_config()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn the_token_gets_in_as_a_query_parameter() {
+    let server = private().await;
+    let mut client = match server.upgrade(&format!("?token={TOKEN}"), &[]).await {
+        Ok(client) => client,
+        Err(status) => panic!("refused with {status}"),
+    };
+    client.create_room(&player("host", "Host")).await;
+}
+
+#[tokio::test]
+async fn the_token_gets_in_as_a_subprotocol() {
+    let server = private().await;
+    let offers = format!("fruitbox.v1, {TOKEN}");
+    expect_ws_ok(&server, &[("sec-websocket-protocol", &offers)]).await;
+    // offered alone, it's echoed back so the browser's handshake completes
+    expect_ws_ok(&server, &[("sec-websocket-protocol", TOKEN)]).await;
+}
+
+#[tokio::test]
+async fn no_token_or_a_wrong_one_gets_401_then_429() {
+    let server = private().await;
+    assert_eq!(expect_ws_status(&server, "", &[]).await, 401);
+    assert_eq!(expect_ws_status(&server, "?token=guess", &[]).await, 401);
+    let wrong = [("sec-websocket-protocol", "fruitbox.v1, guess")];
+    assert_eq!(expect_ws_status(&server, "", &wrong).await, 401);
+    for _ in 3..10 {
+        assert_eq!(expect_ws_status(&server, "?token=guess", &[]).await, 401);
+    }
+    // ten strikes: not even the right token is checked any more
+    let right = format!("?token={TOKEN}");
+    assert_eq!(expect_ws_status(&server, &right, &[]).await, 429);
+}
+
+#[tokio::test]
+async fn the_token_never_reaches_the_logs() {
+    let logs = LogBuffer::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_writer(logs.clone())
+        .finish();
+    // a current-thread runtime: the server's tasks log on this thread too
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let server = private().await;
+    let mut client = match server.upgrade(&format!("?token={TOKEN}"), &[]).await {
+        Ok(client) => client,
+        Err(status) => panic!("refused with {status}"),
+    };
+    client.create_room(&player("host", "Host")).await;
+    assert_eq!(expect_ws_status(&server, "?token=guess", &[]).await, 401);
+
+    let logs = logs.text();
+    assert!(logs.contains("client connecting"), "nothing was logged");
+    assert!(!logs.contains(TOKEN));
+}
+
+/// Everything logged, for checking what made it in.
+#[derive(Clone, Default)]
+struct LogBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl LogBuffer {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogBuffer {
+    type Writer = LogBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
@@ -0,0 +1,72 @@
+// src/tls.rs
+use crate::config::TlsPaths;
+use anyhow::{Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use std::sync::Arc;
+
+/// Reads the PEM certificate chain and key and builds a rustls server config.
+/// Fails with a message naming the offending file for unreadable, malformed or mismatched inputs.
+pub fn build_server_config(paths: &TlsPaths) -> Result<Arc<ServerConfig>> {
+    let certs: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(&paths.cert)
+        .with_context(|| format!("cannot read TLS certificate {}", paths.cert.display()))?
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("malformed TLS certificate {}", paths.cert.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", paths.cert.display());
+    }
+    let key = PrivateKeyDer::from_pem_file(&paths.key)
+        .with_context(|| format!("cannot read TLS private key {}", paths.key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("unsupported TLS protocol configuration")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| {
+            format!(
+                "TLS key {} does not match certificate {}",
+                paths.key.display(),
+                paths.cert.display()
+            )
+        })?;
+    // WebSocket upgrades need HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Initial TLS config for the listener.
+pub fn load(paths: &TlsPaths) -> Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(build_server_config(paths)?))
+}
+
+/// Re-reads the certificate and key whenever SIGHUP arrives, so renewals don't need a restart.
+/// A broken renewal is logged and the previous certificate stays in use.
+#[cfg(unix)]
+pub async fn reload_on_sighup(config: RustlsConfig, paths: TlsPaths) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot listen for SIGHUP, TLS reload disabled");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match build_server_config(&paths) {
+            Ok(new_config) => {
+                config.reload_from_config(new_config);
+                tracing::info!(cert = %paths.cert.display(), "reloaded TLS certificate");
+            }
+            Err(e) => tracing::error!(error = format!("{e:#}"), "TLS reload failed, keeping old certificate"),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(_config: RustlsConfig, _paths: TlsPaths) {}
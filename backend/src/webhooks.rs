@@ -0,0 +1,436 @@
+// src/webhooks.rs
+use crate::ws_messages::RoomId;
+use reqwest::Url;
+use serde::Serialize;
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Semaphore};
+
+/// Finished games waiting to be sent. Past this, new ones are dropped rather than letting a
+/// dead webhook back up the game timers.
+const QUEUE_CAPACITY: usize = 256;
+/// Deliveries (including their retries) in flight at once.
+const MAX_IN_FLIGHT: usize = 16;
+/// Tries per delivery; the waits between them double from `FIRST_RETRY_DELAY`.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a webhook can subscribe to.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Every finished game.
+    GameEnded,
+    /// A game put at least one player into the all-time top 10.
+    NewTop10Entry,
+    /// A game beat the all-time best score.
+    NewRecord,
+}
+
+impl FromStr for WebhookEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "game_ended" => Ok(WebhookEvent::GameEnded),
+            "new_top10_entry" => Ok(WebhookEvent::NewTop10Entry),
+            "new_record" => Ok(WebhookEvent::NewRecord),
+            other => anyhow::bail!(
+                "unknown webhook event {other:?} (expected game_ended, new_top10_entry or new_record)"
+            ),
+        }
+    }
+}
+
+/// One `--webhooks` entry: `https://host/path`, optionally followed by `#event+event` to only
+/// receive those events (the fragment is never sent). No fragment means every event.
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub url: Url,
+    pub events: Vec<WebhookEvent>,
+}
+
+impl WebhookTarget {
+    /// What we log instead of the URL, whose path is often the webhook's secret.
+    fn host(&self) -> &str {
+        self.url.host_str().unwrap_or("?")
+    }
+}
+
+impl FromStr for WebhookTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (url, events) = match s.split_once('#') {
+            Some((url, events)) => (
+                url,
+                events.split('+').map(str::parse).collect::<anyhow::Result<_>>()?,
+            ),
+            None => (
+                s,
+                vec![
+                    WebhookEvent::GameEnded,
+                    WebhookEvent::NewTop10Entry,
+                    WebhookEvent::NewRecord,
+                ],
+            ),
+        };
+        let url = Url::parse(url).map_err(|e| anyhow::anyhow!("invalid webhook URL: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("webhook URLs must be http:// or https://");
+        }
+        Ok(WebhookTarget { url, events })
+    }
+}
+
+/// A player's final score in a webhook payload.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerScore {
+    pub name: String,
+    pub score: u32,
+}
+
+/// The JSON body POSTed to a webhook, once per matching event.
+///
+/// `content` is a one-line summary, which is all a Discord webhook needs to post the result;
+/// other consumers can ignore it and read the structured fields.
+#[derive(Serialize, Debug, Clone)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub content: String,
+    pub room_id: RoomId,
+    pub duration_secs: u64,
+    pub ended_early: bool,
+    /// Everyone who played, best score first.
+    pub players: Vec<PlayerScore>,
+    /// The players the event is about: new top-10 entries, or the new record holder.
+    /// Empty for `game_ended`.
+    pub highlights: Vec<PlayerScore>,
+}
+
+/// A finished game, as handed over by the room timer.
+#[derive(Debug, Clone)]
+pub struct GameResult {
+    pub room_id: RoomId,
+    pub duration_secs: u64,
+    pub ended_early: bool,
+    pub players: Vec<PlayerScore>,
+    pub new_top10: Vec<PlayerScore>,
+    pub new_record: Option<PlayerScore>,
+}
+
+impl GameResult {
+    /// The payloads this game produces, one per event that happened.
+    fn payloads(&self) -> Vec<WebhookPayload> {
+        let payload = |event, content: String, highlights: Vec<PlayerScore>| WebhookPayload {
+            event,
+            content,
+            room_id: self.room_id.clone(),
+            duration_secs: self.duration_secs,
+            ended_early: self.ended_early,
+            players: self.players.clone(),
+            highlights,
+        };
+        let standings = self
+            .players
+            .iter()
+            .map(|p| format!("{} {}", p.name, p.score))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut payloads = vec![payload(
+            WebhookEvent::GameEnded,
+            format!("Game over in room {}: {standings}", self.room_id),
+            Vec::new(),
+        )];
+        if !self.new_top10.is_empty() {
+            let names = self
+                .new_top10
+                .iter()
+                .map(|p| format!("{} ({})", p.name, p.score))
+                .collect::<Vec<_>>()
+                .join(", ");
+            payloads.push(payload(
+                WebhookEvent::NewTop10Entry,
+                format!("New top-10 entry: {names}"),
+                self.new_top10.clone(),
+            ));
+        }
+        if let Some(record) = &self.new_record {
+            payloads.push(payload(
+                WebhookEvent::NewRecord,
+                format!("New all-time record: {} with {}", record.name, record.score),
+                vec![record.clone()],
+            ));
+        }
+        payloads
+    }
+}
+
+/// Outgoing game notifications.
+///
+/// `game_finished` never waits: results go through a bounded channel to a sender task, which
+/// POSTs each matching payload with retries and exponential backoff.
+#[derive(Debug)]
+pub struct Webhooks {
+    tx: Option<mpsc::Sender<GameResult>>,
+}
+
+impl Default for Webhooks {
+    /// No webhooks configured: results are discarded.
+    fn default() -> Self {
+        Webhooks { tx: None }
+    }
+}
+
+impl Webhooks {
+    /// Spawns the sender task when there is anything to send to. Must be called inside the runtime.
+    pub fn start(targets: Vec<WebhookTarget>) -> Self {
+        if targets.is_empty() {
+            return Webhooks::default();
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .expect("cannot build HTTP client");
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(send_results(client, Arc::new(targets), rx));
+        Webhooks { tx: Some(tx) }
+    }
+
+    pub fn game_finished(&self, result: GameResult) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if tx.try_send(result).is_err() {
+            tracing::warn!("webhook queue full, game result dropped");
+        }
+    }
+}
+
+async fn send_results(
+    client: reqwest::Client,
+    targets: Arc<Vec<WebhookTarget>>,
+    mut rx: mpsc::Receiver<GameResult>,
+) {
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    while let Some(result) = rx.recv().await {
+        for payload in result.payloads() {
+            for target in targets.iter().filter(|t| t.events.contains(&payload.event)) {
+                // waiting here (not in the timer) is what lets the queue fill up and shed load
+                let permit = in_flight.clone().acquire_owned().await.unwrap();
+                let (client, target, payload) = (client.clone(), target.clone(), payload.clone());
+                tokio::spawn(async move {
+                    deliver(&client, &target, &payload).await;
+                    drop(permit);
+                });
+            }
+        }
+    }
+}
+
+/// POSTs `payload`, retrying network errors, 429s and 5xxs; other 4xxs are final.
+async fn deliver(client: &reqwest::Client, target: &WebhookTarget, payload: &WebhookPayload) {
+    let host = target.host();
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let outcome = client.post(target.url.clone()).json(payload).send().await;
+        let retry = match &outcome {
+            Ok(resp) if resp.status().is_success() => {
+                tracing::debug!(host, event = ?payload.event, attempt, "webhook delivered");
+                return;
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                tracing::warn!(host, event = ?payload.event, attempt, %status, "webhook rejected");
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                tracing::warn!(host, event = ?payload.event, attempt, error = %e, "webhook failed");
+                true
+            }
+        };
+        if !retry || attempt == MAX_ATTEMPTS {
+            break;
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+    tracing::error!(host, event = ?payload.event, "giving up on webhook");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use serde_json::Value;
+    use std::{collections::VecDeque, sync::Mutex};
+    use tokio::net::TcpListener;
+
+    /// What the mock webhook received, and the statuses it answers with before settling on 204.
+    #[derive(Clone, Default)]
+    struct Hook {
+        received: Arc<Mutex<Vec<(String, Value)>>>,
+        statuses: Arc<Mutex<VecDeque<StatusCode>>>,
+    }
+
+    impl Hook {
+        /// Serves the mock on a loopback port; returns the base URL.
+        async fn serve(&self) -> String {
+            async fn receive(
+                State(hook): State<Hook>,
+                axum::extract::Path(path): axum::extract::Path<String>,
+                Json(body): Json<Value>,
+            ) -> StatusCode {
+                hook.received.lock().unwrap().push((path, body));
+                hook.statuses.lock().unwrap().pop_front().unwrap_or(StatusCode::NO_CONTENT)
+            }
+            let app = Router::new().route("/{path}", post(receive)).with_state(self.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            format!("http://{addr}")
+        }
+
+        fn answer_first_with(&self, statuses: &[StatusCode]) {
+            self.statuses.lock().unwrap().extend(statuses);
+        }
+
+        fn received(&self) -> Vec<(String, Value)> {
+            self.received.lock().unwrap().clone()
+        }
+
+        /// Waits (up to 5s) until `n` requests have come in.
+        async fn until_received(&self, n: usize) -> Vec<(String, Value)> {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+            while self.received.lock().unwrap().len() < n {
+                assert!(tokio::time::Instant::now() < deadline, "only got {:?}", self.received());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            self.received()
+        }
+    }
+
+    fn score(name: &str, score: u32) -> PlayerScore {
+        PlayerScore { name: name.to_owned(), score }
+    }
+
+    fn result() -> GameResult {
+        GameResult {
+            room_id: "4821".to_owned(),
+            duration_secs: 120,
+            ended_early: false,
+            players: vec![score("Ada", 151), score("Bo", 97)],
+            new_top10: vec![score("Ada", 151)],
+            new_record: Some(score("Ada", 151)),
+        }
+    }
+
+    fn payload() -> WebhookPayload {
+        result().payloads().remove(0)
+    }
+
+    #[test]
+    fn targets_subscribe_to_everything_unless_a_fragment_says_otherwise() {
+        let all: WebhookTarget = "https://hooks.example/T0/abc".parse().unwrap();
+        assert_eq!(all.events.len(), 3);
+        assert_eq!(all.host(), "hooks.example");
+
+        let some: WebhookTarget = "https://hooks.example/x#new_record+game_ended".parse().unwrap();
+        assert_eq!(some.events, [WebhookEvent::NewRecord, WebhookEvent::GameEnded]);
+        assert_eq!(some.url.fragment(), None, "the fragment would be sent");
+
+        assert!("https://hooks.example/x#new_records".parse::<WebhookTarget>().is_err());
+        assert!("ftp://hooks.example/x".parse::<WebhookTarget>().is_err());
+        assert!("hooks.example/x".parse::<WebhookTarget>().is_err());
+    }
+
+    #[test]
+    fn a_game_makes_one_payload_per_event_it_caused() {
+        let payloads = result().payloads();
+        let events: Vec<_> = payloads.iter().map(|p| p.event).collect();
+        assert_eq!(
+            events,
+            [WebhookEvent::GameEnded, WebhookEvent::NewTop10Entry, WebhookEvent::NewRecord]
+        );
+        assert_eq!(payloads[0].content, "Game over in room 4821: Ada 151, Bo 97");
+        assert!(payloads[0].highlights.is_empty());
+        assert_eq!(payloads[2].content, "New all-time record: Ada with 151");
+
+        let quiet = GameResult { new_top10: Vec::new(), new_record: None, ..result() };
+        assert_eq!(quiet.payloads().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn results_reach_the_webhooks_subscribed_to_them() {
+        let hook = Hook::default();
+        let base = hook.serve().await;
+        let webhooks = Webhooks::start(vec![
+            format!("{base}/all").parse().unwrap(),
+            format!("{base}/records#new_record").parse().unwrap(),
+        ]);
+        webhooks.game_finished(result());
+
+        let mut received = hook.until_received(4).await;
+        received.sort_by_key(|(path, body)| (path.clone(), body["event"].to_string()));
+        let got: Vec<_> = received
+            .iter()
+            .map(|(path, body)| (path.as_str(), body["event"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("all", "game_ended"),
+                ("all", "new_record"),
+                ("all", "new_top10_entry"),
+                ("records", "new_record"),
+            ]
+        );
+        let (_, ended) = &received[0];
+        assert_eq!(ended["room_id"], "4821");
+        assert_eq!(ended["duration_secs"], 120);
+        assert_eq!(ended["players"][0]["name"], "Ada");
+        assert_eq!(ended["players"][1]["score"], 97);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_until_delivered() {
+        let hook = Hook::default();
+        let base = hook.serve().await;
+        hook.answer_first_with(&[StatusCode::SERVICE_UNAVAILABLE]);
+        let target = format!("{base}/all").parse().unwrap();
+
+        deliver(&reqwest::Client::new(), &target, &payload()).await;
+        let received = hook.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].1, received[1].1, "the retry sent something else");
+    }
+
+    #[tokio::test]
+    async fn other_client_errors_are_final() {
+        let hook = Hook::default();
+        let base = hook.serve().await;
+        hook.answer_first_with(&[StatusCode::NOT_FOUND]);
+        let target = format!("{base}/all").parse().unwrap();
+
+        deliver(&reqwest::Client::new(), &target, &payload()).await;
+        assert_eq!(hook.received().len(), 1);
+    }
+
+    #[test]
+    fn a_full_queue_sheds_results_instead_of_waiting() {
+        // a sender task stuck on a dead webhook stops draining the queue
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let webhooks = Webhooks { tx: Some(tx) };
+        for _ in 0..QUEUE_CAPACITY + 10 {
+            webhooks.game_finished(result());
+        }
+        assert_eq!(rx.len(), QUEUE_CAPACITY);
+    }
+
+    #[test]
+    fn without_webhooks_results_go_nowhere() {
+        // no runtime needed: nothing is spawned or sent
+        Webhooks::start(Vec::new()).game_finished(result());
+    }
+}
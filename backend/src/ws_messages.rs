@@ -1,6 +1,7 @@
 // src/ws_messages.rs
 
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use ts_rs::TS;
 
 /// The dimensions of our game board.
@@ -8,10 +9,49 @@ pub const ROWS: usize = 10;
 pub const COLS: usize = 17;
 pub const BOARD_SIZE: usize = ROWS * COLS;
 
+/// By default apples carry values in `MIN_APPLE_VALUE..=MAX_APPLE_VALUE`, and a clear must sum to `TARGET_SUM`.
+/// Rooms can change all three through `RoomSettings`.
+pub const MIN_APPLE_VALUE: u8 = 1;
+pub const MAX_APPLE_VALUE: u8 = 9;
+pub const TARGET_SUM: u32 = 10;
+
+/// Most messages one `ChatHistory` carries.
+pub const CHAT_HISTORY_PAGE_MAX: u8 = 50;
+
+/// Longest display name accepted anywhere a `Player` comes in, in characters.
+/// Rejections carry it as `ErrorCode::InvalidName { max_len }`, so the frontend needn't hardcode it.
+pub const MAX_NAME_LEN: usize = 24;
+
+/// Shortest `RoomSettings::idle_kick_secs` a room may ask for.
+pub const MIN_IDLE_KICK_SECS: u32 = 10;
+
+/// Longest `RoomSettings::slow_mode_secs` a room may ask for.
+pub const MAX_SLOW_MODE_SECS: u32 = 600;
+
+/// How long a game lasts unless the room sets `RoomSettings::duration_secs`.
+pub const DEFAULT_DURATION_SECS: u32 = 120;
+
+/// Shortest and longest `RoomSettings::duration_secs` a room may ask for.
+pub const MIN_DURATION_SECS: u32 = 30;
+pub const MAX_DURATION_SECS: u32 = 900;
+
+/// How long an Endless game may go on before it's taken for abandoned and stopped.
+pub const ENDLESS_CAP_SECS: u32 = 2 * 60 * 60;
+
 /// A full “sum‐to‐10” board is now just a flat array of 170 `u8`s (values 1..=9).
 /// Index calculation on the front end is: `index = y * COLS + x`.
 pub type BoardData = Vec<u8>;
 
+/// A rectangle of cells: top-left corner `(x, y)` plus width and height, in board cells.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
 /// A globally unique ID for a room (we use a UUID string).
 pub type RoomId = String;
 
@@ -28,6 +68,296 @@ pub struct Player {
     pub name: String,
     /// Whether if the player is ready for the current game to start.
     pub ready: bool,
+    /// Muted in the room chat (by the owner or for misbehaving). Only the server sets it.
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl Player {
+    /// Rejects blank names and names over `MAX_NAME_LEN` characters.
+    pub fn validate_name(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Name can't be empty".to_string());
+        }
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("Name is longer than {MAX_NAME_LEN} characters"));
+        }
+        Ok(())
+    }
+}
+
+/// How a clear turns into points. Either way `cleared_count` is the number of apples removed;
+/// the mode only decides what they're worth.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum ScoringMode {
+    /// One point per apple removed, so clearing more apples at once pays more.
+    #[default]
+    ByApples,
+    /// One point per group adding up to the target sum, however many apples it took. A clear
+    /// sent with a `rect` is one group; one without counts `sum / target_sum` groups.
+    ByGroups,
+}
+
+impl ScoringMode {
+    /// Points for a clear of `apples` apples making up `groups` groups.
+    pub fn points(self, apples: u32, groups: u32) -> u32 {
+        match self {
+            ScoringMode::ByApples => apples,
+            ScoringMode::ByGroups => groups,
+        }
+    }
+}
+
+/// Per-room game rules, picked by the owner when the room is created.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub struct RoomSettings {
+    /// Smallest apple value dealt (0 is allowed).
+    pub min_value: u8,
+    /// Largest apple value dealt; must stay below `target_sum`.
+    pub max_value: u8,
+    /// What the apples in a clear must add up to.
+    pub target_sum: u32,
+    /// Points taken off for each hint requested.
+    pub hint_penalty: u32,
+    /// What a clear scores: by apples (the classic rule) or by groups.
+    pub scoring_mode: ScoringMode,
+    /// End the round early once every player's board has no valid clears left.
+    pub end_when_stuck: bool,
+    /// End the round early once every player has sent `FinishRound` (with `end_when_stuck`,
+    /// players out of moves count as finished too).
+    pub end_when_finished: bool,
+    /// Whether spectators may post to the whole room (`ChatChannel::All`). They can always
+    /// talk among themselves on `ChatChannel::Spectators`.
+    pub spectator_chat: bool,
+    /// Move players who go this many seconds of a round without a `ScoreUpdate` to the
+    /// spectators. Off when `null`.
+    pub idle_kick_secs: Option<u32>,
+    /// Whether scores from this room count toward the global top 10. Casual (private or
+    /// practice) rooms set it to `false`.
+    pub ranked: bool,
+    /// Start the game as soon as every player but the owner is ready (and there is at least
+    /// one), without waiting for `StartGame`.
+    pub auto_start: bool,
+    /// Most players seated at once, owner included; 0 doesn't limit them. Spectators don't
+    /// count.
+    pub max_players: u32,
+    /// Slow mode: each member but the owner may post one chat message per this many seconds,
+    /// on top of the server's spam limits. 0 is off; the owner can change it with
+    /// `SetSlowMode`.
+    pub slow_mode_secs: u32,
+    /// How many chat and system lines the room keeps for joiners. Only ever shrinks the
+    /// server's own limit; the server's when `null`.
+    pub history_len: Option<u32>,
+    /// How long a game lasts, `MIN_DURATION_SECS` to `MAX_DURATION_SECS`. `null` is Endless:
+    /// no clock and no `TimerTick`s, the game runs until it ends early (see `end_when_stuck`
+    /// and `end_when_finished`). One still going after `ENDLESS_CAP_SECS` is aborted.
+    pub duration_secs: Option<u32>,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        RoomSettings {
+            min_value: MIN_APPLE_VALUE,
+            max_value: MAX_APPLE_VALUE,
+            target_sum: TARGET_SUM,
+            hint_penalty: 0,
+            scoring_mode: ScoringMode::ByApples,
+            end_when_stuck: false,
+            end_when_finished: true,
+            spectator_chat: true,
+            idle_kick_secs: None,
+            ranked: true,
+            auto_start: false,
+            max_players: 0,
+            slow_mode_secs: 0,
+            history_len: None,
+            duration_secs: Some(DEFAULT_DURATION_SECS),
+        }
+    }
+}
+
+/// One rule a `RoomSettings` breaks, as listed by `InvalidSettings`.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub struct SettingError {
+    /// The `RoomSettings` field to fix (for a rule between two fields, the one that's usually
+    /// changed).
+    pub field: String,
+    pub msg: String,
+}
+
+impl SettingError {
+    fn new(field: &str, msg: String) -> Self {
+        SettingError {
+            field: field.to_string(),
+            msg,
+        }
+    }
+}
+
+impl RoomSettings {
+    /// Every rule these settings break, or `Ok` if there are none: no two apples adding up to
+    /// `target_sum`, a lone apple already doing so, an `idle_kick_secs` below
+    /// `MIN_IDLE_KICK_SECS`, a `slow_mode_secs` above `MAX_SLOW_MODE_SECS`, a `duration_secs`
+    /// outside `MIN_DURATION_SECS..=MAX_DURATION_SECS`.
+    pub fn validate(&self) -> Result<(), Vec<SettingError>> {
+        let (min, max, target) = (self.min_value as u32, self.max_value as u32, self.target_sum);
+        let mut errors = Vec::new();
+        if min > max {
+            errors.push(SettingError::new(
+                "min_value",
+                format!("min_value {min} is above max_value {max}"),
+            ));
+        }
+        if max >= target {
+            errors.push(SettingError::new(
+                "max_value",
+                format!("max_value must be below the target sum {target}"),
+            ));
+        }
+        // with the range itself broken, there's nothing sensible to say about its pairs
+        if min <= max && (2 * min > target || 2 * max < target) {
+            errors.push(SettingError::new(
+                "target_sum",
+                format!("no two values in {min}..={max} add up to {target}"),
+            ));
+        }
+        if self.idle_kick_secs.is_some_and(|secs| secs < MIN_IDLE_KICK_SECS) {
+            errors.push(SettingError::new(
+                "idle_kick_secs",
+                format!("idle_kick_secs must be at least {MIN_IDLE_KICK_SECS}"),
+            ));
+        }
+        if self.slow_mode_secs > MAX_SLOW_MODE_SECS {
+            errors.push(SettingError::new(
+                "slow_mode_secs",
+                format!("slow_mode_secs can be at most {MAX_SLOW_MODE_SECS}"),
+            ));
+        }
+        if self
+            .duration_secs
+            .is_some_and(|secs| !(MIN_DURATION_SECS..=MAX_DURATION_SECS).contains(&secs))
+        {
+            errors.push(SettingError::new(
+                "duration_secs",
+                format!(
+                    "duration_secs must be from {MIN_DURATION_SECS} to {MAX_DURATION_SECS} \
+                     (or null for Endless)"
+                ),
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// How long a game may run before it's stopped: `duration_secs`, or `ENDLESS_CAP_SECS` for
+    /// Endless. `validate` turns away durations out of range; this holds them to it anyway,
+    /// so nothing that skipped validation can start a game that ends at once or never.
+    pub fn game_secs(&self) -> u64 {
+        let secs = self.duration_secs.map_or(ENDLESS_CAP_SECS, |secs| {
+            secs.clamp(MIN_DURATION_SECS, MAX_DURATION_SECS)
+        });
+        secs.into()
+    }
+
+    pub fn is_endless(&self) -> bool {
+        self.duration_secs.is_none()
+    }
+
+    /// Whether boards can come from the precomputed 1..=9 combos.
+    pub fn uses_classic_values(&self) -> bool {
+        self.min_value == MIN_APPLE_VALUE
+            && self.max_value == MAX_APPLE_VALUE
+            && self.target_sum == TARGET_SUM
+    }
+
+    pub fn contains(&self, value: u8) -> bool {
+        (self.min_value..=self.max_value).contains(&value)
+    }
+}
+
+/// One line of a room's visible history, kept in the order it happened.
+#[derive(Serialize, Deserialize, TS, Debug, Clone)]
+#[serde(tag = "type", content = "data")]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum RoomLogEntry {
+    /// A `ChatBroadcast`, as it was sent.
+    Chat {
+        message_id: u64,
+        channel: ChatChannel,
+        player: Player,
+        message: String,
+        is_spectator: bool,
+        at_ms: u64,
+    },
+    /// A `SystemMessage`, as it was sent.
+    System {
+        kind: SystemMessageKind,
+        text: String,
+        at_ms: u64,
+    },
+}
+
+/// Who a chat message is for. Players post to `All` unless they say otherwise, spectators to
+/// `Spectators`, so spectator chatter doesn't distract a game unless players ask for it.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum ChatChannel {
+    /// Everyone in the room.
+    All,
+    /// Only the players.
+    Players,
+    /// The spectators, and players who sent `FollowSpectatorChat`.
+    Spectators,
+}
+
+impl ChatChannel {
+    /// Whether chat on this channel reaches a room member, by whether they're spectating and
+    /// whether they (as a player) follow the spectators' chat.
+    pub fn reaches(self, spectating: bool, follows_spectators: bool) -> bool {
+        match self {
+            ChatChannel::All => true,
+            ChatChannel::Players => !spectating,
+            ChatChannel::Spectators => spectating || follows_spectators,
+        }
+    }
+}
+
+impl RoomLogEntry {
+    /// Whether this entry is for a room member, as `ChatChannel::reaches` tells it.
+    pub fn reaches(&self, spectating: bool, follows_spectators: bool) -> bool {
+        match self {
+            RoomLogEntry::Chat { channel, .. } => channel.reaches(spectating, follows_spectators),
+            RoomLogEntry::System { .. } => true,
+        }
+    }
+}
+
+/// What a `SystemMessage` is about, so clients can style each kind of notice differently.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum SystemMessageKind {
+    PlayerJoined,
+    PlayerLeft,
+    PlayerDisconnected,
+    PlayerReconnected,
+    OwnerChanged,
+    MovedToSpectators,
+    Muted,
+    Unmuted,
+    GameStarted,
+    GameEnded,
+    /// A `GameStarting` countdown ran out with the room no longer able to start.
+    StartCalledOff,
+    /// Slow mode was turned on, off or changed; also sent to just the sender of a message
+    /// that came too soon, saying how long to wait.
+    SlowMode,
 }
 
 /// All messages the **front end** can send to the server.
@@ -36,39 +366,308 @@ pub struct Player {
 #[ts(export, export_to = "../frontend/src/types/ws.ts")]
 pub enum WsClientMsg {
     /// Client wants to create a new room. Sends their `Player` (name + a client‐generated `player_id` or `""`).
+    /// `settings` picks the game rules (defaults to values 1..=9) and how much history the room
+    /// keeps.
     CreateRoom {
         player: Player,
+        #[serde(default)]
+        #[ts(optional)]
+        settings: Option<RoomSettings>,
     },
 
     /// Client wants to join an existing room: the `room_id` and their `Player` (with `player_id=""` if they don’t have one yet).
+    /// To come back to a seat already held under `player_id` (after a drop, or from a new
+    /// tab), send the `seat_token` from that seat's last `SeatGranted`; without it the seat
+    /// is refused with `SeatTaken`.
     JoinRoom {
         room_id: RoomId,
         player: Player,
+        #[serde(default)]
+        #[ts(optional)]
+        seat_token: Option<String>,
+    },
+
+    /// Take up the `ReclaimOffer` this socket was last sent for `room_id`. Refused unless that
+    /// seat is still being held for its dropped player, so it can't take a seat from someone
+    /// connected. `SeatGranted` then tells the client which player ID it now holds.
+    ReclaimSeat { room_id: RoomId },
+
+    /// Watch a room without taking a seat: room broadcasts and chat, but no board or score.
+    SpectateRoom {
+        room_id: RoomId,
+        player: Player,
     },
 
     /// Only the room’s owner can issue this once everyone has joined.
     /// Server will generate and broadcast a `BoardData`.
+    /// While a game runs it's refused with `GameAlreadyRunning`, unless `restart` is set: then
+    /// the running game is dropped (`GameAborted`) and a fresh one dealt to the same players
+    /// at once, without the countdown or ready checks.
     StartGame {
+        #[serde(default)]
+        #[ts(optional)]
+        restart: Option<bool>,
+    },
+
+    /// Solo practice in one step: create a room owned by `player` and start a game in it
+    /// right away. The client gets `RoomCreated`, `RoomPlayersUpdate` and then `GameStarted`.
+    StartSolo {
+        player: Player,
     },
 
-    /// Whenever a client clears some apples, it reports how many it just cleared.
+    /// Whenever a client clears some apples, it reports how many apples it just removed
+    /// (`cleared_count`, which must match `cleared_values`) and their values. The server checks
+    /// the values sum to a multiple of the target and awards points by the room's
+    /// `scoring_mode`. Clients that also send the cleared `rect` get it checked against their
+    /// server-side board (it must add up to exactly the target), which keeps hints accurate.
     ScoreUpdate {
         // room_id: RoomId,
         // player_id: PlayerId,
         cleared_count: u32,
+        /// Counts up from 1 each game, one at a time. An update whose `turn` isn't above the
+        /// last one applied is ignored (and acknowledged) so retries don't score twice; one
+        /// that skips a turn is refused with `TurnOutOfOrder`.
         turn: u32,
+        cleared_values: Vec<u8>,
+        #[serde(default)]
+        #[ts(optional)]
+        rect: Option<Rect>,
     },
 
+    /// Ask the server for one valid clear on this player's board (answered with `Hint`).
+    RequestHint {},
+
     ReadyUp {
         ready: bool,
     },
 
-    /// Player sends a chat message to everyone in the room.
+    /// Ask who the room's start is waiting on (answered with `ReadyStatus`).
+    GetReadyStatus {},
+
+    /// Player sends a chat message to everyone in the room, or to `channel` (see
+    /// `ChatChannel` for the default). Outside a room it goes to the lobby instead (if the
+    /// server has `lobby_chat` on), shown under `name`.
     ChatMessage {
         // room_id: RoomId,
         // player_id: PlayerId,
         message: String,
+        #[serde(default)]
+        #[ts(optional)]
+        name: Option<String>,
+        #[serde(default)]
+        #[ts(optional)]
+        channel: Option<ChatChannel>,
+    },
+
+    /// A player starts (or stops) getting the spectators' chat. Off on joining a room, and
+    /// needed to post to `ChatChannel::Spectators`.
+    FollowSpectatorChat {
+        follow: bool,
+    },
+
+    /// A one-keypress reaction, shown to the room as `EmoteBroadcast`. Not chat: it isn't
+    /// kept in the room log and has a rate limit of its own.
+    Emote {
+        emote: EmoteKind,
+    },
+
+    /// Owner only: keep a player or spectator out of the chat and emotes for `duration_secs`,
+    /// or until `UnmutePlayer` if it's left out. The owner can't be muted.
+    MutePlayer {
+        player_id: PlayerId,
+        #[serde(default)]
+        #[ts(optional)]
+        duration_secs: Option<u64>,
     },
+
+    /// Owner only: lift a mute early.
+    UnmutePlayer {
+        player_id: PlayerId,
+    },
+
+    /// Owner only: set the room's `slow_mode_secs` (0 turns slow mode off).
+    SetSlowMode {
+        secs: u32,
+    },
+
+    /// Ask for earlier chat than `RoomHistory` gave: up to `limit` messages (at most
+    /// `CHAT_HISTORY_PAGE_MAX`) from before `before_id`, or the newest without it. Answered
+    /// with `ChatHistory`.
+    GetChatHistory {
+        #[serde(default)]
+        #[ts(optional, type = "number")]
+        before_id: Option<u64>,
+        limit: u8,
+    },
+
+    /// Ask for a fresh `GlobalPresence` snapshot instead of waiting for the next tick.
+    GetPresence {},
+
+    /// Ask for one player's current score in this room (answered with `PlayerScore`).
+    GetPlayerScore {
+        player_id: PlayerId,
+    },
+
+    /// Leave the current room on purpose (e.g. closing the tab). Unlike a dropped connection,
+    /// this frees the seat immediately instead of holding it for a reconnect.
+    LeaveRoom {},
+
+    /// "I'm done": freeze this player's score for the rest of the round and record how long
+    /// they took. Further `ScoreUpdate`s and hints are refused until the next game.
+    FinishRound {},
+}
+
+impl WsClientMsg {
+    /// Every value `kind` can return.
+    pub const KINDS: [&'static str; 21] = [
+        "CreateRoom",
+        "JoinRoom",
+        "ReclaimSeat",
+        "SpectateRoom",
+        "StartGame",
+        "StartSolo",
+        "ScoreUpdate",
+        "RequestHint",
+        "ReadyUp",
+        "GetReadyStatus",
+        "ChatMessage",
+        "FollowSpectatorChat",
+        "Emote",
+        "MutePlayer",
+        "UnmutePlayer",
+        "SetSlowMode",
+        "GetChatHistory",
+        "GetPresence",
+        "GetPlayerScore",
+        "LeaveRoom",
+        "FinishRound",
+    ];
+
+    /// Variant name, used as a low-cardinality label in logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WsClientMsg::CreateRoom { .. } => "CreateRoom",
+            WsClientMsg::JoinRoom { .. } => "JoinRoom",
+            WsClientMsg::ReclaimSeat { .. } => "ReclaimSeat",
+            WsClientMsg::SpectateRoom { .. } => "SpectateRoom",
+            WsClientMsg::StartGame { .. } => "StartGame",
+            WsClientMsg::StartSolo { .. } => "StartSolo",
+            WsClientMsg::ScoreUpdate { .. } => "ScoreUpdate",
+            WsClientMsg::RequestHint {} => "RequestHint",
+            WsClientMsg::ReadyUp { .. } => "ReadyUp",
+            WsClientMsg::GetReadyStatus {} => "GetReadyStatus",
+            WsClientMsg::ChatMessage { .. } => "ChatMessage",
+            WsClientMsg::FollowSpectatorChat { .. } => "FollowSpectatorChat",
+            WsClientMsg::Emote { .. } => "Emote",
+            WsClientMsg::MutePlayer { .. } => "MutePlayer",
+            WsClientMsg::UnmutePlayer { .. } => "UnmutePlayer",
+            WsClientMsg::SetSlowMode { .. } => "SetSlowMode",
+            WsClientMsg::GetChatHistory { .. } => "GetChatHistory",
+            WsClientMsg::GetPresence {} => "GetPresence",
+            WsClientMsg::GetPlayerScore { .. } => "GetPlayerScore",
+            WsClientMsg::LeaveRoom {} => "LeaveRoom",
+            WsClientMsg::FinishRound {} => "FinishRound",
+        }
+    }
+}
+
+/// The reactions a client can send with `Emote`; the frontend picks how each one looks.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum EmoteKind {
+    Clap,
+    Laugh,
+    Wow,
+    Scream,
+    Cry,
+    Angry,
+    Fire,
+    Heart,
+    ThumbsUp,
+    ThumbsDown,
+    GoodGame,
+}
+
+/// Why a room was torn down (`RoomClosed`).
+#[derive(Serialize, Deserialize, TS, Debug, Clone, Copy, PartialEq, Eq)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum RoomCloseReason {
+    /// The last player left; only spectators were still watching.
+    Empty,
+    /// An operator closed it through the admin API.
+    AdminClosed,
+    /// The server is going down.
+    ServerShutdown,
+}
+
+/// Machine-readable reasons attached to some `Error`s.
+#[derive(Serialize, Deserialize, TS, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "data")]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub enum ErrorCode {
+    /// Slow down: the same request will be accepted again after `retry_after_ms`.
+    RateLimited {
+        #[ts(type = "number")]
+        retry_after_ms: u64,
+    },
+    /// The sender is muted in this room's chat; the mute ends after `retry_after_ms`, or when
+    /// the owner lifts it if that's `None`.
+    Muted {
+        #[ts(type = "number | null")]
+        retry_after_ms: Option<u64>,
+    },
+    /// The name was blank or longer than `max_len` characters (`MAX_NAME_LEN`).
+    InvalidName { max_len: u32 },
+    /// The server already has `max_rooms` rooms; joining an existing one still works.
+    ServerFull { max_rooms: u32 },
+    /// The server already runs `max_games` games; starting this one can wait until one ends.
+    ServerBusy { max_games: u32 },
+    /// The server is draining before a restart: no new rooms or games until it's back.
+    Maintenance,
+    /// The message wasn't JSON, or wasn't any known message. `line` and `column` (1-based, the
+    /// column in bytes) are where parsing stopped; `snippet` is the input around that point,
+    /// at most `MALFORMED_JSON_SNIPPET_BYTES` long.
+    MalformedJson {
+        line: u32,
+        column: u32,
+        snippet: String,
+    },
+    /// `CreateRoom` settings that break one or more rules, each named by field.
+    InvalidSettings { errors: Vec<SettingError> },
+    /// `JoinRoom` found every one of the room's `max_players` seats taken.
+    RoomFull { max_players: u32 },
+    /// `JoinRoom` arrived while a game was starting or running; spectate, or join once it's over.
+    GameInProgress,
+    /// `StartGame` without `restart` while a game is running; the game carries on untouched.
+    GameAlreadyRunning,
+    /// A `ScoreUpdate` with no game to count it in: in the lobby, or during the start
+    /// countdown. Nothing was scored or broadcast.
+    GameNotRunning,
+    /// A `ScoreUpdate` skipped ahead of the next turn, `expected`; nothing was counted. The
+    /// turns in between never arrived, so the client should resend from `expected`.
+    TurnOutOfOrder { expected: u32 },
+    /// `JoinRoom` named a player ID that already has a seat in the room, without that seat's
+    /// token. Join under a new ID instead.
+    SeatTaken,
+}
+
+/// Longest input excerpt a `MalformedJson` error echoes back.
+pub const MALFORMED_JSON_SNIPPET_BYTES: usize = 64;
+
+/// A room broadcast together with its place in that room's sequence, sent as the message's own
+/// `type`/`data` plus a top-level `seq`.
+///
+/// `seq` goes up by one with every broadcast in the room. Direct replies that snapshot the room
+/// (the join acknowledgement, `RoomHistory`, `Resumed`) carry the `seq` they are current as of,
+/// so a client can drop any update with a `seq` it has already seen. Chat on a channel a
+/// socket doesn't get is skipped for it, so its `seq` can jump past those.
+#[derive(Serialize, Deserialize, TS, Debug, Clone)]
+#[ts(export, export_to = "../frontend/src/types/ws.ts")]
+pub struct RoomEvent {
+    #[ts(type = "number")]
+    pub seq: u64,
+    #[serde(flatten)]
+    pub msg: WsServerMsg,
 }
 
 /// All messages the **server** can push back to every client in a room.
@@ -79,6 +678,15 @@ pub enum WsServerMsg {
     /// A new room was created. Server returns the `room_id` and the `Player` (with assigned `player_id`).
     RoomCreated { room_id: RoomId },
 
+    /// This socket now holds `player_id`'s seat in the room. Keep `seat_token` to take the
+    /// seat back with `JoinRoom` later; a fresh one is issued every time the seat is taken,
+    /// and the one before stops working.
+    SeatGranted {
+        room_id: RoomId,
+        player_id: PlayerId,
+        seat_token: String,
+    },
+
     // /// Broadcast to that client (and any later joiners) the full current room info:
     // /// room ID and the list of current players (their `Player` structs).
     // JoinedRoom {
@@ -92,41 +700,370 @@ pub enum WsServerMsg {
         owner_id: PlayerId, // who is the room owner
     },
 
+    /// The room has a new owner. Always follows the `RoomPlayersUpdate` that carries the new
+    /// `owner_id`, in which the previous owner may already be gone.
+    OwnerChanged {
+        room_id: RoomId,
+        owner_id: PlayerId,
+        previous_owner_id: PlayerId,
+    },
+
+    /// Broadcast when a game is about to start, by `StartGame` or `auto_start`: `GameStarted`
+    /// follows in `in_secs` seconds, unless someone isn't ready by then (which the room is told).
+    GameStarting { room_id: RoomId, in_secs: u64 },
+
     /// Sent once when the owner hits “Start Game.” Contains an array of 170 u8s (1..=9).
     GameStarted {
         room_id: RoomId,
-        board: BoardData,
-        duration_secs: u64, // e.g. 60
+        board: Arc<BoardData>,
+        /// How long the game runs (`RoomSettings::game_secs`); for Endless, its cap.
+        duration_secs: u64,
+        /// The room's rules for this round, `scoring_mode` and `ranked` included.
+        settings: RoomSettings,
     },
 
-    /// Sent once per second so clients can update their countdown timer.
+    /// Sent once per second so clients can update their countdown timer. `remaining_secs` is
+    /// read off the server's clock, so a tick that goes out late still says the right thing;
+    /// `ends_at_ms` is when the game ends (Unix ms), for clients that count down themselves.
     TimerTick {
         // room_id: RoomId,
         remaining_secs: u64,
+        #[ts(type = "number")]
+        ends_at_ms: u64,
     },
 
     /// Sent whenever anyone’s score changes (or on initial GameStarted if you prefer).
     /// `scores` is a Vec of `(PlayerId, u32)` pairs. The front‐end can merge this with its `players` list.
+    /// `turns` holds each player's last applied `turn` the same way, so a client can tell when
+    /// the server has caught up with what it sent.
     LeaderboardUpdate {
         room_id: RoomId,
         scores: Vec<(PlayerId, u32)>,
+        turns: Vec<(PlayerId, u32)>,
     },
 
-    /// Server broadcasts a chat message to all players in the room.
+    /// Server broadcasts a chat message to all players in the room. `message_id` goes up by
+    /// one with every message in the room, for as long as it exists, and `at_ms` is when the
+    /// server took it (Unix ms); both are kept in the room log, so a client merging
+    /// `RoomHistory` or `StateSync` into what it has can skip the messages it already shows.
     ChatBroadcast {
         room_id: RoomId,
+        #[ts(type = "number")]
+        message_id: u64,
+        channel: ChatChannel,
         player: Player,
         message: String,
+        /// Sent by a spectator rather than someone playing.
+        is_spectator: bool,
+        #[ts(type = "number")]
+        at_ms: u64,
+    },
+
+    /// A `ChatMessage` sent from the lobby, to every socket that isn't in a room.
+    LobbyChatBroadcast {
+        name: String,
+        message: String,
+    },
+
+    /// Someone in the room sent an `Emote`.
+    EmoteBroadcast {
+        room_id: RoomId,
+        player_id: PlayerId,
+        emote: EmoteKind,
+    },
+
+    /// The room is going away; sent to everyone still in it just before the socket is closed.
+    RoomClosed {
+        room_id: RoomId,
+        reason: RoomCloseReason,
+    },
+
+    /// Confirms `LeaveRoom`; the socket is back in the lobby.
+    LeftRoom { room_id: RoomId },
+
+    /// Another socket took this one's place: it joined the same room under the same player ID
+    /// (with the seat's token), or entered any room under it while the server allows one session per player. This
+    /// socket is closed right after.
+    SessionReplaced {},
+
+    /// Sent instead of joining when a `JoinRoom` has a new player ID but the name of a player
+    /// whose seat is being held after a drop (and the server has `reclaim_by_name`):
+    /// probably the same person, having lost their ID. `ReclaimSeat` on the same socket takes
+    /// the seat and its score back; to join as someone else, pick another name. The held
+    /// seat's ID isn't sent: the offer is kept with this connection until it is taken up.
+    ///
+    /// This is weaker than reconnecting with the seat token: it only proves the name is known,
+    /// which is why it is limited to seats nobody is connected to.
+    ReclaimOffer { room_id: RoomId, name: String },
+
+    /// Sent instead of the usual join reply when a dropped player comes back within the grace
+    /// period: their board as they left it (if a game is running) and the current scores.
+    Resumed {
+        room_id: RoomId,
+        board: Option<Vec<Option<u8>>>,
+        scores: Vec<(PlayerId, u32)>,
+    },
+
+    /// Sent to a socket that fell behind the room's broadcasts and missed some, right after a
+    /// `RoomPlayersUpdate` with the same `seq`: what the missed updates would have told it.
+    /// `board` is the recipient's own board while they play a game; `remaining_secs` and
+    /// `ends_at_ms` (as in `TimerTick`) are set while a game runs, unless it's Endless.
+    /// `entries` is the room log, as in `RoomHistory`, for the chat and notices that went by
+    /// meanwhile.
+    StateSync {
+        room_id: RoomId,
+        scores: Vec<(PlayerId, u32)>,
+        // boxed, which keeps this, the biggest message, under clippy's `result_large_err`
+        board: Option<Box<[Option<u8>]>>,
+        remaining_secs: Option<u64>,
+        #[ts(type = "number | null")]
+        ends_at_ms: Option<u64>,
+        entries: Vec<RoomLogEntry>,
+    },
+
+    /// Reply to the sender of `ReadyUp` once their ready flag is set to `ready`. The owner
+    /// starts the game rather than readying up, so their `ReadyUp` changes nothing: it's
+    /// answered with `ready: false` and `counted: false`.
+    ReadyAck { room_id: RoomId, ready: bool, counted: bool },
+
+    /// Answer to `GetReadyStatus`: the seated players other than the owner (who never readies
+    /// up), split by their ready flag, in join order. `can_start` is whether a `StartGame`
+    /// from the owner would go through right now, by the very checks it makes.
+    ReadyStatus {
+        room_id: RoomId,
+        ready: Vec<PlayerId>,
+        not_ready: Vec<PlayerId>,
+        can_start: bool,
+    },
+
+    /// Reply to the sender of every `ScoreUpdate`. `applied` is false when `turn` had already been
+    /// seen (a retry or a stale duplicate) and the update was ignored; `total` is the score either way.
+    ScoreAck {
+        room_id: RoomId,
+        turn: u32,
+        total: u32,
+        applied: bool,
+    },
+
+    /// A player's clear sent with a `rect` checked out: those cells are now empty on their
+    /// board. Just the rectangle, so spectators can follow each board without a resend.
+    BoardCleared {
+        room_id: RoomId,
+        player_id: PlayerId,
+        rect: Rect,
+    },
+
+    /// A player's board has no valid clears left (only detected for clears sent with a `rect`).
+    NoMovesLeft {
+        room_id: RoomId,
+        player_id: PlayerId,
+    },
+
+    /// A player sent no score for the room's `idle_kick_secs` and now only watches: their seat,
+    /// score and board are gone. Followed by the new `RoomPlayersUpdate`.
+    MovedToSpectators {
+        room_id: RoomId,
+        player_id: PlayerId,
+    },
+
+    /// Reply to `GetPlayerScore`: the player's score so far and the last `turn` applied for
+    /// them this game (0 before their first clear).
+    PlayerScore {
+        room_id: RoomId,
+        player_id: PlayerId,
+        score: u32,
+        turns: u32,
+    },
+
+    /// A player sent `FinishRound`: their `score` is final, reached `elapsed_ms` into the round.
+    PlayerFinished {
+        room_id: RoomId,
+        player_id: PlayerId,
+        score: u32,
+        elapsed_ms: u32,
+    },
+
+    /// The round is over, either because time ran out or everyone was done early.
+    /// `finish_times_ms` lists the players who sent `FinishRound`, quickest first, for breaking
+    /// ties on score. Players who dropped but whose seat is still held are scored like everyone
+    /// else, and listed in `disconnected`; those whose grace ran out are gone from `scores`.
+    GameOver {
+        room_id: RoomId,
+        scores: Vec<(PlayerId, u32)>,
+        ended_early: bool,
+        finish_times_ms: Vec<(PlayerId, u32)>,
+        disconnected: Vec<PlayerId>,
+    },
+
+    /// The round was abandoned, because of a server error or because the owner restarted it;
+    /// nothing was recorded. `scores` are the ones thrown away. After an error the room is back
+    /// in its lobby; a restart is followed by `GameStarted`.
+    GameAborted {
+        room_id: RoomId,
+        reason: String,
+        scores: Vec<(PlayerId, u32)>,
+    },
+
+    /// Answer to `RequestHint`: a rectangle that clears, or `None` if the board has no moves left.
+    Hint {
+        room_id: RoomId,
+        rect: Option<Rect>,
+    },
+
+    /// A server notice in the room's log, e.g. someone joining or leaving. `text` is ready
+    /// to show; `at_ms` is when it happened (Unix ms), as kept in the log.
+    SystemMessage {
+        room_id: RoomId,
+        kind: SystemMessageKind,
+        text: String,
+        #[ts(type = "number")]
+        at_ms: u64,
+    },
+
+    /// Sent to a player right after they join: the room's recent chat and notices, oldest first.
+    RoomHistory {
+        room_id: RoomId,
+        entries: Vec<RoomLogEntry>,
+    },
+
+    /// Answer to `GetChatHistory`: `Chat` entries only, oldest first. `has_more` says whether
+    /// the room still has older ones (ask again with the first one's `message_id`).
+    ChatHistory {
+        room_id: RoomId,
+        messages: Vec<RoomLogEntry>,
+        has_more: bool,
     },
 
     /// Used to notify of any error: invalid room, not owner, etc.
     Error {
         room_id: Option<RoomId>,
         msg: String,
+        /// Set for errors a client may want to handle rather than just show.
+        code: Option<ErrorCode>,
+    },
+
+    /// The first message on every connection, and pushed again to the lobby when the MOTD changes.
+    ServerInfo {
+        server_name: String,
+        motd: Option<String>,
     },
 
     /// Sent to newly connected clients (before joining a room), showing the global top 10 scores.
     Top10Scores {
-        scores: Vec<(u32, String)>, // (player_name, score)
+        scores: Arc<Vec<(u32, String)>>, // (score, player_name), best first
+    },
+
+    /// Pushed periodically to clients that are not in a room, for the landing page's live counts.
+    GlobalPresence {
+        online: u32, // connected sockets
+        rooms: u32,  // active rooms
     },
+
+    /// Sent alongside `GlobalPresence`: running totals since the server started.
+    ServerStats {
+        #[ts(type = "number")]
+        messages_handled: u64,
+        #[ts(type = "number")]
+        broadcasts: u64,
+        #[ts(type = "number")]
+        games_completed: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fields `settings` is refused for, in the order they're reported.
+    fn broken(settings: RoomSettings) -> Vec<String> {
+        match settings.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| e.field).collect(),
+        }
+    }
+
+    #[test]
+    fn the_default_settings_are_valid() {
+        assert_eq!(RoomSettings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn min_value_may_not_be_above_max_value() {
+        let settings = RoomSettings { min_value: 6, max_value: 5, ..RoomSettings::default() };
+        // with the range broken, its pairs aren't looked at
+        assert_eq!(broken(settings), ["min_value"]);
+        let settings = RoomSettings { min_value: 5, max_value: 5, ..RoomSettings::default() };
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn max_value_must_be_below_the_target() {
+        let settings = RoomSettings { max_value: 10, ..RoomSettings::default() };
+        assert_eq!(broken(settings), ["max_value"]);
+        let settings = RoomSettings { max_value: 12, target_sum: 12, ..RoomSettings::default() };
+        assert_eq!(broken(settings), ["max_value"]);
+    }
+
+    #[test]
+    fn some_two_values_must_add_up_to_the_target() {
+        // the largest pair falls short...
+        let settings = RoomSettings { max_value: 4, ..RoomSettings::default() };
+        assert_eq!(broken(settings), ["target_sum"]);
+        // ...or the smallest one is already over
+        let settings = RoomSettings { min_value: 6, ..RoomSettings::default() };
+        assert_eq!(broken(settings), ["target_sum"]);
+        // a pair of the same value will do
+        let settings = RoomSettings { min_value: 5, max_value: 5, ..RoomSettings::default() };
+        assert_eq!(settings.validate(), Ok(()));
+    }
+
+    #[test]
+    fn an_idle_kick_must_leave_time_to_play() {
+        let kick = |secs| RoomSettings { idle_kick_secs: secs, ..RoomSettings::default() };
+        assert_eq!(broken(kick(Some(MIN_IDLE_KICK_SECS - 1))), ["idle_kick_secs"]);
+        assert_eq!(kick(Some(MIN_IDLE_KICK_SECS)).validate(), Ok(()));
+        assert_eq!(kick(None).validate(), Ok(()));
+    }
+
+    #[test]
+    fn slow_mode_is_capped() {
+        let slow = |secs| RoomSettings { slow_mode_secs: secs, ..RoomSettings::default() };
+        assert_eq!(broken(slow(MAX_SLOW_MODE_SECS + 1)), ["slow_mode_secs"]);
+        assert_eq!(slow(MAX_SLOW_MODE_SECS).validate(), Ok(()));
+    }
+
+    #[test]
+    fn a_game_lasts_from_the_shortest_to_the_longest_duration() {
+        let lasting = |secs| RoomSettings { duration_secs: secs, ..RoomSettings::default() };
+        assert_eq!(broken(lasting(Some(0))), ["duration_secs"]);
+        assert_eq!(broken(lasting(Some(MIN_DURATION_SECS - 1))), ["duration_secs"]);
+        assert_eq!(lasting(Some(MIN_DURATION_SECS)).validate(), Ok(()));
+        assert_eq!(lasting(Some(MAX_DURATION_SECS)).validate(), Ok(()));
+        assert_eq!(broken(lasting(Some(MAX_DURATION_SECS + 1))), ["duration_secs"]);
+        // Endless
+        assert_eq!(lasting(None).validate(), Ok(()));
+    }
+
+    #[test]
+    fn the_timer_is_never_given_a_length_out_of_range() {
+        let secs = |secs| {
+            RoomSettings { duration_secs: secs, ..RoomSettings::default() }.game_secs()
+        };
+        assert_eq!(secs(Some(0)), u64::from(MIN_DURATION_SECS));
+        assert_eq!(secs(Some(MIN_DURATION_SECS)), u64::from(MIN_DURATION_SECS));
+        assert_eq!(secs(Some(MAX_DURATION_SECS)), u64::from(MAX_DURATION_SECS));
+        assert_eq!(secs(Some(u32::MAX)), u64::from(MAX_DURATION_SECS));
+        assert_eq!(secs(None), u64::from(ENDLESS_CAP_SECS));
+    }
+
+    #[test]
+    fn every_broken_rule_is_reported_at_once() {
+        let settings = RoomSettings {
+            max_value: 10,
+            idle_kick_secs: Some(1),
+            slow_mode_secs: MAX_SLOW_MODE_SECS + 1,
+            ..RoomSettings::default()
+        };
+        assert_eq!(broken(settings), ["max_value", "idle_kick_secs", "slow_mode_secs"]);
+    }
 }